@@ -0,0 +1,70 @@
+//! Rewriting `#[dokechi(codec = "...")]` of fields into `#[serde(with = "...")]`, for `#[with_codecs]`.
+
+use syn::{parse_quote, Attribute, Data, DeriveInput, Error, Fields, LitStr, Path, Result};
+
+/// Replace `#[dokechi(codec = "...")]` of fields of `input` with `#[serde(with = "...")]`.
+pub(crate) fn rewrite(input: &mut DeriveInput) -> Result<()> {
+    match &mut input.data {
+        Data::Struct(data) => rewrite_fields(&mut data.fields),
+        Data::Enum(data) => {
+            for variant in &mut data.variants {
+                rewrite_fields(&mut variant.fields)?;
+            }
+            Ok(())
+        }
+        Data::Union(data) => Err(Error::new_spanned(
+            data.union_token,
+            "unions are not supported",
+        )),
+    }
+}
+
+fn rewrite_fields(fields: &mut Fields) -> Result<()> {
+    for field in fields.iter_mut() {
+        let mut attrs = Vec::with_capacity(field.attrs.len());
+        for attr in field.attrs.drain(..) {
+            if !attr.path().is_ident("dokechi") {
+                attrs.push(attr);
+                continue;
+            }
+            let codec = codec(&attr)?;
+            attrs.push(parse_quote!(#[serde(with = #codec)]));
+        }
+        field.attrs = attrs;
+    }
+    Ok(())
+}
+
+/// The path of the codec module given by a `#[dokechi(codec = "...")]` of a field.
+fn codec(attr: &Attribute) -> Result<LitStr> {
+    let mut codec = None;
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("codec") {
+            let path: LitStr = meta.value()?.parse()?;
+            path.parse::<Path>()?;
+            codec = Some(path);
+            Ok(())
+        } else {
+            Err(meta.error("unknown dokechi field attribute"))
+        }
+    })?;
+    codec.ok_or_else(|| Error::new_spanned(attr, "expected `codec = \"...\"`"))
+}
+
+/// Fail if a field has `#[dokechi(...)]`, which is left only without `#[with_codecs]`.
+pub(crate) fn check_rewritten(input: &DeriveInput) -> Result<()> {
+    let fields: Vec<&Fields> = match &input.data {
+        Data::Struct(data) => vec![&data.fields],
+        Data::Enum(data) => data.variants.iter().map(|v| &v.fields).collect(),
+        Data::Union(_) => Vec::new(),
+    };
+    for field in fields.into_iter().flat_map(Fields::iter) {
+        if let Some(attr) = field.attrs.iter().find(|a| a.path().is_ident("dokechi")) {
+            return Err(Error::new_spanned(
+                attr,
+                "#[dokechi] of fields needs #[serde_dokechi::with_codecs] above #[derive]",
+            ));
+        }
+    }
+    Ok(())
+}
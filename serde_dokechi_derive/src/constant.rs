@@ -0,0 +1,326 @@
+//! Encoding literal values into Dokechi format at compile time, for `dokechi_const!`.
+//!
+//! Values are encoded as the default [`Serializer`](https://docs.rs/serde_dokechi/*/serde_dokechi/ser/struct.Serializer.html)
+//! writes them.
+
+use syn::punctuated::Punctuated;
+use syn::{Error, Expr, GenericArgument, Lit, PathArguments, Result, Token, Type, UnOp};
+
+/// Encode `expr` as a value of `ty` into `out`.
+pub(crate) fn encode(expr: &Expr, ty: &Type, out: &mut Vec<u8>) -> Result<()> {
+    let expr = strip_expr(expr);
+    match ty {
+        Type::Paren(ty) => encode(expr, &ty.elem, out),
+        Type::Group(ty) => encode(expr, &ty.elem, out),
+        Type::Reference(r) => match &*r.elem {
+            Type::Slice(slice) => seq(expr, &slice.elem, out),
+            elem => encode(expr, elem, out),
+        },
+        Type::Tuple(tuple) => {
+            let elements = match expr {
+                Expr::Tuple(t) => t.elems.iter().collect::<Vec<_>>(),
+                _ => return Err(Error::new_spanned(expr, "expected a tuple")),
+            };
+            if elements.len() != tuple.elems.len() {
+                return Err(Error::new_spanned(
+                    expr,
+                    format!("expected a tuple of {} elements", tuple.elems.len()),
+                ));
+            }
+            for (e, t) in elements.into_iter().zip(tuple.elems.iter()) {
+                encode(e, t, out)?;
+            }
+            Ok(())
+        }
+        Type::Array(array) => {
+            let len = match &array.len {
+                Expr::Lit(l) => match &l.lit {
+                    Lit::Int(n) => n.base10_parse::<usize>()?,
+                    _ => return Err(Error::new_spanned(&array.len, "expected a length")),
+                },
+                len => return Err(Error::new_spanned(len, "expected a literal length")),
+            };
+            let elements = elements(expr)?;
+            if elements.len() != len {
+                return Err(Error::new_spanned(
+                    expr,
+                    format!("expected an array of {} elements", len),
+                ));
+            }
+            for e in &elements {
+                encode(e, &array.elem, out)?;
+            }
+            Ok(())
+        }
+        Type::Path(path) if path.qself.is_none() => {
+            let segment = match path.path.segments.last() {
+                Some(segment) => segment,
+                None => return Err(unsupported(ty)),
+            };
+            let name = segment.ident.to_string();
+            match name.as_str() {
+                "Option" => {
+                    let inner = type_argument(&segment.arguments, ty)?;
+                    option(expr, inner, out)
+                }
+                "Vec" => {
+                    let inner = type_argument(&segment.arguments, ty)?;
+                    seq(expr, inner, out)
+                }
+                _ => primitive(expr, &name, ty, out),
+            }
+        }
+        _ => Err(unsupported(ty)),
+    }
+}
+
+/// Encode a value of a primitive type named `name`.
+fn primitive(expr: &Expr, name: &str, ty: &Type, out: &mut Vec<u8>) -> Result<()> {
+    match name {
+        "bool" => match expr {
+            Expr::Lit(l) => match &l.lit {
+                Lit::Bool(b) => {
+                    out.push(b.value as u8);
+                    Ok(())
+                }
+                _ => Err(Error::new_spanned(expr, "expected a bool")),
+            },
+            _ => Err(Error::new_spanned(expr, "expected a bool")),
+        },
+        "u8" => {
+            out.push(unsigned(expr, u8::MAX as u128)? as u8);
+            Ok(())
+        }
+        "u16" | "u32" | "u64" | "usize" => {
+            let max = match name {
+                "u16" => u16::MAX as u128,
+                "u32" => u32::MAX as u128,
+                _ => u64::MAX as u128,
+            };
+            varuint(out, unsigned(expr, max)?, false);
+            Ok(())
+        }
+        "u128" => {
+            varuint(out, unsigned(expr, u128::MAX)?, true);
+            Ok(())
+        }
+        "i8" => {
+            out.push(signed(expr, i8::MIN as i128, i8::MAX as i128)? as i8 as u8);
+            Ok(())
+        }
+        "i16" | "i32" | "i64" | "isize" => {
+            let (min, max) = match name {
+                "i16" => (i16::MIN as i128, i16::MAX as i128),
+                "i32" => (i32::MIN as i128, i32::MAX as i128),
+                _ => (i64::MIN as i128, i64::MAX as i128),
+            };
+            varuint(out, zigzag(signed(expr, min, max)?), false);
+            Ok(())
+        }
+        "i128" => {
+            varuint(out, zigzag(signed(expr, i128::MIN, i128::MAX)?), true);
+            Ok(())
+        }
+        "f32" => {
+            let v: f32 = float(expr)?
+                .parse()
+                .map_err(|e| Error::new_spanned(expr, e))?;
+            out.extend_from_slice(&v.to_le_bytes());
+            Ok(())
+        }
+        "f64" => {
+            let v: f64 = float(expr)?
+                .parse()
+                .map_err(|e| Error::new_spanned(expr, e))?;
+            out.extend_from_slice(&v.to_le_bytes());
+            Ok(())
+        }
+        "char" => match expr {
+            Expr::Lit(l) => match &l.lit {
+                Lit::Char(c) => {
+                    out.extend_from_slice(&(c.value() as u32).to_le_bytes()[..3]);
+                    Ok(())
+                }
+                _ => Err(Error::new_spanned(expr, "expected a char")),
+            },
+            _ => Err(Error::new_spanned(expr, "expected a char")),
+        },
+        "str" | "String" => match expr {
+            Expr::Lit(l) => match &l.lit {
+                Lit::Str(s) => {
+                    let s = s.value();
+                    varuint(out, s.len() as u128, false);
+                    out.extend_from_slice(s.as_bytes());
+                    Ok(())
+                }
+                _ => Err(Error::new_spanned(expr, "expected a string")),
+            },
+            _ => Err(Error::new_spanned(expr, "expected a string")),
+        },
+        _ => Err(unsupported(ty)),
+    }
+}
+
+/// Encode `None` or `Some(expr)` of `Option<inner>`.
+fn option(expr: &Expr, inner: &Type, out: &mut Vec<u8>) -> Result<()> {
+    match expr {
+        Expr::Path(p) if p.path.is_ident("None") => {
+            out.push(0);
+            Ok(())
+        }
+        Expr::Call(call) if call.args.len() == 1 => match strip_expr(&call.func) {
+            Expr::Path(p) if p.path.is_ident("Some") => {
+                out.push(1);
+                encode(&call.args[0], inner, out)
+            }
+            _ => Err(Error::new_spanned(expr, "expected `None` or `Some(..)`")),
+        },
+        _ => Err(Error::new_spanned(expr, "expected `None` or `Some(..)`")),
+    }
+}
+
+/// Encode a sequence of `element`, the length followed by the elements.
+fn seq(expr: &Expr, element: &Type, out: &mut Vec<u8>) -> Result<()> {
+    // A byte string is a sequence of bytes.
+    if let Expr::Lit(l) = expr {
+        if let Lit::ByteStr(bs) = &l.lit {
+            let bs = bs.value();
+            varuint(out, bs.len() as u128, false);
+            out.extend_from_slice(&bs);
+            return Ok(());
+        }
+    }
+    let elements = elements(expr)?;
+    varuint(out, elements.len() as u128, false);
+    for e in &elements {
+        encode(e, element, out)?;
+    }
+    Ok(())
+}
+
+/// The elements of an array expression, or of `vec![..]`.
+fn elements(expr: &Expr) -> Result<Vec<Expr>> {
+    match expr {
+        Expr::Array(array) => Ok(array.elems.iter().cloned().collect()),
+        Expr::Macro(m) if m.mac.path.is_ident("vec") => {
+            let elems = m
+                .mac
+                .parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)?;
+            Ok(elems.into_iter().collect())
+        }
+        _ => Err(Error::new_spanned(expr, "expected an array")),
+    }
+}
+
+/// Remove parentheses and references around `expr`.
+fn strip_expr(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Paren(p) => strip_expr(&p.expr),
+        Expr::Group(g) => strip_expr(&g.expr),
+        Expr::Reference(r) => strip_expr(&r.expr),
+        _ => expr,
+    }
+}
+
+/// The type argument of `Option<T>` or `Vec<T>`.
+fn type_argument<'a>(arguments: &'a PathArguments, ty: &Type) -> Result<&'a Type> {
+    if let PathArguments::AngleBracketed(args) = arguments {
+        if let Some(GenericArgument::Type(inner)) = args.args.first() {
+            return Ok(inner);
+        }
+    }
+    Err(Error::new_spanned(ty, "expected a type argument"))
+}
+
+/// The value of an integer literal, which may be negated.
+fn integer(expr: &Expr) -> Result<(bool, u128)> {
+    match expr {
+        Expr::Lit(l) => match &l.lit {
+            Lit::Int(n) => Ok((false, n.base10_parse::<u128>()?)),
+            _ => Err(Error::new_spanned(expr, "expected an integer")),
+        },
+        Expr::Unary(u) => match u.op {
+            UnOp::Neg(_) => {
+                let (negative, v) = integer(strip_expr(&u.expr))?;
+                Ok((!negative, v))
+            }
+            _ => Err(Error::new_spanned(expr, "expected an integer")),
+        },
+        _ => Err(Error::new_spanned(expr, "expected an integer")),
+    }
+}
+
+fn unsigned(expr: &Expr, max: u128) -> Result<u128> {
+    match integer(expr)? {
+        (false, v) if v <= max => Ok(v),
+        (true, 0) => Ok(0),
+        _ => Err(Error::new_spanned(expr, "integer out of range")),
+    }
+}
+
+fn signed(expr: &Expr, min: i128, max: i128) -> Result<i128> {
+    let out_of_range = || Error::new_spanned(expr, "integer out of range");
+    let v = match integer(expr)? {
+        (false, v) if v <= i128::MAX as u128 => v as i128,
+        (true, v) if v <= i128::MAX as u128 => -(v as i128),
+        (true, v) if v == i128::MAX as u128 + 1 => i128::MIN,
+        _ => return Err(out_of_range()),
+    };
+    if v < min || max < v {
+        return Err(out_of_range());
+    }
+    Ok(v)
+}
+
+fn zigzag(v: i128) -> u128 {
+    if v >= 0 {
+        (v as u128) << 1
+    } else {
+        ((-(v + 1)) as u128) << 1 | 1
+    }
+}
+
+/// The text of a float literal or an integer literal, with the sign.
+fn float(expr: &Expr) -> Result<String> {
+    match expr {
+        Expr::Lit(l) => match &l.lit {
+            Lit::Float(f) => Ok(f.base10_digits().to_owned()),
+            Lit::Int(n) => Ok(n.base10_digits().to_owned()),
+            _ => Err(Error::new_spanned(expr, "expected a float")),
+        },
+        Expr::Unary(u) => match u.op {
+            UnOp::Neg(_) => {
+                let digits = float(strip_expr(&u.expr))?;
+                match digits.strip_prefix('-') {
+                    Some(digits) => Ok(digits.to_owned()),
+                    None => Ok(format!("-{}", digits)),
+                }
+            }
+            _ => Err(Error::new_spanned(expr, "expected a float")),
+        },
+        _ => Err(Error::new_spanned(expr, "expected a float")),
+    }
+}
+
+/// Write `v` as a varuint. `wide` is for `u128`, written in 16 bytes if larger than 56 bits.
+fn varuint(out: &mut Vec<u8>, v: u128, wide: bool) {
+    let bits = 128 - v.leading_zeros() as usize;
+    let bs = v.to_be_bytes();
+    if bits <= 56 {
+        let len = ((bits + 6) / 7).max(1);
+        let mut head = bs[16 - len];
+        head |= !(0xFFu8 >> (len - 1));
+        out.push(head);
+        out.extend_from_slice(&bs[16 - len + 1..]);
+    } else if wide {
+        out.push(0xFF);
+        out.extend_from_slice(&bs);
+    } else {
+        out.push(0xFF);
+        out.extend_from_slice(&bs[8..]);
+    }
+}
+
+fn unsupported(ty: &Type) -> Error {
+    Error::new_spanned(ty, "unsupported type for dokechi_const!")
+}
@@ -0,0 +1,331 @@
+//! Procedural macros of [`serde_dokechi`](https://docs.rs/serde_dokechi).
+//!
+//! Use them through the `derive` feature of `serde_dokechi`, which re-exports them.
+
+#![warn(missing_docs)]
+
+extern crate proc_macro;
+
+mod codecs;
+mod constant;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, Expr, LitInt, Type};
+
+/// Add inherent methods encoding and decoding the type as Dokechi format.
+///
+/// The type gets:
+///
+/// * `fn to_bytes(&self) -> Result<Vec<u8>, serde_dokechi::Error>`
+/// * `fn from_bytes(bs: &[u8]) -> Result<Self, serde_dokechi::Error>`
+/// * `fn encoded_size(&self) -> Result<usize, serde_dokechi::Error>`
+///
+/// The type must implement `Serialize` and `Deserialize`.
+/// This is available with the `derive` feature of `serde_dokechi`.
+///
+/// With `#[dokechi(versioned)]` or `#[dokechi(version = N)]` of [`Versioned`](derive.Versioned.html),
+/// the bytes start with the version header of
+/// [`versioned`](https://docs.rs/serde_dokechi/*/serde_dokechi/versioned/index.html),
+/// and `from_bytes` upgrades older versions. The type must then implement `Versioned`.
+///
+/// ```
+/// use serde_derive::{Deserialize, Serialize};
+/// use serde_dokechi::DokechiMessage;
+///
+/// #[derive(Debug, PartialEq, Serialize, Deserialize, DokechiMessage)]
+/// struct Ping {
+///     seq: u32,
+/// }
+///
+/// let bs = Ping { seq: 7 }.to_bytes().unwrap();
+/// assert_eq!(bs, vec![7]);
+/// assert_eq!(Ping::from_bytes(&bs).unwrap(), Ping { seq: 7 });
+/// assert_eq!(Ping { seq: 7 }.encoded_size().unwrap(), 1);
+/// ```
+#[proc_macro_derive(DokechiMessage, attributes(dokechi))]
+pub fn derive_dokechi_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match dokechi_message(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Implement `Versioned` for a version of a type, as `versioned!` does.
+///
+/// `#[dokechi(version = N)]` declares the first version, and
+/// `#[dokechi(version = N, from = Previous)]` declares a version upgraded from `Previous` by `From`.
+/// Versions must increase along the chain.
+/// This is available with the `derive` feature of `serde_dokechi`.
+///
+/// ```
+/// use serde_derive::{Deserialize, Serialize};
+/// use serde_dokechi::versioned::{from_reader_versioned, to_writer_versioned};
+/// use serde_dokechi::Versioned;
+///
+/// #[derive(Serialize, Deserialize, Versioned)]
+/// #[dokechi(version = 1)]
+/// struct UserV1 {
+///     name: String,
+/// }
+///
+/// #[derive(Debug, PartialEq, Serialize, Deserialize, Versioned)]
+/// #[dokechi(version = 2, from = UserV1)]
+/// struct User {
+///     name: String,
+///     age: Option<u8>,
+/// }
+///
+/// impl From<UserV1> for User {
+///     fn from(v: UserV1) -> User {
+///         User { name: v.name, age: None }
+///     }
+/// }
+///
+/// let mut bs = Vec::new();
+/// to_writer_versioned(&mut bs, &UserV1 { name: "alice".to_owned() }).unwrap();
+///
+/// let user: User = from_reader_versioned(bs.as_slice()).unwrap();
+/// assert_eq!(user, User { name: "alice".to_owned(), age: None });
+/// ```
+#[proc_macro_derive(Versioned, attributes(dokechi))]
+pub fn derive_versioned(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match versioned(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Encode a literal value as Dokechi format at compile time, giving a `&'static [u8]`.
+///
+/// The input is `EXPR as TYPE`, and the bytes are what the default `Serializer` writes for
+/// the value of `TYPE`. Supported are `bool`, integers, floats, `char`, `&str` and `String`
+/// from string literals, `&[u8]` and `Vec<u8>` from byte strings, `Option<T>`, `Vec<T>` and
+/// `&[T]` from arrays or `vec![..]`, arrays `[T; N]`, and tuples, built from literals.
+/// This is available with the `derive` feature of `serde_dokechi`.
+///
+/// ```
+/// use serde_dokechi::dokechi_const;
+///
+/// const PREAMBLE: &[u8] = dokechi_const!(("DKC", 300u32) as (&str, u32));
+/// assert_eq!(PREAMBLE, &serde_dokechi::to_vec(&("DKC", 300u32)).unwrap()[..]);
+/// assert_eq!(PREAMBLE, &[3, b'D', b'K', b'C', 0x81, 0x2C]);
+/// ```
+#[proc_macro]
+pub fn dokechi_const(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as Expr);
+    match constant(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Swap the representation of fields by `#[dokechi(codec = "path::to::module")]`.
+///
+/// Each `#[dokechi(codec = "...")]` of a field is rewritten into `#[serde(with = "...")]`,
+/// so the module provides `serialize` and `deserialize` functions as for `serde(with)`,
+/// such as [`gorilla`](https://docs.rs/serde_dokechi/*/serde_dokechi/gorilla/index.html)
+/// and [`group_varint`](https://docs.rs/serde_dokechi/*/serde_dokechi/group_varint/index.html).
+/// Place this above `#[derive]`, so the derives see the rewritten attributes.
+/// This is available with the `derive` feature of `serde_dokechi`.
+///
+/// ```
+/// use serde_derive::{Deserialize, Serialize};
+/// use serde_dokechi::with_codecs;
+///
+/// #[with_codecs]
+/// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// struct Samples {
+///     #[dokechi(codec = "serde_dokechi::gorilla")]
+///     temperature: Vec<f64>,
+/// }
+///
+/// let v = Samples { temperature: vec![20.5; 100] };
+/// let bs = serde_dokechi::to_vec(&v).unwrap();
+/// assert!(bs.len() < 100);
+/// assert_eq!(serde_dokechi::from_slice::<Samples>(&bs).unwrap(), v);
+/// ```
+#[proc_macro_attribute]
+pub fn with_codecs(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = TokenStream2::from(args);
+        return syn::Error::new_spanned(args, "with_codecs takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let mut input = parse_macro_input!(input as DeriveInput);
+    match codecs::rewrite(&mut input) {
+        Ok(()) => quote!(#input).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Settings of a type given by `#[dokechi(...)]`.
+#[derive(Default)]
+struct Attrs {
+    versioned: bool,
+    version: Option<LitInt>,
+    from: Option<Type>,
+}
+
+impl Attrs {
+    fn parse(input: &DeriveInput) -> syn::Result<Attrs> {
+        let mut attrs = Attrs::default();
+        for attr in &input.attrs {
+            if !attr.path().is_ident("dokechi") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("versioned") {
+                    attrs.versioned = true;
+                    Ok(())
+                } else if meta.path.is_ident("version") {
+                    let version: LitInt = meta.value()?.parse()?;
+                    version.base10_parse::<u32>()?;
+                    attrs.version = Some(version);
+                    Ok(())
+                } else if meta.path.is_ident("from") {
+                    attrs.from = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown dokechi attribute"))
+                }
+            })?;
+        }
+        Ok(attrs)
+    }
+}
+
+fn dokechi_message(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    codecs::check_rewritten(input)?;
+    let attrs = Attrs::parse(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let methods = if attrs.versioned || attrs.version.is_some() {
+        quote! {
+            /// Serialize `self` as Dokechi format into a byte vector, prefixed with its version.
+            pub fn to_bytes(&self) -> ::std::result::Result<::std::vec::Vec<u8>, ::serde_dokechi::Error>
+            where
+                Self: ::serde_dokechi::versioned::Versioned,
+            {
+                let mut bs = ::std::vec::Vec::new();
+                ::serde_dokechi::versioned::to_writer_versioned(&mut bs, self)?;
+                ::std::result::Result::Ok(bs)
+            }
+
+            /// Deserialize an instance from bytes of Dokechi format prefixed with a version,
+            /// upgrading older versions.
+            pub fn from_bytes(bs: &[u8]) -> ::std::result::Result<Self, ::serde_dokechi::Error>
+            where
+                Self: ::serde_dokechi::versioned::Versioned,
+            {
+                ::serde_dokechi::versioned::from_reader_versioned(bs)
+            }
+
+            /// The size of `self` in Dokechi format with the version, in bytes.
+            pub fn encoded_size(&self) -> ::std::result::Result<usize, ::serde_dokechi::Error>
+            where
+                Self: ::serde_dokechi::versioned::Versioned,
+            {
+                let version = <Self as ::serde_dokechi::versioned::Versioned>::VERSION;
+                let header = ::serde_dokechi::varuint::encoded_len_u64(version as u64);
+                let size = ::serde_dokechi::DokechiExt::dokechi_encoded_size(self)?;
+                ::std::result::Result::Ok(header + size)
+            }
+        }
+    } else {
+        quote! {
+            /// Serialize `self` as Dokechi format into a byte vector.
+            pub fn to_bytes(&self) -> ::std::result::Result<::std::vec::Vec<u8>, ::serde_dokechi::Error>
+            where
+                Self: ::serde_dokechi::__private::Serialize,
+            {
+                ::std::result::Result::Ok(::serde_dokechi::to_vec(self)?)
+            }
+
+            /// Deserialize an instance from bytes of Dokechi format.
+            pub fn from_bytes(bs: &[u8]) -> ::std::result::Result<Self, ::serde_dokechi::Error>
+            where
+                Self: ::serde_dokechi::__private::DeserializeOwned,
+            {
+                ::std::result::Result::Ok(::serde_dokechi::from_slice(bs)?)
+            }
+
+            /// The size of `self` in Dokechi format, in bytes.
+            pub fn encoded_size(&self) -> ::std::result::Result<usize, ::serde_dokechi::Error>
+            where
+                Self: ::serde_dokechi::__private::Serialize,
+            {
+                ::std::result::Result::Ok(::serde_dokechi::DokechiExt::dokechi_encoded_size(self)?)
+            }
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            #methods
+        }
+    })
+}
+
+fn constant(input: &Expr) -> syn::Result<TokenStream2> {
+    let cast = match input {
+        Expr::Cast(cast) => cast,
+        _ => return Err(syn::Error::new_spanned(input, "expected `EXPR as TYPE`")),
+    };
+    let mut bs = Vec::new();
+    constant::encode(&cast.expr, &cast.ty, &mut bs)?;
+    Ok(quote! {
+        {
+            const BYTES: &[u8] = &[#(#bs),*];
+            BYTES
+        }
+    })
+}
+
+fn versioned(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    codecs::check_rewritten(input)?;
+    let attrs = Attrs::parse(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let version = match attrs.version {
+        Some(version) => version,
+        None => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "missing #[dokechi(version = N)]",
+            ))
+        }
+    };
+    let older = match attrs.from {
+        Some(prev) => quote! {
+            else if version < #version {
+                <#prev as ::serde_dokechi::versioned::Versioned>::decode_version(version, r)
+                    .map(<Self as ::std::convert::From<#prev>>::from)
+            }
+        },
+        None => quote! {},
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::serde_dokechi::versioned::Versioned for #name #ty_generics #where_clause {
+            const VERSION: u32 = #version;
+
+            fn decode_version<R: ::std::io::Read>(
+                version: u32,
+                r: R,
+            ) -> ::std::result::Result<Self, ::serde_dokechi::de::Error> {
+                if version == #version {
+                    ::serde_dokechi::from_reader(r)
+                } #older else {
+                    ::std::result::Result::Err(::serde_dokechi::versioned::unknown_version(version, #version))
+                }
+            }
+        }
+    })
+}
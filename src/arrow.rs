@@ -0,0 +1,457 @@
+//! Exporting rows of flat structs as Arrow record batches.
+//!
+//! [`to_record_batch`](fn.to_record_batch.html) converts structs into a `RecordBatch` with a column per field,
+//! and [`record_batch_from_slice`](fn.record_batch_from_slice.html) reads a payload of `Vec<T>`
+//! straight into one, so analytical tools can ingest Dokechi datasets without decoding them by hand.
+//!
+//! Dokechi format has no columnar mode, so rows are read one by one and their fields appended to columns.
+//! Columns are decided by tracing `T` through its `Deserialize` implementation.
+//! Only structs of the following fields are supported, as are newtypes of them:
+//!
+//! * `bool`, integers up to 64 bits, `f32` and `f64`, as the Arrow types of the same width.
+//! * `char` and strings, as `Utf8`.
+//! * Bytes and `Vec<u8>`, as `Binary`.
+//! * `Option` of the above, as nullable columns.
+//!
+//! Available with the `arrow` feature.
+//!
+//! ```
+//! use arrow_array::Array;
+//! use serde_derive::{Deserialize, Serialize};
+//! use serde_dokechi::arrow::to_record_batch;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Reading {
+//!     sensor: String,
+//!     value: f64,
+//!     flags: Option<u8>,
+//! }
+//!
+//! let rows = vec![
+//!     Reading { sensor: "a".to_owned(), value: 1.5, flags: None },
+//!     Reading { sensor: "b".to_owned(), value: 2.0, flags: Some(3) },
+//! ];
+//! let batch = to_record_batch(&rows).unwrap();
+//! assert_eq!(batch.num_rows(), 2);
+//! assert_eq!(batch.column(2).null_count(), 1);
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    ArrayBuilder, BinaryBuilder, BooleanBuilder, Float32Builder, Float64Builder, Int16Builder,
+    Int32Builder, Int64Builder, Int8Builder, StringBuilder, UInt16Builder, UInt32Builder,
+    UInt64Builder, UInt8Builder,
+};
+use arrow_array::{RecordBatch, RecordBatchOptions};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use serde::de::{
+    self as serde_de, Deserialize, DeserializeOwned, DeserializeSeed, Deserializer, SeqAccess,
+    Visitor,
+};
+use serde::ser::Serialize;
+use thiserror::Error;
+
+use crate::de::{self, from_slice_seed};
+use crate::schema::{trace, Shape};
+use crate::ser::{self, to_vec};
+
+/// An error of exporting rows.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The type of rows is not a struct with fields.
+    #[error("rows must be structs with fields")]
+    NotStruct,
+    /// The field is not of a type supported as a column.
+    #[error("field `{0}` is not of a type supported as a column")]
+    UnsupportedField(&'static str),
+    /// Failed to serialize the rows.
+    #[error("{0}")]
+    Serialize(#[from] ser::Error),
+    /// Failed to trace the type or to deserialize the rows.
+    #[error("{0}")]
+    Deserialize(#[from] de::Error),
+    /// Failed to build the record batch.
+    #[error("{0}")]
+    Arrow(#[from] ArrowError),
+}
+
+/// Convert `rows` into a `RecordBatch` with a column per field of `T`.
+///
+/// The rows are encoded into Dokechi format once and read back by
+/// [`record_batch_from_slice`](fn.record_batch_from_slice.html).
+pub fn to_record_batch<T: Serialize + DeserializeOwned>(rows: &[T]) -> Result<RecordBatch, Error> {
+    let bs = to_vec(rows)?;
+    record_batch_from_slice::<T>(&bs)
+}
+
+/// Read a payload of `Vec<T>` in Dokechi format into a `RecordBatch` with a column per field of `T`.
+///
+/// Values of `T` are not constructed.
+pub fn record_batch_from_slice<T: DeserializeOwned>(bs: &[u8]) -> Result<RecordBatch, Error> {
+    let schema = trace::<T>()?;
+    let fields = match &schema.root {
+        Shape::Struct(_, fields) if !fields.is_empty() => fields,
+        _ => return Err(Error::NotStruct),
+    };
+    let mut columns = fields
+        .iter()
+        .map(|(name, shape)| Column::new(name, shape).ok_or(Error::UnsupportedField(name)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let rows = from_slice_seed(bs, Rows(&mut columns))?;
+
+    let fields = columns
+        .iter()
+        .map(|c| Field::new(c.name, c.builder.data_type(), c.nullable))
+        .collect::<Vec<_>>();
+    let arrays = columns
+        .iter_mut()
+        .map(|c| c.builder.finish())
+        .collect::<Vec<_>>();
+    let options = RecordBatchOptions::new().with_row_count(Some(rows));
+    let batch = RecordBatch::try_new_with_options(Arc::new(Schema::new(fields)), arrays, &options)?;
+    Ok(batch)
+}
+
+/// A column being built.
+struct Column {
+    name: &'static str,
+    nullable: bool,
+    builder: Builder,
+}
+
+impl Column {
+    /// Create an empty column of a field, or `None` if the shape is not supported.
+    fn new(name: &'static str, shape: &Shape) -> Option<Column> {
+        let (nullable, shape) = match shape {
+            Shape::Option(inner) => (true, &**inner),
+            shape => (false, shape),
+        };
+        Some(Column {
+            name,
+            nullable,
+            builder: Builder::new(shape)?,
+        })
+    }
+}
+
+enum Builder {
+    Bool(BooleanBuilder),
+    I8(Int8Builder),
+    I16(Int16Builder),
+    I32(Int32Builder),
+    I64(Int64Builder),
+    U8(UInt8Builder),
+    U16(UInt16Builder),
+    U32(UInt32Builder),
+    U64(UInt64Builder),
+    F32(Float32Builder),
+    F64(Float64Builder),
+    Char(StringBuilder),
+    Str(StringBuilder),
+    Bytes(BinaryBuilder),
+}
+
+impl Builder {
+    fn new(shape: &Shape) -> Option<Builder> {
+        let builder = match shape {
+            Shape::Bool => Builder::Bool(BooleanBuilder::new()),
+            Shape::I8 => Builder::I8(Int8Builder::new()),
+            Shape::I16 => Builder::I16(Int16Builder::new()),
+            Shape::I32 => Builder::I32(Int32Builder::new()),
+            Shape::I64 => Builder::I64(Int64Builder::new()),
+            Shape::U8 => Builder::U8(UInt8Builder::new()),
+            Shape::U16 => Builder::U16(UInt16Builder::new()),
+            Shape::U32 => Builder::U32(UInt32Builder::new()),
+            Shape::U64 => Builder::U64(UInt64Builder::new()),
+            Shape::F32 => Builder::F32(Float32Builder::new()),
+            Shape::F64 => Builder::F64(Float64Builder::new()),
+            Shape::Char => Builder::Char(StringBuilder::new()),
+            Shape::Str => Builder::Str(StringBuilder::new()),
+            // Bytes and sequences of `u8` are encoded the same.
+            Shape::Bytes => Builder::Bytes(BinaryBuilder::new()),
+            Shape::Seq(element) if **element == Shape::U8 => Builder::Bytes(BinaryBuilder::new()),
+            Shape::Newtype(_, inner) => Builder::new(inner)?,
+            _ => return None,
+        };
+        Some(builder)
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Builder::Bool(_) => DataType::Boolean,
+            Builder::I8(_) => DataType::Int8,
+            Builder::I16(_) => DataType::Int16,
+            Builder::I32(_) => DataType::Int32,
+            Builder::I64(_) => DataType::Int64,
+            Builder::U8(_) => DataType::UInt8,
+            Builder::U16(_) => DataType::UInt16,
+            Builder::U32(_) => DataType::UInt32,
+            Builder::U64(_) => DataType::UInt64,
+            Builder::F32(_) => DataType::Float32,
+            Builder::F64(_) => DataType::Float64,
+            Builder::Char(_) | Builder::Str(_) => DataType::Utf8,
+            Builder::Bytes(_) => DataType::Binary,
+        }
+    }
+
+    fn finish(&mut self) -> arrow_array::ArrayRef {
+        match self {
+            Builder::Bool(b) => ArrayBuilder::finish(b),
+            Builder::I8(b) => ArrayBuilder::finish(b),
+            Builder::I16(b) => ArrayBuilder::finish(b),
+            Builder::I32(b) => ArrayBuilder::finish(b),
+            Builder::I64(b) => ArrayBuilder::finish(b),
+            Builder::U8(b) => ArrayBuilder::finish(b),
+            Builder::U16(b) => ArrayBuilder::finish(b),
+            Builder::U32(b) => ArrayBuilder::finish(b),
+            Builder::U64(b) => ArrayBuilder::finish(b),
+            Builder::F32(b) => ArrayBuilder::finish(b),
+            Builder::F64(b) => ArrayBuilder::finish(b),
+            Builder::Char(b) | Builder::Str(b) => ArrayBuilder::finish(b),
+            Builder::Bytes(b) => ArrayBuilder::finish(b),
+        }
+    }
+}
+
+/// Read a value of a column, wrapped in `Option` if the column is nullable.
+fn value<'de, D, T>(d: D, nullable: bool) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    if nullable {
+        Option::<T>::deserialize(d)
+    } else {
+        T::deserialize(d).map(Some)
+    }
+}
+
+/// Appends the rows of a sequence to the columns, returning the number of rows.
+struct Rows<'a>(&'a mut [Column]);
+
+impl<'de, 'a> DeserializeSeed<'de> for Rows<'a> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<usize, D::Error> {
+        d.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for Rows<'a> {
+    type Value = usize;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of rows")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+        let mut rows = 0;
+        while seq.next_element_seed(Row(self.0))?.is_some() {
+            rows += 1;
+        }
+        Ok(rows)
+    }
+}
+
+/// Appends the fields of a row to the columns.
+struct Row<'a>(&'a mut [Column]);
+
+impl<'de, 'a> DeserializeSeed<'de> for Row<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+        // Structs are encoded as tuples of their fields.
+        d.deserialize_tuple(self.0.len(), self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for Row<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a row")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let len = self.0.len();
+        for (i, column) in self.0.iter_mut().enumerate() {
+            if seq.next_element_seed(Cell(column))?.is_none() {
+                return Err(serde_de::Error::invalid_length(i, &Expected(len)));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Expected(usize);
+
+impl serde_de::Expected for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a row of {} fields", self.0)
+    }
+}
+
+/// Appends a field to its column.
+struct Cell<'a>(&'a mut Column);
+
+impl<'de, 'a> DeserializeSeed<'de> for Cell<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<(), D::Error> {
+        let nullable = self.0.nullable;
+        match &mut self.0.builder {
+            Builder::Bool(b) => b.append_option(value(d, nullable)?),
+            Builder::I8(b) => b.append_option(value(d, nullable)?),
+            Builder::I16(b) => b.append_option(value(d, nullable)?),
+            Builder::I32(b) => b.append_option(value(d, nullable)?),
+            Builder::I64(b) => b.append_option(value(d, nullable)?),
+            Builder::U8(b) => b.append_option(value(d, nullable)?),
+            Builder::U16(b) => b.append_option(value(d, nullable)?),
+            Builder::U32(b) => b.append_option(value(d, nullable)?),
+            Builder::U64(b) => b.append_option(value(d, nullable)?),
+            Builder::F32(b) => b.append_option(value(d, nullable)?),
+            Builder::F64(b) => b.append_option(value(d, nullable)?),
+            Builder::Char(b) => b.append_option(value::<_, char>(d, nullable)?.map(String::from)),
+            Builder::Str(b) => b.append_option(value::<_, String>(d, nullable)?),
+            Builder::Bytes(b) => b.append_option(value::<_, Vec<u8>>(d, nullable)?),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Int64Type, UInt16Type};
+    use arrow_array::Array;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    struct Id(u16);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Event {
+        id: Id,
+        at: i64,
+        ok: bool,
+        kind: char,
+        name: String,
+        note: Option<String>,
+        payload: Vec<u8>,
+    }
+
+    #[test]
+    fn record_batch() {
+        let rows = vec![
+            Event {
+                id: Id(1),
+                at: -5,
+                ok: true,
+                kind: 'a',
+                name: "boot".to_owned(),
+                note: None,
+                payload: vec![1, 2],
+            },
+            Event {
+                id: Id(300),
+                at: 1 << 40,
+                ok: false,
+                kind: 'β',
+                name: "halt".to_owned(),
+                note: Some("late".to_owned()),
+                payload: vec![],
+            },
+        ];
+        let batch = to_record_batch(&rows).unwrap();
+
+        let schema = batch.schema();
+        let types = schema
+            .fields()
+            .iter()
+            .map(|f| (f.name().as_str(), f.data_type().clone(), f.is_nullable()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                ("id", DataType::UInt16, false),
+                ("at", DataType::Int64, false),
+                ("ok", DataType::Boolean, false),
+                ("kind", DataType::Utf8, false),
+                ("name", DataType::Utf8, false),
+                ("note", DataType::Utf8, true),
+                ("payload", DataType::Binary, false),
+            ]
+        );
+
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(
+            batch.column(0).as_primitive::<UInt16Type>().values(),
+            &[1, 300]
+        );
+        assert_eq!(
+            batch.column(1).as_primitive::<Int64Type>().values(),
+            &[-5, 1 << 40]
+        );
+        assert!(batch.column(2).as_boolean().value(0));
+        assert_eq!(batch.column(3).as_string::<i32>().value(1), "β");
+        assert_eq!(batch.column(4).as_string::<i32>().value(0), "boot");
+        assert!(batch.column(5).is_null(0));
+        assert_eq!(batch.column(5).as_string::<i32>().value(1), "late");
+        assert_eq!(batch.column(6).as_binary::<i32>().value(0), &[1, 2]);
+
+        let empty = to_record_batch::<Event>(&[]).unwrap();
+        assert_eq!(empty.num_rows(), 0);
+        assert_eq!(empty.num_columns(), 7);
+    }
+
+    #[test]
+    fn unsupported() {
+        #[derive(Serialize, Deserialize)]
+        struct Nested {
+            id: u32,
+            tags: Vec<String>,
+        }
+
+        #[derive(Serialize, Deserialize)]
+        struct Empty {}
+
+        match to_record_batch(&[(1u8, 2u8)]).unwrap_err() {
+            Error::NotStruct => {}
+            e => panic!("unexpected error: {}", e),
+        }
+        match to_record_batch(&[Empty {}]).unwrap_err() {
+            Error::NotStruct => {}
+            e => panic!("unexpected error: {}", e),
+        }
+        let nested = Nested {
+            id: 1,
+            tags: Vec::new(),
+        };
+        match to_record_batch(&[nested]).unwrap_err() {
+            Error::UnsupportedField("tags") => {}
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        #[derive(Deserialize)]
+        struct Pair {
+            _n: u32,
+            _s: String,
+        }
+
+        let bs = to_vec(vec![(3u32, "abc")]).unwrap();
+        record_batch_from_slice::<Pair>(&bs).unwrap();
+        match record_batch_from_slice::<Pair>(&bs[..bs.len() - 1]).unwrap_err() {
+            Error::Deserialize(e) => assert!(e.is_eof()),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Assertions on encoded sizes, to guard message sizes in tests.
+//!
+//! See [`assert_encoded_size!`](../macro.assert_encoded_size.html).
+
+use std::fmt::Write as _;
+
+use serde::ser::Serialize;
+
+use crate::ser::to_vec;
+
+/// Assert that a value encodes to the expected byte count or bytes.
+///
+/// On failure, the panic message shows the encoded bytes, marking bytes different from the expected ones.
+///
+/// ```
+/// use serde_dokechi::assert_encoded_size;
+///
+/// // 300 is a 2 bytes varuint.
+/// assert_encoded_size!(300u32, 2);
+/// assert_encoded_size!((true, "ab"), [1, 2, b'a', b'b']);
+/// ```
+///
+/// ```should_panic
+/// serde_dokechi::assert_encoded_size!(70000u32, 2);
+/// ```
+#[macro_export]
+macro_rules! assert_encoded_size {
+    ($value:expr, [$($byte:expr),* $(,)?]) => {
+        if let Err(msg) = $crate::assert::check_bytes(&$value, &[$($byte),*]) {
+            panic!(
+                "assertion failed: `{}` doesn't encode to expected bytes\n{}",
+                stringify!($value),
+                msg
+            );
+        }
+    };
+    ($value:expr, $len:expr) => {
+        if let Err(msg) = $crate::assert::check_len(&$value, $len) {
+            panic!(
+                "assertion failed: `{}` doesn't encode to {} bytes\n{}",
+                stringify!($value),
+                $len,
+                msg
+            );
+        }
+    };
+}
+
+/// Check `value` encodes to `expected` bytes, and describe the encoded bytes if not.
+#[doc(hidden)]
+pub fn check_len<T: Serialize + ?Sized>(value: &T, expected: usize) -> Result<(), String> {
+    let actual = encode(value)?;
+    if actual.len() == expected {
+        return Ok(());
+    }
+
+    let mut msg = format!(
+        "encoded to {} bytes ({:+}):\n",
+        actual.len(),
+        actual.len() as i64 - expected as i64
+    );
+    for (i, line) in actual.chunks(16).enumerate() {
+        let _ = write!(msg, "{:04x} ", i * 16);
+        for b in line {
+            let _ = write!(msg, " {:02x}", b);
+        }
+        msg.push('\n');
+    }
+    Err(msg)
+}
+
+/// Check `value` encodes to `expected`, and show the difference if not.
+#[doc(hidden)]
+pub fn check_bytes<T: Serialize + ?Sized>(value: &T, expected: &[u8]) -> Result<(), String> {
+    let actual = encode(value)?;
+    if actual == expected {
+        return Ok(());
+    }
+
+    let mut msg = format!(
+        "encoded to {} bytes, expected {} bytes:\noffset  expected  actual\n",
+        actual.len(),
+        expected.len()
+    );
+    for i in 0..actual.len().max(expected.len()) {
+        let e = expected.get(i);
+        let a = actual.get(i);
+        let _ = write!(msg, "{:04x}    {:<8}  {:<6}", i, hex(e), hex(a));
+        if e != a {
+            msg.push_str("  <");
+        }
+        msg.push('\n');
+    }
+    Err(msg)
+}
+
+fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, String> {
+    to_vec(value).map_err(|e| format!("failed to serialize: {}", e))
+}
+
+fn hex(b: Option<&u8>) -> String {
+    match b {
+        Some(b) => format!("{:02x}", b),
+        None => "--".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::Serialize;
+
+    #[derive(Serialize)]
+    struct Hot {
+        id: u32,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn passing_assertions() {
+        let v = Hot {
+            id: 1,
+            tags: vec!["a".to_owned()],
+        };
+        assert_encoded_size!(v, 4);
+        assert_encoded_size!(v, [1, 1, 1, b'a']);
+        assert_encoded_size!(Vec::<u8>::new(), [0,]);
+    }
+
+    #[test]
+    fn len_message() {
+        let msg = check_len(&(1u8, 2u8, 3u8), 2).unwrap_err();
+        assert_eq!(msg, "encoded to 3 bytes (+1):\n0000  01 02 03\n");
+    }
+
+    #[test]
+    fn bytes_message() {
+        let msg = check_bytes(&(1u8, 5u8, 3u8), &[1, 2]).unwrap_err();
+        assert_eq!(
+            msg,
+            "encoded to 3 bytes, expected 2 bytes:\n\
+             offset  expected  actual\n\
+             0000    01        01    \n\
+             0001    02        05      <\n\
+             0002    --        03      <\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't encode to 1 bytes")]
+    fn failing_assertion() {
+        assert_encoded_size!(1000u32, 1);
+    }
+}
@@ -0,0 +1,250 @@
+//! Loading and saving Bevy assets in Dokechi format.
+//!
+//! [`DokechiAssetLoader<A>`](struct.DokechiAssetLoader.html) loads `.dok` files into `A`,
+//! and [`DokechiAssetSaver<A>`](struct.DokechiAssetSaver.html) writes them for asset processing.
+//! Files start with the version header of [`versioned`](../versioned/index.html),
+//! so files of older versions of `A` are upgraded on loading, and files of newer versions are rejected.
+//!
+//! File format:
+//!
+//! ```text
+//! version (varuint) , value
+//! ```
+//!
+//! Support for `bevy_asset` is available with the `bevy_asset` feature.
+//!
+//! ```ignore
+//! #[derive(Asset, TypePath, Serialize, Deserialize)]
+//! struct Level {
+//!     name: String,
+//! }
+//!
+//! versioned!(Level = 1);
+//!
+//! app.register_asset_loader(DokechiAssetLoader::<Level>::new());
+//! ```
+
+use std::marker::PhantomData;
+
+use bevy_asset::io::{Reader, Writer};
+use bevy_asset::saver::{AssetSaver, SavedAsset};
+use bevy_asset::{Asset, AssetLoader, AsyncWriteExt, LoadContext};
+use serde::de::Error as _;
+
+use crate::versioned::{from_reader_versioned, to_writer_versioned, Versioned};
+use crate::Error;
+
+/// The extension of asset files, without the preceding dot.
+pub const EXTENSION: &str = "dok";
+
+/// An `AssetLoader` of `.dok` files into `A`.
+#[derive(Debug)]
+pub struct DokechiAssetLoader<A> {
+    _asset: PhantomData<fn() -> A>,
+}
+
+impl<A> DokechiAssetLoader<A> {
+    /// Create new `DokechiAssetLoader`.
+    pub fn new() -> DokechiAssetLoader<A> {
+        DokechiAssetLoader {
+            _asset: PhantomData,
+        }
+    }
+}
+
+impl<A> Default for DokechiAssetLoader<A> {
+    fn default() -> DokechiAssetLoader<A> {
+        DokechiAssetLoader::new()
+    }
+}
+
+impl<A: Asset + Versioned> AssetLoader for DokechiAssetLoader<A> {
+    type Asset = A;
+    type Settings = ();
+    type Error = Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<A, Error> {
+        read_asset(reader).await
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[EXTENSION]
+    }
+}
+
+/// An `AssetSaver` of `A` into `.dok` files, loaded by [`DokechiAssetLoader`](struct.DokechiAssetLoader.html).
+#[derive(Debug)]
+pub struct DokechiAssetSaver<A> {
+    _asset: PhantomData<fn() -> A>,
+}
+
+impl<A> DokechiAssetSaver<A> {
+    /// Create new `DokechiAssetSaver`.
+    pub fn new() -> DokechiAssetSaver<A> {
+        DokechiAssetSaver {
+            _asset: PhantomData,
+        }
+    }
+}
+
+impl<A> Default for DokechiAssetSaver<A> {
+    fn default() -> DokechiAssetSaver<A> {
+        DokechiAssetSaver::new()
+    }
+}
+
+impl<A: Asset + Versioned> AssetSaver for DokechiAssetSaver<A> {
+    type Asset = A;
+    type Settings = ();
+    type OutputLoader = DokechiAssetLoader<A>;
+    type Error = Error;
+
+    async fn save(
+        &self,
+        writer: &mut Writer,
+        asset: SavedAsset<'_, A>,
+        _settings: &(),
+    ) -> Result<(), Error> {
+        write_asset(writer, asset.get()).await
+    }
+}
+
+/// Read a whole file of `A` or an older version, without trailing bytes.
+async fn read_asset<A: Versioned>(reader: &mut dyn Reader) -> Result<A, Error> {
+    let mut bs = Vec::new();
+    reader.read_to_end(&mut bs).await?;
+    let mut r = bs.as_slice();
+    let value = from_reader_versioned(&mut r)?;
+    if !r.is_empty() {
+        return Err(Error::custom(format_args!(
+            "asset has {} trailing bytes",
+            r.len()
+        )));
+    }
+    Ok(value)
+}
+
+async fn write_asset<A: Versioned>(writer: &mut Writer, value: &A) -> Result<(), Error> {
+    let mut bs = Vec::new();
+    to_writer_versioned(&mut bs, value)?;
+    writer.write_all(&bs).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use bevy_asset::io::VecReader;
+    use bevy_asset::{ErasedLoadedAsset, LoadedAsset};
+    use bevy_reflect::TypePath;
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::versioned;
+
+    #[derive(Serialize, Deserialize)]
+    struct LevelV1 {
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, Asset, TypePath, Serialize, Deserialize)]
+    struct Level {
+        name: String,
+        width: u32,
+    }
+
+    impl From<LevelV1> for Level {
+        fn from(v: LevelV1) -> Level {
+            Level {
+                name: v.name,
+                width: 16,
+            }
+        }
+    }
+
+    versioned!(LevelV1 = 1);
+    versioned!(Level = 2, from LevelV1);
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // Readers and writers in tests are in memory, so they are always ready.
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+        let mut f = Box::pin(f);
+        loop {
+            if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+                return v;
+            }
+        }
+    }
+
+    fn read(bs: Vec<u8>) -> Result<Level, Error> {
+        block_on(read_asset(&mut VecReader::new(bs)))
+    }
+
+    #[test]
+    fn save_and_load() {
+        let level = Level {
+            name: "cave".to_owned(),
+            width: 40,
+        };
+        let loaded: ErasedLoadedAsset = LoadedAsset::from(level).into();
+        let saved = SavedAsset::<Level>::from_loaded(&loaded).unwrap();
+
+        let mut bs = Vec::new();
+        block_on(DokechiAssetSaver::new().save(&mut bs, saved, &())).unwrap();
+        assert_eq!(bs[0], 2);
+
+        let level = read(bs).unwrap();
+        assert_eq!(
+            level,
+            Level {
+                name: "cave".to_owned(),
+                width: 40
+            }
+        );
+    }
+
+    #[test]
+    fn versions() {
+        let mut bs = Vec::new();
+        let old = LevelV1 {
+            name: "field".to_owned(),
+        };
+        to_writer_versioned(&mut bs, &old).unwrap();
+        assert_eq!(
+            read(bs).unwrap(),
+            Level {
+                name: "field".to_owned(),
+                width: 16
+            }
+        );
+
+        let newer = vec![3, 0, 0];
+        read(newer).unwrap_err();
+
+        let mut trailing = Vec::new();
+        to_writer_versioned(&mut trailing, &old).unwrap();
+        trailing.push(0);
+        read(trailing).unwrap_err();
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(DokechiAssetLoader::<Level>::new().extensions(), &["dok"]);
+    }
+}
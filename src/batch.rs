@@ -0,0 +1,136 @@
+//! Writing and reading many records at once.
+//!
+//! Records are written back to back without a count or length prefix,
+//! the same as calling `to_writer` for each of them.
+//! Errors report the index of the record that failed.
+//!
+//! ```
+//! use serde_dokechi::batch::{from_reader_n, to_writer_all};
+//!
+//! let mut bs = Vec::new();
+//! to_writer_all(&mut bs, &[(1u32, "a"), (2, "b")]).unwrap();
+//!
+//! let records: Vec<(u32, String)> = from_reader_n(bs.as_slice(), 2).unwrap();
+//! assert_eq!(records[1], (2, "b".to_owned()));
+//!
+//! let e = from_reader_n::<_, (u32, String)>(bs.as_slice(), 3).unwrap_err();
+//! assert_eq!(e.index(), 2);
+//! ```
+
+use std::io::{Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use thiserror::Error;
+
+use crate::de::Deserializer;
+use crate::ser::StreamSerializer;
+
+/// An error of a record in a batch.
+#[derive(Debug, Error)]
+#[error("record {index}: {error}")]
+pub struct Error {
+    index: usize,
+    #[source]
+    error: crate::Error,
+}
+
+impl Error {
+    /// The index of the record that failed.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The error of the record.
+    pub fn error(&self) -> &crate::Error {
+        &self.error
+    }
+
+    /// Unwrap the error of the record.
+    pub fn into_inner(self) -> crate::Error {
+        self.error
+    }
+}
+
+/// Serialize each of `values` back to back, returning the number of records written.
+pub fn to_writer_all<W, I>(w: W, values: I) -> Result<usize, Error>
+where
+    W: Write,
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    let mut stream = StreamSerializer::new(w);
+    for v in values {
+        stream.write_value(&v).map_err(|e| Error {
+            index: stream.count() as usize,
+            error: e.into(),
+        })?;
+    }
+    let count = stream.count() as usize;
+    stream.finish().map_err(|e| Error {
+        index: count,
+        error: e.into(),
+    })?;
+    Ok(count)
+}
+
+/// Deserialize exactly `n` records written back to back.
+///
+/// Returns error if the stream ends before `n` records.
+pub fn from_reader_n<R: Read, T: DeserializeOwned>(r: R, n: usize) -> Result<Vec<T>, Error> {
+    let mut deserializer = Deserializer::new(r);
+    // `n` may be far more than the stream has, so the capacity is bounded.
+    let mut values = Vec::with_capacity(n.min(1024));
+    for index in 0..n {
+        let v = T::deserialize(&mut deserializer).map_err(|e| Error {
+            index,
+            error: e.at(deserializer.position()).into(),
+        })?;
+        values.push(v);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut bs = Vec::new();
+        let n = to_writer_all(&mut bs, (0..100u32).map(|i| i * 1000)).unwrap();
+        assert_eq!(n, 100);
+
+        let mut r = bs.as_slice();
+        let v: Vec<u32> = from_reader_n(&mut r, 60).unwrap();
+        assert_eq!(v[59], 59_000);
+        let v: Vec<u32> = from_reader_n(&mut r, 40).unwrap();
+        assert_eq!(v[0], 60_000);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn failed_record() {
+        let bs = [1u8, b'a', 1, 0xFF, 1, b'c'];
+        let e = from_reader_n::<_, String>(&bs[..], 3).unwrap_err();
+        assert_eq!(e.index(), 1);
+        assert_eq!(e.error().offset(), Some(4));
+
+        struct Record(bool);
+
+        impl Serialize for Record {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                if self.0 {
+                    s.serialize_bool(true)
+                } else {
+                    Err(serde::ser::Error::custom("broken record"))
+                }
+            }
+        }
+
+        let records = vec![Record(true), Record(true), Record(false)];
+        let e = to_writer_all(Vec::new(), &records).unwrap_err();
+        assert_eq!(e.index(), 2);
+        assert_eq!(e.into_inner().code(), 5);
+    }
+}
@@ -0,0 +1,176 @@
+//! Arrays larger than 32 elements for `#[serde(with = "...")]`.
+//!
+//! serde implements `Serialize` and `Deserialize` only for arrays up to 32 elements.
+//! This module supports arrays of the sizes implementing [`BigArray`](trait.BigArray.html):
+//! every size from 33 to 128, and common larger sizes up to 65536.
+//!
+//! Arrays are encoded the same as smaller arrays, as a tuple without length prefix,
+//! so `[u8; 4096]` takes just 4096 bytes.
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Page {
+//!     #[serde(with = "serde_dokechi::big_array")]
+//!     data: [u8; 4096],
+//! }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr;
+
+use serde::de::{Deserialize, Deserializer, Error as _, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+/// Arrays supported by this module.
+pub trait BigArray<'de>: Sized {
+    /// Serialize the array as a tuple.
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error>;
+
+    /// Deserialize the array from a tuple.
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error>;
+}
+
+/// Serialize a big array.
+pub fn serialize<'de, T: BigArray<'de>, S: Serializer>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+    v.serialize(s)
+}
+
+/// Deserialize a big array.
+pub fn deserialize<'de, T: BigArray<'de>, D: Deserializer<'de>>(d: D) -> Result<T, D::Error> {
+    T::deserialize(d)
+}
+
+struct ArrayVisitor<A, T> {
+    len: usize,
+    marker: PhantomData<(A, T)>,
+}
+
+impl<'de, A, T: Deserialize<'de>> Visitor<'de> for ArrayVisitor<A, T> {
+    type Value = A;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an array of length {}", self.len)
+    }
+
+    fn visit_seq<V: SeqAccess<'de>>(self, mut seq: V) -> Result<A, V::Error> {
+        let mut v = Vec::<T>::with_capacity(self.len);
+        for i in 0..self.len {
+            match seq.next_element()? {
+                Some(e) => v.push(e),
+                None => return Err(V::Error::invalid_length(i, &self)),
+            }
+        }
+        // Safety: `A` is `[T; len]`, and `v` has `len` elements.
+        // The length of `v` is set to 0 so elements moved into the array are not dropped.
+        unsafe {
+            let array = ptr::read(v.as_ptr() as *const A);
+            v.set_len(0);
+            Ok(array)
+        }
+    }
+}
+
+macro_rules! impl_big_array {
+    ($($n:expr),*) => {
+        $(
+            impl<'de, T: Serialize + Deserialize<'de>> BigArray<'de> for [T; $n] {
+                fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                    let mut tuple = s.serialize_tuple($n)?;
+                    for e in self.iter() {
+                        tuple.serialize_element(e)?;
+                    }
+                    tuple.end()
+                }
+
+                fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                    d.deserialize_tuple(
+                        $n,
+                        ArrayVisitor::<Self, T> {
+                            len: $n,
+                            marker: PhantomData,
+                        },
+                    )
+                }
+            }
+        )*
+    };
+}
+
+impl_big_array!(
+    33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56,
+    57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80,
+    81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98, 99, 100, 101, 102, 103,
+    104, 105, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115, 116, 117, 118, 119, 120, 121, 122,
+    123, 124, 125, 126, 127, 128, 160, 192, 200, 224, 256, 300, 384, 400, 500, 512, 768, 1000,
+    1024, 1500, 2048, 3072, 4096, 8192, 16384, 32768, 65536
+);
+
+#[cfg(test)]
+mod test {
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_slice, to_vec};
+
+    #[derive(Serialize, Deserialize)]
+    struct Page {
+        #[serde(with = "super")]
+        data: [u8; 4096],
+        #[serde(with = "super")]
+        words: [u32; 33],
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut page = Page {
+            data: [0; 4096],
+            words: [1; 33],
+        };
+        for (i, b) in page.data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        page.words[32] = 300;
+
+        let bs = to_vec(&page).unwrap();
+        assert_eq!(bs.len(), 4096 + 32 + 2);
+        assert_eq!(&bs[..4], &[0, 1, 2, 3]);
+
+        let d: Page = from_slice(&bs).unwrap();
+        assert_eq!(&d.data[..], &page.data[..]);
+        assert_eq!(d.words, page.words);
+    }
+
+    #[test]
+    fn same_as_small_arrays() {
+        #[derive(Serialize)]
+        struct Big(#[serde(with = "super")] [u16; 40]);
+
+        let mut small = Vec::new();
+        for _ in 0..5 {
+            small.extend_from_slice(&to_vec([7u16; 8]).unwrap());
+        }
+        assert_eq!(to_vec(Big([7; 40])).unwrap(), small);
+    }
+
+    #[test]
+    fn truncated() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Big(#[serde(with = "super")] [u8; 64]);
+
+        let _ = from_slice::<Big>(&[0u8; 63]).unwrap_err();
+    }
+
+    #[test]
+    fn owned_elements() {
+        #[derive(Deserialize)]
+        struct Big(#[serde(with = "super")] [String; 40]);
+
+        let bs = to_vec(vec!["ab"; 40]).unwrap();
+        // Skip the length prefix of the Vec.
+        let Big(strings) = from_slice(&bs[1..]).unwrap();
+        assert!(strings.iter().all(|s| s == "ab"));
+    }
+}
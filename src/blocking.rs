@@ -0,0 +1,167 @@
+//! Encoding and decoding on the blocking thread pool of `tokio`.
+//!
+//! Encoding or decoding large values takes long enough to stall other tasks of the runtime.
+//! [`Blocking`](struct.Blocking.html) moves such jobs onto the blocking pool by `spawn_blocking`,
+//! and runs small ones inline, where the cost of moving to another thread dominates.
+//!
+//! ```
+//! # let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//! # rt.block_on(async {
+//! use serde_dokechi::blocking;
+//!
+//! let snapshot = vec![0u32; 1_000_000];
+//! let bs = blocking::to_vec(snapshot).await.unwrap();
+//! let decoded: Vec<u32> = blocking::from_vec(bs).await.unwrap();
+//! assert_eq!(decoded.len(), 1_000_000);
+//! # });
+//! ```
+//!
+//! This module is available with the `tokio` feature.
+
+use std::io::{self, Write};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::de::from_slice;
+use crate::error::Error;
+use crate::ser::{self, to_writer};
+
+/// The default size in bytes up to which jobs run inline.
+pub const DEFAULT_INLINE_LEN: usize = 64 * 1024;
+
+/// Encode `value` with the default [`Blocking`](struct.Blocking.html).
+pub async fn to_vec<T: Serialize + Send + 'static>(value: T) -> Result<Vec<u8>, Error> {
+    Blocking::new().to_vec(value).await
+}
+
+/// Decode `bs` with the default [`Blocking`](struct.Blocking.html).
+pub async fn from_vec<T: DeserializeOwned + Send + 'static>(bs: Vec<u8>) -> Result<T, Error> {
+    Blocking::new().from_vec(bs).await
+}
+
+/// A policy to run encoding and decoding inline or on the blocking pool.
+#[derive(Debug, Clone, Copy)]
+pub struct Blocking {
+    inline_len: usize,
+}
+
+impl Blocking {
+    /// Create new `Blocking` running jobs up to [`DEFAULT_INLINE_LEN`](constant.DEFAULT_INLINE_LEN.html) bytes inline.
+    pub fn new() -> Blocking {
+        Blocking {
+            inline_len: DEFAULT_INLINE_LEN,
+        }
+    }
+
+    /// Set the size in bytes up to which jobs run inline.
+    pub fn inline_len(self, inline_len: usize) -> Blocking {
+        Blocking { inline_len }
+    }
+
+    /// Encode `value`.
+    ///
+    /// Encoding starts inline, and moves to the blocking pool when the output exceeds the inline size,
+    /// so at most the inline size of work is done twice.
+    pub async fn to_vec<T: Serialize + Send + 'static>(&self, value: T) -> Result<Vec<u8>, Error> {
+        let mut out = Limited {
+            buf: Vec::new(),
+            limit: self.inline_len,
+        };
+        match to_writer(&mut out, &value) {
+            Ok(()) => return Ok(out.buf),
+            Err(ser::Error::IO(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        spawn(move || Ok(crate::ser::to_vec(&value)?)).await
+    }
+
+    /// Decode `bs`.
+    ///
+    /// Decoding runs on the blocking pool if `bs` is longer than the inline size.
+    pub async fn from_vec<T: DeserializeOwned + Send + 'static>(
+        &self,
+        bs: Vec<u8>,
+    ) -> Result<T, Error> {
+        if bs.len() <= self.inline_len {
+            return Ok(from_slice(&bs)?);
+        }
+        spawn(move || Ok(from_slice(&bs)?)).await
+    }
+}
+
+impl Default for Blocking {
+    fn default() -> Blocking {
+        Blocking::new()
+    }
+}
+
+async fn spawn<T, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+}
+
+/// An output failing when more than `limit` bytes are written.
+struct Limited {
+    buf: Vec<u8>,
+    limit: usize,
+}
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + buf.len() > self.limit {
+            return Err(io::Error::from(io::ErrorKind::WriteZero));
+        }
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn inline_and_blocking() {
+        block_on(async {
+            let blocking = Blocking::new().inline_len(16);
+            for &len in &[0usize, 3, 100] {
+                let v = vec!["abc".to_owned(); len];
+                let bs = blocking.to_vec(v.clone()).await.unwrap();
+                assert_eq!(bs, crate::to_vec(&v).unwrap());
+
+                let d: Vec<String> = blocking.from_vec(bs).await.unwrap();
+                assert_eq!(d, v);
+            }
+        });
+    }
+
+    #[test]
+    fn decode_error() {
+        block_on(async {
+            let blocking = Blocking::new().inline_len(1);
+            let _ = blocking
+                .from_vec::<String>(vec![5, b'a'])
+                .await
+                .unwrap_err();
+            let _ = from_vec::<String>(vec![5, b'a']).await.unwrap_err();
+        });
+    }
+}
@@ -0,0 +1,106 @@
+//! Byte arrays as a single block for `#[serde(with = "...")]`.
+//!
+//! serde serializes `Vec<u8>` as a sequence, so each byte goes through the serializer one by one.
+//! This module serializes it as bytes instead, written with a single copy and read in large chunks,
+//! like `serde_bytes` does.
+//!
+//! A sequence of `u8` and bytes have the same encoding, a length (varuint) followed by the bytes,
+//! so fields can switch to this module without changing the data.
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Blob {
+//!     #[serde(with = "serde_dokechi::bytes")]
+//!     data: Vec<u8>,
+//! }
+//! ```
+
+use std::fmt;
+
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
+
+/// Serialize bytes.
+pub fn serialize<T, S>(v: &T, s: S) -> Result<S::Ok, S::Error>
+where
+    T: ?Sized + AsRef<[u8]>,
+    S: Serializer,
+{
+    s.serialize_bytes(v.as_ref())
+}
+
+/// Deserialize bytes.
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("byte array")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            // The size hint may come from untrusted input, so the capacity is bounded.
+            let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+            while let Some(b) = seq.next_element()? {
+                v.push(b);
+            }
+            Ok(v)
+        }
+    }
+
+    d.deserialize_byte_buf(BytesVisitor)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_slice, to_vec};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Blob {
+        #[serde(with = "super")]
+        data: Vec<u8>,
+    }
+
+    #[test]
+    fn same_as_seq() {
+        let data: Vec<u8> = (0..=255).collect();
+        let bs = to_vec(Blob { data: data.clone() }).unwrap();
+        assert_eq!(bs, to_vec(&data).unwrap());
+
+        let v: Blob = from_slice(&bs).unwrap();
+        assert_eq!(v.data, data);
+        let v: Vec<u8> = from_slice(&bs).unwrap();
+        assert_eq!(v, data);
+    }
+
+    #[test]
+    fn large_blob() {
+        #[derive(Serialize)]
+        struct Borrowed<'a> {
+            #[serde(with = "super")]
+            data: &'a [u8],
+        }
+
+        let data = vec![0xA5u8; 3 << 20];
+        let bs = to_vec(Borrowed { data: &data }).unwrap();
+        assert_eq!(bs.len(), 4 + data.len());
+
+        let v: Blob = from_slice(&bs).unwrap();
+        assert_eq!(v.data, data);
+        from_slice::<Blob>(&bs[..bs.len() - 1]).unwrap_err();
+    }
+}
@@ -0,0 +1,316 @@
+//! Splitting large sequences into size-bounded, self-contained chunks.
+//!
+//! [`Chunker`](struct.Chunker.html) serializes a sequence into chunks no larger than a byte limit,
+//! such as the message size limit of a message bus.
+//! Unlike [`fragment`](../fragment/index.html), each chunk holds whole elements
+//! and can be decoded on its own with [`decode_chunk`](fn.decode_chunk.html),
+//! while [`Unchunker`](struct.Unchunker.html) reassembles the whole sequence.
+//!
+//! Chunk format:
+//!
+//! ```text
+//! sequence ID (varuint) , chunk index (varuint) , last flag (u8) , element count (varuint) , elements
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use serde::de::{Deserialize, DeserializeOwned, Error as _};
+use serde::ser::{Error as _, Serialize};
+
+use crate::de::{self, Deserializer};
+use crate::ser::{self, Serializer};
+use crate::varuint::{decode_u64, encode_u64, encoded_len_u64};
+
+/// Splits sequences into chunks no larger than the limit.
+#[derive(Debug)]
+pub struct Chunker {
+    max_len: usize,
+    next_id: u64,
+}
+
+impl Chunker {
+    /// Create new `Chunker` producing chunks of at most `max_len` bytes.
+    pub fn new(max_len: usize) -> Chunker {
+        Chunker {
+            max_len,
+            next_id: 0,
+        }
+    }
+
+    /// Serialize `values` into chunks.
+    ///
+    /// Returns error if an element doesn't fit in a chunk by itself.
+    pub fn chunk<T: Serialize>(&mut self, values: &[T]) -> Result<Vec<Vec<u8>>, ser::Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let header_len = |index: usize, count: usize| {
+            encoded_len_u64(id) + encoded_len_u64(index as u64) + 1 + encoded_len_u64(count as u64)
+        };
+
+        let mut chunks = Vec::new();
+        let mut body = Vec::new();
+        let mut count = 0;
+        let mut elem = Vec::new();
+        for v in values {
+            elem.clear();
+            v.serialize(&mut Serializer::new(&mut elem))?;
+
+            let index = chunks.len();
+            if count > 0 && header_len(index, count + 1) + body.len() + elem.len() > self.max_len {
+                chunks.push(encode_chunk(id, index, false, count, &body)?);
+                body.clear();
+                count = 0;
+            }
+
+            let index = chunks.len();
+            if header_len(index, 1) + elem.len() > self.max_len {
+                return Err(ser::Error::custom(
+                    "element is larger than the chunk size limit",
+                ));
+            }
+            body.extend_from_slice(&elem);
+            count += 1;
+        }
+
+        let index = chunks.len();
+        if header_len(index, count) + body.len() > self.max_len {
+            return Err(ser::Error::custom("chunk size limit is too small"));
+        }
+        chunks.push(encode_chunk(id, index, true, count, &body)?);
+        Ok(chunks)
+    }
+}
+
+fn encode_chunk(
+    id: u64,
+    index: usize,
+    last: bool,
+    count: usize,
+    body: &[u8],
+) -> Result<Vec<u8>, ser::Error> {
+    let mut bs = Vec::new();
+    encode_u64(&mut bs, id)?;
+    encode_u64(&mut bs, index as u64)?;
+    bs.push(last as u8);
+    encode_u64(&mut bs, count as u64)?;
+    bs.extend_from_slice(body);
+    Ok(bs)
+}
+
+/// A decoded chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk<T> {
+    /// The ID of the sequence.
+    pub id: u64,
+    /// The index of the chunk in the sequence.
+    pub index: u64,
+    /// Whether this is the last chunk of the sequence.
+    pub last: bool,
+    /// Elements in this chunk.
+    pub items: Vec<T>,
+}
+
+/// Decode a single chunk.
+pub fn decode_chunk<T: DeserializeOwned>(chunk: &[u8]) -> Result<Chunk<T>, de::Error> {
+    let mut r = chunk;
+    let id = decode_u64(&mut r)?;
+    let index = decode_u64(&mut r)?;
+    let last = match <u8 as Deserialize>::deserialize(&mut Deserializer::new(&mut r))? {
+        0 => false,
+        1 => true,
+        v => {
+            return Err(de::Error::invalid_value(
+                serde::de::Unexpected::Unsigned(v as u64),
+                &"last chunk flag",
+            ))
+        }
+    };
+    let items = Vec::<T>::deserialize(&mut Deserializer::new(&mut r))?;
+    if !r.is_empty() {
+        return Err(de::Error::custom("trailing bytes in chunk"));
+    }
+    Ok(Chunk {
+        id,
+        index,
+        last,
+        items,
+    })
+}
+
+/// Reassembles sequences from chunks.
+///
+/// Chunks may arrive in any order, and chunks of several sequences may be interleaved.
+/// Incomplete sequences are discarded after the timeout,
+/// and the number of incomplete sequences is limited.
+#[derive(Debug)]
+pub struct Unchunker<T> {
+    timeout: Duration,
+    max_pending: usize,
+    pending: HashMap<u64, Pending<T>>,
+}
+
+#[derive(Debug)]
+struct Pending<T> {
+    count: Option<u64>,
+    started: Instant,
+    chunks: BTreeMap<u64, Vec<T>>,
+}
+
+impl<T: DeserializeOwned> Unchunker<T> {
+    /// Create new `Unchunker` which discards incomplete sequences after `timeout`.
+    ///
+    /// Chunks of a new sequence are rejected while `max_pending` sequences are incomplete.
+    pub fn new(timeout: Duration, max_pending: usize) -> Unchunker<T> {
+        Unchunker {
+            timeout,
+            max_pending,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Add a chunk and return the whole sequence if it is complete.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Option<Vec<T>>, de::Error> {
+        self.expire();
+
+        let chunk = decode_chunk::<T>(chunk)?;
+        if !self.pending.contains_key(&chunk.id) && self.pending.len() >= self.max_pending {
+            return Err(de::Error::custom("too many pending sequences"));
+        }
+
+        let pending = self.pending.entry(chunk.id).or_insert_with(|| Pending {
+            count: None,
+            started: Instant::now(),
+            chunks: BTreeMap::new(),
+        });
+        if chunk.last {
+            pending.count = Some(chunk.index + 1);
+        }
+        pending.chunks.insert(chunk.index, chunk.items);
+
+        match pending.count {
+            Some(count) if pending.chunks.len() as u64 == count => {
+                let pending = self
+                    .pending
+                    .remove(&chunk.id)
+                    .expect("pending sequence exists");
+                if pending.chunks.keys().next_back() != Some(&(count - 1)) {
+                    return Err(de::Error::custom("chunk index is out of range"));
+                }
+                Ok(Some(
+                    pending.chunks.into_iter().flat_map(|(_, v)| v).collect(),
+                ))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Discard incomplete sequences older than the timeout, returning the number of discarded sequences.
+    pub fn expire(&mut self) -> usize {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        self.pending.retain(|_, p| p.started.elapsed() <= timeout);
+        before - self.pending.len()
+    }
+
+    /// The number of incomplete sequences.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+
+    fn sample() -> Vec<String> {
+        (0..1000).map(|i| format!("element {}", i)).collect()
+    }
+
+    #[test]
+    fn chunks_fit_in_limit() {
+        let mut chunker = Chunker::new(100);
+        let chunks = chunker.chunk(&sample()).unwrap();
+        assert!(chunks.len() > 100);
+        for c in &chunks {
+            assert!(c.len() <= 100);
+        }
+    }
+
+    #[test]
+    fn chunks_are_self_contained() {
+        let mut chunker = Chunker::new(100);
+        let chunks = chunker.chunk(&sample()).unwrap();
+
+        let mut all = Vec::new();
+        for (i, c) in chunks.iter().enumerate() {
+            let chunk: Chunk<String> = decode_chunk(c).unwrap();
+            assert_eq!(chunk.index, i as u64);
+            assert_eq!(chunk.last, i == chunks.len() - 1);
+            all.extend(chunk.items);
+        }
+        assert_eq!(all, sample());
+    }
+
+    #[test]
+    fn reassemble_out_of_order() {
+        let mut chunker = Chunker::new(256);
+        let a = chunker.chunk(&sample()).unwrap();
+        let b = chunker.chunk(&["b".to_owned()]).unwrap();
+
+        let mut unchunker = Unchunker::new(Duration::from_secs(10), 16);
+        assert_eq!(unchunker.push(&a[3]).unwrap(), None);
+        assert_eq!(unchunker.push(&b[0]).unwrap(), Some(vec!["b".to_owned()]));
+        let mut result = None;
+        for (i, c) in a.iter().enumerate().rev() {
+            if i != 3 {
+                result = unchunker.push(c).unwrap();
+            }
+        }
+        assert_eq!(result, Some(sample()));
+        assert_eq!(unchunker.pending(), 0);
+    }
+
+    #[test]
+    fn empty_sequence() {
+        let mut chunker = Chunker::new(8);
+        let chunks = chunker.chunk::<u32>(&[]).unwrap();
+        assert_eq!(chunks.len(), 1);
+
+        let mut unchunker = Unchunker::<u32>::new(Duration::from_secs(10), 16);
+        assert_eq!(unchunker.push(&chunks[0]).unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn too_many_pending_sequences() {
+        let mut chunker = Chunker::new(64);
+        let a = chunker.chunk(&sample()).unwrap();
+        let b = chunker.chunk(&sample()).unwrap();
+
+        let mut unchunker = Unchunker::<String>::new(Duration::from_secs(10), 1);
+        assert_eq!(unchunker.push(&a[0]).unwrap(), None);
+        let _ = unchunker.push(&b[0]).unwrap_err();
+        assert_eq!(unchunker.pending(), 1);
+    }
+
+    #[test]
+    fn incomplete_sequence_expires() {
+        let mut chunker = Chunker::new(64);
+        let chunks = chunker.chunk(&sample()).unwrap();
+
+        let mut unchunker = Unchunker::<String>::new(Duration::from_millis(0), 16);
+        assert_eq!(unchunker.push(&chunks[0]).unwrap(), None);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(unchunker.expire(), 1);
+        assert_eq!(unchunker.pending(), 0);
+    }
+
+    #[test]
+    fn too_large_element() {
+        let mut chunker = Chunker::new(16);
+        let _ = chunker.chunk(&["x".repeat(20)]).unwrap_err();
+    }
+}
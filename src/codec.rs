@@ -0,0 +1,166 @@
+//! Framing codec for `asynchronous-codec`.
+//!
+//! [`DokechiCodec`](struct.DokechiCodec.html) implements `Encoder` and `Decoder`,
+//! so `Framed` turns any `AsyncRead + AsyncWrite` of `futures` into a `Sink` and `Stream`
+//! of typed messages, independently of the async runtime.
+//!
+//! Frame format:
+//!
+//! ```text
+//! payload length (varuint) , payload
+//! ```
+//!
+//! This module is available with the `asynchronous-codec` feature.
+
+use std::io;
+use std::marker::PhantomData;
+
+use asynchronous_codec::{BytesMut, Decoder, Encoder};
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Serialize;
+
+use crate::de::Deserializer;
+use crate::error::Error;
+use crate::ser::to_vec;
+use crate::varuint::{decode_u64, encode_u64};
+
+/// The default size limit of frame payloads in bytes.
+pub const DEFAULT_MAX_LEN: usize = 16 * 1024 * 1024;
+
+/// A codec encoding `T` and decoding `U` as length-prefixed frames.
+#[derive(Debug)]
+pub struct DokechiCodec<T, U> {
+    max_len: usize,
+    _marker: PhantomData<(T, U)>,
+}
+
+impl<T, U> DokechiCodec<T, U> {
+    /// Create new `DokechiCodec` accepting payloads up to [`DEFAULT_MAX_LEN`](constant.DEFAULT_MAX_LEN.html) bytes.
+    pub fn new() -> DokechiCodec<T, U> {
+        DokechiCodec::with_max_len(DEFAULT_MAX_LEN)
+    }
+
+    /// Create new `DokechiCodec` accepting payloads up to `max_len` bytes.
+    pub fn with_max_len(max_len: usize) -> DokechiCodec<T, U> {
+        DokechiCodec {
+            max_len,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U> Default for DokechiCodec<T, U> {
+    fn default() -> DokechiCodec<T, U> {
+        DokechiCodec::new()
+    }
+}
+
+impl<T, U> Clone for DokechiCodec<T, U> {
+    fn clone(&self) -> DokechiCodec<T, U> {
+        DokechiCodec::with_max_len(self.max_len)
+    }
+}
+
+impl<T: Serialize, U> Encoder for DokechiCodec<T, U> {
+    type Item<'a> = T;
+    type Error = Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Error> {
+        let payload = to_vec(&item)?;
+        if payload.len() > self.max_len {
+            return Err(too_large(payload.len(), self.max_len));
+        }
+
+        let mut header = Vec::with_capacity(9);
+        encode_u64(&mut header, payload.len() as u64)?;
+        dst.reserve(header.len() + payload.len());
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(&payload);
+        Ok(())
+    }
+}
+
+impl<T, U: DeserializeOwned> Decoder for DokechiCodec<T, U> {
+    type Item = U;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<U>, Error> {
+        let mut head = &src[..];
+        let len = match decode_u64(&mut head) {
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if len > self.max_len as u64 {
+            return Err(too_large(len as usize, self.max_len));
+        }
+
+        let len = len as usize;
+        let header_len = src.len() - head.len();
+        if head.len() < len {
+            src.reserve(len - head.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(header_len + len);
+        let mut r = &frame[header_len..];
+        let value = U::deserialize(&mut Deserializer::new(&mut r))?;
+        if !r.is_empty() {
+            return Err(Error::custom(format_args!(
+                "frame has {} trailing bytes",
+                r.len()
+            )));
+        }
+        Ok(Some(value))
+    }
+}
+
+fn too_large(len: usize, max_len: usize) -> Error {
+    Error::custom(format_args!(
+        "frame of {} bytes is larger than the limit of {} bytes",
+        len, max_len
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() {
+        let mut codec = DokechiCodec::<(u32, String), (u32, String)>::new();
+        let mut buf = BytesMut::new();
+        codec.encode((1, "abc".to_owned()), &mut buf).unwrap();
+        codec.encode((2, "de".to_owned()), &mut buf).unwrap();
+        assert_eq!(buf[0], 5);
+
+        // Frames arrive in pieces.
+        let mut src = BytesMut::new();
+        let mut decoded = Vec::new();
+        for &b in buf.iter() {
+            src.extend_from_slice(&[b]);
+            if let Some(v) = codec.decode(&mut src).unwrap() {
+                decoded.push(v);
+            }
+        }
+        assert_eq!(decoded, vec![(1, "abc".to_owned()), (2, "de".to_owned())]);
+        assert!(src.is_empty());
+    }
+
+    #[test]
+    fn frame_too_large() {
+        let mut codec = DokechiCodec::<Vec<u8>, Vec<u8>>::with_max_len(8);
+        let mut buf = BytesMut::new();
+        let _ = codec.encode(vec![0; 10], &mut buf).unwrap_err();
+
+        let mut src = BytesMut::from(&[100u8][..]);
+        let _ = codec.decode(&mut src).unwrap_err();
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut codec = DokechiCodec::<u8, u8>::new();
+        let mut src = BytesMut::from(&[2u8, 1, 1][..]);
+        let _ = codec.decode(&mut src).unwrap_err();
+    }
+}
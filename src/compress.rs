@@ -0,0 +1,514 @@
+//! Compressed envelope of Dokechi format payloads.
+//!
+//! Payloads which do not shrink by compression, such as already compressed blobs,
+//! are stored raw, so they never get bigger than one flag byte and a length prefix.
+//!
+//! Envelope format:
+//!
+//! ```text
+//! flag (u8) , data length (varuint) , data
+//! ```
+//!
+//! | flag     | data                 |
+//! |----------|----------------------|
+//! | 0        | raw payload          |
+//! | 1        | zstd compressed      |
+//! | 2        | LZ4 frame compressed |
+//! | 3        | Snappy compressed    |
+//! | 4        | Brotli compressed    |
+//! | 128..255 | user defined         |
+//!
+//! Built-in codecs are available with the `zstd`, `lz4_flex`, `snap` and `brotli` features.
+//! Other algorithms, such as hardware codecs, can be plugged in by implementing
+//! [`CompressionBackend`](trait.CompressionBackend.html).
+
+use std::io::{self, Read, Write};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::{Error as _, Serialize};
+
+use crate::de::{self, from_reader, Deserializer, Limits};
+use crate::error::Offset;
+use crate::ser::{self, Serializer};
+use crate::varuint::{decode_u64, encode_u64};
+
+const FLAG_RAW: u8 = 0;
+const FLAG_ZSTD: u8 = 1;
+const FLAG_LZ4: u8 = 2;
+const FLAG_SNAPPY: u8 = 3;
+const FLAG_BROTLI: u8 = 4;
+
+/// The smallest flag of user defined backends.
+pub const MIN_USER_FLAG: u8 = 128;
+
+/// Payloads smaller than this are always stored raw.
+const MIN_COMPRESS_LEN: usize = 32;
+
+/// A compression algorithm of envelopes.
+pub trait CompressionBackend {
+    /// Flag identifying this algorithm in envelopes.
+    ///
+    /// User defined backends must use [`MIN_USER_FLAG`](constant.MIN_USER_FLAG.html) or greater.
+    fn flag(&self) -> u8;
+
+    /// Compress `raw` bytes.
+    fn compress(&self, raw: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Decompress bytes written by `compress`.
+    ///
+    /// The output should stop growing once it is longer than `max_len` bytes, as `data`
+    /// may be a small input expanding to a huge output. Output longer than `max_len` is
+    /// rejected by the caller, so returning `max_len + 1` bytes or an error are both fine.
+    fn decompress(&self, data: &[u8], max_len: usize) -> io::Result<Vec<u8>>;
+}
+
+/// A built-in compression algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Codec {
+    /// zstd with the given compression level.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+    /// LZ4 frame format.
+    #[cfg(feature = "lz4_flex")]
+    Lz4,
+    /// Snappy raw format.
+    #[cfg(feature = "snap")]
+    Snappy,
+    /// Brotli with the given quality, from 0 to 11.
+    #[cfg(feature = "brotli")]
+    Brotli(u32),
+}
+
+// With no codec features, `Codec` has no variants.
+#[cfg_attr(
+    not(any(
+        feature = "zstd",
+        feature = "lz4_flex",
+        feature = "snap",
+        feature = "brotli"
+    )),
+    allow(unused_variables)
+)]
+impl CompressionBackend for Codec {
+    fn flag(&self) -> u8 {
+        match *self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(_) => FLAG_ZSTD,
+            #[cfg(feature = "lz4_flex")]
+            Codec::Lz4 => FLAG_LZ4,
+            #[cfg(feature = "snap")]
+            Codec::Snappy => FLAG_SNAPPY,
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(_) => FLAG_BROTLI,
+        }
+    }
+
+    fn compress(&self, raw: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(level) => zstd::stream::encode_all(raw, level),
+            #[cfg(feature = "lz4_flex")]
+            Codec::Lz4 => {
+                let mut enc = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                enc.write_all(raw)?;
+                enc.finish()
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            }
+            #[cfg(feature = "snap")]
+            Codec::Snappy => Ok(snap::raw::Encoder::new().compress_vec(raw)?),
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(quality) => {
+                let mut enc = brotli::CompressorWriter::new(Vec::new(), 4096, quality, 22);
+                enc.write_all(raw)?;
+                Ok(enc.into_inner())
+            }
+        }
+    }
+
+    fn decompress(&self, data: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+        let limit = (max_len as u64).saturating_add(1);
+        match *self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(_) => {
+                let mut raw = Vec::new();
+                zstd::stream::read::Decoder::new(data)?
+                    .take(limit)
+                    .read_to_end(&mut raw)?;
+                Ok(raw)
+            }
+            #[cfg(feature = "lz4_flex")]
+            Codec::Lz4 => {
+                let mut raw = Vec::new();
+                lz4_flex::frame::FrameDecoder::new(data)
+                    .take(limit)
+                    .read_to_end(&mut raw)?;
+                Ok(raw)
+            }
+            #[cfg(feature = "snap")]
+            Codec::Snappy => {
+                // The raw format stores the decompressed length up front.
+                if snap::raw::decompress_len(data)? > max_len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "decompressed length exceeds the limit",
+                    ));
+                }
+                Ok(snap::raw::Decoder::new().decompress_vec(data)?)
+            }
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(_) => {
+                let mut raw = Vec::new();
+                brotli::Decompressor::new(data, 4096)
+                    .take(limit)
+                    .read_to_end(&mut raw)?;
+                Ok(raw)
+            }
+        }
+    }
+}
+
+/// Decompressed payloads longer than this are rejected by
+/// [`from_reader_compressed`](fn.from_reader_compressed.html) and
+/// [`from_reader_with_backend`](fn.from_reader_with_backend.html).
+///
+/// This is the allocation budget of [`Limits::untrusted`](../de/struct.Limits.html#method.untrusted).
+pub const DEFAULT_MAX_DECOMPRESSED_LEN: usize = 64 << 20;
+
+/// Serialize `value` and write it as a compressed envelope into the IO stream.
+///
+/// The payload is stored raw if compression doesn't make it smaller.
+pub fn to_writer_compressed<W: Write, T: Serialize>(
+    w: W,
+    value: &T,
+    codec: Codec,
+) -> Result<(), ser::Error> {
+    to_writer_with_backend(w, value, &codec)
+}
+
+/// Serialize `value` and write it as an envelope compressed by `backend` into the IO stream.
+///
+/// The payload is stored raw if compression doesn't make it smaller.
+pub fn to_writer_with_backend<W, T, B>(mut w: W, value: &T, backend: &B) -> Result<(), ser::Error>
+where
+    W: Write,
+    T: Serialize,
+    B: CompressionBackend + ?Sized,
+{
+    let flag = backend.flag();
+    if flag == FLAG_RAW {
+        return Err(ser::Error::custom(
+            "compression flag 0 is reserved for raw payloads",
+        ));
+    }
+
+    let mut raw = Vec::new();
+    value.serialize(&mut Serializer::new(&mut raw))?;
+
+    let compressed = if raw.len() >= MIN_COMPRESS_LEN {
+        Some(backend.compress(&raw)?)
+    } else {
+        None
+    };
+
+    match compressed {
+        Some(data) if data.len() < raw.len() => write_envelope(&mut w, flag, &data)?,
+        _ => write_envelope(&mut w, FLAG_RAW, &raw)?,
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Read a compressed envelope from the IO stream and deserialize its payload.
+///
+/// Envelopes compressed by built-in codecs enabled by features can be read.
+/// Payloads decompressing to more than
+/// [`DEFAULT_MAX_DECOMPRESSED_LEN`](constant.DEFAULT_MAX_DECOMPRESSED_LEN.html) bytes are rejected.
+pub fn from_reader_compressed<R: Read, T: DeserializeOwned>(r: R) -> Result<T, de::Error> {
+    let (flag, data) = read_envelope(r)?;
+    let raw = match flag {
+        FLAG_RAW => data,
+        _ => decompress_with(&builtin(flag)?, &data, DEFAULT_MAX_DECOMPRESSED_LEN)?,
+    };
+    from_reader(raw.as_slice())
+}
+
+/// Read a compressed envelope from the IO stream and deserialize its payload with `limits`.
+///
+/// The decompressed payload is charged against the allocation budget of `limits`.
+pub fn from_reader_compressed_with_limits<R: Read, T: DeserializeOwned>(
+    r: R,
+    limits: Limits,
+) -> Result<T, de::Error> {
+    let (flag, data) = read_envelope(r)?;
+    let raw = match flag {
+        FLAG_RAW => data,
+        _ => decompress_with(&builtin(flag)?, &data, limits.alloc_budget)?,
+    };
+    deserialize_limited(&raw, limits)
+}
+
+/// Read an envelope compressed by `backend` from the IO stream and deserialize its payload.
+///
+/// Envelopes compressed by built-in codecs enabled by features can be read too.
+pub fn from_reader_with_backend<R, T, B>(r: R, backend: &B) -> Result<T, de::Error>
+where
+    R: Read,
+    T: DeserializeOwned,
+    B: CompressionBackend + ?Sized,
+{
+    let (flag, data) = read_envelope(r)?;
+    let raw = match flag {
+        FLAG_RAW => data,
+        f if f == backend.flag() => decompress_with(backend, &data, DEFAULT_MAX_DECOMPRESSED_LEN)?,
+        _ => decompress_with(&builtin(flag)?, &data, DEFAULT_MAX_DECOMPRESSED_LEN)?,
+    };
+    from_reader(raw.as_slice())
+}
+
+/// Read an envelope compressed by `backend` from the IO stream and deserialize its payload with `limits`.
+///
+/// The decompressed payload is charged against the allocation budget of `limits`.
+pub fn from_reader_with_backend_and_limits<R, T, B>(
+    r: R,
+    backend: &B,
+    limits: Limits,
+) -> Result<T, de::Error>
+where
+    R: Read,
+    T: DeserializeOwned,
+    B: CompressionBackend + ?Sized,
+{
+    let (flag, data) = read_envelope(r)?;
+    let raw = match flag {
+        FLAG_RAW => data,
+        f if f == backend.flag() => decompress_with(backend, &data, limits.alloc_budget)?,
+        _ => decompress_with(&builtin(flag)?, &data, limits.alloc_budget)?,
+    };
+    deserialize_limited(&raw, limits)
+}
+
+fn write_envelope<W: Write>(mut w: W, flag: u8, data: &[u8]) -> io::Result<()> {
+    w.write_all(&[flag])?;
+    encode_u64(&mut w, data.len() as u64)?;
+    w.write_all(data)
+}
+
+fn read_envelope<R: Read>(mut r: R) -> Result<(u8, Vec<u8>), de::Error> {
+    let mut flag = [0u8];
+    r.read_exact(&mut flag)?;
+    let len = decode_u64(&mut r)?;
+
+    let mut data = Vec::new();
+    (&mut r).take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(de::Error::Eof(Offset::UNKNOWN));
+    }
+    Ok((flag[0], data))
+}
+
+fn deserialize_limited<T: DeserializeOwned>(raw: &[u8], limits: Limits) -> Result<T, de::Error> {
+    let mut deserializer = Deserializer::new(raw).limits(limits);
+    let value: T = serde::Deserialize::deserialize(&mut deserializer)
+        .map_err(|e| e.at(deserializer.position()))?;
+    Ok(value)
+}
+
+/// Decompress `data` by `backend`, rejecting output longer than `max_len`.
+fn decompress_with<B>(backend: &B, data: &[u8], max_len: usize) -> Result<Vec<u8>, de::Error>
+where
+    B: CompressionBackend + ?Sized,
+{
+    let raw = backend.decompress(data, max_len)?;
+    if raw.len() > max_len {
+        return Err(de::Error::BudgetExceeded(max_len, Offset::UNKNOWN));
+    }
+    Ok(raw)
+}
+
+/// Get the built-in codec to decompress data of `flag`.
+fn builtin(flag: u8) -> Result<Codec, de::Error> {
+    match flag {
+        #[cfg(feature = "zstd")]
+        FLAG_ZSTD => Ok(Codec::Zstd(0)),
+        #[cfg(feature = "lz4_flex")]
+        FLAG_LZ4 => Ok(Codec::Lz4),
+        #[cfg(feature = "snap")]
+        FLAG_SNAPPY => Ok(Codec::Snappy),
+        #[cfg(feature = "brotli")]
+        FLAG_BROTLI => Ok(Codec::Brotli(0)),
+        #[cfg(not(feature = "zstd"))]
+        FLAG_ZSTD => Err(de::Error::custom("zstd support is not enabled")),
+        #[cfg(not(feature = "lz4_flex"))]
+        FLAG_LZ4 => Err(de::Error::custom("LZ4 support is not enabled")),
+        #[cfg(not(feature = "snap"))]
+        FLAG_SNAPPY => Err(de::Error::custom("Snappy support is not enabled")),
+        #[cfg(not(feature = "brotli"))]
+        FLAG_BROTLI => Err(de::Error::custom("Brotli support is not enabled")),
+        v => Err(de::Error::invalid_value(
+            serde::de::Unexpected::Unsigned(v as u64),
+            &"compression flag",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn codecs() -> Vec<Codec> {
+        vec![
+            #[cfg(feature = "zstd")]
+            Codec::Zstd(3),
+            #[cfg(feature = "lz4_flex")]
+            Codec::Lz4,
+            #[cfg(feature = "snap")]
+            Codec::Snappy,
+            #[cfg(feature = "brotli")]
+            Codec::Brotli(5),
+        ]
+    }
+
+    #[test]
+    fn compressible_payload() {
+        let v = vec!["repeated text".to_owned(); 100];
+
+        for codec in codecs() {
+            let mut bs = Vec::new();
+            to_writer_compressed(&mut bs, &v, codec).unwrap();
+            assert_ne!(bs[0], FLAG_RAW);
+            assert!(bs.len() < 200);
+
+            let d: Vec<String> = from_reader_compressed(bs.as_slice()).unwrap();
+            assert_eq!(d, v);
+        }
+    }
+
+    #[test]
+    fn incompressible_payload_is_raw() {
+        // xorshift noise
+        let mut x = 0x2545_f491_4f6c_dd1du64;
+        let v: Vec<u8> = (0..4096)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect();
+
+        for codec in codecs() {
+            let mut bs = Vec::new();
+            to_writer_compressed(&mut bs, &v, codec).unwrap();
+            assert_eq!(bs[0], FLAG_RAW);
+            assert_eq!(bs.len(), 1 + 2 + 2 + 4096);
+
+            let d: Vec<u8> = from_reader_compressed(bs.as_slice()).unwrap();
+            assert_eq!(d, v);
+        }
+    }
+
+    #[test]
+    fn small_payload_is_raw() {
+        for codec in codecs() {
+            let mut bs = Vec::new();
+            to_writer_compressed(&mut bs, &12u8, codec).unwrap();
+            assert_eq!(bs, vec![FLAG_RAW, 1, 12]);
+        }
+    }
+
+    #[test]
+    fn decompressed_len_limit() {
+        let v = vec![0u8; 1 << 20];
+
+        for codec in codecs() {
+            let mut bs = Vec::new();
+            to_writer_compressed(&mut bs, &v, codec).unwrap();
+            assert!(bs.len() < v.len() / 10);
+
+            let limits = Limits::default().alloc_budget(v.len() - 1);
+            let _ = from_reader_compressed_with_limits::<_, Vec<u8>>(bs.as_slice(), limits)
+                .unwrap_err();
+
+            let limits = Limits::default().alloc_budget(2 << 20);
+            let d: Vec<u8> = from_reader_compressed_with_limits(bs.as_slice(), limits).unwrap();
+            assert_eq!(d, v);
+        }
+    }
+
+    #[test]
+    fn unknown_flag() {
+        let bs = [9u8, 1, 0];
+        let _ = from_reader_compressed::<_, u8>(&bs[..]).unwrap_err();
+    }
+
+    /// Run-length encoding, standing in for a user defined backend.
+    struct Rle;
+
+    impl CompressionBackend for Rle {
+        fn flag(&self) -> u8 {
+            200
+        }
+
+        fn compress(&self, raw: &[u8]) -> io::Result<Vec<u8>> {
+            let mut out = Vec::new();
+            for &b in raw {
+                match out.len() {
+                    n if n >= 2 && out[n - 1] == b && out[n - 2] < 255 => out[n - 2] += 1,
+                    _ => out.extend_from_slice(&[1, b]),
+                }
+            }
+            Ok(out)
+        }
+
+        fn decompress(&self, data: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+            let mut out = Vec::new();
+            for pair in data.chunks(2) {
+                match pair {
+                    [n, b] => out.extend(std::iter::repeat(*b).take(*n as usize)),
+                    _ => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                }
+                if out.len() > max_len {
+                    break;
+                }
+            }
+            Ok(out)
+        }
+    }
+
+    #[test]
+    fn user_backend() {
+        let v = vec![0u8; 300];
+
+        let mut bs = Vec::new();
+        to_writer_with_backend(&mut bs, &v, &Rle).unwrap();
+        assert_eq!(bs[0], 200);
+        assert!(bs.len() < 20);
+
+        let d: Vec<u8> = from_reader_with_backend(bs.as_slice(), &Rle).unwrap();
+        assert_eq!(d, v);
+
+        let _ = from_reader_compressed::<_, Vec<u8>>(bs.as_slice()).unwrap_err();
+
+        let backend: &dyn CompressionBackend = &Rle;
+        let d: Vec<u8> = from_reader_with_backend(bs.as_slice(), backend).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn user_backend_len_limit() {
+        let v = vec![0u8; 300];
+
+        let mut bs = Vec::new();
+        to_writer_with_backend(&mut bs, &v, &Rle).unwrap();
+
+        let limits = Limits::default().alloc_budget(100);
+        let err = from_reader_with_backend_and_limits::<_, Vec<u8>, _>(bs.as_slice(), &Rle, limits)
+            .unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::BudgetExceeded);
+
+        let limits = Limits::default().alloc_budget(1000);
+        let d: Vec<u8> = from_reader_with_backend_and_limits(bs.as_slice(), &Rle, limits).unwrap();
+        assert_eq!(d, v);
+    }
+}
@@ -0,0 +1,42 @@
+/// Initial state of incremental CRC-32 computation.
+pub const CRC32_INIT: u32 = 0xffff_ffff;
+
+/// CRC-32 (IEEE 802.3) checksum.
+pub fn crc32(bs: &[u8]) -> u32 {
+    crc32_finish(crc32_update(CRC32_INIT, bs))
+}
+
+/// Feed `bs` into incremental CRC-32 state.
+pub fn crc32_update(mut crc: u32, bs: &[u8]) -> u32 {
+    for &b in bs {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    crc
+}
+
+/// Get checksum from incremental CRC-32 state.
+pub fn crc32_finish(crc: u32) -> u32 {
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_crc32_incremental() {
+        let crc = crc32_update(CRC32_INIT, b"1234");
+        let crc = crc32_update(crc, b"56789");
+        assert_eq!(crc32_finish(crc), 0xcbf4_3926);
+    }
+}
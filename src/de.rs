@@ -1,70 +1,1075 @@
 //! Deserialize Dokechi format to Rust data structure.
 
-use std::fmt::Display;
-use std::io::{self, Read};
+use std::fmt::{self, Display};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::marker::PhantomData;
 
 use serde::de::Error as _;
-use serde::de::{self, DeserializeOwned, IntoDeserializer, Unexpected, Visitor};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
 use thiserror::Error;
 
-use crate::varuint::{decode_u128, decode_u64};
+#[cfg(feature = "compact-errors")]
+use crate::error::Message;
+use crate::error::{message, ErrorKind, Offset};
+use crate::io::{read_to_vec, Input, SizedInput};
+use crate::options::{Endian, FloatEncoding, IntEncoding, Options};
+use crate::stats::Collector;
+#[cfg(feature = "log")]
+use crate::trace::Tracer;
+use crate::varuint::{
+    decode_u128, decode_u16, decode_u32, decode_u64, encode_u64, encoded_len_u128, encoded_len_u64,
+};
 
 /// Deserialize an instance of type `T` from IO stream of Dokechi format.
+///
+/// Errors keep the [`Offset`](../struct.Offset.html) they occurred at.
 pub fn from_reader<R: Read, T: DeserializeOwned>(r: R) -> Result<T, Error> {
     let mut deserializer = Deserializer::new(r);
-    let value: T = de::Deserialize::deserialize(&mut deserializer)?;
+    let value: T = de::Deserialize::deserialize(&mut deserializer)
+        .map_err(|e| e.at(deserializer.position()))?;
     Ok(value)
 }
 
+/// Deserialize an instance of type `T` from IO stream of Dokechi format with `options`.
+pub fn from_reader_with_config<R: Read, T: DeserializeOwned>(
+    r: R,
+    options: &Options,
+) -> Result<T, Error> {
+    let mut deserializer = options.deserializer(r);
+    let value: T = de::Deserialize::deserialize(&mut deserializer)
+        .map_err(|e| e.at(deserializer.position()))?;
+    Ok(value)
+}
+
+/// Deserialize an instance of type `T` from bytes of Dokechi format.
+pub fn from_slice<T: DeserializeOwned>(bs: &[u8]) -> Result<T, Error> {
+    from_reader(bs)
+}
+
+/// Deserialize a value with `seed` from IO stream of Dokechi format.
+///
+/// Use this for types depending on a context, such as values allocated in an arena.
+pub fn from_reader_seed<'de, R: Read, S: DeserializeSeed<'de>>(
+    r: R,
+    seed: S,
+) -> Result<S::Value, Error> {
+    let mut deserializer = Deserializer::new(r);
+    seed.deserialize(&mut deserializer)
+        .map_err(|e| e.at(deserializer.position()))
+}
+
+/// Deserialize a value with `seed` from bytes of Dokechi format.
+pub fn from_slice_seed<'de, S: DeserializeSeed<'de>>(
+    bs: &[u8],
+    seed: S,
+) -> Result<S::Value, Error> {
+    from_reader_seed(bs, seed)
+}
+
+/// Deserialize Dokechi format from IO stream into an existing `place`.
+///
+/// Allocations owned by `place`, such as capacities of `String` and `Vec`, are reused where possible.
+pub fn from_reader_in_place<R: Read, T: DeserializeOwned>(
+    r: R,
+    place: &mut T,
+) -> Result<(), Error> {
+    let mut deserializer = Deserializer::new(r);
+    deserializer.in_place = true;
+    de::Deserialize::deserialize_in_place(&mut deserializer, place)
+        .map_err(|e| e.at(deserializer.position()))?;
+    Ok(())
+}
+
+/// Deserialize bytes of Dokechi format into an existing `place`.
+///
+/// See [`from_reader_in_place`](fn.from_reader_in_place.html).
+pub fn from_slice_in_place<T: DeserializeOwned>(bs: &[u8], place: &mut T) -> Result<(), Error> {
+    from_reader_in_place(bs, place)
+}
+
+/// Deserialize one value from IO stream of Dokechi format, returning it and the number of bytes it took.
+///
+/// Only the bytes of the value are read, so passing `&mut r` leaves `r` positioned at the data after it,
+/// such as the next record of a file holding records of different types back to back.
+pub fn from_reader_partial<R: Read, T: DeserializeOwned>(r: R) -> Result<(T, u64), Error> {
+    let mut deserializer = Deserializer::new(r);
+    let value = de::Deserialize::deserialize(&mut deserializer)
+        .map_err(|e| e.at(deserializer.position()))?;
+    Ok((value, deserializer.position()))
+}
+
+/// Deserialize only the first `n` elements of a sequence from IO stream of Dokechi format.
+///
+/// Returns the elements and the total length of the sequence.
+/// The rest of the sequence is left unread in the IO stream.
+pub fn from_reader_take<R: Read, T: DeserializeOwned>(
+    r: R,
+    n: usize,
+) -> Result<(Vec<T>, u64), Error> {
+    let mut deserializer = Deserializer::new(r);
+    let len = deserializer
+        .parse_len(deserializer.limits.max_seq_len)
+        .map_err(|e| e.at(deserializer.position()))?;
+    let mut values = Vec::new();
+    while values.len() < len && values.len() < n {
+        let v = de::Deserialize::deserialize(&mut deserializer)
+            .map_err(|e| e.at(deserializer.position()))?;
+        values.push(v);
+    }
+    Ok((values, len as u64))
+}
+
+/// Deserialize an enum of type `T` whose variant index is given out-of-band, from IO stream
+/// holding only the variant's data.
+///
+/// This is the same as reading the enum from the encoded `index` followed by the stream.
+pub fn decode_variant<R: Read, T: DeserializeOwned>(r: R, index: u32) -> Result<T, Error> {
+    let mut head = Vec::new();
+    encode_u64(&mut head, index as u64)?;
+    from_reader(head.as_slice().chain(r))
+}
+
+/// Deserialize bytes of Dokechi format as the first of candidate types that matches.
+///
+/// `C` is a tuple of candidate types such as `(V3, V2, V1)`, tried in order.
+/// A candidate matches if it decodes successfully and consumes all of `bs`.
+/// The matched value is converted into the first type, so the other types must implement `Into` of it.
+///
+/// ```
+/// use serde_derive::Deserialize;
+/// use serde_dokechi::{to_vec, try_decode_any};
+///
+/// #[derive(Debug, PartialEq, Deserialize)]
+/// struct V2 {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// #[derive(Deserialize)]
+/// struct V1 {
+///     name: String,
+/// }
+///
+/// impl From<V1> for V2 {
+///     fn from(v: V1) -> V2 {
+///         V2 { name: v.name, age: 0 }
+///     }
+/// }
+///
+/// let bs = to_vec("Alice").unwrap();
+/// let v = try_decode_any::<(V2, V1)>(&bs).unwrap();
+/// assert_eq!(v, V2 { name: "Alice".to_owned(), age: 0 });
+/// ```
+pub fn try_decode_any<C: Candidates>(bs: &[u8]) -> Result<C::Output, Error> {
+    C::try_decode(bs)
+}
+
+/// Candidate types of [`try_decode_any`](fn.try_decode_any.html).
+///
+/// Implemented for tuples of up to 8 types.
+pub trait Candidates {
+    /// The type matched values are converted into.
+    type Output;
+
+    /// Decode `bs` as the first candidate matching it.
+    fn try_decode(bs: &[u8]) -> Result<Self::Output, Error>;
+}
+
+/// Deserialize `bs` as `T` if it consumes all of `bs`.
+fn decode_exact<T: DeserializeOwned>(bs: &[u8]) -> Option<T> {
+    let mut r = bs;
+    let v = de::Deserialize::deserialize(&mut Deserializer::new(&mut r)).ok()?;
+    if r.is_empty() {
+        Some(v)
+    } else {
+        None
+    }
+}
+
+macro_rules! impl_candidates {
+    ($first:ident $(, $rest:ident)*) => {
+        impl<$first: DeserializeOwned $(, $rest: DeserializeOwned + Into<$first>)*> Candidates
+            for ($first, $($rest,)*)
+        {
+            type Output = $first;
+
+            fn try_decode(bs: &[u8]) -> Result<$first, Error> {
+                if let Some(v) = decode_exact::<$first>(bs) {
+                    return Ok(v);
+                }
+                $(
+                    if let Some(v) = decode_exact::<$rest>(bs) {
+                        return Ok(v.into());
+                    }
+                )*
+                Err(Error::custom("no candidate type matches"))
+            }
+        }
+    };
+}
+
+impl_candidates!(A);
+impl_candidates!(A, B);
+impl_candidates!(A, B, C);
+impl_candidates!(A, B, C, D);
+impl_candidates!(A, B, C, D, E);
+impl_candidates!(A, B, C, D, E, F);
+impl_candidates!(A, B, C, D, E, F, G);
+impl_candidates!(A, B, C, D, E, F, G, H);
+
+/// Read a number of type `$t` as a fixed-width word in the configured byte order.
+macro_rules! read_fixed {
+    ($d:expr, $t:ty) => {{
+        let mut bs = [0u8; std::mem::size_of::<$t>()];
+        $d.r.read_exact(&mut bs[..])?;
+        $d.endian.order(&mut bs[..]);
+        <$t>::from_le_bytes(bs)
+    }};
+}
+
+/// The default limit of nesting of values.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
 /// A structure that deserializes Dokechi format into Rust values.
 #[derive(Debug)]
-pub struct Deserializer<R: Read> {
-    r: R,
+pub struct Deserializer<R: Input> {
+    r: Counted<R>,
+    scratch: Vec<u8>,
+    in_place: bool,
+    reject_non_finite: bool,
+    narrowing: Narrowing,
+    narrowed: Vec<Narrowed>,
+    field: Option<&'static str>,
+    depth: usize,
+    max_depth: usize,
+    limits: Limits,
+    allocated: usize,
+    int_encoding: IntEncoding,
+    float_encoding: FloatEncoding,
+    lossy_f64: bool,
+    endian: Endian,
+    option_bitmap: bool,
+    variant_names: bool,
+    struct_maps: bool,
+    truncated_structs: bool,
+    compact_chars: bool,
+    pack_bools: bool,
+    chunked_seqs: bool,
+    canonical: bool,
+    pub(crate) stats: Option<Collector>,
+    #[cfg(feature = "log")]
+    pub(crate) trace: Option<Tracer>,
+}
+
+/// How to decode an integer too large for the type it is decoded into.
+///
+/// Applies to `u16`, `u32`, `i16` and `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Narrowing {
+    /// Return error. This is the default.
+    Error,
+    /// Use the nearest value the type can represent.
+    Saturate,
+    /// Use the lowest bits of the value, as `as` casting does.
+    Wrap,
+}
+
+impl Default for Narrowing {
+    fn default() -> Narrowing {
+        Narrowing::Error
+    }
+}
+
+/// Limits of lengths of values decoded by a [`Deserializer`](struct.Deserializer.html).
+///
+/// A length over the limit returns error before reading the value.
+/// The default is no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub(crate) max_string_len: usize,
+    pub(crate) max_bytes_len: usize,
+    pub(crate) max_seq_len: usize,
+    pub(crate) alloc_budget: usize,
+}
+
+impl Limits {
+    /// Limits for input from untrusted sources.
+    ///
+    /// Strings are limited to 1 MiB, byte arrays to 16 MiB, and sequences and maps to 2<sup>20</sup> elements.
+    /// The allocation budget is 64 MiB.
+    pub fn untrusted() -> Limits {
+        Limits {
+            max_string_len: 1 << 20,
+            max_bytes_len: 16 << 20,
+            max_seq_len: 1 << 20,
+            alloc_budget: 64 << 20,
+        }
+    }
+
+    /// Set the maximum length of strings in bytes.
+    pub fn max_string_len(mut self, max: usize) -> Limits {
+        self.max_string_len = max;
+        self
+    }
+
+    /// Set the maximum length of byte arrays.
+    pub fn max_bytes_len(mut self, max: usize) -> Limits {
+        self.max_bytes_len = max;
+        self
+    }
+
+    /// Set the maximum number of elements of sequences, or entries of maps.
+    pub fn max_seq_len(mut self, max: usize) -> Limits {
+        self.max_seq_len = max;
+        self
+    }
+
+    /// Set the total bytes a value may ask to allocate.
+    ///
+    /// Strings and byte arrays are charged their lengths. Sequences and maps are charged
+    /// one byte for each element, as the size of elements is unknown to the deserializer,
+    /// and the elements are charged as they are decoded.
+    ///
+    /// Exceeding the budget returns [`Error::BudgetExceeded`](enum.Error.html#variant.BudgetExceeded).
+    pub fn alloc_budget(mut self, bytes: usize) -> Limits {
+        self.alloc_budget = bytes;
+        self
+    }
+
+    /// Check a decoded length against `max` and the allocation budget, and charge it to `allocated`.
+    pub(crate) fn charge_len(
+        &self,
+        len: u64,
+        max: usize,
+        allocated: &mut usize,
+    ) -> Result<usize, Error> {
+        if len > usize::max_value() as u64 {
+            return Err(Error::invalid_value(
+                Unexpected::Unsigned(len),
+                &"length fitting in usize",
+            ));
+        }
+        let len = len as usize;
+        if len > max {
+            return Err(Error::invalid_length(len, &MaxLen(max)));
+        }
+        if len > self.alloc_budget - *allocated {
+            return Err(Error::BudgetExceeded(self.alloc_budget, Offset::UNKNOWN));
+        }
+        *allocated += len;
+        Ok(len)
+    }
 }
 
-impl<R: Read> Deserializer<R> {
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_string_len: usize::max_value(),
+            max_bytes_len: usize::max_value(),
+            max_seq_len: usize::max_value(),
+            alloc_budget: usize::max_value(),
+        }
+    }
+}
+
+/// An integer decoded with [`Narrowing::Saturate`](enum.Narrowing.html#variant.Saturate)
+/// or [`Narrowing::Wrap`](enum.Narrowing.html#variant.Wrap).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Narrowed {
+    /// The struct field the integer is decoded into, if any.
+    ///
+    /// For nested values, this is the innermost field.
+    pub field: Option<&'static str>,
+    /// The type the integer is decoded into.
+    pub target: &'static str,
+    /// The encoded value.
+    pub value: i128,
+    /// The decoded value.
+    pub narrowed: i128,
+}
+
+impl<R: Input> Deserializer<R> {
     /// Create new `Deserializer`
     pub fn new(r: R) -> Deserializer<R> {
-        Deserializer { r }
+        Deserializer {
+            r: Counted {
+                r,
+                position: 0,
+                peeked: None,
+                recorded: None,
+            },
+            scratch: Vec::new(),
+            in_place: false,
+            reject_non_finite: false,
+            narrowing: Narrowing::Error,
+            narrowed: Vec::new(),
+            field: None,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            limits: Limits::default(),
+            allocated: 0,
+            int_encoding: IntEncoding::Varint,
+            float_encoding: FloatEncoding::Raw,
+            lossy_f64: false,
+            endian: Endian::Little,
+            option_bitmap: false,
+            variant_names: false,
+            struct_maps: false,
+            truncated_structs: false,
+            compact_chars: false,
+            pack_bools: false,
+            chunked_seqs: false,
+            canonical: false,
+            stats: None,
+            #[cfg(feature = "log")]
+            trace: None,
+        }
+    }
+
+    /// Return [`Error::NonFiniteFloat`](enum.Error.html#variant.NonFiniteFloat) for NaN and infinite floats.
+    pub fn reject_non_finite(mut self) -> Deserializer<R> {
+        self.reject_non_finite = true;
+        self
+    }
+
+    fn check_finite(&self, v: f64) -> Result<(), Error> {
+        if self.reject_non_finite && !v.is_finite() {
+            return Err(Error::NonFiniteFloat(v, Offset::UNKNOWN));
+        }
+        Ok(())
+    }
+
+    /// Set the limit of nesting of sequences, maps, structs, enums and options.
+    ///
+    /// Deeper values return [`Error::DepthLimitExceeded`](enum.Error.html#variant.DepthLimitExceeded),
+    /// instead of overflowing the stack on recursive types.
+    /// The default is [`DEFAULT_MAX_DEPTH`](constant.DEFAULT_MAX_DEPTH.html).
+    pub fn max_depth(mut self, max_depth: usize) -> Deserializer<R> {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Set the limits of lengths.
+    pub fn limits(mut self, limits: Limits) -> Deserializer<R> {
+        self.limits = limits;
+        self
+    }
+
+    /// Decode integers wider than 8 bits with `encoding`.
+    pub fn int_encoding(mut self, encoding: IntEncoding) -> Deserializer<R> {
+        self.int_encoding = encoding;
+        self
+    }
+
+    /// Decode floats with `encoding`.
+    pub fn float_encoding(mut self, encoding: FloatEncoding) -> Deserializer<R> {
+        self.float_encoding = encoding;
+        self
+    }
+
+    /// Read `f64` written as `f32`, widening it back.
+    ///
+    /// See [`Serializer::lossy_f64`](../ser/struct.Serializer.html#method.lossy_f64).
+    pub fn lossy_f64(mut self) -> Deserializer<R> {
+        self.lossy_f64 = true;
+        self
+    }
+
+    /// Read enum variants by their names instead of their indexes.
+    ///
+    /// See [`Serializer::variant_names`](../ser/struct.Serializer.html#method.variant_names).
+    pub fn variant_names(mut self) -> Deserializer<R> {
+        self.variant_names = true;
+        self
+    }
+
+    /// Read structs as maps of field names to values.
+    ///
+    /// Values are not length-prefixed, so a field unknown to the type can't be skipped,
+    /// and fails with [`Error::Unsupported`](enum.Error.html#variant.Unsupported).
+    /// Types ignoring unknown fields still fail, since they can't skip the value either.
+    /// See [`Serializer::struct_maps`](../ser/struct.Serializer.html#method.struct_maps).
+    pub fn struct_maps(mut self) -> Deserializer<R> {
+        self.struct_maps = true;
+        self
+    }
+
+    /// End structs at the end of the input, leaving the remaining fields missing.
+    ///
+    /// Missing fields get their defaults with `#[serde(default)]`, or return error without.
+    /// This allows data written before fields were appended to a struct to be read,
+    /// if the struct is at the end of the input.
+    pub fn truncated_structs(mut self) -> Deserializer<R> {
+        self.truncated_structs = true;
+        self
+    }
+
+    /// Read chars as varuints of their code points.
+    ///
+    /// See [`Serializer::compact_chars`](../ser/struct.Serializer.html#method.compact_chars).
+    pub fn compact_chars(mut self) -> Deserializer<R> {
+        self.compact_chars = true;
+        self
+    }
+
+    /// Read consecutive bool elements of sequences, tuples and structs packed into bytes.
+    ///
+    /// See [`Serializer::pack_bools`](../ser/struct.Serializer.html#method.pack_bools).
+    pub fn pack_bools(mut self) -> Deserializer<R> {
+        self.pack_bools = true;
+        self
+    }
+
+    /// Read sequences and maps in chunks.
+    ///
+    /// See [`Serializer::chunked_seqs`](../ser/struct.Serializer.html#method.chunked_seqs).
+    pub fn chunked_seqs(mut self) -> Deserializer<R> {
+        self.chunked_seqs = true;
+        self
+    }
+
+    /// Read floats, chars and fixed-width integers in `endian` byte order.
+    pub fn endian(mut self, endian: Endian) -> Deserializer<R> {
+        self.endian = endian;
+        self
+    }
+
+    /// Read `Some`/`None` of `Option` fields of structs from a bitmap before the fields.
+    ///
+    /// See [`Serializer::option_bitmap`](../ser/struct.Serializer.html#method.option_bitmap).
+    pub fn option_bitmap(mut self) -> Deserializer<R> {
+        self.option_bitmap = true;
+        self
+    }
+
+    /// Accept only the canonical encoding, so each value has exactly one accepted encoding.
+    ///
+    /// Variable-length integers and lengths not in the shortest form are rejected,
+    /// and so are entries of maps not sorted by the bytes of their encoded keys, including duplicate keys.
+    /// Fields of structs written as maps are in the order of the struct, and are not checked.
+    /// See [`Serializer::canonical`](../ser/struct.Serializer.html#method.canonical).
+    pub fn canonical(mut self) -> Deserializer<R> {
+        self.canonical = true;
+        self
+    }
+
+    /// Check the variable-length integer read from `start` takes `len` bytes, its shortest form, if canonical.
+    fn check_shortest(&self, start: u64, len: usize) -> Result<(), Error> {
+        if self.canonical && self.r.position - start != len as u64 {
+            return Err(Error::custom("integer is not in the shortest form"));
+        }
+        Ok(())
+    }
+
+    /// Run `f` one level deeper.
+    fn nest<T, F>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Deserializer<R>) -> Result<T, Error>,
+    {
+        if self.depth >= self.max_depth {
+            return Err(Error::DepthLimitExceeded(self.max_depth, Offset::UNKNOWN));
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    /// Read a length prefix of at most `max`, charging it to the allocation budget.
+    fn parse_len(&mut self, max: usize) -> Result<usize, Error> {
+        let start = self.r.position;
+        let len = decode_u64(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u64(len))?;
+        self.limits.charge_len(len, max, &mut self.allocated)
+    }
+
+    /// The number of elements the chunks after the first chunk of `len` elements may have,
+    /// or `None` if the sequence isn't chunked or ends with the first chunk.
+    fn chunks_after(&self, len: usize) -> Option<usize> {
+        if self.chunked_seqs && len > 0 {
+            Some(self.limits.max_seq_len - len)
+        } else {
+            None
+        }
+    }
+
+    /// Read the length of the next chunk of a sequence, whose chunks may have `left` more elements.
+    fn next_chunk(&mut self, left: usize) -> Result<usize, Error> {
+        let len = self.parse_len(left)?;
+        self.trace_prefix("chunk", len as u64);
+        Ok(len)
+    }
+
+    /// Decode integers too large for their types with `policy`.
+    pub fn narrowing(mut self, policy: Narrowing) -> Deserializer<R> {
+        self.narrowing = policy;
+        self
+    }
+
+    /// Integers decoded by narrowing so far, in order.
+    ///
+    /// Always empty with [`Narrowing::Error`](enum.Narrowing.html#variant.Error).
+    pub fn narrowed(&self) -> &[Narrowed] {
+        &self.narrowed
+    }
+
+    /// Apply the narrowing policy to `value` which is out of `min..=max` of `target`.
+    fn narrow(
+        &mut self,
+        value: i128,
+        min: i128,
+        max: i128,
+        target: &'static str,
+    ) -> Result<i128, Error> {
+        let narrowed = match self.narrowing {
+            Narrowing::Error => {
+                let unexpected = if value < 0 {
+                    Unexpected::Signed(value as i64)
+                } else {
+                    Unexpected::Unsigned(value as u64)
+                };
+                return Err(Error::invalid_value(unexpected, &target));
+            }
+            Narrowing::Saturate => {
+                if value < min {
+                    min
+                } else {
+                    max
+                }
+            }
+            Narrowing::Wrap => {
+                let modulo = max - min + 1;
+                (value - min).rem_euclid(modulo) + min
+            }
+        };
+        self.narrowed.push(Narrowed {
+            field: self.field,
+            target,
+            value,
+            narrowed,
+        });
+        Ok(narrowed)
+    }
+
+    /// Log decoding a value of `kind`, if traced.
+    fn trace(&self, kind: &'static str) {
+        #[cfg(feature = "log")]
+        {
+            if let Some(t) = &self.trace {
+                t.step(kind);
+            }
+        }
+        let _ = kind;
+    }
+
+    /// Log a prefix `n` read, if traced.
+    fn trace_prefix(&self, what: &'static str, n: u64) {
+        #[cfg(feature = "log")]
+        {
+            if let Some(t) = &self.trace {
+                t.prefix(what, n);
+            }
+        }
+        let _ = (what, n);
+    }
+
+    /// Go into a field, or the `index`th element if `field` is `None`, if traced.
+    fn enter(&mut self, field: Option<&'static str>, index: usize) {
+        #[cfg(feature = "log")]
+        {
+            if let Some(t) = &mut self.trace {
+                t.enter(field, index);
+            }
+        }
+        let _ = (field, index);
+    }
+
+    /// Go back from the last entered field or element, if traced.
+    fn leave(&mut self) {
+        #[cfg(feature = "log")]
+        {
+            if let Some(t) = &mut self.trace {
+                t.leave();
+            }
+        }
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.r.r
+    }
+
+    /// Get a mutable reference to the underlying reader.
+    ///
+    /// Writing to or reading from it directly in the middle of a value breaks the value.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.r.r
+    }
+
+    /// Unwrap this `Deserializer`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.r.r
+    }
+
+    /// The number of bytes read so far.
+    ///
+    /// Bytes read directly from [`get_mut`](#method.get_mut) are not counted.
+    pub fn position(&self) -> u64 {
+        self.r.position
     }
 
     fn parse_u16(&mut self) -> Result<u16, Error> {
-        let v = decode_u64(&mut self.r)?;
+        if self.int_encoding == IntEncoding::Fixed {
+            return Ok(read_fixed!(self, u16));
+        }
+        let start = self.r.position;
+        let v = decode_u16(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u64(v))?;
         if v <= u16::max_value() as u64 {
             Ok(v as u16)
         } else {
-            Err(Error::invalid_value(Unexpected::Unsigned(v as u64), &"u16"))
+            Ok(self.narrow(v as i128, 0, u16::max_value() as i128, "u16")? as u16)
         }
     }
 
     fn parse_u32(&mut self) -> Result<u32, Error> {
-        let v = decode_u64(&mut self.r)?;
+        if self.int_encoding == IntEncoding::Fixed {
+            return Ok(read_fixed!(self, u32));
+        }
+        let start = self.r.position;
+        let v = decode_u32(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u64(v))?;
         if v <= u32::max_value() as u64 {
             Ok(v as u32)
         } else {
-            Err(Error::invalid_value(Unexpected::Unsigned(v as u64), &"u16"))
+            Ok(self.narrow(v as i128, 0, u32::max_value() as i128, "u32")? as u32)
+        }
+    }
+
+    fn parse_i16(&mut self) -> Result<i16, Error> {
+        if self.int_encoding == IntEncoding::Fixed {
+            return Ok(read_fixed!(self, i16));
+        }
+        let start = self.r.position;
+        let u = decode_u16(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u64(u))?;
+        let v = unzigzag(u);
+        if u <= u16::max_value() as u64 {
+            Ok(v as i16)
+        } else {
+            let min = i16::min_value() as i128;
+            let max = i16::max_value() as i128;
+            Ok(self.narrow(v as i128, min, max, "i16")? as i16)
+        }
+    }
+
+    fn parse_i32(&mut self) -> Result<i32, Error> {
+        if self.int_encoding == IntEncoding::Fixed {
+            return Ok(read_fixed!(self, i32));
+        }
+        let start = self.r.position;
+        let u = decode_u32(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u64(u))?;
+        let v = unzigzag(u);
+        if u <= u32::max_value() as u64 {
+            Ok(v as i32)
+        } else {
+            let min = i32::min_value() as i128;
+            let max = i32::max_value() as i128;
+            Ok(self.narrow(v as i128, min, max, "i32")? as i32)
+        }
+    }
+
+    /// Deserialize a byte array as a slice of the input if the input supports it.
+    #[cfg(feature = "bytes")]
+    fn deserialize_shared_bytes<'de, V: Visitor<'de>>(
+        &mut self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let len = self.parse_len(self.limits.max_bytes_len)?;
+        self.trace_prefix("length", len as u64);
+        if let Some(c) = &mut self.stats {
+            c.stats.byte_arrays += 1;
+            c.stats.byte_array_bytes = c.stats.byte_array_bytes.saturating_add(len as u64);
+        }
+
+        match self.r.take_bytes(len) {
+            Some(bs) => crate::io::pass_shared_bytes(bs?, || visitor.visit_unit()).unwrap_or(Err(
+                Error::Unsupported("shared bytes for a type not SharedBytes", Offset::UNKNOWN),
+            )),
+            None => {
+                let mut bs = Vec::new();
+                read_to_vec(&mut self.r, &mut bs, len)?;
+                visitor.visit_byte_buf(bs)
+            }
+        }
+    }
+
+    fn parse_u64(&mut self) -> Result<u64, Error> {
+        if self.int_encoding == IntEncoding::Fixed {
+            return Ok(read_fixed!(self, u64));
+        }
+        let start = self.r.position;
+        let v = decode_u64(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u64(v))?;
+        Ok(v)
+    }
+
+    fn parse_i64(&mut self) -> Result<i64, Error> {
+        if self.int_encoding == IntEncoding::Fixed {
+            return Ok(read_fixed!(self, i64));
+        }
+        let start = self.r.position;
+        let u = decode_u64(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u64(u))?;
+        Ok(unzigzag(u))
+    }
+
+    /// Read the tag of a float, and the float as an integer if so written, if floats are compact.
+    fn parse_integral(&mut self) -> Result<Option<i64>, Error> {
+        if self.float_encoding != FloatEncoding::Compact {
+            return Ok(None);
+        }
+        let mut tag = [0u8];
+        self.r.read_exact(&mut tag[..])?;
+        match tag[0] {
+            0 => {
+                let start = self.r.position;
+                let u = decode_u64(&mut self.r)?;
+                self.check_shortest(start, encoded_len_u64(u))?;
+                Ok(Some(unzigzag(u)))
+            }
+            1 => Ok(None),
+            v => Err(Error::invalid_value(
+                Unexpected::Unsigned(v as u64),
+                &"integer (0) or bits (1) of float",
+            )),
         }
     }
 
     fn parse_u128(&mut self) -> Result<u128, Error> {
-        Ok(decode_u128(&mut self.r)?)
+        if self.int_encoding == IntEncoding::Fixed {
+            return Ok(read_fixed!(self, u128));
+        }
+        let start = self.r.position;
+        let v = decode_u128(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u128(v))?;
+        Ok(v)
+    }
+
+    fn parse_i128(&mut self) -> Result<i128, Error> {
+        if self.int_encoding == IntEncoding::Fixed {
+            return Ok(read_fixed!(self, i128));
+        }
+        let start = self.r.position;
+        let u = decode_u128(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u128(u))?;
+        if u & 1 == 0 {
+            Ok((u >> 1) as i128)
+        } else {
+            Ok(-((u >> 1) as i128) - 1)
+        }
+    }
+}
+
+impl<R: SizedInput> Deserializer<R> {
+    /// The number of bytes left undecoded in the input.
+    ///
+    /// Use this to find where the data after a value starts, when values are embedded in larger containers.
+    pub fn remaining(&self) -> usize {
+        self.r.r.remaining() + self.r.peeked.is_some() as usize
+    }
+}
+
+impl<R: Input> Deserializer<R> {
+    /// Whether any byte is left in the input, without consuming it.
+    ///
+    /// Use this to decide whether another value follows.
+    /// A byte read ahead is kept for the next value, but it is lost by [`into_inner`](#method.into_inner)
+    /// and not seen by reading from [`get_mut`](#method.get_mut).
+    pub fn has_remaining(&mut self) -> Result<bool, Error> {
+        Ok(!self.r.at_end()?)
+    }
+
+    /// The next byte of the input without consuming it, or `None` at the end of the input.
+    ///
+    /// The byte is kept in the same way as [`has_remaining`](#method.has_remaining).
+    pub fn peek(&mut self) -> Result<Option<u8>, Error> {
+        self.r.at_end()?;
+        Ok(self.r.peeked)
+    }
+
+    /// Turn into an iterator over values of type `T` written back to back, until the input ends.
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter<T: DeserializeOwned>(self) -> StreamDeserializer<R, T> {
+        StreamDeserializer {
+            deserializer: self,
+            failed: false,
+            marker: PhantomData,
+        }
+    }
+}
+
+/// An iterator over values written back to back, such as records appended by repeated `to_writer`.
+///
+/// Created by [`Deserializer::into_iter`](struct.Deserializer.html#method.into_iter).
+/// Iteration ends when the input ends between values. If the input ends in the middle of a value,
+/// or a value is broken, an error is returned and iteration ends.
+#[derive(Debug)]
+pub struct StreamDeserializer<R: Input, T> {
+    deserializer: Deserializer<R>,
+    failed: bool,
+    marker: PhantomData<T>,
+}
+
+impl<R: Input, T> StreamDeserializer<R, T> {
+    /// The number of bytes of the values decoded so far.
+    pub fn byte_offset(&self) -> u64 {
+        self.deserializer.position()
+    }
+}
+
+impl<R: Input, T: DeserializeOwned> Iterator for StreamDeserializer<R, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Result<T, Error>> {
+        if self.failed {
+            return None;
+        }
+
+        let res = match self.deserializer.r.at_end() {
+            Ok(true) => return None,
+            Ok(false) => {
+                self.deserializer.allocated = 0;
+                de::Deserialize::deserialize(&mut self.deserializer)
+            }
+            Err(e) => Err(e.into()),
+        };
+        if res.is_err() {
+            self.failed = true;
+        }
+        Some(res.map_err(|e| e.at(self.deserializer.position())))
+    }
+}
+
+/// A position in the input of a [`Deserializer`](struct.Deserializer.html) to rewind to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    position: u64,
+    read: u64,
+    narrowed: usize,
+    allocated: usize,
+}
+
+impl Mark {
+    /// The position in bytes from the start of the input.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+/// An input counting bytes read from it.
+#[derive(Debug)]
+struct Counted<R> {
+    r: R,
+    position: u64,
+    peeked: Option<u8>,
+    /// Bytes read while recording encoded map keys.
+    recorded: Option<Vec<u8>>,
+}
+
+impl<R: Input> Counted<R> {
+    /// Whether the input ends here, reading ahead one byte if not.
+    fn at_end(&mut self) -> io::Result<bool> {
+        if self.peeked.is_some() {
+            return Ok(false);
+        }
+        let mut bs = [0u8];
+        match self.r.read_exact(&mut bs) {
+            Ok(()) => {
+                self.peeked = Some(bs[0]);
+                Ok(false)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<R: Input> Input for Counted<R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        match (self.peeked, buf.split_first_mut()) {
+            (Some(b), Some((first, rest))) => {
+                *first = b;
+                self.peeked = None;
+                self.r.read_exact(rest)?;
+            }
+            _ => self.r.read_exact(buf)?,
+        }
+        self.position += buf.len() as u64;
+        if let Some(recorded) = &mut self.recorded {
+            recorded.extend_from_slice(buf);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "bytes")]
+    fn take_bytes(&mut self, len: usize) -> Option<io::Result<bytes::Bytes>> {
+        if self.peeked.is_some() || self.recorded.is_some() {
+            return None;
+        }
+        let res = self.r.take_bytes(len);
+        if let Some(Ok(_)) = &res {
+            self.position += len as u64;
+        }
+        res
+    }
+}
+
+impl<R: Input + Seek> Deserializer<R> {
+    /// Mark the current position to [`rewind`](#method.rewind) to.
+    ///
+    /// For slices, wrap them in `std::io::Cursor`.
+    pub fn mark(&mut self) -> Result<Mark, Error> {
+        Ok(Mark {
+            position: self.r.r.seek(SeekFrom::Current(0))? - self.r.peeked.is_some() as u64,
+            read: self.r.position,
+            narrowed: self.narrowed.len(),
+            allocated: self.allocated,
+        })
+    }
+
+    /// Go back to `mark`, to decode the input again after a failure.
+    ///
+    /// [`narrowed`](#method.narrowed) integers after `mark` are discarded.
+    pub fn rewind(&mut self, mark: Mark) -> Result<(), Error> {
+        self.r.r.seek(SeekFrom::Start(mark.position))?;
+        self.r.position = mark.read;
+        self.r.peeked = None;
+        self.narrowed.truncate(mark.narrowed);
+        self.allocated = mark.allocated;
+        self.field = None;
+        self.depth = 0;
+        Ok(())
     }
 }
 
-impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
+impl<'de, R: Input> de::Deserializer<'de> for &mut Deserializer<R> {
     type Error = Error;
 
     fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Unsupported("deserialize_any"))
+        Err(Error::Unsupported("deserialize_any", Offset::UNKNOWN))
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
+        self.trace("bool");
         let mut bs = [0u8];
         self.r.read_exact(&mut bs[..])?;
 
@@ -82,6 +1087,7 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
+        self.trace("i8");
         let mut bs = [0u8];
         self.r.read_exact(&mut bs[..])?;
         visitor.visit_i8(i8::from_le_bytes(bs))
@@ -91,66 +1097,39 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
-        let u = self.parse_u16()?;
-
-        let v = if u & 1 == 0 {
-            (u >> 1) as i16
-        } else {
-            -((u >> 1) as i16) - 1
-        };
-
-        visitor.visit_i16(v)
+        self.trace("i16");
+        visitor.visit_i16(self.parse_i16()?)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let u = self.parse_u32()?;
-
-        let v = if u & 1 == 0 {
-            (u >> 1) as i32
-        } else {
-            -((u >> 1) as i32) - 1
-        };
-
-        visitor.visit_i32(v)
+        self.trace("i32");
+        visitor.visit_i32(self.parse_i32()?)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let u = decode_u64(&mut self.r)?;
-
-        let v = if u & 1 == 0 {
-            (u >> 1) as i64
-        } else {
-            -((u >> 1) as i64) - 1
-        };
-
-        visitor.visit_i64(v)
+        self.trace("i64");
+        visitor.visit_i64(self.parse_i64()?)
     }
 
     fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let u = self.parse_u128()?;
-
-        let v = if u & 1 == 0 {
-            (u >> 1) as i128
-        } else {
-            -((u >> 1) as i128) - 1
-        };
-
-        visitor.visit_i128(v)
+        self.trace("i128");
+        visitor.visit_i128(self.parse_i128()?)
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
+        self.trace("u8");
         let mut bs = [0u8];
         self.r.read_exact(&mut bs[..])?;
         visitor.visit_u8(u8::from_le_bytes(bs))
@@ -160,6 +1139,7 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
+        self.trace("u16");
         visitor.visit_u16(self.parse_u16()?)
     }
 
@@ -167,6 +1147,7 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
+        self.trace("u32");
         visitor.visit_u32(self.parse_u32()?)
     }
 
@@ -174,14 +1155,15 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
-        let v = decode_u64(&mut self.r)?;
-        visitor.visit_u64(v)
+        self.trace("u64");
+        visitor.visit_u64(self.parse_u64()?)
     }
 
     fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
+        self.trace("u128");
         visitor.visit_u128(self.parse_u128()?)
     }
 
@@ -189,27 +1171,51 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
-        let mut bs = [0u8; 4];
-        self.r.read_exact(&mut bs[..])?;
-        visitor.visit_f32(f32::from_le_bytes(bs))
+        self.trace("f32");
+        let v = match self.parse_integral()? {
+            Some(i) => i as f32,
+            None => read_fixed!(self, f32),
+        };
+        self.check_finite(v as f64)?;
+        visitor.visit_f32(v)
     }
 
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut bs = [0u8; 8];
-        self.r.read_exact(&mut bs[..])?;
-        visitor.visit_f64(f64::from_le_bytes(bs))
+        self.trace("f64");
+        let v = match self.parse_integral()? {
+            Some(i) => i as f64,
+            None if self.lossy_f64 => read_fixed!(self, f32) as f64,
+            None => read_fixed!(self, f64),
+        };
+        self.check_finite(v)?;
+        visitor.visit_f64(v)
     }
 
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let mut bs = [0u8; 4];
-        self.r.read_exact(&mut bs[..3])?;
-        let v = u32::from_le_bytes(bs);
+        self.trace("char");
+        let v = if self.compact_chars {
+            let start = self.r.position;
+            let v = decode_u32(&mut self.r)?;
+            self.check_shortest(start, encoded_len_u64(v))?;
+            if v > u32::max_value() as u64 {
+                return Err(Error::invalid_value(
+                    Unexpected::Unsigned(v),
+                    &"Unicode code point",
+                ));
+            }
+            v as u32
+        } else {
+            let mut bs = [0u8; 4];
+            self.r.read_exact(&mut bs[..3])?;
+            self.endian.order(&mut bs[..3]);
+            u32::from_le_bytes(bs)
+        };
         if let Some(ch) = std::char::from_u32(v) {
             visitor.visit_char(ch)
         } else {
@@ -231,10 +1237,25 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
-        let len = decode_u64(&mut self.r)? as usize;
+        self.trace("string");
+        let len = self.parse_len(self.limits.max_string_len)?;
+        self.trace_prefix("length", len as u64);
+        if let Some(c) = &mut self.stats {
+            c.stats.strings += 1;
+            c.stats.string_bytes = c.stats.string_bytes.saturating_add(len as u64);
+        }
+
+        if self.in_place {
+            // Visiting a borrowed `str` lets in-place visitors reuse the existing capacity.
+            read_to_vec(&mut self.r, &mut self.scratch, len)?;
+            return match std::str::from_utf8(&self.scratch) {
+                Ok(s) => visitor.visit_str(s),
+                Err(_) => Err(Error::custom("invalid UTF-8 sequence")),
+            };
+        }
 
-        let mut bs = vec![0u8; len];
-        self.r.read_exact(&mut bs)?;
+        let mut bs = Vec::new();
+        read_to_vec(&mut self.r, &mut bs, len)?;
 
         match String::from_utf8(bs) {
             Ok(s) => visitor.visit_string(s),
@@ -253,10 +1274,16 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
-        let len = decode_u64(&mut self.r)? as usize;
+        self.trace("bytes");
+        let len = self.parse_len(self.limits.max_bytes_len)?;
+        self.trace_prefix("length", len as u64);
+        if let Some(c) = &mut self.stats {
+            c.stats.byte_arrays += 1;
+            c.stats.byte_array_bytes = c.stats.byte_array_bytes.saturating_add(len as u64);
+        }
 
-        let mut bs = vec![0u8; len];
-        self.r.read_exact(&mut bs)?;
+        let mut bs = Vec::new();
+        read_to_vec(&mut self.r, &mut bs, len)?;
 
         visitor.visit_byte_buf(bs)
     }
@@ -265,12 +1292,13 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
+        self.trace("option");
         let mut bs = [0u8];
         self.r.read_exact(&mut bs[..])?;
 
         match bs[0] {
             0 => visitor.visit_none(),
-            1 => visitor.visit_some(self),
+            1 => self.nest(|d| visitor.visit_some(d)),
             v => Err(Error::invalid_value(
                 Unexpected::Unsigned(v as u64),
                 &"None (0) or Some (1)",
@@ -282,6 +1310,7 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
+        self.trace("unit");
         visitor.visit_unit()
     }
 
@@ -293,63 +1322,50 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
+        self.trace("unit struct");
         visitor.visit_unit()
     }
 
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_newtype_struct(self)
+        self.trace("newtype struct");
+        #[cfg(feature = "bytes")]
+        {
+            if name == crate::io::SHARED_BYTES_TOKEN {
+                return self.deserialize_shared_bytes(visitor);
+            }
+        }
+        let _ = name;
+
+        self.nest(|d| visitor.visit_newtype_struct(d))
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let len = decode_u64(&mut self.r)? as usize;
-        self.deserialize_tuple(len, visitor)
+        self.trace("seq");
+        let len = self.parse_len(self.limits.max_seq_len)?;
+        self.trace_prefix("length", len as u64);
+        if let Some(c) = &mut self.stats {
+            c.stats.seq_elements = c.stats.seq_elements.saturating_add(len as u64);
+        }
+        let chunks = self.chunks_after(len);
+        visit_tuple(self, len, None, false, chunks, visitor)
     }
 
     fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        struct Access<'a, R: Read> {
-            deserializer: &'a mut Deserializer<R>,
-            len: usize,
-        }
-
-        impl<'de, 'a, R: Read> de::SeqAccess<'de> for Access<'a, R> {
-            type Error = Error;
-
-            fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
-            where
-                T: de::DeserializeSeed<'de>,
-            {
-                if self.len > 0 {
-                    self.len -= 1;
-                    let value =
-                        serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                    Ok(Some(value))
-                } else {
-                    Ok(None)
-                }
-            }
-
-            fn size_hint(&self) -> Option<usize> {
-                Some(self.len)
-            }
-        }
-
-        visitor.visit_seq(Access {
-            deserializer: self,
-            len,
-        })
+        self.trace("tuple");
+        visit_tuple(self, len, None, false, None, visitor)
     }
 
     fn deserialize_tuple_struct<V>(
@@ -361,54 +1377,16 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
-        self.deserialize_tuple(len, visitor)
+        self.trace("tuple struct");
+        visit_tuple(self, len, None, false, None, visitor)
     }
 
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        struct Access<'a, R: Read> {
-            deserializer: &'a mut Deserializer<R>,
-            len: usize,
-        }
-
-        impl<'de, 'a, R: Read> de::MapAccess<'de> for Access<'a, R> {
-            type Error = Error;
-
-            fn next_key_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
-            where
-                T: de::DeserializeSeed<'de>,
-            {
-                if self.len > 0 {
-                    self.len -= 1;
-                    let value =
-                        serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                    Ok(Some(value))
-                } else {
-                    Ok(None)
-                }
-            }
-
-            fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, Error>
-            where
-                T: de::DeserializeSeed<'de>,
-            {
-                let value = serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                Ok(value)
-            }
-
-            fn size_hint(&self) -> Option<usize> {
-                Some(self.len)
-            }
-        }
-
-        let len = decode_u64(&mut self.r)? as usize;
-
-        visitor.visit_map(Access {
-            deserializer: self,
-            len,
-        })
+        let sorted = self.canonical;
+        visit_map(self, sorted, visitor)
     }
 
     fn deserialize_struct<V>(
@@ -420,7 +1398,15 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
-        self.deserialize_tuple(fields.len(), visitor)
+        self.trace("struct");
+        if self.struct_maps {
+            return visit_map(self, false, visitor);
+        }
+        let root = match &mut self.stats {
+            Some(c) => c.enter_root(),
+            None => false,
+        };
+        visit_tuple(self, fields.len(), Some(fields), root, None, visitor)
     }
 
     fn deserialize_enum<V>(
@@ -432,35 +1418,406 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     where
         V: Visitor<'de>,
     {
-        impl<'de, 'a, R: Read> de::EnumAccess<'de> for &'a mut Deserializer<R> {
-            type Error = Error;
-            type Variant = Self;
-
-            fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Error>
-            where
-                V: de::DeserializeSeed<'de>,
-            {
-                let idx = decode_u64(&mut self.r)? as u32;
-                let val: Result<_, Error> = seed.deserialize(idx.into_deserializer());
-                Ok((val?, self))
-            }
-        }
-
-        visitor.visit_enum(self)
+        self.trace("enum");
+        self.nest(|d| visitor.visit_enum(d))
     }
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Unsupported("deserialize_identifier"))
+        self.deserialize_string(visitor)
     }
 
     fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Unsupported("deserialize_ignored_any"))
+        Err(Error::Unsupported(
+            "deserialize_ignored_any",
+            Offset::UNKNOWN,
+        ))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The longest length expected, formatted only if the message is kept.
+struct MaxLen(usize);
+
+impl de::Expected for MaxLen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "length of at most {}", self.0)
+    }
+}
+
+fn unzigzag(u: u64) -> i64 {
+    if u & 1 == 0 {
+        (u >> 1) as i64
+    } else {
+        -((u >> 1) as i64) - 1
+    }
+}
+
+/// Visit the entries of a map, checking keys are sorted by their encoded bytes if `sorted`.
+fn visit_map<'de, R: Input, V: Visitor<'de>>(
+    deserializer: &mut Deserializer<R>,
+    sorted: bool,
+    visitor: V,
+) -> Result<V::Value, Error> {
+    struct Access<'a, R: Input> {
+        deserializer: &'a mut Deserializer<R>,
+        len: usize,
+        chunks: Option<usize>,
+        index: usize,
+        sorted: bool,
+        last_key: Option<Vec<u8>>,
+    }
+
+    impl<'a, R: Input> Access<'a, R> {
+        /// Deserialize a key, checking it comes after the previous key.
+        fn sorted_key<'de, T>(&mut self, seed: T) -> Result<T::Value, Error>
+        where
+            T: de::DeserializeSeed<'de>,
+        {
+            // Keys containing maps are recorded as a part of the outer key.
+            let outer = self.deserializer.r.recorded.as_ref().map(Vec::len);
+            if outer.is_none() {
+                self.deserializer.r.recorded = Some(Vec::new());
+            }
+            let value = serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer);
+            let recorded = self
+                .deserializer
+                .r
+                .recorded
+                .as_mut()
+                .expect("recording keys");
+            let key = recorded[outer.unwrap_or(0)..].to_vec();
+            if outer.is_none() {
+                self.deserializer.r.recorded = None;
+            }
+            let value = value?;
+
+            if let Some(last) = &self.last_key {
+                if key <= *last {
+                    return Err(Error::custom("map keys are not in canonical order"));
+                }
+            }
+            self.last_key = Some(key);
+            Ok(value)
+        }
+    }
+
+    impl<'de, 'a, R: Input> de::MapAccess<'de> for Access<'a, R> {
+        type Error = Error;
+
+        fn next_key_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+        where
+            T: de::DeserializeSeed<'de>,
+        {
+            if self.len == 0 {
+                if let Some(left) = self.chunks {
+                    let len = self.deserializer.next_chunk(left)?;
+                    if let Some(c) = &mut self.deserializer.stats {
+                        c.stats.map_entries = c.stats.map_entries.saturating_add(len as u64);
+                    }
+                    self.len = len;
+                    self.chunks = if len > 0 { Some(left - len) } else { None };
+                }
+            }
+            if self.len > 0 {
+                self.len -= 1;
+                self.deserializer.enter(None, self.index);
+                let value = if self.sorted {
+                    self.sorted_key(seed)?
+                } else {
+                    serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?
+                };
+                self.deserializer.leave();
+                Ok(Some(value))
+            } else {
+                Ok(None)
+            }
+        }
+
+        fn next_value_seed<T>(&mut self, seed: T) -> Result<T::Value, Error>
+        where
+            T: de::DeserializeSeed<'de>,
+        {
+            self.deserializer.enter(None, self.index);
+            let value = serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
+            self.deserializer.leave();
+            self.index += 1;
+            Ok(value)
+        }
+
+        fn size_hint(&self) -> Option<usize> {
+            match self.chunks {
+                Some(_) => None,
+                None => Some(self.len),
+            }
+        }
+    }
+
+    deserializer.trace("map");
+    let len = deserializer.parse_len(deserializer.limits.max_seq_len)?;
+    deserializer.trace_prefix("length", len as u64);
+    if let Some(c) = &mut deserializer.stats {
+        c.stats.map_entries = c.stats.map_entries.saturating_add(len as u64);
+    }
+    let chunks = deserializer.chunks_after(len);
+
+    deserializer.nest(|deserializer| {
+        visitor.visit_map(Access {
+            deserializer,
+            len,
+            chunks,
+            index: 0,
+            sorted,
+            last_key: None,
+        })
+    })
+}
+
+/// Visit `len` elements, which are fields of a struct if `fields` is given.
+///
+/// Per field statistics are collected if `root` is true.
+/// Chunks of at most `chunks` elements in total follow the elements if `chunks` is given.
+fn visit_tuple<'de, R: Input, V: Visitor<'de>>(
+    deserializer: &mut Deserializer<R>,
+    len: usize,
+    fields: Option<&'static [&'static str]>,
+    root: bool,
+    chunks: Option<usize>,
+    visitor: V,
+) -> Result<V::Value, Error> {
+    struct Access<'a, R: Input> {
+        deserializer: &'a mut Deserializer<R>,
+        len: usize,
+        chunks: Option<usize>,
+        fields: Option<&'static [&'static str]>,
+        root: bool,
+        index: usize,
+        // The option bitmap of the fields, empty if not used.
+        flags: Vec<u8>,
+        run: Option<BoolRun>,
+    }
+
+    impl<'de, 'a, R: Input> de::SeqAccess<'de> for Access<'a, R> {
+        type Error = Error;
+
+        fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+        where
+            T: de::DeserializeSeed<'de>,
+        {
+            if self.len == 0 {
+                let left = match self.chunks {
+                    Some(left) => left,
+                    None => return Ok(None),
+                };
+                let len = self.deserializer.next_chunk(left)?;
+                if len == 0 {
+                    self.chunks = None;
+                    return Ok(None);
+                }
+                if let Some(c) = &mut self.deserializer.stats {
+                    c.stats.seq_elements = c.stats.seq_elements.saturating_add(len as u64);
+                }
+                self.len = len;
+                self.chunks = Some(left - len);
+                // A run of bools ends at the end of a chunk.
+                if let Some(ref mut run) = self.run {
+                    *run = BoolRun::default();
+                }
+            }
+            // Bools left in the current byte are the following fields.
+            let in_run = self.run.as_ref().map_or(false, |run| run.left > 0);
+            if self.fields.is_some()
+                && self.deserializer.truncated_structs
+                && !in_run
+                && self.deserializer.r.at_end()?
+            {
+                self.len = 0;
+                return Ok(None);
+            }
+            self.len -= 1;
+
+            let field = self
+                .fields
+                .map(|fields| fields[fields.len() - self.len - 1]);
+            let outer = match field {
+                Some(_) => std::mem::replace(&mut self.deserializer.field, field),
+                None => self.deserializer.field,
+            };
+            let start = match &self.deserializer.stats {
+                Some(c) if self.root => Some(c.position()),
+                _ => None,
+            };
+
+            self.deserializer.enter(field, self.index);
+            let value = if self.flags.is_empty() && self.run.is_none() {
+                seed.deserialize(&mut *self.deserializer)?
+            } else {
+                let i = self.index;
+                let present = if self.flags.is_empty() {
+                    None
+                } else {
+                    Some(self.flags[i / 8] & (1 << (i % 8)) != 0)
+                };
+                seed.deserialize(ElementDeserializer {
+                    deserializer: &mut *self.deserializer,
+                    present,
+                    run: self.run.as_mut(),
+                })?
+            };
+            self.deserializer.leave();
+            self.index += 1;
+
+            self.deserializer.field = outer;
+            if let (Some(field), Some(start), Some(c)) =
+                (field, start, &mut self.deserializer.stats)
+            {
+                let len = c.position() - start;
+                c.stats.field_bytes.push((field, len));
+            }
+            Ok(Some(value))
+        }
+
+        fn size_hint(&self) -> Option<usize> {
+            match self.chunks {
+                Some(_) => None,
+                None => Some(self.len),
+            }
+        }
+    }
+
+    deserializer.nest(|deserializer| {
+        let mut flags = Vec::new();
+        if fields.is_some() && deserializer.option_bitmap {
+            flags.resize((len + 7) / 8, 0);
+            deserializer.r.read_exact(&mut flags)?;
+        }
+        let run = if deserializer.pack_bools {
+            Some(BoolRun::default())
+        } else {
+            None
+        };
+        visitor.visit_seq(Access {
+            deserializer,
+            len,
+            chunks,
+            fields,
+            root,
+            index: 0,
+            flags,
+            run,
+        })
+    })
+}
+
+/// Bools of a byte of consecutive bool elements not read yet.
+#[derive(Debug, Default)]
+struct BoolRun {
+    bits: u8,
+    left: u8,
+}
+
+/// A deserializer of an element of a compound, reading bools from the run of bools
+/// and `Some`/`None` of an `Option` field from the bitmap.
+struct ElementDeserializer<'a, R: Input> {
+    deserializer: &'a mut Deserializer<R>,
+    present: Option<bool>,
+    run: Option<&'a mut BoolRun>,
+}
+
+impl<'a, R: Input> ElementDeserializer<'a, R> {
+    /// End the run of bools before a value other than a bool.
+    fn end_run(&mut self) {
+        if let Some(ref mut run) = self.run {
+            run.left = 0;
+        }
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(mut self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Error> {
+                self.end_run();
+                self.deserializer.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a, R: Input> de::Deserializer<'de> for ElementDeserializer<'a, R> {
+    type Error = Error;
+
+    forward_deserialize! {
+        deserialize_any();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_i128();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_u128();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let run = match self.run {
+            Some(run) => run,
+            None => return self.deserializer.deserialize_bool(visitor),
+        };
+        self.deserializer.trace("bool");
+        if run.left == 0 {
+            let mut bs = [0u8];
+            self.deserializer.r.read_exact(&mut bs[..])?;
+            run.bits = bs[0];
+            run.left = 8;
+        }
+        let v = run.bits & 1 != 0;
+        run.bits >>= 1;
+        run.left -= 1;
+        visitor.visit_bool(v)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Error> {
+        self.end_run();
+        match self.present {
+            Some(present) => {
+                self.deserializer.trace("option");
+                if present {
+                    self.deserializer.nest(|d| visitor.visit_some(d))
+                } else {
+                    visitor.visit_none()
+                }
+            }
+            None => self.deserializer.deserialize_option(visitor),
+        }
     }
 
     fn is_human_readable(&self) -> bool {
@@ -468,57 +1825,269 @@ impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
     }
 }
 
-impl<'de, 'a, R: Read> de::VariantAccess<'de> for &'a mut Deserializer<R> {
+impl<'de, R: Input> de::EnumAccess<'de> for &mut Deserializer<R> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Error>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        if self.variant_names {
+            let len = self.parse_len(self.limits.max_string_len)?;
+            self.trace_prefix("variant name length", len as u64);
+            read_to_vec(&mut self.r, &mut self.scratch, len)?;
+            let val: Result<_, Error> = match std::str::from_utf8(&self.scratch) {
+                Ok(name) => seed.deserialize(name.into_deserializer()),
+                Err(_) => Err(Error::custom("invalid UTF-8 sequence")),
+            };
+            return Ok((val?, self));
+        }
+
+        let start = self.r.position;
+        let idx = decode_u64(&mut self.r)?;
+        self.check_shortest(start, encoded_len_u64(idx))?;
+        self.trace_prefix("variant", idx);
+        if idx > u32::max_value() as u64 {
+            return Err(Error::invalid_value(
+                Unexpected::Unsigned(idx),
+                &"variant index",
+            ));
+        }
+        let idx = idx as u32;
+        let val: Result<_, Error> = seed.deserialize(idx.into_deserializer());
+        Ok((val?, self))
+    }
+}
+
+impl<'de, R: Input> de::VariantAccess<'de> for &mut Deserializer<R> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<(), Error> {
         Ok(())
     }
 
-    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
-    where
-        T: serde::de::DeserializeSeed<'de>,
-    {
-        serde::de::DeserializeSeed::deserialize(seed, self)
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where
+        T: serde::de::DeserializeSeed<'de>,
+    {
+        serde::de::DeserializeSeed::deserialize(seed, self)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        serde::de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        if self.struct_maps {
+            return serde::de::Deserializer::deserialize_map(self, visitor);
+        }
+        visit_tuple(self, fields.len(), Some(fields), false, None, visitor)
+    }
+}
+
+/// The [Deserializer](struct.Deserializer.html)'s error type.
+///
+/// Each variant keeps the [`Offset`](../struct.Offset.html) in the input the error occurred at.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The underlying reader returnd IO error.
+    #[error("{0}{1}")]
+    IO(#[source] io::Error, Offset),
+    /// The input ended in the middle of a value.
+    #[error("unexpected end of input{0}")]
+    Eof(Offset),
+    /// Values are nested deeper than the limit.
+    #[error("nesting deeper than {0} levels{1}")]
+    DepthLimitExceeded(usize, Offset),
+    /// A value asks to allocate more than the budget in bytes.
+    #[error("allocation budget of {0} bytes exceeded{1}")]
+    BudgetExceeded(usize, Offset),
+    /// Unsupported deseriazising operation called.
+    #[error("{0} is unsupported{1}")]
+    Unsupported(&'static str, Offset),
+    /// A non-finite float is read while it is rejected.
+    #[error("non-finite float {0} is rejected{1}")]
+    NonFiniteFloat(f64, Offset),
+    /// A complete value didn't arrive before the deadline.
+    #[error("deadline exceeded{0}")]
+    Timeout(Offset),
+    /// An error from serde framework.
+    #[cfg(not(feature = "compact-errors"))]
+    #[error("{0}{1}")]
+    Serde(String, Offset),
+    /// An error from serde framework.
+    #[cfg(feature = "compact-errors")]
+    #[error("{0}{1}")]
+    Serde(Message, Offset),
+}
+
+impl Error {
+    /// A compact code of the error kind, the same as [`crate::Error::code`](../enum.Error.html#method.code).
+    pub fn code(&self) -> u8 {
+        match self {
+            Error::IO(..) => 1,
+            Error::Unsupported(..) => 3,
+            Error::NonFiniteFloat(..) => 4,
+            Error::Serde(..) => 5,
+            Error::Timeout(_) => 6,
+            Error::Eof(_) => 7,
+            Error::DepthLimitExceeded(..) => 8,
+            Error::BudgetExceeded(..) => 9,
+        }
+    }
+
+    /// The kind of the error, the same as [`crate::Error::kind`](../enum.Error.html#method.kind).
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::IO(..) => ErrorKind::Io,
+            Error::Eof(_) => ErrorKind::Eof,
+            Error::DepthLimitExceeded(..) => ErrorKind::DepthLimitExceeded,
+            Error::BudgetExceeded(..) => ErrorKind::BudgetExceeded,
+            Error::Unsupported(..) => ErrorKind::Unsupported,
+            Error::NonFiniteFloat(..) => ErrorKind::NonFiniteFloat,
+            Error::Serde(..) => ErrorKind::Serde,
+            Error::Timeout(_) => ErrorKind::Timeout,
+        }
+    }
+
+    /// Whether the input ended in the middle of a value.
+    ///
+    /// Decoding from an empty input also fails with this.
+    pub fn is_eof(&self) -> bool {
+        self.kind() == ErrorKind::Eof
+    }
+
+    /// The offset in the input the error occurred at, if known.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Error::IO(_, offset)
+            | Error::Eof(offset)
+            | Error::DepthLimitExceeded(_, offset)
+            | Error::BudgetExceeded(_, offset)
+            | Error::Unsupported(_, offset)
+            | Error::NonFiniteFloat(_, offset)
+            | Error::Timeout(offset)
+            | Error::Serde(_, offset) => offset.get(),
+        }
+    }
+
+    /// Set the offset to `n` unless the error already has one.
+    pub(crate) fn at(mut self, n: u64) -> Error {
+        match &mut self {
+            Error::IO(_, offset)
+            | Error::Eof(offset)
+            | Error::DepthLimitExceeded(_, offset)
+            | Error::BudgetExceeded(_, offset)
+            | Error::Unsupported(_, offset)
+            | Error::NonFiniteFloat(_, offset)
+            | Error::Timeout(offset)
+            | Error::Serde(_, offset) => {
+                if *offset == Offset::UNKNOWN {
+                    *offset = Offset::new(n);
+                }
+            }
+        }
+        self
+    }
+}
+
+impl From<io::Error> for Error {
+    /// `UnexpectedEof` becomes [`Eof`](#variant.Eof).
+    fn from(e: io::Error) -> Error {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            Error::Eof(Offset::UNKNOWN)
+        } else {
+            Error::IO(e, Offset::UNKNOWN)
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    /// IO errors are unwrapped, and other errors are wrapped in `io::Error` of the matching kind.
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::IO(e, _) => e,
+            e => io::Error::new(e.kind().io_kind(), e),
+        }
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::Serde(message(msg), Offset::UNKNOWN)
+    }
+    #[cfg(feature = "compact-errors")]
+    fn invalid_type(_unexp: de::Unexpected, _exp: &dyn de::Expected) -> Error {
+        Error::Serde(Message::InvalidType, Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn invalid_value(unexp: de::Unexpected, _exp: &dyn de::Expected) -> Error {
+        Error::Serde(Message::invalid_value(unexp), Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn invalid_length(len: usize, _exp: &dyn de::Expected) -> Error {
+        Error::Serde(Message::InvalidLength(len), Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn unknown_variant(_variant: &str, _expected: &'static [&'static str]) -> Error {
+        Error::Serde(Message::UnknownVariant, Offset::UNKNOWN)
     }
 
-    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        serde::de::Deserializer::deserialize_tuple(self, len, visitor)
+    #[cfg(feature = "compact-errors")]
+    fn unknown_field(_field: &str, _expected: &'static [&'static str]) -> Error {
+        Error::Serde(Message::UnknownField, Offset::UNKNOWN)
     }
 
-    fn struct_variant<V>(
-        self,
-        fields: &'static [&'static str],
-        visitor: V,
-    ) -> Result<V::Value, Error>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        serde::de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    #[cfg(feature = "compact-errors")]
+    fn missing_field(field: &'static str) -> Error {
+        Error::Serde(Message::MissingField(field), Offset::UNKNOWN)
     }
-}
 
-/// The [Deserializer](struct.Deserializer.html)'s error type.
-#[derive(Debug, Error)]
-#[non_exhaustive]
-pub enum Error {
-    /// The underlying reader returnd IO error.
-    #[error("{0}")]
-    IO(#[from] io::Error),
-    /// Unsupported deseriazising operation called.
-    #[error("{0} is unsupported")]
-    Unsupported(&'static str),
-    /// An error from serde framework.
-    #[error("{0}")]
-    Serde(String),
+    #[cfg(feature = "compact-errors")]
+    fn duplicate_field(field: &'static str) -> Error {
+        Error::Serde(Message::DuplicateField(field), Offset::UNKNOWN)
+    }
 }
 
-impl de::Error for Error {
-    fn custom<T: Display>(msg: T) -> Error {
-        Error::Serde(msg.to_string())
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::IO(_, at) => defmt::write!(f, "E{=u8}: IO error{}", self.code(), at),
+            Error::Unsupported(op, at) => defmt::write!(f, "E{=u8}: {=str}{}", self.code(), op, at),
+            Error::NonFiniteFloat(v, at) => {
+                defmt::write!(f, "E{=u8}: {=f64}{}", self.code(), v, at)
+            }
+            Error::Timeout(at) => defmt::write!(f, "E{=u8}: deadline exceeded{}", self.code(), at),
+            Error::Eof(at) => {
+                defmt::write!(f, "E{=u8}: unexpected end of input{}", self.code(), at)
+            }
+            Error::DepthLimitExceeded(max, at) => {
+                defmt::write!(f, "E{=u8}: {=usize}{}", self.code(), max, at)
+            }
+            Error::BudgetExceeded(max, at) => {
+                defmt::write!(f, "E{=u8}: {=usize}{}", self.code(), max, at)
+            }
+            #[cfg(not(feature = "compact-errors"))]
+            Error::Serde(msg, at) => defmt::write!(f, "E{=u8}: {=str}{}", self.code(), msg, at),
+            #[cfg(feature = "compact-errors")]
+            Error::Serde(msg, at) => defmt::write!(f, "E{=u8}: {}{}", self.code(), msg, at),
+        }
     }
 }
 
@@ -526,7 +2095,7 @@ impl de::Error for Error {
 mod test {
     use super::*;
 
-    use std::collections::{HashMap, HashSet};
+    use std::collections::{BTreeMap, HashMap, HashSet};
 
     use serde_derive::Deserialize;
 
@@ -657,7 +2226,7 @@ mod test {
     fn deserialize_u64() {
         let to_be = u64::max_value();
         let mut bs = Vec::new();
-        encode_u64(&mut bs, to_be as u64).unwrap();
+        encode_u64(&mut bs, to_be).unwrap();
 
         let v: u64 = from_reader(&bs[..]).unwrap();
         assert_eq!(v, to_be);
@@ -690,6 +2259,235 @@ mod test {
         assert_eq!(v, to_be);
     }
 
+    #[test]
+    fn deserialize_take() {
+        let mut bs = Vec::new();
+        crate::to_writer(&mut bs, vec!["a", "bc", "def", "ghij"]).unwrap();
+
+        let (v, len): (Vec<String>, _) = from_reader_take(bs.as_slice(), 2).unwrap();
+        assert_eq!(v, vec!["a".to_owned(), "bc".to_owned()]);
+        assert_eq!(len, 4);
+
+        let (v, len): (Vec<String>, _) = from_reader_take(bs.as_slice(), 10).unwrap();
+        assert_eq!(v.len(), 4);
+        assert_eq!(len, 4);
+    }
+
+    #[test]
+    fn deserialize_take_stops_reading() {
+        let mut bs = Vec::new();
+        crate::to_writer(&mut bs, vec![1u8, 2, 3]).unwrap();
+        let mut r = bs.as_slice();
+
+        let (v, _): (Vec<u8>, _) = from_reader_take(&mut r, 1).unwrap();
+        assert_eq!(v, vec![1]);
+        assert_eq!(r, &[2, 3]);
+    }
+
+    #[test]
+    fn deserialize_take_fails_at_offset() {
+        let e = from_reader_take::<_, u8>(&[0x81][..], 1).unwrap_err();
+        assert!(e.is_eof());
+        assert_eq!(e.offset(), Some(1));
+    }
+
+    #[test]
+    fn deserialize_partial() {
+        let mut bs = Vec::new();
+        crate::to_writer(&mut bs, "abc").unwrap();
+        crate::to_writer(&mut bs, 300u32).unwrap();
+        crate::to_writer(&mut bs, (true, 'x')).unwrap();
+        let mut r = bs.as_slice();
+
+        let (v, n): (String, _) = from_reader_partial(&mut r).unwrap();
+        assert_eq!((v.as_str(), n), ("abc", 4));
+        let (v, n): (u32, _) = from_reader_partial(&mut r).unwrap();
+        assert_eq!((v, n), (300, 2));
+        let (v, n): ((bool, char), _) = from_reader_partial(&mut r).unwrap();
+        assert_eq!((v, n), ((true, 'x'), 4));
+        assert!(r.is_empty());
+
+        let e = from_reader_partial::<_, u32>(&mut r).unwrap_err();
+        assert_eq!(e.offset(), Some(0));
+    }
+
+    #[test]
+    fn stream_of_values() {
+        let mut bs = Vec::new();
+        for s in &["a", "bc", ""] {
+            crate::to_writer(&mut bs, s).unwrap();
+        }
+
+        let mut stream = Deserializer::new(bs.as_slice()).into_iter::<String>();
+        assert_eq!(stream.next().unwrap().unwrap(), "a");
+        assert_eq!(stream.byte_offset(), 2);
+        let rest: Result<Vec<_>, _> = stream.collect();
+        assert_eq!(rest.unwrap(), vec!["bc".to_owned(), String::new()]);
+
+        assert_eq!(Deserializer::new(&[][..]).into_iter::<u8>().count(), 0);
+
+        // Ends in the middle of the second value.
+        bs.truncate(4);
+        let mut stream = Deserializer::new(bs.as_slice()).into_iter::<String>();
+        assert_eq!(stream.next().unwrap().unwrap(), "a");
+        let e = stream.next().unwrap().unwrap_err();
+        assert_eq!(e.offset(), Some(3));
+        assert!(stream.next().is_none());
+
+        // A multi-byte value ending in the middle isn't mistaken for the end.
+        let bs = [1u8, 0, 0, 0, 0, 0, 0, 0, 2, 0];
+        let v: Vec<_> = Deserializer::new(&bs[..]).into_iter::<f64>().collect();
+        assert_eq!(v.len(), 2);
+        assert!(v[0].is_ok());
+        assert!(v[1].is_err());
+    }
+
+    #[test]
+    fn peek_next_byte() {
+        let bs = [1u8, b'a', 0x80, 0x80];
+        let mut d = Deserializer::new(&bs[..]);
+        assert!(d.has_remaining().unwrap());
+        assert_eq!(d.peek().unwrap(), Some(1));
+        assert_eq!(d.position(), 0);
+        assert_eq!(d.remaining(), 4);
+
+        let s: String = de::Deserialize::deserialize(&mut d).unwrap();
+        assert_eq!(s, "a");
+        assert_eq!(d.peek().unwrap(), Some(0x80));
+        assert_eq!(d.peek().unwrap(), Some(0x80));
+        let v: u64 = de::Deserialize::deserialize(&mut d).unwrap();
+        assert_eq!(v, 128);
+        assert_eq!(d.position(), 4);
+
+        assert!(!d.has_remaining().unwrap());
+        assert_eq!(d.peek().unwrap(), None);
+    }
+
+    #[test]
+    fn deserialize_seed() {
+        use std::marker::PhantomData;
+
+        /// Interns decoded strings into a table, returning their indexes.
+        struct Intern<'a>(&'a mut Vec<String>);
+
+        impl<'de, 'a> DeserializeSeed<'de> for Intern<'a> {
+            type Value = usize;
+
+            fn deserialize<D: de::Deserializer<'de>>(self, d: D) -> Result<usize, D::Error> {
+                let s: String = de::Deserialize::deserialize(d)?;
+                match self.0.iter().position(|t| *t == s) {
+                    Some(i) => Ok(i),
+                    None => {
+                        self.0.push(s);
+                        Ok(self.0.len() - 1)
+                    }
+                }
+            }
+        }
+
+        let mut table = Vec::new();
+        let mut r = &[1u8, b'a', 1, b'b', 1, b'a'][..];
+        for &expected in &[0, 1, 0] {
+            let i = from_reader_seed(&mut r, Intern(&mut table)).unwrap();
+            assert_eq!(i, expected);
+        }
+        assert_eq!(table, vec!["a".to_owned(), "b".to_owned()]);
+
+        let v: u16 = from_slice_seed(&[0x81, 0x2C], PhantomData).unwrap();
+        assert_eq!(v, 300);
+        let e = from_slice_seed(&[1u8], Intern(&mut table)).unwrap_err();
+        assert_eq!(e.offset(), Some(1));
+    }
+
+    #[test]
+    fn deserialize_non_finite() {
+        let bs = std::f64::NEG_INFINITY.to_le_bytes();
+        let v: f64 = from_reader(&bs[..]).unwrap();
+        assert!(v.is_infinite());
+
+        let mut de = Deserializer::new(&bs[..]).reject_non_finite();
+        match <f64 as de::Deserialize>::deserialize(&mut de) {
+            Err(Error::NonFiniteFloat(v, _)) => assert!(v.is_infinite()),
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        let bs = std::f32::NAN.to_le_bytes();
+        let mut de = Deserializer::new(&bs[..]).reject_non_finite();
+        let _ = <f32 as de::Deserialize>::deserialize(&mut de).unwrap_err();
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Counters {
+        small: u16,
+        count: u32,
+        delta: i32,
+    }
+
+    fn counters_overflowed() -> Vec<u8> {
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, 70000).unwrap();
+        encode_u64(&mut bs, 0x1_0000_0005).unwrap();
+        encode_u64(&mut bs, 2 * 0x8000_0000 + 1).unwrap(); // -2^31 - 1
+        bs
+    }
+
+    #[test]
+    fn deserialize_narrowing_error() {
+        let bs = counters_overflowed();
+        let _ = from_reader::<_, Counters>(&bs[..]).unwrap_err();
+    }
+
+    #[test]
+    fn deserialize_narrowing_saturate() {
+        let bs = counters_overflowed();
+        let mut de = Deserializer::new(&bs[..]).narrowing(Narrowing::Saturate);
+        let v = <Counters as de::Deserialize>::deserialize(&mut de).unwrap();
+        assert_eq!(
+            v,
+            Counters {
+                small: u16::max_value(),
+                count: u32::max_value(),
+                delta: i32::min_value(),
+            }
+        );
+
+        let narrowed = de.narrowed();
+        assert_eq!(narrowed.len(), 3);
+        assert_eq!(narrowed[0].field, Some("small"));
+        assert_eq!(narrowed[0].target, "u16");
+        assert_eq!(narrowed[0].value, 70000);
+        assert_eq!(narrowed[1].field, Some("count"));
+        assert_eq!(narrowed[1].value, 0x1_0000_0005);
+        assert_eq!(narrowed[2].field, Some("delta"));
+        assert_eq!(narrowed[2].value, -0x8000_0001);
+        assert_eq!(narrowed[2].narrowed, -0x8000_0000);
+    }
+
+    #[test]
+    fn deserialize_narrowing_wrap() {
+        let bs = counters_overflowed();
+        let mut de = Deserializer::new(&bs[..]).narrowing(Narrowing::Wrap);
+        let v = <Counters as de::Deserialize>::deserialize(&mut de).unwrap();
+        assert_eq!(
+            v,
+            Counters {
+                small: 70000u32 as u16,
+                count: 5,
+                delta: i32::max_value(),
+            }
+        );
+        assert_eq!(de.narrowed().len(), 3);
+    }
+
+    #[test]
+    fn deserialize_narrowing_in_range() {
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, 7).unwrap();
+        let mut de = Deserializer::new(&bs[..]).narrowing(Narrowing::Wrap);
+        assert_eq!(<u16 as de::Deserialize>::deserialize(&mut de).unwrap(), 7);
+        assert!(de.narrowed().is_empty());
+    }
+
     #[test]
     fn deserialize_char_a() {
         let bs = [0x41, 0x00, 0x00]; // A
@@ -737,6 +2535,50 @@ mod test {
         assert_eq!(v, to_be);
     }
 
+    #[test]
+    fn deserialize_str_in_place() {
+        let to_be = "sample例";
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, to_be.len() as u64).unwrap();
+        bs.extend(to_be.as_bytes().iter());
+
+        let mut v = String::with_capacity(64);
+        v.push_str("previous value");
+        let ptr = v.as_ptr();
+
+        from_reader_in_place(bs.as_slice(), &mut v).unwrap();
+        assert_eq!(&v, to_be);
+        assert_eq!(v.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn deserialize_string_owned() {
+        struct OwnedVisitor;
+
+        impl<'de> Visitor<'de> for OwnedVisitor {
+            type Value = bool;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("string")
+            }
+
+            fn visit_str<E: de::Error>(self, _v: &str) -> Result<bool, E> {
+                Ok(false)
+            }
+
+            fn visit_string<E: de::Error>(self, _v: String) -> Result<bool, E> {
+                Ok(true)
+            }
+        }
+
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, 6).unwrap();
+        bs.extend(b"sample");
+        let mut deserializer = Deserializer::new(bs.as_slice());
+        let owned = de::Deserializer::deserialize_string(&mut deserializer, OwnedVisitor).unwrap();
+        assert!(owned);
+    }
+
     #[test]
     fn deserialize_option_none_u8() {
         let bs = [0u8];
@@ -785,6 +2627,26 @@ mod test {
         assert_eq!(v, vec![1, 2, 3]);
     }
 
+    #[test]
+    fn deserialize_vec_in_place() {
+        let bs = [3u8, 1, 2, 3];
+
+        let mut v = Vec::<u8>::with_capacity(16);
+        v.extend(&[9, 9, 9, 9, 9]);
+        let ptr = v.as_ptr();
+
+        from_reader_in_place(&bs[..], &mut v).unwrap();
+        assert_eq!(v, vec![1, 2, 3]);
+        assert_eq!(v.as_ptr(), ptr);
+
+        // Decoding messages in a loop keeps the first allocation.
+        for msg in &[[2u8, 7, 8], [1, 5, 0]] {
+            from_slice_in_place(&msg[..], &mut v).unwrap();
+            assert_eq!(v.as_ptr(), ptr);
+        }
+        assert_eq!(v, vec![5]);
+    }
+
     #[test]
     fn deserialize_hashset() {
         let bs = [3u8, 1, 2, 3];
@@ -859,6 +2721,31 @@ mod test {
         assert_eq!(v.score, 97.3f32);
     }
 
+    #[test]
+    fn deserialize_struct_in_place() {
+        let actual_name = "岸田　宏";
+
+        let mut bs = Vec::<u8>::new();
+
+        encode_u64(&mut bs, 123).unwrap();
+        encode_u64(&mut bs, actual_name.len() as u64).unwrap();
+        bs.extend(actual_name.as_bytes());
+        bs.extend(&97.3f32.to_le_bytes()[..]);
+
+        let mut v = BasicStruct {
+            id: 0,
+            name: String::with_capacity(64),
+            score: 0.0,
+        };
+        let ptr = v.name.as_ptr();
+
+        from_reader_in_place(&bs[..], &mut v).unwrap();
+        assert_eq!(v.id, 123);
+        assert_eq!(&v.name, actual_name);
+        assert_eq!(v.score, 97.3f32);
+        assert_eq!(v.name.as_ptr(), ptr);
+    }
+
     #[derive(Debug, PartialEq, Deserialize)]
     enum BasicEnum {
         UnitA,
@@ -899,4 +2786,396 @@ mod test {
         let v: BasicEnum = from_reader(&bs[..]).unwrap();
         assert_eq!(v, BasicEnum::Tuple(0x1234, "Abe".to_owned()));
     }
+
+    #[test]
+    fn mark_and_rewind() {
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, 3).unwrap();
+        bs.extend(b"abc");
+
+        let mut deserializer = Deserializer::new(std::io::Cursor::new(&bs[..]));
+        let mark = deserializer.mark().unwrap();
+        assert_eq!(mark.position(), 0);
+
+        let _ = <(u8, bool) as de::Deserialize>::deserialize(&mut deserializer).unwrap_err();
+        deserializer.rewind(mark).unwrap();
+        let v: String = de::Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(v, "abc");
+        assert_eq!(deserializer.mark().unwrap().position(), 4);
+    }
+
+    #[test]
+    fn remaining() {
+        let mut bs = vec![1u8, 2, b'a', b'b'];
+        bs.extend(b"next");
+
+        let mut deserializer = Deserializer::new(&bs[..]);
+        assert_eq!(deserializer.remaining(), 8);
+        let v: (u8, String) = de::Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(v, (1, "ab".to_owned()));
+        assert_eq!(deserializer.remaining(), 4);
+        assert_eq!(deserializer.into_inner(), b"next");
+
+        let mut deserializer = Deserializer::new(std::io::Cursor::new(&bs[..]));
+        let _: u8 = de::Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(deserializer.remaining(), 7);
+        assert_eq!(deserializer.get_ref().position(), 1);
+
+        deserializer.get_mut().set_position(0);
+        let v: u8 = de::Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(v, 1);
+    }
+
+    #[test]
+    fn length_limits() {
+        let limits = Limits::default().max_string_len(3).max_seq_len(2);
+
+        let bs = crate::to_vec(("abc", vec![1u8, 2])).unwrap();
+        let mut d = Deserializer::new(bs.as_slice()).limits(limits);
+        let v: (String, Vec<u8>) = de::Deserialize::deserialize(&mut d).unwrap();
+        assert_eq!(v, ("abc".to_owned(), vec![1, 2]));
+
+        let bs = crate::to_vec("abcd").unwrap();
+        let mut d = Deserializer::new(bs.as_slice()).limits(limits);
+        let e = <String as de::Deserialize>::deserialize(&mut d).unwrap_err();
+        assert_eq!(e.code(), 5);
+        assert_eq!(d.position(), 1);
+
+        let mut map = HashMap::new();
+        for i in 0..3u8 {
+            map.insert(i, i);
+        }
+        let bs = crate::to_vec(&map).unwrap();
+        let mut d = Deserializer::new(bs.as_slice()).limits(limits);
+        <HashMap<u8, u8> as de::Deserialize>::deserialize(&mut d).unwrap_err();
+
+        // A huge length in a few bytes fails without allocating.
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, 1 << 40).unwrap();
+        let mut d = Deserializer::new(bs.as_slice()).limits(Limits::untrusted());
+        <String as de::Deserialize>::deserialize(&mut d).unwrap_err();
+        assert_eq!(d.position(), bs.len() as u64);
+    }
+
+    #[test]
+    fn canonical() {
+        fn canonical<T: DeserializeOwned>(bs: &[u8]) -> Result<T, Error> {
+            de::Deserialize::deserialize(&mut Deserializer::new(bs).canonical())
+        }
+
+        // 300 in the shortest form, and in 3 bytes.
+        canonical::<u32>(&[0x81, 0x2C]).unwrap();
+        assert_eq!(from_reader::<_, u32>(&[0xC0, 0x01, 0x2C][..]).unwrap(), 300);
+        let _ = canonical::<u32>(&[0xC0, 0x01, 0x2C]).unwrap_err();
+        let _ = canonical::<u64>(&[0xFF, 0, 0, 0, 0, 0, 0, 0x01, 0x2C]).unwrap_err();
+        let _ = canonical::<i128>(&[0x80, 0x01]).unwrap_err();
+        let _ = canonical::<String>(&[0x80, 0x01, b'a']).unwrap_err();
+
+        let mut map = HashMap::new();
+        for i in 0..100u32 {
+            map.insert(i * 7, vec![i; 2]);
+        }
+        let mut bs = Vec::new();
+        serde::Serialize::serialize(&map, &mut crate::ser::Serializer::new(&mut bs).canonical())
+            .unwrap();
+        assert_eq!(canonical::<HashMap<u32, Vec<u32>>>(&bs).unwrap(), map);
+
+        // Keys containing maps.
+        let nested: BTreeMap<BTreeMap<u8, u8>, u8> = (0..10u8)
+            .map(|i| ((0..i).map(|j| (j, i)).collect(), i))
+            .collect();
+        let mut bs = Vec::new();
+        serde::Serialize::serialize(
+            &nested,
+            &mut crate::ser::Serializer::new(&mut bs).canonical(),
+        )
+        .unwrap();
+        assert_eq!(
+            canonical::<BTreeMap<BTreeMap<u8, u8>, u8>>(&bs).unwrap(),
+            nested
+        );
+
+        // Unsorted and duplicate keys.
+        let _ = canonical::<HashMap<u8, u8>>(&[2, 2, 0, 1, 0]).unwrap_err();
+        let _ = canonical::<HashMap<u8, u8>>(&[2, 1, 0, 1, 0]).unwrap_err();
+        canonical::<HashMap<u8, u8>>(&[2, 1, 0, 2, 0]).unwrap();
+        assert_eq!(
+            from_reader::<_, HashMap<u8, u8>>(&[2, 2, 0, 1, 0][..])
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn alloc_budget() {
+        let limits = Limits::default().alloc_budget(10);
+        let v = vec!["abc".to_owned(), "def".to_owned()];
+
+        // 2 elements and 6 bytes of strings.
+        let bs = crate::to_vec(&v).unwrap();
+        let mut d = Deserializer::new(bs.as_slice()).limits(limits);
+        let d: Vec<String> = de::Deserialize::deserialize(&mut d).unwrap();
+        assert_eq!(d, v);
+
+        let bs = crate::to_vec((&v, "ab", "c")).unwrap();
+        let mut d = Deserializer::new(bs.as_slice()).limits(limits);
+        let e =
+            <(Vec<String>, String, String) as de::Deserialize>::deserialize(&mut d).unwrap_err();
+        match e {
+            Error::BudgetExceeded(10, _) => {}
+            e => panic!("unexpected: {:?}", e),
+        }
+        assert_eq!(d.position(), 13);
+
+        // The budget is for each value of a stream.
+        let mut bs = Vec::new();
+        for _ in 0..3 {
+            crate::to_writer(&mut bs, &v).unwrap();
+        }
+        let d = Deserializer::new(bs.as_slice()).limits(limits);
+        assert_eq!(
+            d.into_iter::<Vec<String>>().filter(Result::is_ok).count(),
+            3
+        );
+    }
+
+    #[test]
+    fn eof_and_io_error() {
+        let e = from_slice::<u32>(&[]).unwrap_err();
+        assert!(e.is_eof());
+        assert_eq!(e.kind(), ErrorKind::Eof);
+        let e = io::Error::from(e);
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(e.to_string(), "unexpected end of input at byte 0");
+
+        let e = from_slice::<bool>(&[2]).unwrap_err();
+        assert!(!e.is_eof());
+        assert_eq!(e.kind(), ErrorKind::Serde);
+        assert_eq!(io::Error::from(e).kind(), io::ErrorKind::InvalidData);
+
+        let e = Error::from(io::Error::from(io::ErrorKind::BrokenPipe)).at(4);
+        assert_eq!(e.kind(), ErrorKind::Io);
+        assert_eq!(io::Error::from(e).kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(
+            io::Error::from(Error::IO(io::ErrorKind::Other.into(), Offset::UNKNOWN)).to_string(),
+            io::Error::from(io::ErrorKind::Other).to_string()
+        );
+    }
+
+    #[test]
+    fn error_offset() {
+        let e = from_slice::<(u8, String)>(&[1, 3, b'a']).unwrap_err();
+        assert_eq!(e.offset(), Some(2));
+        assert_eq!(e.code(), 7);
+        assert_eq!(e.to_string(), "unexpected end of input at byte 2");
+
+        let e = from_slice::<(u16, bool)>(&[0x81, 0x2C, 9]).unwrap_err();
+        assert_eq!(e.offset(), Some(3));
+        assert_eq!(e.code(), 5);
+
+        let mut deserializer = Deserializer::new(std::io::Cursor::new(vec![1u8, 2, 3]));
+        let mark = deserializer.mark().unwrap();
+        let _: (u8, u8) = de::Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(deserializer.position(), 2);
+        deserializer.rewind(mark).unwrap();
+        assert_eq!(deserializer.position(), 0);
+    }
+
+    #[test]
+    fn rewind_discards_narrowed() {
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, 70000).unwrap();
+
+        let mut deserializer =
+            Deserializer::new(std::io::Cursor::new(&bs[..])).narrowing(Narrowing::Saturate);
+        let mark = deserializer.mark().unwrap();
+        let v: u16 = de::Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(v, u16::max_value());
+        assert_eq!(deserializer.narrowed().len(), 1);
+
+        deserializer.rewind(mark).unwrap();
+        let v: u32 = de::Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(v, 70000);
+        assert!(deserializer.narrowed().is_empty());
+    }
+
+    #[test]
+    fn try_decode_any_candidates() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Pair(u8, u8);
+
+        #[derive(Deserialize)]
+        struct Single(u8);
+
+        impl From<Single> for Pair {
+            fn from(v: Single) -> Pair {
+                Pair(v.0, 0)
+            }
+        }
+
+        let v = try_decode_any::<(Pair, Single)>(&[1, 2]).unwrap();
+        assert_eq!(v, Pair(1, 2));
+        let v = try_decode_any::<(Pair, Single)>(&[3]).unwrap();
+        assert_eq!(v, Pair(3, 0));
+
+        // Every candidate leaves trailing bytes.
+        let e = try_decode_any::<(Pair, Single)>(&[1, 2, 3]).unwrap_err();
+        assert_eq!(e.code(), 5);
+    }
+
+    #[test]
+    fn decode_variant_by_index() {
+        let bs = [4u8, b'b', b'i', b'i', b'm', 0xAA];
+        let mut r = &bs[..];
+        let v: BasicEnum = decode_variant(&mut r, 2).unwrap();
+        assert_eq!(v, BasicEnum::Newtype("biim".to_owned()));
+        assert_eq!(r, &[0xAA]);
+
+        let v: BasicEnum = decode_variant(&[][..], 1).unwrap();
+        assert_eq!(v, BasicEnum::UnitB);
+        let _ = decode_variant::<_, BasicEnum>(&[][..], 9).unwrap_err();
+    }
+
+    #[derive(Debug, serde_derive::Serialize, Deserialize)]
+    enum Tree {
+        Leaf(String),
+        Node(Vec<Tree>),
+        Labeled(HashMap<u16, Box<Tree>>),
+        Maybe(Option<Box<Tree>>),
+    }
+
+    #[test]
+    fn huge_lengths() {
+        let mut bs = vec![0xFFu8; 9];
+        bs.extend_from_slice(b"abc");
+
+        let _ = from_slice::<String>(&bs).unwrap_err();
+        let _ = from_slice::<Vec<u32>>(&bs).unwrap_err();
+        let _ = from_slice::<HashMap<u8, u8>>(&bs).unwrap_err();
+        let _ = from_slice::<Tree>(&bs).unwrap_err();
+    }
+
+    #[test]
+    fn variant_index_overflow() {
+        let bs = [0xFFu8, 0, 0, 0, 1, 0, 0, 0, 0];
+        let _ = from_slice::<BasicEnum>(&bs).unwrap_err();
+    }
+
+    #[test]
+    fn recursion_limit() {
+        // Tree::Maybe(Some(Tree::Maybe(Some(...)))) nested far deeper than the stack allows.
+        let mut bs = Vec::new();
+        for _ in 0..1_000_000 {
+            bs.extend_from_slice(&[3, 1]);
+        }
+        bs.extend_from_slice(&[0, 0]);
+        let e = from_slice::<Tree>(&bs).unwrap_err();
+        match e {
+            Error::DepthLimitExceeded(DEFAULT_MAX_DEPTH, _) => {}
+            e => panic!("unexpected: {:?}", e),
+        }
+
+        let bs = [3u8, 1, 3, 1, 0, 0];
+        let _ = from_slice::<Tree>(&bs).unwrap();
+        let mut de = Deserializer::new(&bs[..]).max_depth(4);
+        let e = <Tree as de::Deserialize>::deserialize(&mut de).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::DepthLimitExceeded);
+        assert_eq!(e.to_string(), "nesting deeper than 4 levels");
+    }
+
+    #[test]
+    fn corrupt_input_never_panics() {
+        use std::io::Cursor;
+
+        use crate::generator::{Generator, Lengths};
+        use crate::journal::{LogReader, LogWriter, ReverseLogReader};
+        use crate::patch::{merge_from_reader, to_writer_patch};
+        use crate::timeseries::{PlainCodec, TimeSeriesReader, TimeSeriesWriter};
+        use crate::validate::Validator;
+
+        #[derive(serde_derive::Serialize, Deserialize)]
+        struct Patched {
+            name: String,
+            values: Vec<u64>,
+            tree: Option<Tree>,
+        }
+
+        let mut gen = Generator::new(1)
+            .lengths(Lengths::Uniform { min: 0, max: 8 })
+            .max_depth(6);
+        let mut x = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        let corrupt = |bs: &mut Vec<u8>, next: &mut dyn FnMut() -> u64| match next() % 3 {
+            0 => {
+                let i = next() as usize % bs.len();
+                bs[i] = next() as u8;
+            }
+            1 => {
+                let len = next() as usize % bs.len();
+                bs.truncate(len);
+            }
+            _ => {
+                let i = next() as usize % bs.len();
+                bs.insert(i, 0xFF);
+            }
+        };
+
+        let validator = Validator::new::<(String, Vec<u64>, Option<char>, f32)>().unwrap();
+        for _ in 0..2000 {
+            let mut bs = gen.payload::<Tree>().unwrap();
+            corrupt(&mut bs, &mut next);
+            let _ = from_slice::<Tree>(&bs);
+            let _ = from_slice::<(String, Vec<u64>, Option<char>, f32)>(&bs);
+            let _ = validator.validate(&bs[..]);
+        }
+
+        for _ in 0..200 {
+            let trees: Vec<Tree> = (0..3).map(|_| gen.value().unwrap()).collect();
+
+            let mut w = LogWriter::with_back_pointers(Vec::new());
+            for t in &trees {
+                w.append(t).unwrap();
+            }
+            let mut bs = w.into_inner();
+            corrupt(&mut bs, &mut next);
+            let mut r = LogReader::with_back_pointers(&bs[..]);
+            while let Ok(Some(_)) = r.read_record::<Tree>() {}
+            if let Ok(mut r) = ReverseLogReader::new(Cursor::new(&bs)) {
+                while let Ok(Some(_)) = r.read_prev::<Tree>() {}
+            }
+
+            let mut w = TimeSeriesWriter::with_block_len(Vec::new(), PlainCodec::new(), 2);
+            for (i, t) in trees.iter().enumerate() {
+                w.append(i as i64, t).unwrap();
+            }
+            let mut bs = w.into_inner().unwrap();
+            corrupt(&mut bs, &mut next);
+            let mut r = TimeSeriesReader::new(Cursor::new(&bs), PlainCodec::<Tree>::new());
+            let _ = r.read_all();
+            let _ = r.range(1..2);
+
+            let base = Patched {
+                name: "base".to_owned(),
+                values: vec![1, 2],
+                tree: None,
+            };
+            let mut value = Patched {
+                name: "value".to_owned(),
+                values: vec![3],
+                tree: None,
+            };
+            value.tree = trees.into_iter().next();
+            let mut bs = Vec::new();
+            to_writer_patch(&mut bs, &base, &value).unwrap();
+            corrupt(&mut bs, &mut next);
+            let mut place = base;
+            let _ = merge_from_reader(&bs[..], &mut place);
+        }
+    }
 }
@@ -0,0 +1,295 @@
+//! Decoding from network streams with a deadline.
+//!
+//! Decoding from a socket blocks until the whole value arrives, so a peer stalling in the middle
+//! of a message hangs the reader forever. Functions of this module give up at a deadline
+//! with [`Error::Timeout`](../de/enum.Error.html#variant.Timeout).
+//!
+//! After a timeout, the stream is in the middle of a value, so it should be closed.
+//!
+//! ```no_run
+//! use std::net::TcpStream;
+//! use std::time::{Duration, Instant};
+//!
+//! use serde_dokechi::deadline::from_reader_deadline;
+//!
+//! let mut stream = TcpStream::connect("127.0.0.1:4000").unwrap();
+//! let deadline = Instant::now() + Duration::from_secs(5);
+//! let message: String = from_reader_deadline(&mut stream, deadline).unwrap();
+//! ```
+
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+
+use crate::de::{from_reader, Error};
+#[cfg(feature = "tokio")]
+use crate::de::{Deserializer, Limits};
+#[cfg(feature = "tokio")]
+use crate::error::Offset;
+
+/// A stream with a timeout of reads.
+pub trait ReadTimeout: Read {
+    /// The current read timeout.
+    fn read_timeout(&self) -> io::Result<Option<Duration>>;
+
+    /// Set the read timeout. `None` means reads block forever.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        TcpStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl ReadTimeout for std::os::unix::net::UnixStream {
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        std::os::unix::net::UnixStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Deserialize a value from `s`, failing with `Error::Timeout` if it doesn't complete by `deadline`.
+///
+/// The read timeout of `s` is set for each read, and restored when this returns.
+pub fn from_reader_deadline<S, T>(s: &mut S, deadline: Instant) -> Result<T, Error>
+where
+    S: ReadTimeout,
+    T: DeserializeOwned,
+{
+    let saved = s.read_timeout()?;
+    let res = from_reader(Deadline {
+        s: &mut *s,
+        deadline,
+    });
+    s.set_read_timeout(saved)?;
+
+    if let Err(Error::IO(e, offset)) = &res {
+        if e.kind() == io::ErrorKind::TimedOut {
+            return Err(Error::Timeout(*offset));
+        }
+    }
+    res
+}
+
+struct Deadline<'a, S: ReadTimeout> {
+    s: &'a mut S,
+    deadline: Instant,
+}
+
+impl<'a, S: ReadTimeout> Read for Deadline<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        self.s.set_read_timeout(Some(self.deadline - now))?;
+
+        match self.s.read(buf) {
+            // Unix platforms report timeouts as `WouldBlock`.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                Err(io::Error::from(io::ErrorKind::TimedOut))
+            }
+            res => res,
+        }
+    }
+}
+
+/// Deserialize a value from an async stream, failing with `Error::Timeout` if it doesn't complete by `deadline`.
+///
+/// Only the bytes of the value are consumed from `r`, so following values can be read.
+/// As the length of the value is unknown until it is decoded, decoding is retried each time
+/// more bytes arrive. Wrap `r` in a `BufReader` large enough for most values.
+///
+/// The value is decoded with [`Limits::untrusted`](../de/struct.Limits.html#method.untrusted),
+/// and bytes buffered for it are limited to the allocation budget.
+///
+/// This function is available with the `tokio` feature.
+#[cfg(feature = "tokio")]
+pub async fn from_async_reader_deadline<R, T>(
+    r: &mut R,
+    deadline: tokio::time::Instant,
+) -> Result<T, Error>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    T: DeserializeOwned,
+{
+    from_async_reader_deadline_with_limits(r, deadline, Limits::untrusted()).await
+}
+
+/// Deserialize a value from an async stream with `limits`, failing with `Error::Timeout` if it doesn't complete by `deadline`.
+///
+/// Bytes buffered for the value are limited to the allocation budget of `limits`.
+///
+/// This function is available with the `tokio` feature.
+#[cfg(feature = "tokio")]
+pub async fn from_async_reader_deadline_with_limits<R, T>(
+    r: &mut R,
+    deadline: tokio::time::Instant,
+    limits: Limits,
+) -> Result<T, Error>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    T: DeserializeOwned,
+{
+    match tokio::time::timeout_at(deadline, read_value(r, limits)).await {
+        Ok(res) => res,
+        Err(_) => Err(Error::Timeout(Offset::UNKNOWN)),
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn read_value<R, T>(r: &mut R, limits: Limits) -> Result<T, Error>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    T: DeserializeOwned,
+{
+    use tokio::io::AsyncBufReadExt;
+
+    // Bytes of the value so far: those consumed from `r` by failed attempts,
+    // followed by the bytes available in `r` for the current attempt.
+    let mut buf = Vec::new();
+    loop {
+        let available = r.fill_buf().await?;
+        if available.is_empty() {
+            return Err(Error::Eof(Offset::new(buf.len() as u64)));
+        }
+        if buf.len() >= limits.alloc_budget {
+            return Err(Error::BudgetExceeded(
+                limits.alloc_budget,
+                Offset::new(buf.len() as u64),
+            ));
+        }
+
+        let consumed = buf.len();
+        let n = available.len().min(limits.alloc_budget - consumed);
+        buf.extend_from_slice(&available[..n]);
+        let mut de = Deserializer::new(buf.as_slice()).limits(limits);
+        match serde::Deserialize::deserialize(&mut de) {
+            Ok(v) => {
+                let used = buf.len() - de.remaining() - consumed;
+                r.consume(used);
+                return Ok(v);
+            }
+            Err(ref e) if e.is_eof() => r.consume(n),
+            Err(e) => return Err(e.at(de.position())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    use crate::to_vec;
+
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn complete_value() {
+        let (mut client, mut server) = pair();
+        client.write_all(&to_vec("hello").unwrap()).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let v: String = from_reader_deadline(&mut server, deadline).unwrap();
+        assert_eq!(v, "hello");
+        assert_eq!(server.read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn stalled_peer() {
+        let (mut client, mut server) = pair();
+        client.write_all(&[5, b'a', b'b']).unwrap();
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(100);
+        match from_reader_deadline::<_, String>(&mut server, deadline) {
+            Err(Error::Timeout(_)) => {}
+            r => panic!("unexpected: {:?}", r),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(server.read_timeout().unwrap(), None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_deadline() {
+        use tokio::io::{AsyncWriteExt, BufReader};
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (mut client, server) = tokio::io::duplex(64);
+            let mut server = BufReader::with_capacity(4, server);
+            let deadline = tokio::time::Instant::now() + Duration::from_millis(100);
+
+            let mut bs = to_vec("first value").unwrap();
+            bs.extend(to_vec(7u8).unwrap());
+            bs.extend(&[5, b'a']);
+            client.write_all(&bs).await.unwrap();
+
+            let v: String = from_async_reader_deadline(&mut server, deadline)
+                .await
+                .unwrap();
+            assert_eq!(v, "first value");
+            let v: u8 = from_async_reader_deadline(&mut server, deadline)
+                .await
+                .unwrap();
+            assert_eq!(v, 7);
+
+            match from_async_reader_deadline::<_, String>(&mut server, deadline).await {
+                Err(Error::Timeout(_)) => {}
+                r => panic!("unexpected: {:?}", r),
+            }
+        });
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_buffer_limit() {
+        use tokio::io::{AsyncWriteExt, BufReader};
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (mut client, server) = tokio::io::duplex(64);
+            let mut server = BufReader::with_capacity(4, server);
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+
+            // A string claiming to be 200 bytes long, which never ends.
+            let mut bs = to_vec("x".repeat(200)).unwrap();
+            bs.truncate(40);
+            client.write_all(&bs).await.unwrap();
+
+            let limits = Limits::default().alloc_budget(16);
+            match from_async_reader_deadline_with_limits::<_, String>(&mut server, deadline, limits)
+                .await
+            {
+                Err(Error::BudgetExceeded(16, _)) => {}
+                r => panic!("unexpected: {:?}", r),
+            }
+        });
+    }
+}
@@ -0,0 +1,342 @@
+//! Deduplication of repeated nested values.
+//!
+//! Values wrapped in [`Shared`](struct.Shared.html) are compared by their encoded form.
+//! The first occurrence is written inline, and repeats are written as references to it.
+//! References are resolved within one message serialized with [`to_writer`](fn.to_writer.html),
+//! or across messages of a [`Session`](struct.Session.html).
+//! Outside of them, every `Shared` value is written inline.
+//!
+//! `Shared` value format:
+//!
+//! ```text
+//! 0 (varuint) , encoded value length (varuint) , encoded value
+//! reference (varuint)
+//! ```
+//!
+//! A reference `n` points to the `n`-th distinct value, counted from 1.
+//!
+//! ```
+//! use serde_dokechi::dedup::{self, Shared};
+//!
+//! let block = Shared(vec!["identical component"; 10]);
+//! let scene = vec![block.clone(), block.clone(), block];
+//!
+//! let mut bs = Vec::new();
+//! dedup::to_writer(&mut bs, &scene).unwrap();
+//! assert_eq!(bs.len(), 1 + (1 + 2 + 201) + 1 + 1);
+//!
+//! let decoded: Vec<Shared<Vec<String>>> = dedup::from_reader(bs.as_slice()).unwrap();
+//! assert_eq!(decoded.len(), 3);
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::ops::{Deref, DerefMut};
+
+use serde::de::{
+    self as serde_de, Deserialize, DeserializeOwned, Deserializer, SeqAccess, Visitor,
+};
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+use crate::de::{self, from_slice};
+use crate::ser::{self, to_vec};
+
+/// A value deduplicated by its encoded form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Shared<T>(pub T);
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Shared<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// Distinct encoded values in order of appearance.
+#[derive(Debug, Default)]
+struct Table {
+    values: Vec<Vec<u8>>,
+    index: HashMap<Vec<u8>, u64>,
+}
+
+impl Table {
+    fn push(&mut self, bs: Vec<u8>) {
+        self.values.push(bs.clone());
+        self.index.insert(bs, self.values.len() as u64);
+    }
+
+    fn truncate(&mut self, len: usize) {
+        for bs in self.values.drain(len..) {
+            self.index.remove(&bs);
+        }
+    }
+}
+
+thread_local! {
+    static TABLE: RefCell<Option<Table>> = RefCell::new(None);
+}
+
+fn with_table<R>(f: impl FnOnce(&mut Table) -> R) -> Option<R> {
+    TABLE.with(|t| t.borrow_mut().as_mut().map(f))
+}
+
+/// Run `f` with `table` as the current table, then take it back.
+fn scoped<R>(table: &mut Table, f: impl FnOnce() -> R) -> R {
+    let prev = TABLE.with(|t| t.replace(Some(std::mem::take(table))));
+    let result = f();
+    *table = TABLE.with(|t| t.replace(prev)).expect("dedup table exists");
+    result
+}
+
+/// Serialize `value` into the IO stream, deduplicating `Shared` values within it.
+pub fn to_writer<W: Write, T: Serialize>(w: W, value: &T) -> Result<(), ser::Error> {
+    Session::new().to_writer(w, value)
+}
+
+/// Deserialize a value written by [`to_writer`](fn.to_writer.html).
+pub fn from_reader<R: Read, T: DeserializeOwned>(r: R) -> Result<T, de::Error> {
+    Session::new().from_reader(r)
+}
+
+/// Deduplication state shared by a series of messages.
+///
+/// The writer and the reader must process the same messages in the same order.
+#[derive(Debug, Default)]
+pub struct Session {
+    table: Table,
+}
+
+impl Session {
+    /// Create new `Session`.
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Serialize `value` into the IO stream, referring values written before in this session.
+    pub fn to_writer<W: Write, T: Serialize>(&mut self, w: W, value: &T) -> Result<(), ser::Error> {
+        scoped(&mut self.table, || crate::ser::to_writer(w, value))
+    }
+
+    /// Deserialize a value, resolving references to values read before in this session.
+    pub fn from_reader<R: Read, T: DeserializeOwned>(&mut self, r: R) -> Result<T, de::Error> {
+        scoped(&mut self.table, || crate::de::from_reader(r))
+    }
+
+    /// The number of distinct values seen in this session.
+    pub fn len(&self) -> usize {
+        self.table.values.len()
+    }
+
+    /// Whether no values have been seen in this session.
+    pub fn is_empty(&self) -> bool {
+        self.table.values.is_empty()
+    }
+}
+
+struct RawBytes<'a>(&'a [u8]);
+
+impl<'a> Serialize for RawBytes<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl<T: Serialize> Serialize for Shared<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let before = with_table(|t| t.values.len());
+        let bs = to_vec(&self.0).map_err(serde::ser::Error::custom)?;
+
+        let reference = with_table(|t| match t.index.get(&bs) {
+            Some(&n) => {
+                // Values nested in a repeat are not written, so forget them.
+                t.truncate(before.expect("dedup table exists"));
+                Some(n)
+            }
+            None => {
+                t.push(bs.clone());
+                None
+            }
+        });
+
+        match reference {
+            Some(Some(n)) => {
+                let mut tuple = serializer.serialize_tuple(1)?;
+                tuple.serialize_element(&n)?;
+                tuple.end()
+            }
+            _ => {
+                let mut tuple = serializer.serialize_tuple(2)?;
+                tuple.serialize_element(&0u64)?;
+                tuple.serialize_element(&RawBytes(&bs))?;
+                tuple.end()
+            }
+        }
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ByteBuf, D::Error> {
+        struct ByteBufVisitor;
+
+        impl<'de> Visitor<'de> for ByteBufVisitor {
+            type Value = ByteBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("bytes")
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v))
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.to_vec()))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Shared<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Shared<T>, D::Error> {
+        struct SharedVisitor<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: DeserializeOwned> Visitor<'de> for SharedVisitor<T> {
+            type Value = Shared<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("shared value")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Shared<T>, A::Error> {
+                use serde_de::Error as _;
+
+                let n: u64 = seq
+                    .next_element()?
+                    .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+
+                if n == 0 {
+                    let bs: ByteBuf = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+                    let v = from_slice(&bs.0).map_err(A::Error::custom)?;
+                    with_table(|t| t.push(bs.0));
+                    return Ok(Shared(v));
+                }
+
+                let bs = with_table(|t| t.values.get(n as usize - 1).cloned())
+                    .ok_or_else(|| A::Error::custom("shared value reference outside of session"))?
+                    .ok_or_else(|| {
+                        A::Error::custom(format_args!("unknown shared value reference {}", n))
+                    })?;
+                let before = with_table(|t| t.values.len()).expect("dedup table exists");
+                let v = from_slice(&bs).map_err(A::Error::custom);
+                // Values nested in a repeat were not counted by the writer.
+                with_table(|t| t.truncate(before));
+                Ok(Shared(v?))
+            }
+        }
+
+        // The tuple length is not encoded, so the visitor reads only the elements present.
+        deserializer.deserialize_tuple(2, SharedVisitor(std::marker::PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Component {
+        name: String,
+        data: Vec<u32>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Node {
+        components: Vec<Shared<Component>>,
+    }
+
+    fn component(name: &str) -> Shared<Component> {
+        Shared(Component {
+            name: name.to_owned(),
+            data: (0..100).collect(),
+        })
+    }
+
+    #[test]
+    fn repeated_values_are_referenced() {
+        let nodes: Vec<Node> = (0..10)
+            .map(|i| Node {
+                components: vec![component("mesh"), component(&format!("node {}", i))],
+            })
+            .collect();
+
+        let mut plain = Vec::new();
+        crate::to_writer(&mut plain, &nodes).unwrap();
+        let mut bs = Vec::new();
+        to_writer(&mut bs, &nodes).unwrap();
+        assert!(bs.len() * 3 < plain.len() * 2);
+
+        let d: Vec<Node> = from_reader(bs.as_slice()).unwrap();
+        assert_eq!(d, nodes);
+    }
+
+    #[test]
+    fn nested_shared_values() {
+        let inner = Shared(vec![component("a"), component("a"), component("b")]);
+        let v = vec![inner.clone(), Shared(vec![component("b")]), inner];
+
+        let mut bs = Vec::new();
+        to_writer(&mut bs, &v).unwrap();
+
+        let d: Vec<Shared<Vec<Shared<Component>>>> = from_reader(bs.as_slice()).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn session_across_messages() {
+        let mut writer = Session::new();
+        let mut reader = Session::new();
+
+        for _ in 0..3 {
+            let mut bs = Vec::new();
+            writer.to_writer(&mut bs, &component("mesh")).unwrap();
+            let d: Shared<Component> = reader.from_reader(bs.as_slice()).unwrap();
+            assert_eq!(d, component("mesh"));
+        }
+        assert_eq!(writer.len(), 1);
+        assert_eq!(reader.len(), 1);
+    }
+
+    #[test]
+    fn outside_of_session() {
+        let v = vec![component("x"), component("x")];
+
+        let mut bs = Vec::new();
+        crate::to_writer(&mut bs, &v).unwrap();
+        let d: Vec<Shared<Component>> = crate::from_reader(bs.as_slice()).unwrap();
+        assert_eq!(d, v);
+
+        let _ = crate::from_reader::<_, Shared<Component>>(&[1u8][..]).unwrap_err();
+    }
+
+    #[test]
+    fn unknown_reference() {
+        let _ = from_reader::<_, Shared<u8>>(&[5u8][..]).unwrap_err();
+    }
+}
@@ -0,0 +1,115 @@
+//! Cheap estimation of how well serialized data compresses.
+//!
+//! The estimate combines byte entropy with the share of repeated 4-byte sequences,
+//! which approximates what general-purpose compressors exploit.
+//! It is a rough guide to decide whether compressing a class of messages is worth the CPU time,
+//! not a prediction of any particular compressor's output.
+
+use std::collections::HashSet;
+
+use serde::ser::Serialize;
+
+use crate::ser::{Error, Serializer};
+
+/// Inputs longer than this are estimated from evenly spaced sample blocks.
+const SAMPLE_LIMIT: usize = 64 * 1024;
+const SAMPLE_BLOCKS: usize = 16;
+
+/// Estimate the compression ratio of `value` serialized in Dokechi format.
+///
+/// See [`estimate_bytes_compressibility`](fn.estimate_bytes_compressibility.html).
+pub fn estimate_compressibility<T: Serialize>(value: &T) -> Result<f64, Error> {
+    let mut bs = Vec::new();
+    value.serialize(&mut Serializer::new(&mut bs))?;
+    Ok(estimate_bytes_compressibility(&bs))
+}
+
+/// Estimate the compression ratio (compressed size / original size) of `bs`.
+///
+/// The result is between 0.0 and 1.0. Values near 1.0 mean compression is unlikely to pay off.
+pub fn estimate_bytes_compressibility(bs: &[u8]) -> f64 {
+    if bs.is_empty() {
+        return 1.0;
+    }
+
+    if bs.len() <= SAMPLE_LIMIT {
+        return estimate_sample(bs);
+    }
+
+    let block_len = SAMPLE_LIMIT / SAMPLE_BLOCKS;
+    let stride = (bs.len() - block_len) / (SAMPLE_BLOCKS - 1);
+    let sample: Vec<u8> = (0..SAMPLE_BLOCKS)
+        .flat_map(|i| bs[i * stride..i * stride + block_len].iter().cloned())
+        .collect();
+    estimate_sample(&sample)
+}
+
+fn estimate_sample(bs: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in bs {
+        counts[b as usize] += 1;
+    }
+
+    let len = bs.len() as f64;
+    let entropy: f64 = counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum();
+
+    let novelty = if bs.len() >= 4 {
+        let grams: HashSet<&[u8]> = bs.windows(4).collect();
+        grams.len() as f64 / (bs.len() - 3) as f64
+    } else {
+        1.0
+    };
+
+    (entropy / 8.0 * novelty).max(0.0).min(1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(estimate_bytes_compressibility(&[]), 1.0);
+    }
+
+    #[test]
+    fn constant_bytes() {
+        assert!(estimate_bytes_compressibility(&[0u8; 1000]) < 0.01);
+    }
+
+    #[test]
+    fn random_bytes() {
+        assert!(estimate_bytes_compressibility(&noise(10000)) > 0.95);
+    }
+
+    #[test]
+    fn repeated_text() {
+        let v = vec!["repeated text".to_owned(); 100];
+        assert!(estimate_compressibility(&v).unwrap() < 0.1);
+    }
+
+    #[test]
+    fn large_input_is_sampled() {
+        let v = noise(1024 * 1024);
+        assert!(estimate_bytes_compressibility(&v) > 0.9);
+    }
+}
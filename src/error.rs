@@ -0,0 +1,512 @@
+use std::fmt::Display;
+use std::io;
+
+use serde::{de, ser};
+use thiserror::Error;
+
+/// An error of either serializing or deserializing.
+///
+/// Both [`ser::Error`](ser/enum.Error.html) and [`de::Error`](de/enum.Error.html) convert into this type,
+/// so functions doing both can use `?` on either.
+/// Errors of deserializing keep the [`Offset`](struct.Offset.html) they occurred at.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The underlying writer or reader returned IO error.
+    #[error("{0}{1}")]
+    IO(#[source] io::Error, Offset),
+    /// The input ended in the middle of a value.
+    #[error("unexpected end of input{0}")]
+    Eof(Offset),
+    /// Values are nested deeper than the limit.
+    #[error("nesting deeper than {0} levels{1}")]
+    DepthLimitExceeded(usize, Offset),
+    /// A value asks to allocate more than the budget in bytes.
+    #[error("allocation budget of {0} bytes exceeded{1}")]
+    BudgetExceeded(usize, Offset),
+    /// Sequence size is required.
+    #[error("input sequence has no size hint")]
+    NoSequenceSize,
+    /// Unsupported deseriazising operation called.
+    #[error("{0} is unsupported{1}")]
+    Unsupported(&'static str, Offset),
+    /// A non-finite float is written or read while it is rejected.
+    #[error("non-finite float {0} is rejected{1}")]
+    NonFiniteFloat(f64, Offset),
+    /// A complete value didn't arrive before the deadline.
+    #[error("deadline exceeded{0}")]
+    Timeout(Offset),
+    /// An error from serde framework.
+    #[cfg(not(feature = "compact-errors"))]
+    #[error("{0}{1}")]
+    Serde(String, Offset),
+    /// An error from serde framework.
+    #[cfg(feature = "compact-errors")]
+    #[error("{0}{1}")]
+    Serde(Message, Offset),
+}
+
+/// The offset in the input an error occurred at, if known.
+///
+/// This is kept in each variant of errors, instead of wrapping them,
+/// so errors can be matched directly and stay small without allocation.
+/// It is displayed as ` at byte N`, or nothing if unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Offset(u64);
+
+impl Offset {
+    /// The offset of errors not tied to a position in the input.
+    pub const UNKNOWN: Offset = Offset(u64::max_value());
+
+    /// The offset after `n` bytes read.
+    pub fn new(n: u64) -> Offset {
+        Offset(n)
+    }
+
+    /// The number of bytes read before the error, if known.
+    pub fn get(self) -> Option<u64> {
+        if self == Offset::UNKNOWN {
+            None
+        } else {
+            Some(self.0)
+        }
+    }
+}
+
+impl Default for Offset {
+    fn default() -> Offset {
+        Offset::UNKNOWN
+    }
+}
+
+impl Display for Offset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.get() {
+            Some(n) => write!(f, " at byte {}", n),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Offset {
+    fn format(&self, f: defmt::Formatter) {
+        if let Some(n) = self.get() {
+            defmt::write!(f, " at byte {=u64}", n);
+        }
+    }
+}
+
+/// The kind of an error, shared by [`Error`](enum.Error.html), [`ser::Error`](ser/enum.Error.html#method.kind)
+/// and [`de::Error`](de/enum.Error.html#method.kind).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The underlying writer or reader returned IO error.
+    Io,
+    /// The input ended in the middle of a value.
+    Eof,
+    /// Values are nested deeper than the limit.
+    DepthLimitExceeded,
+    /// A value asks to allocate more than the budget.
+    BudgetExceeded,
+    /// Sequence size is required.
+    NoSequenceSize,
+    /// Unsupported deseriazising operation called.
+    Unsupported,
+    /// A non-finite float is written or read while it is rejected.
+    NonFiniteFloat,
+    /// An error from serde framework, such as an invalid value in the input.
+    Serde,
+    /// A complete value didn't arrive before the deadline.
+    Timeout,
+}
+
+impl ErrorKind {
+    /// The kind of `io::Error` wrapping errors of this kind.
+    pub(crate) fn io_kind(self) -> io::ErrorKind {
+        match self {
+            ErrorKind::Io => io::ErrorKind::Other,
+            ErrorKind::Eof => io::ErrorKind::UnexpectedEof,
+            ErrorKind::NoSequenceSize | ErrorKind::Unsupported => io::ErrorKind::InvalidInput,
+            ErrorKind::NonFiniteFloat
+            | ErrorKind::Serde
+            | ErrorKind::DepthLimitExceeded
+            | ErrorKind::BudgetExceeded => io::ErrorKind::InvalidData,
+            ErrorKind::Timeout => io::ErrorKind::TimedOut,
+        }
+    }
+}
+
+impl Error {
+    /// A compact code of the error kind.
+    ///
+    /// Codes are stable, and shared with [`ser::Error`](ser/enum.Error.html#method.code)
+    /// and [`de::Error`](de/enum.Error.html#method.code):
+    ///
+    /// | code | kind |
+    /// |------|------|
+    /// | 1 | `IO` |
+    /// | 2 | `NoSequenceSize` |
+    /// | 3 | `Unsupported` |
+    /// | 4 | `NonFiniteFloat` |
+    /// | 5 | `Serde` |
+    /// | 6 | `Timeout` |
+    /// | 7 | `Eof` |
+    /// | 8 | `DepthLimitExceeded` |
+    /// | 9 | `BudgetExceeded` |
+    pub fn code(&self) -> u8 {
+        match self {
+            Error::IO(..) => 1,
+            Error::NoSequenceSize => 2,
+            Error::Unsupported(..) => 3,
+            Error::NonFiniteFloat(..) => 4,
+            Error::Serde(..) => 5,
+            Error::Timeout(_) => 6,
+            Error::Eof(_) => 7,
+            Error::DepthLimitExceeded(..) => 8,
+            Error::BudgetExceeded(..) => 9,
+        }
+    }
+
+    /// The kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::IO(..) => ErrorKind::Io,
+            Error::Eof(_) => ErrorKind::Eof,
+            Error::DepthLimitExceeded(..) => ErrorKind::DepthLimitExceeded,
+            Error::BudgetExceeded(..) => ErrorKind::BudgetExceeded,
+            Error::NoSequenceSize => ErrorKind::NoSequenceSize,
+            Error::Unsupported(..) => ErrorKind::Unsupported,
+            Error::NonFiniteFloat(..) => ErrorKind::NonFiniteFloat,
+            Error::Serde(..) => ErrorKind::Serde,
+            Error::Timeout(_) => ErrorKind::Timeout,
+        }
+    }
+
+    /// Whether the input ended in the middle of a value.
+    pub fn is_eof(&self) -> bool {
+        self.kind() == ErrorKind::Eof
+    }
+
+    /// The offset in the input the error occurred at, if known.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Error::IO(_, offset)
+            | Error::Eof(offset)
+            | Error::DepthLimitExceeded(_, offset)
+            | Error::BudgetExceeded(_, offset)
+            | Error::Unsupported(_, offset)
+            | Error::NonFiniteFloat(_, offset)
+            | Error::Timeout(offset)
+            | Error::Serde(_, offset) => offset.get(),
+            Error::NoSequenceSize => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    /// `UnexpectedEof` becomes [`Eof`](#variant.Eof).
+    fn from(e: io::Error) -> Error {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            Error::Eof(Offset::UNKNOWN)
+        } else {
+            Error::IO(e, Offset::UNKNOWN)
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    /// IO errors are unwrapped, and other errors are wrapped in `io::Error` of the matching kind.
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::IO(e, _) => e,
+            e => io::Error::new(e.kind().io_kind(), e),
+        }
+    }
+}
+
+impl From<crate::ser::Error> for Error {
+    fn from(e: crate::ser::Error) -> Error {
+        match e {
+            crate::ser::Error::IO(e) => Error::IO(e, Offset::UNKNOWN),
+            crate::ser::Error::NoSequenceSize => Error::NoSequenceSize,
+            crate::ser::Error::NonFiniteFloat(v) => Error::NonFiniteFloat(v, Offset::UNKNOWN),
+            crate::ser::Error::Serde(msg) => Error::Serde(msg, Offset::UNKNOWN),
+        }
+    }
+}
+
+impl From<crate::de::Error> for Error {
+    fn from(e: crate::de::Error) -> Error {
+        match e {
+            crate::de::Error::IO(e, offset) => Error::IO(e, offset),
+            crate::de::Error::Unsupported(op, offset) => Error::Unsupported(op, offset),
+            crate::de::Error::NonFiniteFloat(v, offset) => Error::NonFiniteFloat(v, offset),
+            crate::de::Error::Timeout(offset) => Error::Timeout(offset),
+            crate::de::Error::Eof(offset) => Error::Eof(offset),
+            crate::de::Error::DepthLimitExceeded(max, offset) => {
+                Error::DepthLimitExceeded(max, offset)
+            }
+            crate::de::Error::BudgetExceeded(max, offset) => Error::BudgetExceeded(max, offset),
+            crate::de::Error::Serde(msg, offset) => Error::Serde(msg, offset),
+        }
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::Serde(message(msg), Offset::UNKNOWN)
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::Serde(message(msg), Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn invalid_type(_unexp: de::Unexpected, _exp: &dyn de::Expected) -> Error {
+        Error::Serde(Message::InvalidType, Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn invalid_value(unexp: de::Unexpected, _exp: &dyn de::Expected) -> Error {
+        Error::Serde(Message::invalid_value(unexp), Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn invalid_length(len: usize, _exp: &dyn de::Expected) -> Error {
+        Error::Serde(Message::InvalidLength(len), Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn unknown_variant(_variant: &str, _expected: &'static [&'static str]) -> Error {
+        Error::Serde(Message::UnknownVariant, Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn unknown_field(_field: &str, _expected: &'static [&'static str]) -> Error {
+        Error::Serde(Message::UnknownField, Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn missing_field(field: &'static str) -> Error {
+        Error::Serde(Message::MissingField(field), Offset::UNKNOWN)
+    }
+
+    #[cfg(feature = "compact-errors")]
+    fn duplicate_field(field: &'static str) -> Error {
+        Error::Serde(Message::DuplicateField(field), Offset::UNKNOWN)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::IO(_, at) => defmt::write!(f, "E{=u8}: IO error{}", self.code(), at),
+            Error::NoSequenceSize => defmt::write!(f, "E{=u8}: no size hint", self.code()),
+            Error::Unsupported(op, at) => defmt::write!(f, "E{=u8}: {=str}{}", self.code(), op, at),
+            Error::NonFiniteFloat(v, at) => {
+                defmt::write!(f, "E{=u8}: {=f64}{}", self.code(), v, at)
+            }
+            Error::Timeout(at) => defmt::write!(f, "E{=u8}: deadline exceeded{}", self.code(), at),
+            Error::Eof(at) => {
+                defmt::write!(f, "E{=u8}: unexpected end of input{}", self.code(), at)
+            }
+            Error::DepthLimitExceeded(max, at) => {
+                defmt::write!(f, "E{=u8}: {=usize}{}", self.code(), max, at)
+            }
+            Error::BudgetExceeded(max, at) => {
+                defmt::write!(f, "E{=u8}: {=usize}{}", self.code(), max, at)
+            }
+            #[cfg(not(feature = "compact-errors"))]
+            Error::Serde(msg, at) => defmt::write!(f, "E{=u8}: {=str}{}", self.code(), msg, at),
+            #[cfg(feature = "compact-errors")]
+            Error::Serde(msg, at) => defmt::write!(f, "E{=u8}: {}{}", self.code(), msg, at),
+        }
+    }
+}
+
+/// A message of serde errors.
+#[cfg(not(feature = "compact-errors"))]
+pub(crate) type Message = String;
+
+/// Build a message of serde errors.
+#[cfg(not(feature = "compact-errors"))]
+pub(crate) fn message<T: Display>(msg: T) -> Message {
+    msg.to_string()
+}
+
+/// Build a message of serde errors, dropping `msg`.
+#[cfg(feature = "compact-errors")]
+pub(crate) fn message<T: Display>(_msg: T) -> Message {
+    Message::Custom
+}
+
+/// A message of serde errors without allocation, used with the `compact-errors` feature.
+///
+/// Messages given to `custom` are dropped. Only a code and a numeric context are kept,
+/// so no error allocates and the error types stay small.
+#[cfg(feature = "compact-errors")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Message {
+    /// A custom error, whose message is dropped.
+    Custom,
+    /// A value of unexpected type.
+    InvalidType,
+    /// A value of unexpected value, with the value if it is an integer.
+    InvalidValue(Option<u64>),
+    /// A sequence or map of unexpected length.
+    InvalidLength(usize),
+    /// An unknown enum variant.
+    UnknownVariant,
+    /// An unknown struct field.
+    UnknownField,
+    /// A missing struct field.
+    MissingField(&'static str),
+    /// A duplicate struct field.
+    DuplicateField(&'static str),
+}
+
+#[cfg(feature = "compact-errors")]
+impl Message {
+    pub(crate) fn invalid_value(unexp: de::Unexpected) -> Message {
+        match unexp {
+            de::Unexpected::Bool(v) => Message::InvalidValue(Some(v as u64)),
+            de::Unexpected::Unsigned(v) => Message::InvalidValue(Some(v)),
+            de::Unexpected::Signed(v) => Message::InvalidValue(Some(v as u64)),
+            de::Unexpected::Char(v) => Message::InvalidValue(Some(v as u64)),
+            _ => Message::InvalidValue(None),
+        }
+    }
+}
+
+#[cfg(feature = "compact-errors")]
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Message::Custom => f.write_str("custom error"),
+            Message::InvalidType => f.write_str("invalid type"),
+            Message::InvalidValue(Some(v)) => write!(f, "invalid value {}", v),
+            Message::InvalidValue(None) => f.write_str("invalid value"),
+            Message::InvalidLength(len) => write!(f, "invalid length {}", len),
+            Message::UnknownVariant => f.write_str("unknown variant"),
+            Message::UnknownField => f.write_str("unknown field"),
+            Message::MissingField(name) => write!(f, "missing field `{}`", name),
+            Message::DuplicateField(name) => write!(f, "duplicate field `{}`", name),
+        }
+    }
+}
+
+#[cfg(all(feature = "compact-errors", feature = "defmt"))]
+impl defmt::Format for Message {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Message::Custom => defmt::write!(f, "custom error"),
+            Message::InvalidType => defmt::write!(f, "invalid type"),
+            Message::InvalidValue(Some(v)) => defmt::write!(f, "invalid value {=u64}", v),
+            Message::InvalidValue(None) => defmt::write!(f, "invalid value"),
+            Message::InvalidLength(len) => defmt::write!(f, "invalid length {=usize}", len),
+            Message::UnknownVariant => defmt::write!(f, "unknown variant"),
+            Message::UnknownField => defmt::write!(f, "unknown field"),
+            Message::MissingField(name) => defmt::write!(f, "missing field {=str}", name),
+            Message::DuplicateField(name) => defmt::write!(f, "duplicate field {=str}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{from_reader, to_writer};
+
+    fn round_trip(v: &[u32]) -> Result<Vec<u32>, Error> {
+        let mut bs = Vec::new();
+        to_writer(&mut bs, v)?;
+        let d = from_reader(bs.as_slice())?;
+        Ok(d)
+    }
+
+    #[test]
+    fn question_mark_on_both() {
+        assert_eq!(round_trip(&[1, 2, 3]).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn convert_variants() {
+        match Error::from(crate::ser::Error::NoSequenceSize) {
+            Error::NoSequenceSize => {}
+            e => panic!("unexpected: {:?}", e),
+        }
+
+        let e: Result<u32, _> = from_reader(&[][..]);
+        let e = Error::from(e.unwrap_err());
+        assert_eq!(e.offset(), Some(0));
+        assert!(e.is_eof());
+    }
+
+    #[test]
+    fn codes_are_shared() {
+        let ser_errors = vec![
+            crate::ser::Error::IO(io::ErrorKind::Other.into()),
+            crate::ser::Error::NoSequenceSize,
+            crate::ser::Error::NonFiniteFloat(std::f64::NAN),
+            crate::ser::Error::Serde(message("")),
+        ];
+        for e in ser_errors {
+            let code = e.code();
+            assert_eq!(Error::from(e).code(), code);
+        }
+
+        let de_errors = vec![
+            crate::de::Error::IO(io::ErrorKind::Other.into(), Offset::UNKNOWN),
+            crate::de::Error::Unsupported("op", Offset::UNKNOWN),
+            crate::de::Error::NonFiniteFloat(std::f64::NAN, Offset::UNKNOWN),
+            crate::de::Error::Serde(message(""), Offset::UNKNOWN),
+            crate::de::Error::Timeout(Offset::UNKNOWN),
+            crate::de::Error::Eof(Offset::UNKNOWN),
+            crate::de::Error::DepthLimitExceeded(1, Offset::UNKNOWN),
+            crate::de::Error::BudgetExceeded(1, Offset::UNKNOWN),
+            crate::de::Error::Timeout(Offset::new(3)),
+        ];
+        for e in de_errors {
+            let code = e.code();
+            assert_eq!(Error::from(e).code(), code);
+        }
+    }
+
+    #[cfg(feature = "compact-errors")]
+    #[test]
+    fn compact_messages() {
+        use serde_derive::Deserialize;
+
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        enum E {
+            A,
+            B,
+        }
+
+        let e = crate::from_slice::<bool>(&[7]).unwrap_err();
+        match e {
+            crate::de::Error::Serde(Message::InvalidValue(Some(7)), offset) => {
+                assert_eq!(offset.get(), Some(1));
+            }
+            e => panic!("unexpected: {:?}", e),
+        }
+
+        let e = crate::from_slice::<E>(&[5]).unwrap_err();
+        match e {
+            crate::de::Error::Serde(Message::InvalidValue(Some(5)), _) => {}
+            e => panic!("unexpected: {:?}", e),
+        }
+
+        // The offset is kept inline, next to the message.
+        assert!(std::mem::size_of::<Error>() <= 32);
+        assert!(std::mem::size_of::<crate::de::Error>() <= 32);
+    }
+}
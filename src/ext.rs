@@ -0,0 +1,176 @@
+//! Extension trait for concise serialization in application code.
+//!
+//! ```
+//! use serde_dokechi::DokechiExt;
+//!
+//! let bs = vec![1u32, 2, 3].to_dokechi_vec().unwrap();
+//! let v = Vec::<u32>::from_dokechi_slice(&bs).unwrap();
+//! assert_eq!(v, vec![1, 2, 3]);
+//! ```
+
+use std::io::{self, Write};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::de::{self, from_slice};
+use crate::ser::{self, to_vec, to_writer};
+
+/// Dokechi format methods for every type.
+///
+/// Serializing methods are available for `Serialize` types,
+/// and deserializing ones for `DeserializeOwned` types.
+pub trait DokechiExt {
+    /// Serialize `self` as Dokechi format into a byte vector.
+    fn to_dokechi_vec(&self) -> Result<Vec<u8>, ser::Error>
+    where
+        Self: Serialize,
+    {
+        to_vec(self)
+    }
+
+    /// Serialize `self` as Dokechi format into the IO stream.
+    fn to_dokechi_writer<W: Write>(&self, w: W) -> Result<(), ser::Error>
+    where
+        Self: Serialize,
+    {
+        to_writer(w, self)
+    }
+
+    /// The size of `self` in Dokechi format, in bytes.
+    ///
+    /// The value is serialized without storing the bytes.
+    fn dokechi_encoded_size(&self) -> Result<usize, ser::Error>
+    where
+        Self: Serialize,
+    {
+        let mut counter = Counter(0);
+        to_writer(&mut counter, self)?;
+        Ok(counter.0)
+    }
+
+    /// Deserialize an instance from bytes of Dokechi format.
+    fn from_dokechi_slice(bs: &[u8]) -> Result<Self, de::Error>
+    where
+        Self: DeserializeOwned + Sized,
+    {
+        from_slice(bs)
+    }
+}
+
+impl<T: ?Sized> DokechiExt for T {}
+
+/// A writer counting bytes written.
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[test]
+    fn round_trip() {
+        let p = Point { x: -1, y: 2 };
+        let bs = p.to_dokechi_vec().unwrap();
+        assert_eq!(bs, vec![1, 4]);
+        assert_eq!(Point::from_dokechi_slice(&bs).unwrap(), p);
+        assert_eq!(p.dokechi_encoded_size().unwrap(), bs.len());
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derive_message() {
+        use crate::DokechiMessage;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize, DokechiMessage)]
+        struct Pair<T> {
+            key: String,
+            value: T,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize, DokechiMessage)]
+        #[dokechi(versioned)]
+        struct Tagged(u16);
+
+        crate::versioned!(Tagged = 300);
+
+        let p = Pair {
+            key: "k".to_owned(),
+            value: -1i32,
+        };
+        let bs = p.to_bytes().unwrap();
+        assert_eq!(bs, vec![1, b'k', 1]);
+        assert_eq!(Pair::from_bytes(&bs).unwrap(), p);
+        assert_eq!(p.encoded_size().unwrap(), bs.len());
+
+        let bs = Tagged(5).to_bytes().unwrap();
+        assert_eq!(bs, vec![0x81, 0x2C, 5]);
+        assert_eq!(Tagged::from_bytes(&bs).unwrap(), Tagged(5));
+        assert_eq!(Tagged(5).encoded_size().unwrap(), bs.len());
+        Tagged::from_bytes(&[5]).unwrap_err();
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derive_codecs() {
+        use crate::{with_codecs, DokechiMessage};
+
+        #[with_codecs]
+        #[derive(Debug, PartialEq, Serialize, Deserialize, DokechiMessage)]
+        struct Reading {
+            id: u32,
+            #[dokechi(codec = "serde_dokechi::gorilla")]
+            samples: Vec<f64>,
+        }
+
+        #[with_codecs]
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Batch {
+            Counts(#[dokechi(codec = "serde_dokechi::group_varint")] Vec<u32>),
+            Empty,
+        }
+
+        let v = Reading {
+            id: 1,
+            samples: vec![0.5; 64],
+        };
+        let bs = v.to_bytes().unwrap();
+        assert!(bs.len() < 8 * 64 / 4);
+        let samples = crate::gorilla::encode(&v.samples).unwrap();
+        let mut expected = vec![1];
+        crate::varuint::encode_u64(&mut expected, samples.len() as u64).unwrap();
+        expected.extend(samples);
+        assert_eq!(bs, expected);
+        assert_eq!(Reading::from_bytes(&bs).unwrap(), v);
+
+        let v = Batch::Counts(vec![1, 300, 70000, 5]);
+        let bs = crate::to_vec(&v).unwrap();
+        assert_eq!(crate::from_slice::<Batch>(&bs).unwrap(), v);
+    }
+
+    #[test]
+    fn unsized_value() {
+        let mut bs = Vec::new();
+        "abc".to_dokechi_writer(&mut bs).unwrap();
+        assert_eq!(String::from_dokechi_slice(&bs).unwrap(), "abc");
+        assert_eq!("abc".dokechi_encoded_size().unwrap(), 4);
+    }
+}
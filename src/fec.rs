@@ -0,0 +1,214 @@
+//! Forward error correction envelope using Reed-Solomon erasure coding.
+//!
+//! A serialized payload is split into data shards, and parity shards are appended.
+//! The payload can be reconstructed from any `data shard count` intact shards,
+//! so lost or corrupted shards up to the number of parity shards are tolerated.
+//! Corrupted shards are detected by their checksums and treated as lost.
+//!
+//! Shard format:
+//!
+//! ```text
+//! shard index (varuint) , data shard count (varuint) , parity shard count (varuint) ,
+//! payload length (varuint) , checksum (u32 LE) , shard data
+//! ```
+//!
+//! The checksum is CRC-32 of the header fields and shard data.
+//!
+//! This module is available with the `reed-solomon-erasure` feature.
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::{Error as _, Serialize};
+
+use crate::crc::crc32;
+use crate::de::{self, from_reader};
+use crate::error::Offset;
+use crate::ser::{self, Serializer};
+use crate::varuint::{decode_u64, encode_u64};
+
+const CHECKSUM_LEN: usize = 4;
+
+/// Serialize `value` and encode it into `data_shards` data shards followed by `parity_shards` parity shards.
+pub fn to_shards<T: Serialize>(
+    value: &T,
+    data_shards: usize,
+    parity_shards: usize,
+) -> Result<Vec<Vec<u8>>, ser::Error> {
+    let mut bs = Vec::new();
+    value.serialize(&mut Serializer::new(&mut bs))?;
+    encode(&bs, data_shards, parity_shards)
+}
+
+/// Reconstruct the payload from `shards` and deserialize it.
+pub fn from_shards<T, S>(shards: &[S]) -> Result<T, de::Error>
+where
+    T: DeserializeOwned,
+    S: AsRef<[u8]>,
+{
+    let bs = decode(shards)?;
+    from_reader(bs.as_slice())
+}
+
+/// Encode `payload` into `data_shards` data shards followed by `parity_shards` parity shards.
+pub fn encode(
+    payload: &[u8],
+    data_shards: usize,
+    parity_shards: usize,
+) -> Result<Vec<Vec<u8>>, ser::Error> {
+    let rs = ReedSolomon::new(data_shards, parity_shards).map_err(ser::Error::custom)?;
+
+    let shard_len = ((payload.len() + data_shards - 1) / data_shards).max(1);
+    let mut shards: Vec<Vec<u8>> = (0..data_shards + parity_shards)
+        .map(|i| {
+            let start = (i * shard_len).min(payload.len());
+            let end = (start + shard_len).min(payload.len());
+            let mut shard = vec![0u8; shard_len];
+            if i < data_shards {
+                shard[..end - start].copy_from_slice(&payload[start..end]);
+            }
+            shard
+        })
+        .collect();
+    rs.encode(&mut shards).map_err(ser::Error::custom)?;
+
+    let mut out = Vec::with_capacity(shards.len());
+    for (i, shard) in shards.into_iter().enumerate() {
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, i as u64)?;
+        encode_u64(&mut bs, data_shards as u64)?;
+        encode_u64(&mut bs, parity_shards as u64)?;
+        encode_u64(&mut bs, payload.len() as u64)?;
+
+        let mut checked = bs.clone();
+        checked.extend_from_slice(&shard);
+        bs.extend_from_slice(&crc32(&checked).to_le_bytes());
+        bs.extend_from_slice(&shard);
+        out.push(bs);
+    }
+    Ok(out)
+}
+
+/// Reconstruct the payload from `shards`.
+///
+/// Shards can be given in any order, and lost shards can be omitted.
+pub fn decode<S: AsRef<[u8]>>(shards: &[S]) -> Result<Vec<u8>, de::Error> {
+    let parsed: Vec<Shard> = shards
+        .iter()
+        .filter_map(|s| Shard::parse(s.as_ref()))
+        .collect();
+
+    // Shards agreeing with the majority header are trusted.
+    let header = parsed
+        .iter()
+        .map(|s| s.header)
+        .max_by_key(|h| parsed.iter().filter(|s| s.header == *h).count())
+        .ok_or_else(|| de::Error::custom("no intact shard"))?;
+    let (data_shards, parity_shards, payload_len) = header;
+
+    let rs = ReedSolomon::new(data_shards, parity_shards).map_err(de::Error::custom)?;
+
+    let mut slots: Vec<Option<Vec<u8>>> = vec![None; data_shards + parity_shards];
+    for s in parsed {
+        if s.header == header && s.index < slots.len() {
+            slots[s.index] = Some(s.data.to_vec());
+        }
+    }
+    rs.reconstruct_data(&mut slots).map_err(de::Error::custom)?;
+
+    let mut payload = Vec::new();
+    for slot in slots.into_iter().take(data_shards) {
+        let shard = slot.ok_or_else(|| de::Error::custom("data shard is not reconstructed"))?;
+        payload.extend_from_slice(&shard);
+    }
+    if payload.len() < payload_len {
+        return Err(de::Error::Eof(Offset::UNKNOWN));
+    }
+    payload.truncate(payload_len);
+    Ok(payload)
+}
+
+struct Shard<'a> {
+    index: usize,
+    header: (usize, usize, usize),
+    data: &'a [u8],
+}
+
+impl<'a> Shard<'a> {
+    fn parse(bs: &'a [u8]) -> Option<Shard<'a>> {
+        let mut r = bs;
+        let index = decode_u64(&mut r).ok()? as usize;
+        let data_shards = decode_u64(&mut r).ok()? as usize;
+        let parity_shards = decode_u64(&mut r).ok()? as usize;
+        let payload_len = decode_u64(&mut r).ok()? as usize;
+        let header_len = bs.len() - r.len();
+
+        if r.len() < CHECKSUM_LEN {
+            return None;
+        }
+        let mut checksum = [0u8; CHECKSUM_LEN];
+        checksum.copy_from_slice(&r[..CHECKSUM_LEN]);
+        let data = &r[CHECKSUM_LEN..];
+
+        let mut checked = bs[..header_len].to_vec();
+        checked.extend_from_slice(data);
+        if crc32(&checked) != u32::from_le_bytes(checksum) {
+            return None;
+        }
+
+        Some(Shard {
+            index,
+            header: (data_shards, parity_shards, payload_len),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample() -> Vec<String> {
+        (0..50).map(|i| format!("record {}", i)).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let shards = to_shards(&sample(), 4, 2).unwrap();
+        assert_eq!(shards.len(), 6);
+
+        let v: Vec<String> = from_shards(&shards).unwrap();
+        assert_eq!(v, sample());
+    }
+
+    #[test]
+    fn lost_shards() {
+        let shards = to_shards(&sample(), 4, 2).unwrap();
+
+        let received = vec![&shards[5], &shards[0], &shards[3], &shards[2]];
+        let v: Vec<String> = from_shards(&received).unwrap();
+        assert_eq!(v, sample());
+    }
+
+    #[test]
+    fn corrupted_shards() {
+        let mut shards = to_shards(&sample(), 4, 2).unwrap();
+        let last = shards[1].len() - 1;
+        shards[1][last] ^= 0x55;
+        shards[2][0] ^= 0x01;
+
+        let v: Vec<String> = from_shards(&shards).unwrap();
+        assert_eq!(v, sample());
+    }
+
+    #[test]
+    fn too_many_lost_shards() {
+        let shards = to_shards(&sample(), 4, 2).unwrap();
+        let _ = decode(&shards[..3]).unwrap_err();
+    }
+
+    #[test]
+    fn empty_payload() {
+        let shards = encode(&[], 3, 1).unwrap();
+        assert_eq!(decode(&shards[1..]).unwrap(), Vec::<u8>::new());
+    }
+}
@@ -0,0 +1,339 @@
+//! Structural schema fingerprints to detect incompatible type changes.
+//!
+//! Dokechi format has no field names or type tags, so a payload decoded as an edited type
+//! silently turns into garbage values.
+//! A fingerprint is a hash of the structure of a type: names, order and types of fields,
+//! and variants of enums, traced through its `Deserialize` implementation.
+//! [`to_writer_with_fingerprint`](fn.to_writer_with_fingerprint.html) prefixes payloads with it,
+//! and [`from_reader_with_fingerprint`](fn.from_reader_with_fingerprint.html) rejects payloads
+//! with a different one.
+//!
+//! Renaming types, fields or variants changes the fingerprint too.
+//! Types implementing `Deserialize` by `deserialize_any`, such as `serde(untagged)` enums,
+//! are not supported, as Dokechi format doesn't support them.
+//!
+//! Payload format:
+//!
+//! ```text
+//! fingerprint (u64 little endian) , value
+//! ```
+
+use std::io::{Read, Write};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Serialize;
+
+use crate::de::{from_reader, Error as DeError};
+use crate::schema::{trace, Shape, Variant};
+use crate::ser::to_writer;
+use crate::Error;
+
+/// Serialize `value` prefixed with the fingerprint of `T`.
+pub fn to_writer_with_fingerprint<W, T>(mut w: W, value: &T) -> Result<(), Error>
+where
+    W: Write,
+    T: Serialize + DeserializeOwned,
+{
+    let fp = fingerprint::<T>()?;
+    w.write_all(&fp.to_le_bytes())?;
+    to_writer(w, value)?;
+    Ok(())
+}
+
+/// Deserialize a payload after checking its fingerprint is the one of `T`.
+pub fn from_reader_with_fingerprint<R, T>(mut r: R) -> Result<T, Error>
+where
+    R: Read,
+    T: DeserializeOwned,
+{
+    let mut bs = [0u8; 8];
+    r.read_exact(&mut bs)?;
+    let found = u64::from_le_bytes(bs);
+    let expected = fingerprint::<T>()?;
+    if found != expected {
+        return Err(Error::custom(format_args!(
+            "schema fingerprint {:016x} doesn't match {:016x}",
+            found, expected
+        )));
+    }
+    Ok(from_reader(r)?)
+}
+
+/// Compute the fingerprint of `T`.
+///
+/// The fingerprint is the FNV-1a hash of [`describe`](fn.describe.html), so it is stable
+/// across builds and platforms.
+pub fn fingerprint<T: DeserializeOwned>() -> Result<u64, DeError> {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for b in describe::<T>()?.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    Ok(hash)
+}
+
+/// Describe the structure of `T` as a string.
+///
+/// The description is for debugging, and its syntax may change.
+pub fn describe<T: DeserializeOwned>() -> Result<String, DeError> {
+    let schema = trace::<T>()?;
+
+    let mut s = String::new();
+    write_shape(&mut s, &schema.root);
+    for (name, variants) in &schema.enums {
+        s.push('\n');
+        s.push_str(name);
+        s.push('=');
+        for (i, (variant, data)) in variants.iter().enumerate() {
+            if i > 0 {
+                s.push('|');
+            }
+            s.push_str(variant);
+            match data {
+                Variant::Unit => {}
+                Variant::Newtype(shape) => {
+                    s.push('(');
+                    write_shape(&mut s, shape);
+                    s.push(')');
+                }
+                Variant::Tuple(shapes) => write_tuple(&mut s, shapes),
+                Variant::Struct(fields) => write_fields(&mut s, fields),
+            }
+        }
+    }
+    Ok(s)
+}
+
+fn write_shape(s: &mut String, shape: &Shape) {
+    let name = match shape {
+        Shape::Bool => "bool",
+        Shape::I8 => "i8",
+        Shape::I16 => "i16",
+        Shape::I32 => "i32",
+        Shape::I64 => "i64",
+        Shape::I128 => "i128",
+        Shape::U8 => "u8",
+        Shape::U16 => "u16",
+        Shape::U32 => "u32",
+        Shape::U64 => "u64",
+        Shape::U128 => "u128",
+        Shape::F32 => "f32",
+        Shape::F64 => "f64",
+        Shape::Char => "char",
+        Shape::Str => "str",
+        Shape::Bytes => "bytes",
+        Shape::Unit => "()",
+        Shape::UnitStruct(name) => name,
+        Shape::Option(inner) => {
+            s.push('?');
+            return write_shape(s, inner);
+        }
+        Shape::Seq(element) => {
+            s.push('[');
+            write_shape(s, element);
+            s.push(']');
+            return;
+        }
+        Shape::Map(key, value) => {
+            s.push('{');
+            write_shape(s, key);
+            s.push(':');
+            write_shape(s, value);
+            s.push('}');
+            return;
+        }
+        Shape::Tuple(shapes) => return write_tuple(s, shapes),
+        Shape::Newtype(name, inner) => {
+            s.push_str(name);
+            s.push('(');
+            write_shape(s, inner);
+            s.push(')');
+            return;
+        }
+        Shape::TupleStruct(name, shapes) => {
+            s.push_str(name);
+            return write_tuple(s, shapes);
+        }
+        Shape::Struct(name, fields) => {
+            s.push_str(name);
+            return write_fields(s, fields);
+        }
+        Shape::Enum(name) => {
+            s.push('#');
+            name
+        }
+        Shape::Recursive(name) => {
+            s.push_str(name);
+            "@"
+        }
+    };
+    s.push_str(name);
+}
+
+fn write_tuple(s: &mut String, shapes: &[Shape]) {
+    s.push('(');
+    for (i, shape) in shapes.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        write_shape(s, shape);
+    }
+    s.push(')');
+}
+
+fn write_fields(s: &mut String, fields: &[(&str, Shape)]) {
+    s.push('{');
+    for (i, (name, shape)) in fields.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push_str(name);
+        s.push(':');
+        write_shape(s, shape);
+    }
+    s.push('}');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    mod v1 {
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        pub struct User {
+            pub id: u32,
+            pub name: String,
+        }
+    }
+
+    mod v2 {
+        use serde_derive::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        pub struct User {
+            pub id: u32,
+            pub name: String,
+            pub age: u8,
+        }
+    }
+
+    mod v1_copy {
+        use serde_derive::Deserialize;
+
+        #[derive(Deserialize)]
+        pub struct User {
+            pub id: u32,
+            pub name: String,
+        }
+    }
+
+    mod swapped {
+        use serde_derive::Deserialize;
+
+        #[derive(Deserialize)]
+        pub struct User {
+            pub name: String,
+            pub id: u32,
+        }
+    }
+
+    #[test]
+    fn structural_changes() {
+        let v1 = fingerprint::<v1::User>().unwrap();
+        assert_eq!(v1, fingerprint::<v1_copy::User>().unwrap());
+        assert_ne!(v1, fingerprint::<v2::User>().unwrap());
+        assert_ne!(v1, fingerprint::<swapped::User>().unwrap());
+        assert_ne!(
+            fingerprint::<Vec<u32>>().unwrap(),
+            fingerprint::<Vec<u64>>().unwrap()
+        );
+        assert_ne!(
+            fingerprint::<HashMap<String, u8>>().unwrap(),
+            fingerprint::<Vec<(String, u8)>>().unwrap()
+        );
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Outer {
+        A,
+        B(Inner),
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Inner {
+        X(u8),
+        Y { z: Option<String> },
+    }
+
+    #[test]
+    fn all_variants_are_traced() {
+        let d = describe::<Outer>().unwrap();
+        assert_eq!(
+            d, "#Outer\nInner=X(u8)|Y{z:?str}\nOuter=A|B(#Inner)",
+            "{}",
+            d
+        );
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Node {
+        value: u8,
+        next: Option<Box<Node>>,
+        children: Vec<Node>,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum List {
+        Cons(u8, Box<List>),
+        Nil,
+    }
+
+    #[test]
+    fn recursive_types() {
+        let d = describe::<Node>().unwrap();
+        assert_eq!(d, "Node{value:u8,next:?Node@,children:[Node@]}");
+
+        let d = describe::<List>().unwrap();
+        assert_eq!(d, "#List\nList=Cons(u8,#List)|Nil");
+    }
+
+    #[test]
+    fn round_trip() {
+        let user = v1::User {
+            id: 1,
+            name: "alice".to_owned(),
+        };
+        let mut bs = Vec::new();
+        to_writer_with_fingerprint(&mut bs, &user).unwrap();
+        assert_eq!(&bs[..8], &fingerprint::<v1::User>().unwrap().to_le_bytes());
+
+        let v: v1::User = from_reader_with_fingerprint(bs.as_slice()).unwrap();
+        assert_eq!(v, user);
+
+        let _ = from_reader_with_fingerprint::<_, v2::User>(bs.as_slice()).unwrap_err();
+    }
+
+    #[test]
+    fn untagged_is_unsupported() {
+        #[derive(Serialize, Deserialize)]
+        #[serde(untagged)]
+        enum Untagged {
+            A(u8),
+        }
+
+        match fingerprint::<Untagged>() {
+            Err(DeError::Unsupported(..)) => {}
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Fragmentation of messages for links with a limited MTU.
+//!
+//! [`Fragmenter`](struct.Fragmenter.html) splits a serialized message into numbered fragments,
+//! each no larger than the configured MTU,
+//! and [`Reassembler`](struct.Reassembler.html) puts them back together on the other side.
+//! Fragments may arrive in any order.
+//!
+//! Fragment format:
+//!
+//! ```text
+//! message ID (varuint) , fragment index (varuint) , fragment count (varuint) , checksum (u32 LE) , data
+//! ```
+//!
+//! The checksum is CRC-32 of the whole message, checked after reassembly.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::{Error as _, Serialize};
+
+use crate::crc::crc32;
+use crate::de::{self, from_reader};
+use crate::ser::{self, Serializer};
+use crate::varuint::{decode_u64, encode_u64, encoded_len_u64};
+
+const CHECKSUM_LEN: usize = 4;
+
+/// Splits messages into fragments no larger than the MTU.
+#[derive(Debug)]
+pub struct Fragmenter {
+    mtu: usize,
+    next_id: u64,
+}
+
+impl Fragmenter {
+    /// Create new `Fragmenter` producing fragments of at most `mtu` bytes.
+    pub fn new(mtu: usize) -> Fragmenter {
+        Fragmenter { mtu, next_id: 0 }
+    }
+
+    /// Serialize `value` and split it into fragments.
+    pub fn fragment<T: Serialize>(&mut self, value: &T) -> Result<Vec<Vec<u8>>, ser::Error> {
+        let mut bs = Vec::new();
+        value.serialize(&mut Serializer::new(&mut bs))?;
+        self.fragment_bytes(&bs)
+    }
+
+    /// Split serialized `message` into fragments.
+    pub fn fragment_bytes(&mut self, message: &[u8]) -> Result<Vec<Vec<u8>>, ser::Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let header_len =
+            |count: usize| encoded_len_u64(id) + 2 * encoded_len_u64(count as u64) + CHECKSUM_LEN;
+
+        let mut count = 1;
+        let chunk_len = loop {
+            let header = header_len(count);
+            if self.mtu <= header {
+                return Err(ser::Error::custom("MTU is too small"));
+            }
+            let chunk_len = self.mtu - header;
+            let needed = ((message.len() + chunk_len - 1) / chunk_len).max(1);
+            if needed <= count {
+                break chunk_len;
+            }
+            count = needed;
+        };
+
+        let checksum = crc32(message).to_le_bytes();
+
+        let mut fragments = Vec::with_capacity(count);
+        for index in 0..count {
+            let start = (index * chunk_len).min(message.len());
+            let end = (start + chunk_len).min(message.len());
+
+            let mut f = Vec::with_capacity(header_len(count) + end - start);
+            encode_u64(&mut f, id)?;
+            encode_u64(&mut f, index as u64)?;
+            encode_u64(&mut f, count as u64)?;
+            f.extend_from_slice(&checksum);
+            f.extend_from_slice(&message[start..end]);
+            fragments.push(f);
+        }
+        Ok(fragments)
+    }
+}
+
+/// Reassembles messages from fragments.
+///
+/// Incomplete messages are discarded after the timeout.
+/// The number of incomplete messages and the length of each message are limited,
+/// so a peer can't make it buffer unbounded data.
+#[derive(Debug)]
+pub struct Reassembler {
+    timeout: Duration,
+    max_pending: usize,
+    max_message_len: usize,
+    pending: HashMap<u64, Pending>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    count: u64,
+    checksum: u32,
+    started: Instant,
+    len: usize,
+    fragments: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Reassembler {
+    /// Create new `Reassembler` which discards incomplete messages after `timeout`.
+    ///
+    /// Fragments of a new message are rejected while `max_pending` messages are incomplete,
+    /// and fragments making a message longer than `max_message_len` bytes are rejected.
+    pub fn new(timeout: Duration, max_pending: usize, max_message_len: usize) -> Reassembler {
+        Reassembler {
+            timeout,
+            max_pending,
+            max_message_len,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Add a fragment and deserialize the message if it is complete.
+    pub fn receive<T: DeserializeOwned>(
+        &mut self,
+        fragment: &[u8],
+    ) -> Result<Option<T>, de::Error> {
+        match self.push(fragment)? {
+            Some(bs) => Ok(Some(from_reader(bs.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Add a fragment and return the serialized message if it is complete.
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>, de::Error> {
+        self.expire();
+
+        let mut r = fragment;
+        let id = decode_u64(&mut r)?;
+        let index = decode_u64(&mut r)?;
+        let count = decode_u64(&mut r)?;
+        if r.len() < CHECKSUM_LEN {
+            return Err(de::Error::custom("truncated fragment"));
+        }
+        let mut checksum = [0u8; CHECKSUM_LEN];
+        checksum.copy_from_slice(&r[..CHECKSUM_LEN]);
+        let checksum = u32::from_le_bytes(checksum);
+        let data = &r[CHECKSUM_LEN..];
+
+        if index >= count {
+            return Err(de::Error::custom("fragment index is out of range"));
+        }
+        // Every fragment but the one of an empty message carries at least one byte.
+        if count > self.max_message_len.max(1) as u64 {
+            return Err(de::Error::custom("message is too long"));
+        }
+        if !self.pending.contains_key(&id) && self.pending.len() >= self.max_pending {
+            return Err(de::Error::custom("too many pending messages"));
+        }
+
+        let pending = self.pending.entry(id).or_insert_with(|| Pending {
+            count,
+            checksum,
+            started: Instant::now(),
+            len: 0,
+            fragments: BTreeMap::new(),
+        });
+        if pending.count != count || pending.checksum != checksum {
+            self.pending.remove(&id);
+            return Err(de::Error::custom("inconsistent fragment header"));
+        }
+        let replaced = pending.fragments.get(&index).map_or(0, Vec::len);
+        let len = pending.len - replaced + data.len();
+        if len > self.max_message_len {
+            self.pending.remove(&id);
+            return Err(de::Error::custom("message is too long"));
+        }
+        pending.len = len;
+        pending.fragments.insert(index, data.to_vec());
+
+        if pending.fragments.len() as u64 != pending.count {
+            return Ok(None);
+        }
+
+        let pending = self.pending.remove(&id).expect("pending message exists");
+        let message: Vec<u8> = pending.fragments.into_iter().flat_map(|(_, f)| f).collect();
+        if crc32(&message) != pending.checksum {
+            return Err(de::Error::custom("checksum mismatch"));
+        }
+        Ok(Some(message))
+    }
+
+    /// Discard incomplete messages older than the timeout, returning the number of discarded messages.
+    pub fn expire(&mut self) -> usize {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        self.pending.retain(|_, p| p.started.elapsed() <= timeout);
+        before - self.pending.len()
+    }
+
+    /// The number of incomplete messages.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn fragments_fit_in_mtu() {
+        let message: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+
+        let mut fragmenter = Fragmenter::new(64);
+        let fragments = fragmenter.fragment_bytes(&message).unwrap();
+        assert!(fragments.len() > 1);
+        for f in &fragments {
+            assert!(f.len() <= 64);
+        }
+    }
+
+    #[test]
+    fn small_message_is_single_fragment() {
+        let mut fragmenter = Fragmenter::new(64);
+        let fragments = fragmenter.fragment(&"hello").unwrap();
+        assert_eq!(fragments.len(), 1);
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(10), 16, 1 << 20);
+        let v: Option<String> = reassembler.receive(&fragments[0]).unwrap();
+        assert_eq!(v, Some("hello".to_owned()));
+    }
+
+    #[test]
+    fn empty_message() {
+        let mut fragmenter = Fragmenter::new(16);
+        let fragments = fragmenter.fragment_bytes(&[]).unwrap();
+        assert_eq!(fragments.len(), 1);
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(10), 16, 1 << 20);
+        assert_eq!(reassembler.push(&fragments[0]).unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn reassemble_out_of_order() {
+        let value: Vec<String> = (0..100).map(|i| format!("item {}", i)).collect();
+
+        let mut fragmenter = Fragmenter::new(100);
+        let mut fragments = fragmenter.fragment(&value).unwrap();
+        fragments.reverse();
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(10), 16, 1 << 20);
+        let last = fragments.pop().unwrap();
+        for f in &fragments {
+            assert_eq!(reassembler.receive::<Vec<String>>(f).unwrap(), None);
+        }
+        assert_eq!(reassembler.pending(), 1);
+        assert_eq!(reassembler.receive(&last).unwrap(), Some(value));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn interleaved_messages() {
+        let mut fragmenter = Fragmenter::new(20);
+        let a = fragmenter.fragment(&"a".repeat(50)).unwrap();
+        let b = fragmenter.fragment(&"b".repeat(50)).unwrap();
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(10), 16, 1 << 20);
+        let mut done = Vec::new();
+        for (fa, fb) in a.iter().zip(b.iter()) {
+            done.extend(reassembler.receive::<String>(fa).unwrap());
+            done.extend(reassembler.receive::<String>(fb).unwrap());
+        }
+        assert_eq!(done, vec!["a".repeat(50), "b".repeat(50)]);
+    }
+
+    #[test]
+    fn corrupted_fragment() {
+        let message = vec![7u8; 100];
+
+        let mut fragmenter = Fragmenter::new(32);
+        let mut fragments = fragmenter.fragment_bytes(&message).unwrap();
+        let last = fragments[1].len() - 1;
+        fragments[1][last] ^= 0xff;
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(10), 16, 1 << 20);
+        let mut result = Ok(None);
+        for f in &fragments {
+            result = reassembler.push(f);
+        }
+        let _ = result.unwrap_err();
+    }
+
+    #[test]
+    fn incomplete_message_expires() {
+        let mut fragmenter = Fragmenter::new(16);
+        let fragments = fragmenter.fragment_bytes(&[1u8; 40]).unwrap();
+
+        let mut reassembler = Reassembler::new(Duration::from_millis(0), 16, 1 << 20);
+        assert_eq!(reassembler.push(&fragments[0]).unwrap(), None);
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(reassembler.expire(), 1);
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn too_many_pending_messages() {
+        let mut fragmenter = Fragmenter::new(16);
+        let a = fragmenter.fragment_bytes(&[1u8; 40]).unwrap();
+        let b = fragmenter.fragment_bytes(&[2u8; 40]).unwrap();
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(10), 1, 1 << 20);
+        assert_eq!(reassembler.push(&a[0]).unwrap(), None);
+        let _ = reassembler.push(&b[0]).unwrap_err();
+        assert_eq!(reassembler.pending(), 1);
+
+        let mut result = Ok(None);
+        for f in &a[1..] {
+            result = reassembler.push(f);
+        }
+        assert_eq!(result.unwrap(), Some(vec![1u8; 40]));
+        assert_eq!(reassembler.push(&b[0]).unwrap(), None);
+    }
+
+    #[test]
+    fn too_long_message() {
+        let mut fragmenter = Fragmenter::new(16);
+        let fragments = fragmenter.fragment_bytes(&[1u8; 40]).unwrap();
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(10), 16, 39);
+        let mut result = Ok(None);
+        for f in &fragments {
+            result = reassembler.push(f);
+            if result.is_err() {
+                break;
+            }
+        }
+        let _ = result.unwrap_err();
+        assert_eq!(reassembler.pending(), 0);
+
+        // A huge fragment count is rejected before buffering anything.
+        let mut f = Vec::new();
+        encode_u64(&mut f, 0).unwrap();
+        encode_u64(&mut f, 0).unwrap();
+        encode_u64(&mut f, 1 << 40).unwrap();
+        f.extend_from_slice(&[0; CHECKSUM_LEN]);
+        let _ = reassembler.push(&f).unwrap_err();
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn too_small_mtu() {
+        let mut fragmenter = Fragmenter::new(6);
+        let _ = fragmenter.fragment_bytes(&[1, 2, 3]).unwrap_err();
+    }
+}
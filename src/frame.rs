@@ -0,0 +1,401 @@
+//! Multiplexed framing of Dokechi format messages.
+//!
+//! Each frame carries a channel ID, so messages of different types can share one stream.
+//!
+//! Frame format:
+//!
+//! ```text
+//! channel ID (varuint) , payload length (varuint) , payload
+//! ```
+//!
+//! With the `zstd` or `lz4_flex` feature, all frames of a stream can be compressed with one shared
+//! compression context, which is much more effective for many small messages than compressing each
+//! frame independently. Call [`FrameWriter::flush`](struct.FrameWriter.html#method.flush) to make
+//! frames written so far decodable by the peer.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Serialize;
+
+use crate::de::{self, from_reader};
+use crate::ser::{self, Serializer};
+use crate::varuint::{decode_u64, encode_u64};
+
+/// A decoded frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The channel ID of this frame.
+    pub channel: u64,
+    /// The serialized message.
+    pub payload: Vec<u8>,
+}
+
+/// Writes values as frames into the IO stream.
+#[derive(Debug)]
+pub struct FrameWriter<W: Write> {
+    w: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Create new `FrameWriter`
+    pub fn new(w: W) -> FrameWriter<W> {
+        FrameWriter { w, buf: Vec::new() }
+    }
+
+    /// Serialize `value` and write it as a frame of `channel`.
+    pub fn send<T: Serialize>(&mut self, channel: u64, value: &T) -> Result<(), ser::Error> {
+        self.buf.clear();
+        value.serialize(&mut Serializer::new(&mut self.buf))?;
+
+        encode_u64(&mut self.w, channel)?;
+        encode_u64(&mut self.w, self.buf.len() as u64)?;
+        self.w.write_all(&self.buf)?;
+        Ok(())
+    }
+
+    /// Write already serialized `payload` as a frame of `channel`.
+    pub fn send_payload(&mut self, channel: u64, payload: &[u8]) -> Result<(), ser::Error> {
+        encode_u64(&mut self.w, channel)?;
+        encode_u64(&mut self.w, payload.len() as u64)?;
+        self.w.write_all(payload)?;
+        Ok(())
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> Result<(), ser::Error> {
+        self.w.flush()?;
+        Ok(())
+    }
+
+    /// Get a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<W: Write> FrameWriter<zstd::stream::write::Encoder<'static, W>> {
+    /// Create new `FrameWriter` which compresses all frames with one zstd stream.
+    pub fn zstd(w: W, level: i32) -> Result<Self, ser::Error> {
+        Ok(FrameWriter::new(zstd::stream::write::Encoder::new(
+            w, level,
+        )?))
+    }
+
+    /// Finish the zstd stream and return the underlying writer.
+    pub fn finish(self) -> Result<W, ser::Error> {
+        Ok(self.w.finish()?)
+    }
+}
+
+#[cfg(feature = "lz4_flex")]
+impl<W: Write> FrameWriter<lz4_flex::frame::FrameEncoder<W>> {
+    /// Create new `FrameWriter` which compresses all frames with one LZ4 stream of linked blocks.
+    pub fn lz4(w: W) -> FrameWriter<lz4_flex::frame::FrameEncoder<W>> {
+        let info = lz4_flex::frame::FrameInfo::new().block_mode(lz4_flex::frame::BlockMode::Linked);
+        FrameWriter::new(lz4_flex::frame::FrameEncoder::with_frame_info(info, w))
+    }
+
+    /// Finish the LZ4 stream and return the underlying writer.
+    pub fn finish(self) -> Result<W, ser::Error> {
+        self.w
+            .finish()
+            .map_err(|e| ser::Error::IO(io::Error::new(io::ErrorKind::Other, e)))
+    }
+}
+
+/// Reads frames from the IO stream.
+#[derive(Debug)]
+pub struct FrameReader<R: Read> {
+    r: R,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Create new `FrameReader`
+    pub fn new(r: R) -> FrameReader<R> {
+        FrameReader { r }
+    }
+
+    /// Read the next frame.
+    ///
+    /// Returns `None` if the stream ends at a frame boundary.
+    pub fn read_frame(&mut self) -> Result<Option<Frame>, de::Error> {
+        let mut head = [0u8];
+        loop {
+            match self.r.read(&mut head) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let channel = decode_u64(&mut (&head[..]).chain(&mut self.r))?;
+        let len = decode_u64(&mut self.r)?;
+
+        // Read up to the end of the frame, not allocating for a corrupt length up front.
+        let mut payload = Vec::new();
+        (&mut self.r).take(len).read_to_end(&mut payload)?;
+        if (payload.len() as u64) < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(Some(Frame { channel, payload }))
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<R: Read> FrameReader<zstd::stream::read::Decoder<'static, io::BufReader<R>>> {
+    /// Create new `FrameReader` which reads frames compressed by [`FrameWriter::zstd`](struct.FrameWriter.html#method.zstd).
+    pub fn zstd(r: R) -> Result<Self, de::Error> {
+        Ok(FrameReader::new(zstd::stream::read::Decoder::new(r)?))
+    }
+}
+
+#[cfg(feature = "lz4_flex")]
+impl<R: Read> FrameReader<lz4_flex::frame::FrameDecoder<R>> {
+    /// Create new `FrameReader` which reads frames compressed by [`FrameWriter::lz4`](struct.FrameWriter.html#method.lz4).
+    pub fn lz4(r: R) -> FrameReader<lz4_flex::frame::FrameDecoder<R>> {
+        FrameReader::new(lz4_flex::frame::FrameDecoder::new(r))
+    }
+}
+
+type Handler<'a> = Box<dyn FnMut(&[u8]) -> Result<(), de::Error> + 'a>;
+
+/// Dispatches frames to handlers registered for each channel.
+///
+/// Each handler deserializes messages of its own type.
+#[derive(Default)]
+pub struct Demultiplexer<'a> {
+    handlers: HashMap<u64, Handler<'a>>,
+}
+
+impl<'a> Demultiplexer<'a> {
+    /// Create new `Demultiplexer` without handlers.
+    pub fn new() -> Demultiplexer<'a> {
+        Demultiplexer {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Register `handler` which receives messages of type `T` sent on `channel`.
+    ///
+    /// A handler already registered for `channel` is replaced.
+    pub fn register<T, F>(&mut self, channel: u64, mut handler: F)
+    where
+        T: DeserializeOwned,
+        F: FnMut(T) + 'a,
+    {
+        self.handlers.insert(
+            channel,
+            Box::new(move |bs: &[u8]| {
+                let value: T = from_reader(bs)?;
+                handler(value);
+                Ok(())
+            }),
+        );
+    }
+
+    /// Deserialize the message of `frame` and pass it to the handler of its channel.
+    pub fn dispatch(&mut self, frame: &Frame) -> Result<(), de::Error> {
+        match self.handlers.get_mut(&frame.channel) {
+            Some(handler) => handler(&frame.payload),
+            None => Err(de::Error::custom(format_args!(
+                "no handler for channel {}",
+                frame.channel
+            ))),
+        }
+    }
+
+    /// Dispatch all frames read from `reader` until the stream ends.
+    pub fn run<R: Read>(&mut self, reader: &mut FrameReader<R>) -> Result<(), de::Error> {
+        while let Some(frame) = reader.read_frame()? {
+            self.dispatch(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for Demultiplexer<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut channels: Vec<&u64> = self.handlers.keys().collect();
+        channels.sort();
+        f.debug_struct("Demultiplexer")
+            .field("channels", &channels)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        x: i32,
+        y: i32,
+    }
+
+    #[test]
+    fn frame_format() {
+        let mut bs = Vec::new();
+        let mut w = FrameWriter::new(&mut bs);
+        w.send(3, &0x12u8).unwrap();
+        w.send(200, &"ab").unwrap();
+
+        assert_eq!(bs, vec![3u8, 1, 0x12, 0b1000_0000, 200, 3, 2, b'a', b'b']);
+    }
+
+    #[test]
+    fn read_frames() {
+        let mut bs = Vec::new();
+        let mut w = FrameWriter::new(&mut bs);
+        w.send(1, &10u8).unwrap();
+        w.send(2, &20u8).unwrap();
+
+        let mut r = FrameReader::new(bs.as_slice());
+        assert_eq!(
+            r.read_frame().unwrap(),
+            Some(Frame {
+                channel: 1,
+                payload: vec![10]
+            })
+        );
+        assert_eq!(
+            r.read_frame().unwrap(),
+            Some(Frame {
+                channel: 2,
+                payload: vec![20]
+            })
+        );
+        assert_eq!(r.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn send_payload() {
+        let mut bs = Vec::new();
+        let mut w = FrameWriter::new(&mut bs);
+        w.send_payload(3, &[0x12]).unwrap();
+        w.send(3, &0x12u8).unwrap();
+
+        assert_eq!(bs, vec![3u8, 1, 0x12, 3, 1, 0x12]);
+    }
+
+    #[test]
+    fn read_truncated_frame() {
+        let bs = [1u8, 3, 0];
+        let mut r = FrameReader::new(&bs[..]);
+        let _ = r.read_frame().unwrap_err();
+    }
+
+    #[test]
+    fn read_corrupt_length() {
+        let bs = [1u8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0];
+        let mut r = FrameReader::new(&bs[..]);
+        let _ = r.read_frame().unwrap_err();
+    }
+
+    #[test]
+    fn demultiplex() {
+        let mut bs = Vec::new();
+        let mut w = FrameWriter::new(&mut bs);
+        w.send(1, &"hello").unwrap();
+        w.send(2, &Position { x: -1, y: 2 }).unwrap();
+        w.send(1, &"world").unwrap();
+
+        let mut texts = Vec::<String>::new();
+        let mut positions = Vec::<Position>::new();
+
+        let mut demux = Demultiplexer::new();
+        demux.register(1, |s: String| texts.push(s));
+        demux.register(2, |p: Position| positions.push(p));
+        demux.run(&mut FrameReader::new(bs.as_slice())).unwrap();
+        drop(demux);
+
+        assert_eq!(texts, vec!["hello".to_owned(), "world".to_owned()]);
+        assert_eq!(positions, vec![Position { x: -1, y: 2 }]);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_shared_context() {
+        let mut w = FrameWriter::zstd(Vec::new(), 3).unwrap();
+        for i in 0..100u64 {
+            w.send(1, &Position { x: 1, y: 2 }).unwrap();
+            w.send(2, &i).unwrap();
+        }
+        let bs = w.finish().unwrap();
+        assert!(bs.len() < 200);
+
+        let mut r = FrameReader::zstd(bs.as_slice()).unwrap();
+        for i in 0..100u64 {
+            let f = r.read_frame().unwrap().unwrap();
+            assert_eq!(f.channel, 1);
+            assert_eq!(
+                from_reader::<_, Position>(f.payload.as_slice()).unwrap(),
+                Position { x: 1, y: 2 }
+            );
+            let f = r.read_frame().unwrap().unwrap();
+            assert_eq!(from_reader::<_, u64>(f.payload.as_slice()).unwrap(), i);
+        }
+        assert_eq!(r.read_frame().unwrap(), None);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_flushed_frames_are_readable() {
+        let mut w = FrameWriter::zstd(Vec::new(), 3).unwrap();
+        w.send(1, &"first").unwrap();
+        w.flush().unwrap();
+        let partial = w.w.get_ref().clone();
+
+        let mut r = FrameReader::zstd(partial.as_slice()).unwrap();
+        let f = r.read_frame().unwrap().unwrap();
+        assert_eq!(
+            from_reader::<_, String>(f.payload.as_slice()).unwrap(),
+            "first"
+        );
+    }
+
+    #[cfg(feature = "lz4_flex")]
+    #[test]
+    fn lz4_shared_context() {
+        let mut w = FrameWriter::lz4(Vec::new());
+        for i in 0..100u64 {
+            w.send(1, &Position { x: 1, y: 2 }).unwrap();
+            w.send(2, &i).unwrap();
+            w.flush().unwrap();
+        }
+        let bs = w.finish().unwrap();
+
+        let mut r = FrameReader::lz4(bs.as_slice());
+        for i in 0..100u64 {
+            let f = r.read_frame().unwrap().unwrap();
+            assert_eq!(
+                from_reader::<_, Position>(f.payload.as_slice()).unwrap(),
+                Position { x: 1, y: 2 }
+            );
+            let f = r.read_frame().unwrap().unwrap();
+            assert_eq!(from_reader::<_, u64>(f.payload.as_slice()).unwrap(), i);
+        }
+        assert_eq!(r.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn demultiplex_unknown_channel() {
+        let mut demux = Demultiplexer::new();
+        demux.register(1, |_: u8| {});
+
+        let frame = Frame {
+            channel: 2,
+            payload: vec![0],
+        };
+        let _ = demux.dispatch(&frame).unwrap_err();
+    }
+}
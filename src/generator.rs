@@ -0,0 +1,542 @@
+//! Random values and payloads for load testing and fuzzing.
+//!
+//! A [`Generator`](struct.Generator.html) builds random values of a type through its
+//! `Deserialize` implementation, so the type itself is the schema.
+//! Generated payloads are always valid for the type, and make good seeds for
+//! structure-aware fuzzing of decoders.
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//! use serde_dokechi::generator::{Generator, Lengths};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Order {
+//!     id: u64,
+//!     items: Vec<String>,
+//!     note: Option<String>,
+//! }
+//!
+//! let mut gen = Generator::new(42).lengths(Lengths::Geometric { mean: 4.0, max: 64 });
+//! for _ in 0..100 {
+//!     let payload = gen.payload::<Order>().unwrap();
+//!     let _: Order = serde_dokechi::from_slice(&payload).unwrap();
+//! }
+//! ```
+//!
+//! Generators are deterministic: the same seed and settings generate the same values.
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Error as _, IntoDeserializer, Visitor};
+use serde::ser::Serialize;
+
+use crate::de::Error as DeError;
+use crate::error::Offset;
+use crate::ser::to_vec;
+use crate::Error;
+
+/// Distribution of lengths of strings, byte arrays, sequences and maps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lengths {
+    /// Always the given length.
+    Fixed(usize),
+    /// Uniformly from `min` to `max`, both inclusive.
+    Uniform {
+        /// The minimum length.
+        min: usize,
+        /// The maximum length.
+        max: usize,
+    },
+    /// Geometric distribution with the given mean, cut at `max`.
+    ///
+    /// Short values are common and long values are rare, like real data.
+    Geometric {
+        /// The mean of the distribution before cutting.
+        mean: f64,
+        /// The maximum length.
+        max: usize,
+    },
+}
+
+impl Default for Lengths {
+    fn default() -> Lengths {
+        Lengths::Uniform { min: 0, max: 8 }
+    }
+}
+
+/// A generator of random values.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    state: u64,
+    lengths: Lengths,
+    max_depth: usize,
+    depth: usize,
+}
+
+impl Generator {
+    /// Create new `Generator` from `seed`.
+    pub fn new(seed: u64) -> Generator {
+        Generator {
+            // xorshift doesn't work with zero state.
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+            lengths: Lengths::default(),
+            max_depth: 8,
+            depth: 0,
+        }
+    }
+
+    /// Set the distribution of lengths.
+    pub fn lengths(mut self, lengths: Lengths) -> Generator {
+        self.lengths = lengths;
+        self
+    }
+
+    /// Set the depth of nested values after which options are `None` and sequences and maps are empty.
+    ///
+    /// This keeps values of recursive types finite. The default is 8.
+    pub fn max_depth(mut self, max_depth: usize) -> Generator {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Generate a random value of `T`.
+    pub fn value<T: DeserializeOwned>(&mut self) -> Result<T, DeError> {
+        self.depth = 0;
+        T::deserialize(&mut *self)
+    }
+
+    /// Generate a random value of `T` and serialize it.
+    pub fn payload<T: Serialize + DeserializeOwned>(&mut self) -> Result<Vec<u8>, Error> {
+        let v: T = self.value()?;
+        Ok(to_vec(&v)?)
+    }
+
+    /// xorshift64*
+    fn next_u64(&mut self) -> u64 {
+        if self.state == 0 {
+            self.state = 0x9e37_79b9_7f4a_7c15;
+        }
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A random integer in `0..n`. `n` must not be 0.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// A random number in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A random integer with random bit width, so varuints of all sizes are generated.
+    fn integer(&mut self) -> u64 {
+        let bits = self.below(65);
+        if bits == 0 {
+            0
+        } else {
+            self.next_u64() >> (64 - bits)
+        }
+    }
+
+    fn len(&mut self) -> usize {
+        if self.depth > self.max_depth {
+            return 0;
+        }
+        match self.lengths {
+            Lengths::Fixed(n) => n,
+            Lengths::Uniform { min, max } => {
+                if max <= min {
+                    min
+                } else {
+                    min + self.below((max - min) as u64 + 1) as usize
+                }
+            }
+            Lengths::Geometric { mean, max } => {
+                // Each step continues with probability mean / (mean + 1).
+                let p = if mean > 0.0 { mean / (mean + 1.0) } else { 0.0 };
+                let mut n = 0;
+                while n < max && self.unit() < p {
+                    n += 1;
+                }
+                n
+            }
+        }
+    }
+
+    fn string(&mut self) -> String {
+        (0..self.len()).map(|_| self.char()).collect()
+    }
+
+    fn bytes(&mut self) -> Vec<u8> {
+        (0..self.len()).map(|_| self.next_u64() as u8).collect()
+    }
+
+    fn char(&mut self) -> char {
+        // Mostly ASCII, sometimes any scalar value.
+        if self.below(4) != 0 {
+            return (0x20 + self.below(0x5f) as u8) as char;
+        }
+        loop {
+            if let Some(c) = std::char::from_u32(self.below(0x11_0000) as u32) {
+                return c;
+            }
+        }
+    }
+
+    fn nested<T, F>(&mut self, f: F) -> Result<T, DeError>
+    where
+        F: FnOnce(&mut Generator) -> Result<T, DeError>,
+    {
+        // Recursive enums may keep choosing recursive variants after `max_depth`.
+        if self.depth > self.max_depth.saturating_mul(4).max(64) {
+            return Err(DeError::custom("generated value is nested too deeply"));
+        }
+        self.depth += 1;
+        let r = f(self);
+        self.depth -= 1;
+        r
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Generator {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DeError> {
+        Err(DeError::Unsupported("deserialize_any", Offset::UNKNOWN))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_bool(self.below(2) == 1)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_i8(self.next_u64() as i8)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_i16(self.integer() as i16)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_i32(self.integer() as i32)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_i64(self.integer() as i64)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let v = (self.integer() as u128) << 64 | self.integer() as u128;
+        visitor.visit_i128(v as i128)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_u8(self.next_u64() as u8)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_u16(self.integer() as u16)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_u32(self.integer() as u32)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_u64(self.integer())
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let v = (self.integer() as u128) << 64 | self.integer() as u128;
+        visitor.visit_u128(v)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_f32(f32::from_bits(self.next_u64() as u32))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_f64(f64::from_bits(self.next_u64()))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_char(self.char())
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_string(self.string())
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_string(self.string())
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_byte_buf(self.bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_byte_buf(self.bytes())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if self.depth > self.max_depth || self.below(2) == 0 {
+            visitor.visit_none()
+        } else {
+            self.nested(|gen| visitor.visit_some(gen))
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.nested(|gen| visitor.visit_newtype_struct(gen))
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let len = self.len();
+        self.nested(|gen| visitor.visit_seq(Elements { gen, len }))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.nested(|gen| visitor.visit_seq(Elements { gen, len }))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let len = self.len();
+        self.nested(|gen| visitor.visit_map(Elements { gen, len }))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        if variants.is_empty() {
+            return Err(DeError::custom(format_args!(
+                "enum {} has no variants",
+                name
+            )));
+        }
+        let index = self.below(variants.len() as u64) as u32;
+        self.nested(|gen| visitor.visit_enum(Variant { gen, index }))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DeError> {
+        Err(DeError::Unsupported(
+            "deserialize_identifier",
+            Offset::UNKNOWN,
+        ))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DeError> {
+        Err(DeError::Unsupported(
+            "deserialize_ignored_any",
+            Offset::UNKNOWN,
+        ))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct Elements<'a> {
+    gen: &'a mut Generator,
+    len: usize,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for Elements<'a> {
+    type Error = DeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, DeError> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.gen).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de, 'a> de::MapAccess<'de> for Elements<'a> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, DeError> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.gen).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeError> {
+        seed.deserialize(&mut *self.gen)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+struct Variant<'a> {
+    gen: &'a mut Generator,
+    index: u32,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for Variant<'a> {
+    type Error = DeError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), DeError> {
+        let idx: de::value::U32Deserializer<DeError> = self.index.into_deserializer();
+        let v = seed.deserialize(idx)?;
+        Ok((v, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for Variant<'a> {
+    type Error = DeError;
+
+    fn unit_variant(self) -> Result<(), DeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, DeError> {
+        seed.deserialize(self.gen)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, DeError> {
+        de::Deserializer::deserialize_tuple(self.gen, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        de::Deserializer::deserialize_tuple(self.gen, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::from_slice;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Tree {
+        Leaf(u32),
+        Node(Vec<Tree>),
+        Named {
+            name: String,
+            child: Option<Box<Tree>>,
+        },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        id: u64,
+        score: i32,
+        tags: BTreeMap<String, Vec<u8>>,
+        tree: Tree,
+    }
+
+    #[test]
+    fn payloads_are_valid() {
+        let mut gen = Generator::new(1);
+        for _ in 0..200 {
+            let bs = gen.payload::<Record>().unwrap();
+            let v: Record = from_slice(&bs).unwrap();
+            assert_eq!(to_vec(&v).unwrap(), bs);
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        let a: Vec<Record> = Generator::new(7).value().unwrap();
+        let b: Vec<Record> = Generator::new(7).value().unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn lengths() {
+        let mut gen = Generator::new(3).lengths(Lengths::Fixed(5));
+        let v: Vec<String> = gen.value().unwrap();
+        assert_eq!(v.len(), 5);
+        assert!(v.iter().all(|s| s.chars().count() == 5));
+
+        let mut gen = Generator::new(3).lengths(Lengths::Uniform { min: 2, max: 4 });
+        for _ in 0..50 {
+            let v: Vec<u8> = gen.value().unwrap();
+            assert!(2 <= v.len() && v.len() <= 4);
+        }
+
+        let mut gen = Generator::new(3).lengths(Lengths::Geometric { mean: 2.0, max: 10 });
+        let total: usize = (0..1000)
+            .map(|_| gen.value::<Vec<u8>>().unwrap().len())
+            .sum();
+        assert!(1500 < total && total < 2500, "{}", total);
+    }
+
+    #[test]
+    fn recursion_is_bounded() {
+        let mut gen = Generator::new(5).lengths(Lengths::Fixed(3)).max_depth(2);
+        for _ in 0..50 {
+            let _: Tree = gen.value().unwrap();
+        }
+    }
+}
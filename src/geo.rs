@@ -0,0 +1,195 @@
+//! Compact encodings of latitude / longitude coordinates for `#[serde(with = "...")]`.
+//!
+//! Coordinates are encoded as fixed-point integers with the precision of the chosen module,
+//! instead of pairs of 8-byte floats.
+//! `polyline` submodules additionally encode each point as the difference from the previous one,
+//! which is small for GPS tracks.
+//!
+//! | module | decimal digits | resolution at the equator |
+//! |--------|----------------|---------------------------|
+//! | `e5`   | 5              | about 1.1 m               |
+//! | `e6`   | 6              | about 11 cm               |
+//! | `e7`   | 7              | about 1.1 cm              |
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Track {
+//!     #[serde(with = "serde_dokechi::geo::e6")]
+//!     start: (f64, f64),
+//!     #[serde(with = "serde_dokechi::geo::e5::polyline")]
+//!     points: Vec<(f64, f64)>,
+//! }
+//! ```
+
+use serde::de::{Deserialize, Deserializer, Error as _};
+use serde::ser::{Error as _, Serialize, Serializer};
+
+macro_rules! precision_module {
+    ($name:ident, $scale:expr, $doc:expr) => {
+        #[doc = $doc]
+        pub mod $name {
+            use serde::de::Deserializer;
+            use serde::ser::Serializer;
+
+            const SCALE: f64 = $scale;
+
+            /// Serialize a `(latitude, longitude)` point.
+            pub fn serialize<S: Serializer>(v: &(f64, f64), s: S) -> Result<S::Ok, S::Error> {
+                super::serialize_point(v, SCALE, s)
+            }
+
+            /// Deserialize a `(latitude, longitude)` point.
+            pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<(f64, f64), D::Error> {
+                super::deserialize_point(SCALE, d)
+            }
+
+            /// Delta-encoded sequences of `(latitude, longitude)` points.
+            pub mod polyline {
+                use serde::de::Deserializer;
+                use serde::ser::Serializer;
+
+                use super::SCALE;
+
+                /// Serialize a sequence of points.
+                pub fn serialize<S: Serializer>(v: &[(f64, f64)], s: S) -> Result<S::Ok, S::Error> {
+                    super::super::serialize_polyline(v, SCALE, s)
+                }
+
+                /// Deserialize a sequence of points.
+                pub fn deserialize<'de, D: Deserializer<'de>>(
+                    d: D,
+                ) -> Result<Vec<(f64, f64)>, D::Error> {
+                    super::super::deserialize_polyline(SCALE, d)
+                }
+            }
+        }
+    };
+}
+
+precision_module!(e5, 1e5, "Coordinates with 5 decimal digits.");
+precision_module!(e6, 1e6, "Coordinates with 6 decimal digits.");
+precision_module!(e7, 1e7, "Coordinates with 7 decimal digits.");
+
+fn to_fixed<E: serde::ser::Error>(v: f64, scale: f64) -> Result<i64, E> {
+    let x = (v * scale).round();
+    // i64::MAX as f64 rounds up to 2^63, which is out of range.
+    if x.is_finite() && x >= i64::min_value() as f64 && x < i64::max_value() as f64 {
+        Ok(x as i64)
+    } else {
+        Err(E::custom(format_args!("coordinate {} is out of range", v)))
+    }
+}
+
+fn fixed_pair<E: serde::ser::Error>(v: &(f64, f64), scale: f64) -> Result<(i64, i64), E> {
+    Ok((to_fixed(v.0, scale)?, to_fixed(v.1, scale)?))
+}
+
+fn serialize_point<S: Serializer>(v: &(f64, f64), scale: f64, s: S) -> Result<S::Ok, S::Error> {
+    fixed_pair::<S::Error>(v, scale)?.serialize(s)
+}
+
+fn deserialize_point<'de, D: Deserializer<'de>>(scale: f64, d: D) -> Result<(f64, f64), D::Error> {
+    let (lat, lon) = <(i64, i64)>::deserialize(d)?;
+    Ok((lat as f64 / scale, lon as f64 / scale))
+}
+
+fn serialize_polyline<S: Serializer>(
+    v: &[(f64, f64)],
+    scale: f64,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    let mut prev = (0i64, 0i64);
+    let mut deltas = Vec::with_capacity(v.len());
+    for p in v {
+        let cur = fixed_pair::<S::Error>(p, scale)?;
+        let delta = (cur.0.checked_sub(prev.0), cur.1.checked_sub(prev.1));
+        match delta {
+            (Some(lat), Some(lon)) => deltas.push((lat, lon)),
+            _ => return Err(S::Error::custom("coordinate difference overflows")),
+        }
+        prev = cur;
+    }
+    deltas.serialize(s)
+}
+
+fn deserialize_polyline<'de, D: Deserializer<'de>>(
+    scale: f64,
+    d: D,
+) -> Result<Vec<(f64, f64)>, D::Error> {
+    let deltas = Vec::<(i64, i64)>::deserialize(d)?;
+    let mut cur = (0i64, 0i64);
+    let mut v = Vec::with_capacity(deltas.len());
+    for (lat, lon) in deltas {
+        cur = match (cur.0.checked_add(lat), cur.1.checked_add(lon)) {
+            (Some(lat), Some(lon)) => (lat, lon),
+            _ => return Err(D::Error::custom("coordinate difference overflows")),
+        };
+        v.push((cur.0 as f64 / scale, cur.1 as f64 / scale));
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{from_reader, to_writer};
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        #[serde(with = "crate::geo::e7")]
+        p: (f64, f64),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Track {
+        #[serde(with = "crate::geo::e5::polyline")]
+        points: Vec<(f64, f64)>,
+    }
+
+    #[test]
+    fn point() {
+        let v = Point {
+            p: (35.681_236_8, 139.767_125_1),
+        };
+
+        let mut bs = Vec::new();
+        to_writer(&mut bs, &v).unwrap();
+        assert_eq!(bs.len(), 10);
+
+        let d: Point = from_reader(bs.as_slice()).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn polyline() {
+        let v = Track {
+            points: vec![
+                (35.68124, 139.76712),
+                (35.68130, 139.76720),
+                (35.68141, 139.76733),
+                (35.68139, 139.76750),
+            ],
+        };
+
+        let mut bs = Vec::new();
+        to_writer(&mut bs, &v).unwrap();
+        assert!(bs.len() < 1 + 8 + 3 * 4);
+
+        let d: Track = from_reader(bs.as_slice()).unwrap();
+        for (a, b) in d.points.iter().zip(v.points.iter()) {
+            assert!((a.0 - b.0).abs() < 1e-9);
+            assert!((a.1 - b.1).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn non_finite() {
+        let v = Point {
+            p: (std::f64::NAN, 0.0),
+        };
+        let _ = to_writer(Vec::new(), &v).unwrap_err();
+    }
+}
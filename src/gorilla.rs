@@ -0,0 +1,258 @@
+//! Gorilla-style XOR compression of `f64` sequences for `#[serde(with = "...")]`.
+//!
+//! Each value is XORed with the previous one, and only the bits that differ are written.
+//! A repeated value takes 1 bit, and slowly changing values such as sensor samples
+//! take far less than 8 bytes each.
+//!
+//! The sequence is encoded as a byte array of the value count (varuint) followed by the bit stream.
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Samples {
+//!     #[serde(with = "serde_dokechi::gorilla")]
+//!     temperature: Vec<f64>,
+//! }
+//! ```
+
+use std::fmt;
+use std::io;
+
+use serde::de::{Deserializer, Visitor};
+use serde::ser::{Error as _, Serializer};
+
+use crate::varuint::{decode_u64, encode_u64};
+
+/// Serialize a sequence of `f64`.
+pub fn serialize<S: Serializer>(v: &[f64], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_bytes(&encode(v).map_err(S::Error::custom)?)
+}
+
+/// Deserialize a sequence of `f64`.
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<f64>, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<f64>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("XOR compressed f64 sequence")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Vec<f64>, E> {
+            decode(v).map_err(E::custom)
+        }
+    }
+
+    d.deserialize_bytes(BytesVisitor)
+}
+
+/// Compress `values` into bytes.
+pub fn encode(values: &[f64]) -> io::Result<Vec<u8>> {
+    let mut bs = Vec::new();
+    encode_u64(&mut bs, values.len() as u64)?;
+
+    let mut w = BitWriter { bytes: bs, used: 0 };
+    let mut values = values.iter().map(|v| v.to_bits());
+    let mut prev = match values.next() {
+        Some(v) => v,
+        None => return Ok(w.bytes),
+    };
+    w.write(prev, 64);
+
+    // Leading and trailing zero bits of the current window.
+    let mut window: Option<(u32, u32)> = None;
+    for v in values {
+        let xor = v ^ prev;
+        prev = v;
+
+        if xor == 0 {
+            w.write(0, 1);
+            continue;
+        }
+        w.write(1, 1);
+
+        // The leading count is written in 5 bits.
+        let leading = xor.leading_zeros().min(31);
+        let trailing = xor.trailing_zeros();
+        match window {
+            Some((l, t)) if leading >= l && trailing >= t => {
+                w.write(0, 1);
+                w.write(xor >> t, 64 - l - t);
+            }
+            _ => {
+                let meaningful = 64 - leading - trailing;
+                w.write(1, 1);
+                w.write(leading as u64, 5);
+                // 64 meaningful bits are written as 0.
+                w.write((meaningful % 64) as u64, 6);
+                w.write(xor >> trailing, meaningful);
+                window = Some((leading, trailing));
+            }
+        }
+    }
+    Ok(w.bytes)
+}
+
+/// Decompress bytes written by [`encode`](fn.encode.html).
+pub fn decode(bs: &[u8]) -> io::Result<Vec<f64>> {
+    let mut input = bs;
+    let count = decode_u64(&mut input)?;
+    let mut r = BitReader {
+        bytes: input,
+        pos: 0,
+    };
+
+    // Each value takes at least 1 bit, so `count` can't exceed the bits left.
+    if count > (r.bytes.len() as u64) * 8 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    let mut values = Vec::with_capacity(count as usize);
+    if count == 0 {
+        return Ok(values);
+    }
+
+    let mut prev = r.read(64)?;
+    values.push(f64::from_bits(prev));
+
+    let mut window = (0, 0);
+    for _ in 1..count {
+        if r.read(1)? == 1 {
+            if r.read(1)? == 1 {
+                let leading = r.read(5)? as u32;
+                let meaningful = match r.read(6)? as u32 {
+                    0 => 64,
+                    n => n,
+                };
+                if leading + meaningful > 64 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid XOR window",
+                    ));
+                }
+                window = (leading, 64 - leading - meaningful);
+            }
+            let (l, t) = window;
+            prev ^= r.read(64 - l - t)? << t;
+        }
+        values.push(f64::from_bits(prev));
+    }
+    Ok(values)
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits used in the last byte, or 0 if it is full.
+    used: u32,
+}
+
+impl BitWriter {
+    /// Write lower `n` bits of `v`, from the most significant one.
+    fn write(&mut self, v: u64, n: u32) {
+        for i in (0..n).rev() {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            if (v >> i) & 1 == 1 {
+                *self.bytes.last_mut().expect("byte is pushed") |= 0x80 >> self.used;
+            }
+            self.used = (self.used + 1) % 8;
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Position in bits.
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Read `n` bits as lower bits of the result.
+    fn read(&mut self, n: u32) -> io::Result<u64> {
+        if self.pos + n as usize > self.bytes.len() * 8 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+
+        let mut v = 0u64;
+        for _ in 0..n {
+            let bit = (self.bytes[self.pos / 8] >> (7 - self.pos % 8)) & 1;
+            v = v << 1 | bit as u64;
+            self.pos += 1;
+        }
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_slice, to_vec};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Samples {
+        #[serde(with = "super")]
+        values: Vec<f64>,
+    }
+
+    fn round_trip(values: &[f64]) {
+        let bs = encode(values).unwrap();
+        let d = decode(&bs).unwrap();
+        assert_eq!(d.len(), values.len());
+        for (a, b) in d.iter().zip(values) {
+            assert_eq!(a.to_bits(), b.to_bits());
+        }
+    }
+
+    #[test]
+    fn round_trip_values() {
+        round_trip(&[]);
+        round_trip(&[1.5]);
+        round_trip(&[
+            0.0,
+            -0.0,
+            1.0,
+            std::f64::NAN,
+            std::f64::INFINITY,
+            1e-300,
+            1e300,
+        ]);
+        round_trip(&[
+            std::f64::MIN_POSITIVE,
+            -1.0,
+            f64::from_bits(1),
+            f64::from_bits(!0),
+        ]);
+        round_trip(&(0..1000).map(|i| (i as f64).sin()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn slowly_changing_values() {
+        let values: Vec<f64> = (0..1000).map(|i| 20.0 + (i / 50) as f64 * 0.5).collect();
+        round_trip(&values);
+
+        let bs = encode(&values).unwrap();
+        assert!(bs.len() * 10 < values.len() * 8);
+    }
+
+    #[test]
+    fn with_module() {
+        let v = Samples {
+            values: vec![1.0, 1.0, 1.25, 1.5],
+        };
+        let bs = to_vec(&v).unwrap();
+        let d: Samples = from_slice(&bs).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bs = encode(&[1.0, 2.0, 3.0]).unwrap();
+        bs.pop();
+        let _ = decode(&bs).unwrap_err();
+    }
+}
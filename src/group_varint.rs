@@ -0,0 +1,197 @@
+//! Group varint encoding of `u32` sequences for `#[serde(with = "...")]`.
+//!
+//! Values are packed in groups of four. Each group starts with a tag byte holding the byte length
+//! of each value in 2 bits, followed by the values in 1 to 4 little-endian bytes.
+//! Decoding needs one branch per group instead of one per byte,
+//! at the cost of a tag byte for every four values.
+//!
+//! The sequence is encoded as a byte array of the value count (varuint) followed by the groups.
+//! The last group has only the remaining values.
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Postings {
+//!     #[serde(with = "serde_dokechi::group_varint")]
+//!     doc_ids: Vec<u32>,
+//! }
+//! ```
+
+use std::fmt;
+use std::io;
+
+use serde::de::{Deserializer, Visitor};
+use serde::ser::{Error as _, Serializer};
+
+use crate::varuint::{decode_u64, encode_u64};
+
+/// Serialize a sequence of `u32`.
+pub fn serialize<S: Serializer>(v: &[u32], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_bytes(&encode(v).map_err(S::Error::custom)?)
+}
+
+/// Deserialize a sequence of `u32`.
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u32>, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u32>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("group varint u32 sequence")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Vec<u32>, E> {
+            decode(v).map_err(E::custom)
+        }
+    }
+
+    d.deserialize_bytes(BytesVisitor)
+}
+
+/// Encode `values` into bytes.
+pub fn encode(values: &[u32]) -> io::Result<Vec<u8>> {
+    let mut bs = Vec::with_capacity(values.len() * 2 + 9);
+    encode_u64(&mut bs, values.len() as u64)?;
+
+    for group in values.chunks(4) {
+        let tag_pos = bs.len();
+        bs.push(0);
+        let mut tag = 0u8;
+        for (i, &v) in group.iter().enumerate() {
+            let len = byte_len(v);
+            tag |= ((len - 1) as u8) << (i * 2);
+            bs.extend_from_slice(&v.to_le_bytes()[..len]);
+        }
+        bs[tag_pos] = tag;
+    }
+    Ok(bs)
+}
+
+/// Decode bytes written by [`encode`](fn.encode.html).
+pub fn decode(bs: &[u8]) -> io::Result<Vec<u32>> {
+    let mut input = bs;
+    let count = decode_u64(&mut input)?;
+
+    // Each value takes at least 1 byte, so `count` can't exceed the bytes left.
+    if count > input.len() as u64 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    let count = count as usize;
+    let mut values = Vec::with_capacity(count);
+
+    while values.len() < count {
+        let (&tag, rest) = input
+            .split_first()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        input = rest;
+
+        for i in 0..(count - values.len()).min(4) {
+            let len = ((tag >> (i * 2)) & 0b11) as usize + 1;
+            if input.len() < len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            let mut word = [0u8; 4];
+            word[..len].copy_from_slice(&input[..len]);
+            values.push(u32::from_le_bytes(word));
+            input = &input[len..];
+        }
+    }
+
+    if !input.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "trailing bytes after the last group",
+        ));
+    }
+    Ok(values)
+}
+
+/// The number of bytes to hold `v`, at least 1.
+fn byte_len(v: u32) -> usize {
+    match 32 - v.leading_zeros() {
+        0..=8 => 1,
+        9..=16 => 2,
+        17..=24 => 3,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_slice, to_vec};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Postings {
+        #[serde(with = "super")]
+        doc_ids: Vec<u32>,
+    }
+
+    fn round_trip(values: &[u32]) {
+        let bs = encode(values).unwrap();
+        assert_eq!(decode(&bs).unwrap(), values);
+    }
+
+    #[test]
+    fn round_trip_values() {
+        round_trip(&[]);
+        round_trip(&[0]);
+        round_trip(&[1, 300, 70000, u32::max_value()]);
+        round_trip(&[255, 256, 65535, 65536, 16_777_215, 16_777_216, 0]);
+        round_trip(&(0..1000).map(|i| i * 7919).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn layout() {
+        let bs = encode(&[1, 300, 70000, u32::max_value(), 2]).unwrap();
+        assert_eq!(
+            bs,
+            vec![
+                5,
+                0b11_10_01_00,
+                1,
+                0x2C,
+                0x01,
+                0x70,
+                0x11,
+                0x01,
+                0xFF,
+                0xFF,
+                0xFF,
+                0xFF,
+                0b00,
+                2,
+            ]
+        );
+    }
+
+    #[test]
+    fn corrupt() {
+        let bs = encode(&[1, 300, 70000]).unwrap();
+        for len in 0..bs.len() {
+            decode(&bs[..len]).unwrap_err();
+        }
+
+        let mut bs = bs;
+        bs.push(0);
+        decode(&bs).unwrap_err();
+
+        // A count far larger than the input.
+        decode(&[0xC0, 0xFF, 0xFF, 0]).unwrap_err();
+    }
+
+    #[test]
+    fn with_module() {
+        let v = Postings {
+            doc_ids: vec![3, 5, 8, 13, 21],
+        };
+        let bs = to_vec(&v).unwrap();
+        assert_eq!(bs.len(), 1 + 1 + 2 + 5);
+        assert_eq!(from_slice::<Postings>(&bs).unwrap(), v);
+    }
+}
@@ -0,0 +1,120 @@
+//! gRPC message codec for `tonic`.
+//!
+//! [`DokechiCodec`](struct.DokechiCodec.html) replaces protobuf with Dokechi format
+//! for message bodies, keeping the gRPC transport as it is.
+//! Set it as the codec of generated services, e.g. with `codec_path` of `tonic-build`.
+//!
+//! This module is available with the `grpc` feature.
+
+use std::marker::PhantomData;
+
+use bytes::{Buf, BufMut};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::Status;
+
+use crate::de::{self, Deserializer};
+use crate::io::Bytes;
+use crate::ser::{self, Serializer};
+
+/// A gRPC codec encoding `T` and decoding `U` as Dokechi format.
+#[derive(Debug)]
+pub struct DokechiCodec<T, U> {
+    _marker: PhantomData<(T, U)>,
+}
+
+impl<T, U> Default for DokechiCodec<T, U> {
+    fn default() -> DokechiCodec<T, U> {
+        DokechiCodec {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, U> Codec for DokechiCodec<T, U>
+where
+    T: Serialize + Send + 'static,
+    U: DeserializeOwned + Send + 'static,
+{
+    type Encode = T;
+    type Decode = U;
+    type Encoder = DokechiEncoder<T>;
+    type Decoder = DokechiDecoder<U>;
+
+    fn encoder(&mut self) -> DokechiEncoder<T> {
+        DokechiEncoder(PhantomData)
+    }
+
+    fn decoder(&mut self) -> DokechiDecoder<U> {
+        DokechiDecoder(PhantomData)
+    }
+}
+
+/// The encoder of [`DokechiCodec`](struct.DokechiCodec.html).
+#[derive(Debug)]
+pub struct DokechiEncoder<T>(PhantomData<T>);
+
+impl<T: Serialize> Encoder for DokechiEncoder<T> {
+    type Item = T;
+    type Error = Status;
+
+    fn encode(&mut self, item: T, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        encode(&item, dst).map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+/// The decoder of [`DokechiCodec`](struct.DokechiCodec.html).
+#[derive(Debug)]
+pub struct DokechiDecoder<U>(PhantomData<U>);
+
+impl<U: DeserializeOwned> Decoder for DokechiDecoder<U> {
+    type Item = U;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<U>, Status> {
+        decode(src)
+            .map(Some)
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+fn encode<T: Serialize, B: BufMut>(item: &T, dst: B) -> Result<(), ser::Error> {
+    let mut serializer = Serializer::new(Bytes(dst));
+    item.serialize(&mut serializer)
+}
+
+fn decode<U: DeserializeOwned, B: Buf>(src: B) -> Result<U, de::Error> {
+    let mut deserializer = Deserializer::new(Bytes(src));
+    let value = U::deserialize(&mut deserializer)?;
+    if deserializer.into_inner().0.has_remaining() {
+        return Err(serde::de::Error::custom("trailing bytes in message"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::BytesMut;
+
+    #[test]
+    fn round_trip() {
+        let mut buf = BytesMut::new();
+        encode(&(1u32, "message".to_owned()), &mut buf).unwrap();
+        assert_eq!(buf.len(), 9);
+
+        let v: (u32, String) = decode(buf.freeze()).unwrap();
+        assert_eq!(v, (1, "message".to_owned()));
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut buf = BytesMut::new();
+        encode(&1u32, &mut buf).unwrap();
+        buf.put_u8(0);
+
+        let _ = decode::<u32, _>(buf.freeze()).unwrap_err();
+    }
+}
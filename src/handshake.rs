@@ -0,0 +1,262 @@
+//! Protocol version and feature negotiation for bidirectional streams.
+//!
+//! Each side sends its [`Handshake`](struct.Handshake.html) and reads the peer's one.
+//! The highest version both sides support and the features both sides enable are agreed,
+//! and a [`Session`](struct.Session.html) with serializer and deserializer over the stream is returned.
+//!
+//! Handshake message format:
+//!
+//! ```text
+//! magic (b"DKCH") , version (varuint) , minimum version (varuint) , feature flags (varuint)
+//! ```
+//!
+//! ```
+//! use serde_dokechi::handshake::{Features, Handshake};
+//!
+//! let local = Handshake::new(2, Features::CANONICAL | Features::ZSTD).min_version(1);
+//!
+//! // The peer's message. Usually this is read from a socket.
+//! let mut peer = Vec::new();
+//! Handshake::new(1, Features::ZSTD).write_to(&mut peer).unwrap();
+//!
+//! let session = local.negotiate(peer.as_slice(), Vec::new()).unwrap();
+//! assert_eq!(session.agreement.version, 1);
+//! assert_eq!(session.agreement.features, Features::ZSTD);
+//! ```
+
+use std::io::{Read, Write};
+use std::ops::{BitAnd, BitOr};
+
+use serde::de::Error as _;
+
+use crate::de::Deserializer;
+use crate::ser::Serializer;
+use crate::varuint::{decode_u64, encode_u64};
+use crate::Error;
+
+const MAGIC: &[u8; 4] = b"DKCH";
+
+/// A set of optional format features.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Features(u64);
+
+impl Features {
+    /// No features.
+    pub const NONE: Features = Features(0);
+    /// Canonical encoding.
+    pub const CANONICAL: Features = Features(1);
+    /// Shared dictionaries.
+    pub const DICTIONARY: Features = Features(1 << 1);
+    /// zstd compression.
+    pub const ZSTD: Features = Features(1 << 2);
+    /// LZ4 compression.
+    pub const LZ4: Features = Features(1 << 3);
+
+    /// Create from raw flag bits.
+    ///
+    /// Bits unknown to this version are kept, so applications can use them for their own features.
+    pub fn from_bits(bits: u64) -> Features {
+        Features(bits)
+    }
+
+    /// Raw flag bits.
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Whether all features of `other` are contained.
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, rhs: Features) -> Features {
+        Features(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Features {
+    type Output = Features;
+
+    fn bitand(self, rhs: Features) -> Features {
+        Features(self.0 & rhs.0)
+    }
+}
+
+/// Supported versions and features of one side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handshake {
+    /// The highest supported protocol version.
+    pub version: u64,
+    /// The lowest supported protocol version.
+    pub min_version: u64,
+    /// Enabled features.
+    pub features: Features,
+}
+
+/// The configuration agreed by both sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Agreement {
+    /// The protocol version to use.
+    pub version: u64,
+    /// Features enabled on both sides.
+    pub features: Features,
+}
+
+/// A negotiated stream.
+#[derive(Debug)]
+pub struct Session<R: Read, W: Write> {
+    /// The agreed configuration.
+    pub agreement: Agreement,
+    /// Serializer writing to the peer.
+    pub serializer: Serializer<W>,
+    /// Deserializer reading from the peer.
+    pub deserializer: Deserializer<R>,
+}
+
+impl Handshake {
+    /// Create new `Handshake` supporting only `version`.
+    pub fn new(version: u64, features: Features) -> Handshake {
+        Handshake {
+            version,
+            min_version: version,
+            features,
+        }
+    }
+
+    /// Set the lowest supported protocol version.
+    pub fn min_version(self, min_version: u64) -> Handshake {
+        Handshake {
+            min_version,
+            ..self
+        }
+    }
+
+    /// Write the handshake message.
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<(), Error> {
+        w.write_all(MAGIC)?;
+        encode_u64(&mut w, self.version)?;
+        encode_u64(&mut w, self.min_version)?;
+        encode_u64(&mut w, self.features.bits())?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Read a handshake message.
+    pub fn read_from<R: Read>(mut r: R) -> Result<Handshake, Error> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::custom("not a handshake message"));
+        }
+        let version = decode_u64(&mut r)?;
+        let min_version = decode_u64(&mut r)?;
+        let features = Features::from_bits(decode_u64(&mut r)?);
+        Ok(Handshake {
+            version,
+            min_version,
+            features,
+        })
+    }
+
+    /// Compute the agreement with the peer.
+    pub fn agree(&self, peer: &Handshake) -> Result<Agreement, Error> {
+        let version = self.version.min(peer.version);
+        if version < self.min_version.max(peer.min_version) {
+            return Err(Error::custom(format_args!(
+                "no common protocol version: local {}..={}, peer {}..={}",
+                self.min_version, self.version, peer.min_version, peer.version
+            )));
+        }
+        Ok(Agreement {
+            version,
+            features: self.features & peer.features,
+        })
+    }
+
+    /// Exchange handshake messages with the peer and start a session.
+    pub fn negotiate<R: Read, W: Write>(&self, mut r: R, mut w: W) -> Result<Session<R, W>, Error> {
+        self.write_to(&mut w)?;
+        let peer = Handshake::read_from(&mut r)?;
+        let agreement = self.agree(&peer)?;
+        Ok(Session {
+            agreement,
+            serializer: Serializer::new(w),
+            deserializer: Deserializer::new(r),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn agree() {
+        let a = Handshake::new(3, Features::CANONICAL | Features::LZ4).min_version(1);
+        let b = Handshake::new(2, Features::LZ4 | Features::DICTIONARY).min_version(2);
+
+        let agreement = a.agree(&b).unwrap();
+        assert_eq!(agreement.version, 2);
+        assert_eq!(agreement.features, Features::LZ4);
+        assert_eq!(b.agree(&a).unwrap(), agreement);
+    }
+
+    #[test]
+    fn no_common_version() {
+        let a = Handshake::new(3, Features::NONE).min_version(3);
+        let b = Handshake::new(2, Features::NONE);
+        let _ = a.agree(&b).unwrap_err();
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let h = Handshake::new(5, Features::from_bits(1 << 40) | Features::ZSTD).min_version(2);
+
+        let mut bs = Vec::new();
+        h.write_to(&mut bs).unwrap();
+        assert_eq!(Handshake::read_from(bs.as_slice()).unwrap(), h);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let _ = Handshake::read_from(&b"HTTP/1.1"[..]).unwrap_err();
+    }
+
+    #[test]
+    fn negotiate_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let h = Handshake::new(2, Features::CANONICAL | Features::ZSTD).min_version(1);
+            let mut session = h.negotiate(stream.try_clone().unwrap(), stream).unwrap();
+
+            let v = String::deserialize(&mut session.deserializer).unwrap();
+            v.len().serialize(&mut session.serializer).unwrap();
+            session.serializer.end().unwrap();
+            session.agreement
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let h = Handshake::new(1, Features::ZSTD);
+        let mut session = h.negotiate(stream.try_clone().unwrap(), stream).unwrap();
+        "hello".serialize(&mut session.serializer).unwrap();
+        session.serializer.end().unwrap();
+        let n = usize::deserialize(&mut session.deserializer).unwrap();
+
+        assert_eq!(n, 5);
+        assert_eq!(session.agreement, server.join().unwrap());
+        assert_eq!(session.agreement.version, 1);
+        assert_eq!(session.agreement.features, Features::ZSTD);
+    }
+}
@@ -0,0 +1,181 @@
+//! HTTP client helpers for `reqwest`.
+//!
+//! [`RequestBuilderExt::dokechi_body`](trait.RequestBuilderExt.html#tymethod.dokechi_body) sends a value
+//! as the request body, and [`ResponseExt::dokechi`](trait.ResponseExt.html#tymethod.dokechi)
+//! reads a response body into a typed value.
+//! Both ends use [`CONTENT_TYPE`](constant.CONTENT_TYPE.html) as the content type.
+//!
+//! This module is available with the `reqwest` feature.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+
+use reqwest::header::{HeaderValue, CONTENT_TYPE as CONTENT_TYPE_HEADER};
+use reqwest::{RequestBuilder, Response};
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Serialize;
+
+use crate::de::from_slice;
+use crate::error::{Error, Offset};
+use crate::ser::{self, to_vec};
+
+/// The content type of Dokechi format bodies.
+pub const CONTENT_TYPE: &str = "application/x-dokechi";
+
+/// The default size limit of response bodies in bytes.
+pub const DEFAULT_LIMIT: usize = 16 * 1024 * 1024;
+
+/// A boxed future returned by [`ResponseExt`](trait.ResponseExt.html).
+pub type ResponseFuture<T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send>>;
+
+/// Extension methods of `reqwest::RequestBuilder`.
+pub trait RequestBuilderExt: Sized {
+    /// Set `value` in Dokechi format as the body, with the content type.
+    fn dokechi_body<T: Serialize + ?Sized>(self, value: &T) -> Result<Self, ser::Error>;
+}
+
+impl RequestBuilderExt for RequestBuilder {
+    fn dokechi_body<T: Serialize + ?Sized>(self, value: &T) -> Result<RequestBuilder, ser::Error> {
+        let body = to_vec(value)?;
+        Ok(self
+            .header(CONTENT_TYPE_HEADER, HeaderValue::from_static(CONTENT_TYPE))
+            .body(body))
+    }
+}
+
+/// Extension methods of `reqwest::Response`.
+pub trait ResponseExt {
+    /// Read the body in Dokechi format, up to [`DEFAULT_LIMIT`](constant.DEFAULT_LIMIT.html) bytes.
+    fn dokechi<T: DeserializeOwned + Send + 'static>(self) -> ResponseFuture<T>;
+
+    /// Read the body in Dokechi format, up to `limit` bytes.
+    ///
+    /// Returns error if the content type is not [`CONTENT_TYPE`](constant.CONTENT_TYPE.html)
+    /// or the body is larger than `limit`.
+    fn dokechi_with_limit<T: DeserializeOwned + Send + 'static>(
+        self,
+        limit: usize,
+    ) -> ResponseFuture<T>;
+}
+
+impl ResponseExt for Response {
+    fn dokechi<T: DeserializeOwned + Send + 'static>(self) -> ResponseFuture<T> {
+        self.dokechi_with_limit(DEFAULT_LIMIT)
+    }
+
+    fn dokechi_with_limit<T: DeserializeOwned + Send + 'static>(
+        self,
+        limit: usize,
+    ) -> ResponseFuture<T> {
+        Box::pin(read_response(self, limit))
+    }
+}
+
+async fn read_response<T: DeserializeOwned>(mut resp: Response, limit: usize) -> Result<T, Error> {
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or("").trim().to_owned());
+    if content_type.as_deref() != Some(CONTENT_TYPE) {
+        return Err(Error::custom(format_args!(
+            "unexpected content type {:?}",
+            content_type.unwrap_or_default()
+        )));
+    }
+
+    let too_large = || Error::custom(format_args!("body is larger than {} bytes", limit));
+    if resp
+        .content_length()
+        .map_or(false, |len| len > limit as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(transport_error)? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(from_slice(&body)?)
+}
+
+fn transport_error(e: reqwest::Error) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::Other, e), Offset::UNKNOWN)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // Bodies in tests are in memory, so they are always ready.
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+        let mut f = Box::pin(f);
+        loop {
+            if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+                return v;
+            }
+        }
+    }
+
+    fn response(content_type: &str, body: Vec<u8>) -> Response {
+        let resp = ::http::Response::builder()
+            .header("content-type", content_type)
+            .body(body)
+            .unwrap();
+        Response::from(resp)
+    }
+
+    #[test]
+    fn request_body() {
+        let req = reqwest::Client::new()
+            .post("http://localhost/")
+            .dokechi_body(&(1u32, "abc"))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(req.headers()[CONTENT_TYPE_HEADER], CONTENT_TYPE);
+        assert_eq!(
+            req.body().unwrap().as_bytes().unwrap(),
+            &to_vec((1u32, "abc")).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn read_body() {
+        let body = to_vec(vec!["x".to_owned(); 3]).unwrap();
+        let resp = response("application/x-dokechi; charset=binary", body);
+        let v: Vec<String> = block_on(resp.dokechi()).unwrap();
+        assert_eq!(v, vec!["x".to_owned(); 3]);
+    }
+
+    #[test]
+    fn wrong_content_type() {
+        let body = to_vec(1u32).unwrap();
+        let resp = response("application/json", body);
+        let _ = block_on(resp.dokechi::<u32>()).unwrap_err();
+    }
+
+    #[test]
+    fn too_large() {
+        let body = to_vec(vec![0u8; 100]).unwrap();
+        let resp = response(CONTENT_TYPE, body);
+        let _ = block_on(resp.dokechi_with_limit::<Vec<u8>>(50)).unwrap_err();
+    }
+}
@@ -0,0 +1,434 @@
+//! Byte sources and sinks of serializer and deserializer.
+//!
+//! [`Serializer`](../ser/struct.Serializer.html) and [`Deserializer`](../de/struct.Deserializer.html)
+//! only need [`Output`](trait.Output.html) and [`Input`](trait.Input.html),
+//! so byte sources other than `std::io`, such as ring buffers and DMA regions, can be used
+//! by implementing these traits.
+//!
+//! Every `std::io::Read` is an `Input` and every `std::io::Write` is an `Output`.
+//! Wrappers for `bytes` buffers and `embedded-io` streams are available
+//! with the `bytes` and `embedded-io` features.
+
+use std::io::{self, Read, Write};
+
+/// A source of bytes.
+pub trait Input {
+    /// Read the exact number of bytes required to fill `buf`.
+    ///
+    /// Returns `UnexpectedEof` error if the source ends before filling `buf`.
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Take the next `len` bytes as `bytes::Bytes`.
+    ///
+    /// Returns `None` if this source doesn't support it, and then the bytes are read with `read_exact`.
+    /// The default implementation returns `None`.
+    ///
+    /// This method is available with the `bytes` feature.
+    #[cfg(feature = "bytes")]
+    fn take_bytes(&mut self, len: usize) -> Option<io::Result<bytes::Bytes>> {
+        let _ = len;
+        None
+    }
+}
+
+/// An `Input` which knows how many bytes are left, such as a slice.
+pub trait SizedInput: Input {
+    /// The number of bytes left in this source.
+    fn remaining(&self) -> usize;
+}
+
+impl SizedInput for &[u8] {
+    fn remaining(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: AsRef<[u8]>> SizedInput for io::Cursor<T> {
+    fn remaining(&self) -> usize {
+        let len = self.get_ref().as_ref().len() as u64;
+        len.saturating_sub(self.position()) as usize
+    }
+}
+
+impl<R: SizedInput + Read + ?Sized> SizedInput for &mut R {
+    fn remaining(&self) -> usize {
+        (**self).remaining()
+    }
+}
+
+/// A sink of bytes.
+pub trait Output {
+    /// Write entire `buf`.
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Flush buffered bytes to the underlying sink.
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Read `len` bytes into `buf`, replacing its content.
+///
+/// `buf` grows as bytes arrive, so a corrupt length can't allocate much more memory than the input has.
+pub(crate) fn read_to_vec<R: Input + ?Sized>(
+    r: &mut R,
+    buf: &mut Vec<u8>,
+    len: usize,
+) -> io::Result<()> {
+    const CHUNK_LEN: usize = 64 * 1024;
+
+    buf.clear();
+    while buf.len() < len {
+        let start = buf.len();
+        buf.resize(start + (len - start).min(CHUNK_LEN), 0);
+        r.read_exact(&mut buf[start..])?;
+    }
+    Ok(())
+}
+
+impl<R: Read> Input for R {
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        Read::read_exact(self, buf)
+    }
+}
+
+impl<W: Write> Output for W {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        Write::write_all(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+/// An `Input` of `bytes::Buf`, or an `Output` of `bytes::BufMut`.
+///
+/// This type is available with the `bytes` feature.
+#[cfg(feature = "bytes")]
+#[derive(Debug)]
+pub struct Bytes<B>(pub B);
+
+#[cfg(feature = "bytes")]
+impl<B: bytes::Buf> Input for Bytes<B> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if self.0.remaining() < buf.len() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        self.0.copy_to_slice(buf);
+        Ok(())
+    }
+
+    /// Take bytes with `Buf::copy_to_bytes`, which shares the buffer if `B` is `bytes::Bytes`.
+    fn take_bytes(&mut self, len: usize) -> Option<io::Result<bytes::Bytes>> {
+        if self.0.remaining() < len {
+            return Some(Err(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        }
+        Some(Ok(self.0.copy_to_bytes(len)))
+    }
+}
+
+/// A byte array decoded as `bytes::Bytes`.
+///
+/// When decoded by a [`Deserializer`](../de/struct.Deserializer.html) of [`Bytes`](struct.Bytes.html)
+/// of `bytes::Bytes`, it is a slice sharing the input buffer instead of a copy,
+/// so fragments of a payload can be kept or forwarded without copying.
+/// It is encoded in the same way as `Vec<u8>`.
+///
+/// This type is available with the `bytes` feature.
+#[cfg(feature = "bytes")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SharedBytes(pub bytes::Bytes);
+
+/// The newtype name [`SharedBytes`](struct.SharedBytes.html) is deserialized as,
+/// to be recognized by the deserializer.
+#[cfg(feature = "bytes")]
+pub(crate) const SHARED_BYTES_TOKEN: &str = "$serde_dokechi::SharedBytes";
+
+#[cfg(feature = "bytes")]
+thread_local! {
+    // A slice taken by the deserializer, passed to the `SharedBytes` visitor.
+    static TAKEN: std::cell::RefCell<Option<bytes::Bytes>> = std::cell::RefCell::new(None);
+}
+
+/// Pass `bs` to the `SharedBytes` visitor visited in `visit`.
+///
+/// The slot is emptied after `visit`, so a slice not taken by the visitor can't leak into a later visit.
+/// Returns `None` in that case.
+#[cfg(feature = "bytes")]
+pub(crate) fn pass_shared_bytes<T>(bs: bytes::Bytes, visit: impl FnOnce() -> T) -> Option<T> {
+    TAKEN.with(|t| *t.borrow_mut() = Some(bs));
+    let res = visit();
+    match TAKEN.with(|t| t.borrow_mut().take()) {
+        Some(_) => None,
+        None => Some(res),
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl std::ops::Deref for SharedBytes {
+    type Target = bytes::Bytes;
+
+    fn deref(&self) -> &bytes::Bytes {
+        &self.0
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl serde::Serialize for SharedBytes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<'de> serde::Deserialize<'de> for SharedBytes {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<SharedBytes, D::Error> {
+        use std::fmt;
+
+        use serde::de::{self, Visitor};
+
+        struct SharedBytesVisitor;
+
+        impl<'de> Visitor<'de> for SharedBytesVisitor {
+            type Value = SharedBytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("bytes")
+            }
+
+            // Visited by the Dokechi deserializer after passing the slice.
+            fn visit_unit<E: de::Error>(self) -> Result<SharedBytes, E> {
+                TAKEN
+                    .with(|t| t.borrow_mut().take())
+                    .map(SharedBytes)
+                    .ok_or_else(|| E::invalid_type(de::Unexpected::Unit, &self))
+            }
+
+            fn visit_newtype_struct<D: serde::Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<SharedBytes, D::Error> {
+                deserializer.deserialize_byte_buf(self)
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<SharedBytes, E> {
+                Ok(SharedBytes(bytes::Bytes::copy_from_slice(v)))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<SharedBytes, E> {
+                Ok(SharedBytes(bytes::Bytes::from(v)))
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(SHARED_BYTES_TOKEN, SharedBytesVisitor)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<B: bytes::Buf> SizedInput for Bytes<B> {
+    fn remaining(&self) -> usize {
+        self.0.remaining()
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<B: bytes::BufMut> Output for Bytes<B> {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.0.remaining_mut() < buf.len() {
+            return Err(io::Error::from(io::ErrorKind::WriteZero));
+        }
+        self.0.put_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An `Input` of `embedded_io::Read`, or an `Output` of `embedded_io::Write`.
+///
+/// This type is available with the `embedded-io` feature.
+#[cfg(feature = "embedded-io")]
+#[derive(Debug)]
+pub struct EmbeddedIo<T>(pub T);
+
+#[cfg(feature = "embedded-io")]
+impl<T: embedded_io::Read> Input for EmbeddedIo<T> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.0.read_exact(buf).map_err(|e| match e {
+            embedded_io::ReadExactError::UnexpectedEof => {
+                io::Error::from(io::ErrorKind::UnexpectedEof)
+            }
+            embedded_io::ReadExactError::Other(e) => embedded_error(e),
+        })
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T: embedded_io::Write> Output for EmbeddedIo<T> {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf).map_err(embedded_error)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().map_err(embedded_error)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+fn embedded_error<E: embedded_io::Error>(e: E) -> io::Error {
+    let kind = match e.kind() {
+        embedded_io::ErrorKind::NotFound => io::ErrorKind::NotFound,
+        embedded_io::ErrorKind::PermissionDenied => io::ErrorKind::PermissionDenied,
+        embedded_io::ErrorKind::ConnectionRefused => io::ErrorKind::ConnectionRefused,
+        embedded_io::ErrorKind::ConnectionReset => io::ErrorKind::ConnectionReset,
+        embedded_io::ErrorKind::ConnectionAborted => io::ErrorKind::ConnectionAborted,
+        embedded_io::ErrorKind::NotConnected => io::ErrorKind::NotConnected,
+        embedded_io::ErrorKind::BrokenPipe => io::ErrorKind::BrokenPipe,
+        embedded_io::ErrorKind::InvalidInput => io::ErrorKind::InvalidInput,
+        embedded_io::ErrorKind::InvalidData => io::ErrorKind::InvalidData,
+        embedded_io::ErrorKind::TimedOut => io::ErrorKind::TimedOut,
+        embedded_io::ErrorKind::Interrupted => io::ErrorKind::Interrupted,
+        embedded_io::ErrorKind::WriteZero => io::ErrorKind::WriteZero,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("{:?}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::VecDeque;
+
+    use crate::de::Deserializer;
+    use crate::ser::Serializer;
+    use serde::{Deserialize, Serialize};
+
+    /// A ring buffer which implements neither `Read` nor `Write`.
+    struct Ring(VecDeque<u8>);
+
+    impl Input for Ring {
+        fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+            if self.0.len() < buf.len() {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            for b in buf.iter_mut() {
+                *b = self.0.pop_front().unwrap();
+            }
+            Ok(())
+        }
+    }
+
+    impl Output for Ring {
+        fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+            self.0.extend(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn custom_input_output() {
+        let v = vec!["abc".to_owned(), "defg".to_owned()];
+
+        let mut ser = Serializer::new(Ring(VecDeque::new()));
+        v.serialize(&mut ser).unwrap();
+        ser.end().unwrap();
+        let ring = ser.into_inner();
+        assert_eq!(ring.0.len(), 10);
+
+        let mut de = Deserializer::new(ring);
+        let d: Vec<String> = Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn bytes_buf() {
+        let mut ser = Serializer::new(Bytes(bytes::BytesMut::new()));
+        (1u32, "xy").serialize(&mut ser).unwrap();
+        let bs = ser.into_inner().0.freeze();
+
+        let mut de = Deserializer::new(Bytes(bs));
+        let d: (u32, String) = Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(d, (1, "xy".to_owned()));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn shared_bytes_zero_copy() {
+        let mut ser = Serializer::new(Vec::new());
+        (1u32, vec![7u8; 100], "xy").serialize(&mut ser).unwrap();
+        let bs = bytes::Bytes::from(ser.into_inner());
+
+        let mut de = Deserializer::new(Bytes(bs.clone()));
+        let (n, shared, s): (u32, SharedBytes, String) = Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!((n, s.as_str()), (1, "xy"));
+        assert_eq!(&shared[..], &[7u8; 100][..]);
+        assert_eq!(shared.as_ptr(), bs[2..].as_ptr());
+
+        let copied: (u32, SharedBytes, String) = crate::from_slice(&bs).unwrap();
+        assert_eq!(copied.1, shared);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn shared_bytes_not_taken() {
+        // A newtype with the name of `SharedBytes` but another visitor.
+        struct Impostor;
+
+        impl<'de> Deserialize<'de> for Impostor {
+            fn deserialize<D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<Impostor, D::Error> {
+                struct ImpostorVisitor;
+
+                impl<'de> serde::de::Visitor<'de> for ImpostorVisitor {
+                    type Value = Impostor;
+
+                    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        f.write_str("unit")
+                    }
+
+                    fn visit_unit<E>(self) -> Result<Impostor, E> {
+                        Ok(Impostor)
+                    }
+                }
+
+                deserializer.deserialize_newtype_struct(SHARED_BYTES_TOKEN, ImpostorVisitor)
+            }
+        }
+
+        let bs = bytes::Bytes::from(vec![2u8, 7, 7]);
+        let mut de = Deserializer::new(Bytes(bs));
+        match Impostor::deserialize(&mut de) {
+            Err(crate::de::Error::Unsupported(..)) => {}
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+        assert!(TAKEN.with(|t| t.borrow().is_none()));
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn bytes_unexpected_eof() {
+        let mut de = Deserializer::new(Bytes(&[3u8, b'a'][..]));
+        let _ = <String as Deserialize>::deserialize(&mut de).unwrap_err();
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[test]
+    fn embedded_io_slice() {
+        let mut buf = [0u8; 8];
+        let mut ser = Serializer::new(EmbeddedIo(&mut buf[..]));
+        (300u16, 'x').serialize(&mut ser).unwrap();
+        ser.end().unwrap();
+
+        let mut de = Deserializer::new(EmbeddedIo(&buf[..]));
+        let d: (u16, char) = Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(d, (300, 'x'));
+    }
+}
@@ -0,0 +1,811 @@
+//! Append-only logs of Dokechi format records.
+//!
+//! Record format:
+//!
+//! ```text
+//! payload length (varuint) , payload [ , back pointer ]
+//! ```
+//!
+//! The back pointer is written only when the log is created with back pointers.
+//! It is the byte size of the record before it (`payload length` and `payload`) as varuint
+//! with its bytes in reversed order, so the log can be read from the end backwards
+//! with [`ReverseLogReader`](struct.ReverseLogReader.html).
+//! Writers and readers of the same log must agree on whether back pointers are used.
+//!
+//! [`SegmentedLogWriter`](struct.SegmentedLogWriter.html) spreads a log over segment files in a directory,
+//! rolling to a new segment as configured by [`Rotation`](struct.Rotation.html).
+//! The directory has a `MANIFEST` file listing the segment IDs in order as a Dokechi format `Vec<u64>`,
+//! and the segment with ID `n` is named `n` in 20 decimal digits with extension `.log`.
+//! Segments hold records without back pointers.
+
+use std::convert::TryFrom;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::{Error as _, Serialize};
+
+use crate::de::{self, from_reader};
+use crate::error::Error;
+use crate::io::read_to_vec;
+use crate::ser::{self, Serializer};
+use crate::varuint::{decode_u64, encode_u64};
+
+/// Appends records to a log.
+#[derive(Debug)]
+pub struct LogWriter<W: Write> {
+    w: W,
+    back_pointers: bool,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> LogWriter<W> {
+    /// Create new `LogWriter` which writes records without back pointers.
+    pub fn new(w: W) -> LogWriter<W> {
+        LogWriter {
+            w,
+            back_pointers: false,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Create new `LogWriter` which writes records with back pointers.
+    pub fn with_back_pointers(w: W) -> LogWriter<W> {
+        LogWriter {
+            w,
+            back_pointers: true,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Serialize `value` and append it as a record.
+    pub fn append<T: Serialize>(&mut self, value: &T) -> Result<(), ser::Error> {
+        self.buf.clear();
+        encode_record(&mut self.buf, value, self.back_pointers)?;
+        self.w.write_all(&self.buf)?;
+        Ok(())
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> Result<(), ser::Error> {
+        self.w.flush()?;
+        Ok(())
+    }
+
+    /// Unwrap this `LogWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// Appends records to a log shared by multiple threads.
+///
+/// Each record is serialized into a private buffer first, then written with a single `write_all` call
+/// under a lock, so records of concurrent writers never interleave.
+/// When multiple processes append to the same file, open it in append mode.
+#[derive(Debug)]
+pub struct SharedLogWriter<W: Write> {
+    w: Mutex<W>,
+    back_pointers: bool,
+}
+
+impl<W: Write> SharedLogWriter<W> {
+    /// Create new `SharedLogWriter` which writes records without back pointers.
+    pub fn new(w: W) -> SharedLogWriter<W> {
+        SharedLogWriter {
+            w: Mutex::new(w),
+            back_pointers: false,
+        }
+    }
+
+    /// Create new `SharedLogWriter` which writes records with back pointers.
+    pub fn with_back_pointers(w: W) -> SharedLogWriter<W> {
+        SharedLogWriter {
+            w: Mutex::new(w),
+            back_pointers: true,
+        }
+    }
+
+    /// Serialize `value` and append it as a record.
+    pub fn append<T: Serialize>(&self, value: &T) -> Result<(), ser::Error> {
+        let mut buf = Vec::new();
+        encode_record(&mut buf, value, self.back_pointers)?;
+
+        let mut w = self.lock()?;
+        w.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&self) -> Result<(), ser::Error> {
+        self.lock()?.flush()?;
+        Ok(())
+    }
+
+    /// Unwrap this `SharedLogWriter`, returning the underlying writer.
+    pub fn into_inner(self) -> Result<W, ser::Error> {
+        self.w.into_inner().map_err(|_| poisoned())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, W>, ser::Error> {
+        self.w.lock().map_err(|_| poisoned())
+    }
+}
+
+fn poisoned() -> ser::Error {
+    ser::Error::custom("a writer thread panicked while appending")
+}
+
+fn encode_record<T: Serialize>(
+    buf: &mut Vec<u8>,
+    value: &T,
+    back_pointer: bool,
+) -> Result<(), ser::Error> {
+    let mut payload = Vec::new();
+    value.serialize(&mut Serializer::new(&mut payload))?;
+
+    let start = buf.len();
+    encode_u64(&mut *buf, payload.len() as u64)?;
+    buf.extend_from_slice(&payload);
+
+    if back_pointer {
+        let record_len = buf.len() - start;
+        let pointer_start = buf.len();
+        encode_u64(&mut *buf, record_len as u64)?;
+        buf[pointer_start..].reverse();
+    }
+    Ok(())
+}
+
+/// Reads records of a log from the beginning.
+#[derive(Debug)]
+pub struct LogReader<R: Read> {
+    r: R,
+    back_pointers: bool,
+}
+
+impl<R: Read> LogReader<R> {
+    /// Create new `LogReader` of a log written without back pointers.
+    pub fn new(r: R) -> LogReader<R> {
+        LogReader {
+            r,
+            back_pointers: false,
+        }
+    }
+
+    /// Create new `LogReader` of a log written with back pointers.
+    pub fn with_back_pointers(r: R) -> LogReader<R> {
+        LogReader {
+            r,
+            back_pointers: true,
+        }
+    }
+
+    /// Read the next record.
+    ///
+    /// Returns `None` if the log ends at a record boundary.
+    pub fn read_record<T: DeserializeOwned>(&mut self) -> Result<Option<T>, de::Error> {
+        match self.read_payload()? {
+            Some(payload) => Ok(Some(from_reader(payload.as_slice())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn read_payload(&mut self) -> Result<Option<Vec<u8>>, de::Error> {
+        let mut head = [0u8];
+        loop {
+            match self.r.read(&mut head) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let len = decode_u64(&mut (&head[..]).chain(&mut self.r))?;
+        let len = usize::try_from(len).map_err(|_| de::Error::custom("record too large"))?;
+        // The payload grows as bytes arrive, so a corrupt length fails at the end of the log.
+        let mut payload = Vec::new();
+        read_to_vec(&mut self.r, &mut payload, len)?;
+
+        if self.back_pointers {
+            let record_len = varuint_len(head[0]) as u64 + len as u64;
+            let mut to_be = Vec::new();
+            encode_u64(&mut to_be, record_len)?;
+            to_be.reverse();
+
+            let mut pointer = vec![0u8; to_be.len()];
+            self.r.read_exact(&mut pointer)?;
+            if pointer != to_be {
+                return Err(de::Error::custom("invalid back pointer"));
+            }
+        }
+
+        Ok(Some(payload))
+    }
+}
+
+fn varuint_len(head: u8) -> usize {
+    let ones = (!head).leading_zeros() as usize;
+    if ones < 8 {
+        ones + 1
+    } else {
+        9
+    }
+}
+
+/// Reads records of a log written with back pointers from the end backwards.
+#[derive(Debug)]
+pub struct ReverseLogReader<R: Read + Seek> {
+    r: R,
+    pos: u64,
+}
+
+impl<R: Read + Seek> ReverseLogReader<R> {
+    /// Create new `ReverseLogReader` positioned at the end of the log.
+    pub fn new(mut r: R) -> Result<ReverseLogReader<R>, de::Error> {
+        let pos = r.seek(SeekFrom::End(0))?;
+        Ok(ReverseLogReader { r, pos })
+    }
+
+    /// Read the record before the current position.
+    ///
+    /// Returns `None` if the beginning of the log is reached.
+    pub fn read_prev<T: DeserializeOwned>(&mut self) -> Result<Option<T>, de::Error> {
+        if self.pos == 0 {
+            return Ok(None);
+        }
+
+        let mut head = [0u8];
+        self.r.seek(SeekFrom::Start(self.pos - 1))?;
+        self.r.read_exact(&mut head)?;
+
+        let pointer_len = varuint_len(head[0]) as u64;
+        if pointer_len > self.pos {
+            return Err(de::Error::custom("invalid back pointer"));
+        }
+
+        let mut pointer = vec![0u8; pointer_len as usize];
+        self.r.seek(SeekFrom::Start(self.pos - pointer_len))?;
+        self.r.read_exact(&mut pointer)?;
+        pointer.reverse();
+        let record_len = decode_u64(&mut pointer.as_slice())?;
+
+        let start = (self.pos - pointer_len)
+            .checked_sub(record_len)
+            .ok_or_else(|| de::Error::custom("invalid back pointer"))?;
+        self.r.seek(SeekFrom::Start(start))?;
+
+        // The back pointer bounds the record by the log size, and the length must fill the record.
+        self.r.read_exact(&mut head)?;
+        let len = decode_u64(&mut (&head[..]).chain(&mut self.r))?;
+        if record_len.checked_sub(varuint_len(head[0]) as u64) != Some(len) {
+            return Err(de::Error::custom("invalid record length"));
+        }
+        let mut payload = Vec::new();
+        read_to_vec(&mut self.r, &mut payload, len as usize)?;
+
+        self.pos = start;
+        Ok(Some(from_reader(payload.as_slice())?))
+    }
+}
+
+/// When [`SegmentedLogWriter`](struct.SegmentedLogWriter.html) rolls to a new segment,
+/// and how many segments it keeps.
+///
+/// By default, segments are never rolled nor pruned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    max_bytes: Option<u64>,
+    max_records: Option<u64>,
+    max_segments: Option<usize>,
+}
+
+impl Rotation {
+    /// Create new `Rotation` which never rolls.
+    pub fn new() -> Rotation {
+        Rotation::default()
+    }
+
+    /// Roll when a record would make the segment larger than `n` bytes.
+    ///
+    /// A record larger than `n` bytes is written into a segment by itself.
+    pub fn max_bytes(mut self, n: u64) -> Rotation {
+        self.max_bytes = Some(n);
+        self
+    }
+
+    /// Roll when the segment has `n` records.
+    pub fn max_records(mut self, n: u64) -> Rotation {
+        self.max_records = Some(n);
+        self
+    }
+
+    /// Delete the oldest segments on rolling so that at most `n` segments are kept.
+    pub fn max_segments(mut self, n: usize) -> Rotation {
+        self.max_segments = Some(n);
+        self
+    }
+}
+
+const MANIFEST: &str = "MANIFEST";
+const MANIFEST_TMP: &str = "MANIFEST.tmp";
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.log", id))
+}
+
+fn read_manifest(dir: &Path) -> Result<Vec<u64>, de::Error> {
+    match File::open(dir.join(MANIFEST)) {
+        Ok(f) => from_reader(BufReader::new(f)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replace the manifest atomically, so a crash leaves either the old or the new one.
+fn write_manifest(dir: &Path, segments: &[u64]) -> Result<(), ser::Error> {
+    let tmp = dir.join(MANIFEST_TMP);
+    let mut f = File::create(&tmp)?;
+    crate::ser::to_writer(&mut f, segments)?;
+    f.sync_all()?;
+    fs::rename(tmp, dir.join(MANIFEST))?;
+    Ok(())
+}
+
+/// Appends records to a log split into segment files.
+#[derive(Debug)]
+pub struct SegmentedLogWriter {
+    dir: PathBuf,
+    rotation: Rotation,
+    segments: Vec<u64>,
+    w: BufWriter<File>,
+    bytes: u64,
+    records: u64,
+    buf: Vec<u8>,
+}
+
+impl SegmentedLogWriter {
+    /// Open the log in `dir`, creating it if it doesn't exist.
+    ///
+    /// Records are appended to the last segment of an existing log.
+    /// A record torn by a crash at the end of the last segment is truncated away.
+    pub fn open<P: AsRef<Path>>(dir: P, rotation: Rotation) -> Result<SegmentedLogWriter, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = read_manifest(&dir)?;
+        let (w, bytes, records) = match segments.last() {
+            Some(&id) => {
+                let path = segment_path(&dir, id);
+                let mut records = 0;
+                let mut bytes = 0;
+                let mut r = LogReader::new(BufReader::new(File::open(&path)?));
+                loop {
+                    match r.read_payload() {
+                        Ok(Some(_)) => {
+                            records += 1;
+                            bytes = r.r.seek(SeekFrom::Current(0))?;
+                        }
+                        Ok(None) => break,
+                        Err(e) if e.is_eof() => break,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+
+                let f = OpenOptions::new().append(true).open(&path)?;
+                if f.metadata()?.len() > bytes {
+                    f.set_len(bytes)?;
+                    f.sync_data()?;
+                }
+                (f, bytes, records)
+            }
+            None => {
+                let f = File::create(segment_path(&dir, 0))?;
+                segments.push(0);
+                write_manifest(&dir, &segments)?;
+                (f, 0, 0)
+            }
+        };
+
+        Ok(SegmentedLogWriter {
+            dir,
+            rotation,
+            segments,
+            w: BufWriter::new(w),
+            bytes,
+            records,
+            buf: Vec::new(),
+        })
+    }
+
+    /// Serialize `value` and append it as a record, rolling to a new segment if needed.
+    pub fn append<T: Serialize>(&mut self, value: &T) -> Result<(), ser::Error> {
+        self.buf.clear();
+        encode_record(&mut self.buf, value, false)?;
+
+        let len = self.buf.len() as u64;
+        let full_bytes = self
+            .rotation
+            .max_bytes
+            .map_or(false, |max| self.bytes + len > max);
+        let full_records = self
+            .rotation
+            .max_records
+            .map_or(false, |max| self.records >= max);
+        if self.records > 0 && (full_bytes || full_records) {
+            self.roll()?;
+        }
+
+        self.w.write_all(&self.buf)?;
+        self.bytes += len;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Close the current segment and start a new one.
+    pub fn roll(&mut self) -> Result<(), ser::Error> {
+        self.w.flush()?;
+        self.w.get_ref().sync_data()?;
+
+        let id = self.segments.last().map_or(0, |&id| id + 1);
+        let f = File::create(segment_path(&self.dir, id))?;
+        self.w = BufWriter::new(f);
+        self.bytes = 0;
+        self.records = 0;
+        self.segments.push(id);
+
+        match self.rotation.max_segments {
+            Some(n) => self.prune(n),
+            None => write_manifest(&self.dir, &self.segments),
+        }
+    }
+
+    /// Delete the oldest segments so that at most `keep` segments are left.
+    ///
+    /// The current segment is always kept.
+    pub fn prune(&mut self, keep: usize) -> Result<(), ser::Error> {
+        let keep = keep.max(1);
+        let removed = self.segments.len().saturating_sub(keep);
+        let removed: Vec<u64> = self.segments.drain(..removed).collect();
+
+        // Update the manifest first, so it never lists a deleted segment.
+        write_manifest(&self.dir, &self.segments)?;
+        for id in removed {
+            match fs::remove_file(segment_path(&self.dir, id)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the current segment.
+    pub fn flush(&mut self) -> Result<(), ser::Error> {
+        self.w.flush()?;
+        Ok(())
+    }
+
+    /// IDs of the segments in order.
+    pub fn segments(&self) -> &[u64] {
+        &self.segments
+    }
+}
+
+/// Reads records of a log split into segment files, across segments in order.
+#[derive(Debug)]
+pub struct SegmentedLogReader {
+    dir: PathBuf,
+    segments: Vec<u64>,
+    next: usize,
+    current: Option<LogReader<BufReader<File>>>,
+}
+
+impl SegmentedLogReader {
+    /// Open the log in `dir`.
+    ///
+    /// Segments listed in the manifest at this time are read.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<SegmentedLogReader, de::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        let segments = read_manifest(&dir)?;
+        Ok(SegmentedLogReader {
+            dir,
+            segments,
+            next: 0,
+            current: None,
+        })
+    }
+
+    /// Read the next record.
+    ///
+    /// Returns `None` at the end of the last segment.
+    /// Segments pruned before being opened are skipped.
+    pub fn read_record<T: DeserializeOwned>(&mut self) -> Result<Option<T>, de::Error> {
+        loop {
+            if let Some(r) = &mut self.current {
+                match r.read_record()? {
+                    Some(v) => return Ok(Some(v)),
+                    None => self.current = None,
+                }
+            }
+
+            let id = match self.segments.get(self.next) {
+                Some(&id) => id,
+                None => return Ok(None),
+            };
+            self.next += 1;
+            match File::open(segment_path(&self.dir, id)) {
+                Ok(f) => self.current = Some(LogReader::new(BufReader::new(f))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn record_format() {
+        let mut w = LogWriter::new(Vec::new());
+        w.append(&"ab").unwrap();
+        assert_eq!(w.into_inner(), vec![3u8, 2, b'a', b'b']);
+    }
+
+    #[test]
+    fn record_format_with_back_pointers() {
+        let mut w = LogWriter::with_back_pointers(Vec::new());
+        w.append(&"ab").unwrap();
+        assert_eq!(w.into_inner(), vec![3u8, 2, b'a', b'b', 4]);
+    }
+
+    #[test]
+    fn read_forward() {
+        let mut w = LogWriter::new(Vec::new());
+        for i in 0..10u64 {
+            w.append(&i).unwrap();
+        }
+        let bs = w.into_inner();
+
+        let mut r = LogReader::new(bs.as_slice());
+        for i in 0..10u64 {
+            assert_eq!(r.read_record::<u64>().unwrap(), Some(i));
+        }
+        assert_eq!(r.read_record::<u64>().unwrap(), None);
+    }
+
+    #[test]
+    fn read_forward_with_back_pointers() {
+        let long = "x".repeat(300);
+
+        let mut w = LogWriter::with_back_pointers(Vec::new());
+        w.append(&long).unwrap();
+        w.append(&"short").unwrap();
+        let bs = w.into_inner();
+
+        let mut r = LogReader::with_back_pointers(bs.as_slice());
+        assert_eq!(r.read_record::<String>().unwrap(), Some(long));
+        assert_eq!(r.read_record::<String>().unwrap(), Some("short".to_owned()));
+        assert_eq!(r.read_record::<String>().unwrap(), None);
+    }
+
+    #[test]
+    fn read_forward_with_corrupt_length() {
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, u64::max_value()).unwrap();
+        bs.extend(b"abc");
+
+        let mut r = LogReader::new(bs.as_slice());
+        assert!(r.read_record::<String>().unwrap_err().is_eof());
+    }
+
+    #[test]
+    fn read_backward_with_corrupt_length() {
+        // A record holding only a length of u64::MAX, with a valid back pointer.
+        let mut bs = Vec::new();
+        encode_u64(&mut bs, u64::max_value()).unwrap();
+        let mut pointer = Vec::new();
+        encode_u64(&mut pointer, bs.len() as u64).unwrap();
+        pointer.reverse();
+        bs.extend(pointer);
+
+        let mut r = ReverseLogReader::new(Cursor::new(bs)).unwrap();
+        let _ = r.read_prev::<String>().unwrap_err();
+    }
+
+    #[test]
+    fn read_backward() {
+        let mut w = LogWriter::with_back_pointers(Vec::new());
+        for i in 0..1000u64 {
+            w.append(&(i, "y".repeat(i as usize))).unwrap();
+        }
+        let bs = w.into_inner();
+
+        let mut r = ReverseLogReader::new(Cursor::new(bs)).unwrap();
+        for i in (0..1000u64).rev() {
+            let (j, s) = r.read_prev::<(u64, String)>().unwrap().unwrap();
+            assert_eq!(j, i);
+            assert_eq!(s.len(), i as usize);
+        }
+        assert_eq!(r.read_prev::<(u64, String)>().unwrap(), None);
+    }
+
+    #[test]
+    fn shared_writer_does_not_interleave() {
+        let w = Arc::new(SharedLogWriter::with_back_pointers(Vec::new()));
+
+        let handles: Vec<_> = (0..4u8)
+            .map(|t| {
+                let w = w.clone();
+                thread::spawn(move || {
+                    for i in 0..200u64 {
+                        w.append(&(t, i, vec![t; (i % 50) as usize])).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let bs = Arc::try_unwrap(w).unwrap().into_inner().unwrap();
+
+        let mut next = [0u64; 4];
+        let mut r = LogReader::with_back_pointers(bs.as_slice());
+        while let Some((t, i, v)) = r.read_record::<(u8, u64, Vec<u8>)>().unwrap() {
+            assert_eq!(i, next[t as usize]);
+            assert_eq!(v, vec![t; (i % 50) as usize]);
+            next[t as usize] += 1;
+        }
+        assert_eq!(next, [200; 4]);
+    }
+
+    #[test]
+    fn read_backward_broken_pointer() {
+        let bs = vec![1u8, 0, 9];
+        let mut r = ReverseLogReader::new(Cursor::new(bs)).unwrap();
+        let _ = r.read_prev::<u8>().unwrap_err();
+    }
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path =
+                std::env::temp_dir().join(format!("serde_dokechi-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read_all(dir: &Path) -> Vec<u64> {
+        let mut r = SegmentedLogReader::open(dir).unwrap();
+        let mut vs = Vec::new();
+        while let Some(v) = r.read_record::<u64>().unwrap() {
+            vs.push(v);
+        }
+        vs
+    }
+
+    #[test]
+    fn segments_roll_by_records() {
+        let dir = TempDir::new("roll-records");
+
+        let mut w = SegmentedLogWriter::open(&dir.0, Rotation::new().max_records(10)).unwrap();
+        for i in 0..35u64 {
+            w.append(&i).unwrap();
+        }
+        w.flush().unwrap();
+        assert_eq!(w.segments(), &[0, 1, 2, 3]);
+
+        assert_eq!(read_all(&dir.0), (0..35).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn segments_roll_by_bytes() {
+        let dir = TempDir::new("roll-bytes");
+
+        let mut w = SegmentedLogWriter::open(&dir.0, Rotation::new().max_bytes(64)).unwrap();
+        for i in 0..100u64 {
+            w.append(&(i, "z".repeat(10))).unwrap();
+        }
+        w.flush().unwrap();
+
+        for &id in w.segments() {
+            let len = fs::metadata(segment_path(&dir.0, id)).unwrap().len();
+            assert!(len <= 64);
+        }
+
+        let mut r = SegmentedLogReader::open(&dir.0).unwrap();
+        for i in 0..100u64 {
+            let (j, _) = r.read_record::<(u64, String)>().unwrap().unwrap();
+            assert_eq!(j, i);
+        }
+        assert_eq!(r.read_record::<(u64, String)>().unwrap(), None);
+    }
+
+    #[test]
+    fn reopen_appends_to_last_segment() {
+        let dir = TempDir::new("reopen");
+
+        let rotation = Rotation::new().max_records(4);
+        let mut w = SegmentedLogWriter::open(&dir.0, rotation).unwrap();
+        for i in 0..6u64 {
+            w.append(&i).unwrap();
+        }
+        drop(w);
+
+        let mut w = SegmentedLogWriter::open(&dir.0, rotation).unwrap();
+        for i in 6..10u64 {
+            w.append(&i).unwrap();
+        }
+        w.flush().unwrap();
+        assert_eq!(w.segments(), &[0, 1, 2]);
+
+        assert_eq!(read_all(&dir.0), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reopen_truncates_torn_record() {
+        let dir = TempDir::new("torn");
+
+        let rotation = Rotation::new();
+        let mut w = SegmentedLogWriter::open(&dir.0, rotation).unwrap();
+        for i in 0..3u64 {
+            w.append(&i).unwrap();
+        }
+        drop(w);
+
+        // A crash in the middle of writing a record.
+        let path = segment_path(&dir.0, 0);
+        let complete = fs::metadata(&path).unwrap().len();
+        let mut torn = Vec::new();
+        encode_record(&mut torn, &"torn record", false).unwrap();
+        torn.truncate(torn.len() / 2);
+        let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+        f.write_all(&torn).unwrap();
+        drop(f);
+
+        let mut w = SegmentedLogWriter::open(&dir.0, rotation).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+        for i in 3..5u64 {
+            w.append(&i).unwrap();
+        }
+        w.flush().unwrap();
+
+        assert_eq!(read_all(&dir.0), (0..5).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn retention_prunes_oldest() {
+        let dir = TempDir::new("retention");
+
+        let rotation = Rotation::new().max_records(5).max_segments(2);
+        let mut w = SegmentedLogWriter::open(&dir.0, rotation).unwrap();
+        for i in 0..23u64 {
+            w.append(&i).unwrap();
+        }
+        w.flush().unwrap();
+        assert_eq!(w.segments(), &[3, 4]);
+        assert!(!segment_path(&dir.0, 2).exists());
+
+        assert_eq!(read_all(&dir.0), (15..23).collect::<Vec<_>>());
+
+        w.prune(0).unwrap();
+        assert_eq!(w.segments(), &[4]);
+    }
+}
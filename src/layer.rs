@@ -0,0 +1,243 @@
+//! Composable stages wrapping writers and readers.
+//!
+//! A [`Layer`](trait.Layer.html) wraps an IO stream and returns a new one,
+//! such as counting or checksumming the bytes passing through.
+//! Layers are combined with [`LayerExt::and_then`](trait.LayerExt.html#method.and_then),
+//! and third-party stages (compression, encryption, throttling, ...) only need to implement `Layer`.
+//!
+//! ```
+//! use serde_dokechi::layer::{ChecksumLayer, CountingLayer, Layer, LayerExt};
+//!
+//! let layers = CountingLayer.and_then(ChecksumLayer);
+//! let mut w = layers.wrap(Vec::new());
+//! serde_dokechi::to_writer(&mut w, "example").unwrap();
+//!
+//! assert_eq!(w.get_ref().count(), 8);
+//! ```
+
+use std::io::{self, Read, Write};
+
+use crate::crc;
+
+/// A stage wrapping an IO stream of type `S`.
+pub trait Layer<S> {
+    /// The wrapped stream.
+    type Wrapped;
+
+    /// Wrap `inner`.
+    fn wrap(&self, inner: S) -> Self::Wrapped;
+}
+
+/// Combinators for [`Layer`](trait.Layer.html).
+pub trait LayerExt: Sized {
+    /// Combine with `outer`, which wraps the stream wrapped by this layer.
+    fn and_then<L>(self, outer: L) -> Stack<Self, L> {
+        Stack { inner: self, outer }
+    }
+}
+
+impl<L> LayerExt for L {}
+
+/// A layer which returns the stream as it is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl<S> Layer<S> for Identity {
+    type Wrapped = S;
+
+    fn wrap(&self, inner: S) -> S {
+        inner
+    }
+}
+
+/// Two layers applied in order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stack<I, O> {
+    inner: I,
+    outer: O,
+}
+
+impl<S, I, O> Layer<S> for Stack<I, O>
+where
+    I: Layer<S>,
+    O: Layer<I::Wrapped>,
+{
+    type Wrapped = O::Wrapped;
+
+    fn wrap(&self, inner: S) -> O::Wrapped {
+        self.outer.wrap(self.inner.wrap(inner))
+    }
+}
+
+/// A layer counting bytes passing through.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CountingLayer;
+
+impl<S> Layer<S> for CountingLayer {
+    type Wrapped = Counting<S>;
+
+    fn wrap(&self, inner: S) -> Counting<S> {
+        Counting { inner, count: 0 }
+    }
+}
+
+/// A stream counting bytes passing through.
+#[derive(Debug)]
+pub struct Counting<S> {
+    inner: S,
+    count: u64,
+}
+
+impl<S> Counting<S> {
+    /// The number of bytes written or read.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Unwrap this `Counting`, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Write> Write for Counting<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Read> Read for Counting<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// A layer computing CRC-32 of bytes passing through.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChecksumLayer;
+
+impl<S> Layer<S> for ChecksumLayer {
+    type Wrapped = Checksum<S>;
+
+    fn wrap(&self, inner: S) -> Checksum<S> {
+        Checksum {
+            inner,
+            crc: crc::CRC32_INIT,
+        }
+    }
+}
+
+/// A stream computing CRC-32 of bytes passing through.
+#[derive(Debug)]
+pub struct Checksum<S> {
+    inner: S,
+    crc: u32,
+}
+
+impl<S> Checksum<S> {
+    /// CRC-32 of bytes written or read so far.
+    pub fn checksum(&self) -> u32 {
+        crc::crc32_finish(self.crc)
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Unwrap this `Checksum`, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Write> Write for Checksum<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc = crc::crc32_update(self.crc, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Read> Read for Checksum<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc = crc::crc32_update(self.crc, &buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{from_reader, to_writer};
+
+    #[test]
+    fn identity() {
+        let mut w = Identity.wrap(Vec::new());
+        to_writer(&mut w, 1u8).unwrap();
+        assert_eq!(w, vec![1u8]);
+    }
+
+    #[test]
+    fn counting() {
+        let mut w = CountingLayer.wrap(Vec::new());
+        to_writer(&mut w, "abc").unwrap();
+        assert_eq!(w.count(), 4);
+        assert_eq!(w.into_inner(), vec![3u8, b'a', b'b', b'c']);
+    }
+
+    #[test]
+    fn checksum_matches_on_both_sides() {
+        let layers = ChecksumLayer.and_then(CountingLayer);
+
+        let mut w = layers.wrap(Vec::new());
+        to_writer(&mut w, vec![1u64, 2, 3, 1 << 40]).unwrap();
+        let written = w.get_ref().checksum();
+        let bs = w.into_inner().into_inner();
+
+        let mut r = layers.wrap(bs.as_slice());
+        let v: Vec<u64> = from_reader(&mut r).unwrap();
+        assert_eq!(v, vec![1u64, 2, 3, 1 << 40]);
+        assert_eq!(r.get_ref().checksum(), written);
+        assert_eq!(r.count(), bs.len() as u64);
+        assert_eq!(written, crc::crc32(&bs));
+    }
+
+    #[test]
+    fn stack_order() {
+        let w = Identity
+            .and_then(CountingLayer)
+            .and_then(ChecksumLayer)
+            .wrap(Vec::<u8>::new());
+        let _: &Counting<Vec<u8>> = w.get_ref();
+    }
+}
@@ -1,12 +1,104 @@
 //! `serde_dokechi` is a serializer / deserializer library focus on only serialized binary size.
 //!
-//! Minimum supprted Rust version is `1.40.0 (2019-12-19)`.
+//! Minimum supprted Rust version is `1.40.0 (2019-12-19)`, without optional features
+//! except `compact-errors`.
+//!
+//! The other features enable optional dependencies, which need much newer Rust,
+//! such as `tokio` 1, `tonic` 0.12 (`grpc`), `reqwest` 0.12, `sqlx` 0.8, `diesel` 2.2,
+//! `tungstenite` 0.28, `brotli` 8, `heed-traits` 0.20, `axum` 0.8, `actix-web` 4,
+//! `arrow-array` 57 (`arrow`), `bevy_asset` 0.17 and `syn` 2 (`derive`).
+//! They are supported on the latest stable Rust only, as are the tests.
 
 #![warn(missing_docs)]
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[doc(hidden)]
+pub mod assert;
+#[cfg(feature = "bevy_asset")]
+pub mod asset;
+pub mod batch;
+pub mod big_array;
+#[cfg(feature = "tokio")]
+pub mod blocking;
+pub mod bytes;
+pub mod chunk;
+#[cfg(feature = "asynchronous-codec")]
+pub mod codec;
+pub mod compress;
 pub mod de;
+pub mod deadline;
+pub mod dedup;
+pub mod entropy;
+pub mod ext;
+#[cfg(feature = "reed-solomon-erasure")]
+pub mod fec;
+pub mod fingerprint;
+pub mod fragment;
+pub mod frame;
+pub mod generator;
+pub mod geo;
+pub mod gorilla;
+pub mod group_varint;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handshake;
+#[cfg(feature = "reqwest")]
+pub mod http;
+pub mod io;
+pub mod journal;
+pub mod layer;
+#[cfg(feature = "heed-traits")]
+pub mod lmdb;
+pub mod net;
+pub mod options;
+pub mod patch;
+pub mod pod;
+pub mod profile;
+pub mod pull;
+pub mod registry;
+pub mod rpc;
+#[cfg(feature = "zstd")]
+pub mod seekable;
 pub mod ser;
+#[cfg(any(feature = "sqlx", feature = "diesel"))]
+pub mod sql;
+pub mod stats;
+pub mod timeseries;
+#[cfg(feature = "log")]
+pub mod trace;
+pub mod typescript;
+pub mod validate;
+pub mod varuint;
+pub mod versioned;
+#[cfg(any(feature = "axum", feature = "actix-web"))]
+pub mod web;
+#[cfg(feature = "tungstenite")]
+pub mod websocket;
+
+mod crc;
+mod error;
+mod schema;
 
-mod varuint;
+pub use de::{
+    decode_variant, from_reader, from_reader_in_place, from_reader_partial, from_reader_seed,
+    from_reader_take, from_reader_with_config, from_slice, from_slice_in_place, from_slice_seed,
+    try_decode_any,
+};
+#[cfg(feature = "compact-errors")]
+pub use error::Message;
+pub use error::{Error, ErrorKind, Offset};
+pub use ext::DokechiExt;
+pub use options::Options;
+pub use ser::{serialized_size, to_vec, to_writer, to_writer_with_config};
+#[cfg(feature = "derive")]
+pub use serde_dokechi_derive::{dokechi_const, with_codecs, DokechiMessage, Versioned};
 
-pub use de::from_reader;
-pub use ser::to_writer;
+// Paths used by the derive macros.
+#[cfg(feature = "derive")]
+extern crate self as serde_dokechi;
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use serde::de::DeserializeOwned;
+    pub use serde::ser::Serialize;
+}
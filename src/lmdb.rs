@@ -0,0 +1,72 @@
+//! Storing values in LMDB databases of `heed`.
+//!
+//! [`DokechiCodec<T>`](struct.DokechiCodec.html) encodes and decodes `T` as Dokechi format,
+//! for use as the key or value type of a `heed::Database`.
+//!
+//! ```ignore
+//! let db: Database<Str, DokechiCodec<User>> = env.create_database(&mut wtxn, None)?;
+//! db.put(&mut wtxn, "alice", &user)?;
+//! ```
+//!
+//! Keys in LMDB are sorted by their bytes, and Dokechi format doesn't keep the order of values,
+//! so range queries over keys of this codec don't follow the order of `T`.
+//!
+//! This module is available with the `heed-traits` feature.
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use heed_traits::{BoxedError, BytesDecode, BytesEncode};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::de::from_slice;
+use crate::ser::to_vec;
+
+/// A codec of `heed` encoding `T` as Dokechi format.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DokechiCodec<T>(PhantomData<T>);
+
+impl<'a, T: Serialize + 'a> BytesEncode<'a> for DokechiCodec<T> {
+    type EItem = T;
+
+    fn bytes_encode(item: &'a T) -> Result<Cow<'a, [u8]>, BoxedError> {
+        Ok(Cow::Owned(to_vec(item)?))
+    }
+}
+
+impl<'a, T: DeserializeOwned + 'a> BytesDecode<'a> for DokechiCodec<T> {
+    type DItem = T;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Result<T, BoxedError> {
+        Ok(from_slice(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    #[test]
+    fn round_trip() {
+        let user = User {
+            name: "alice".to_owned(),
+            age: 20,
+        };
+        let bs = DokechiCodec::<User>::bytes_encode(&user).unwrap();
+        assert_eq!(&*bs, &[5, b'a', b'l', b'i', b'c', b'e', 20][..]);
+
+        let d = DokechiCodec::<User>::bytes_decode(&bs).unwrap();
+        assert_eq!(d, user);
+
+        let _ = DokechiCodec::<User>::bytes_decode(&bs[..3]).unwrap_err();
+    }
+}
@@ -0,0 +1,209 @@
+//! Sending values as UDP datagrams.
+//!
+//! Each datagram holds exactly one value in Dokechi format.
+//! Encoded values are checked against the datagram size limit before sending,
+//! and received datagrams are checked to be complete and have no trailing bytes.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use thiserror::Error;
+
+use crate::de::{self, Deserializer};
+use crate::ser::{self, to_vec};
+
+/// The default datagram size limit, the largest payload of a UDP datagram over IPv4.
+pub const DEFAULT_MAX_LEN: usize = 65507;
+
+/// Send `value` to `addr` as a datagram of at most [`DEFAULT_MAX_LEN`](constant.DEFAULT_MAX_LEN.html) bytes.
+pub fn send_datagram<A: ToSocketAddrs, T: Serialize + ?Sized>(
+    socket: &UdpSocket,
+    addr: A,
+    value: &T,
+) -> Result<(), Error> {
+    Datagram::default().send(socket, addr, value)
+}
+
+/// Receive a value from a datagram of at most [`DEFAULT_MAX_LEN`](constant.DEFAULT_MAX_LEN.html) bytes
+/// into `buf`.
+///
+/// Returns the value and the address of the sender.
+pub fn recv_datagram<T: DeserializeOwned>(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> Result<(T, SocketAddr), Error> {
+    Datagram::default().recv(socket, buf)
+}
+
+/// Datagram settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Datagram {
+    max_len: usize,
+}
+
+impl Datagram {
+    /// Create new `Datagram` with size limit of `max_len` bytes.
+    pub fn new(max_len: usize) -> Datagram {
+        Datagram { max_len }
+    }
+
+    /// Send `value` to `addr` as a datagram.
+    ///
+    /// Returns [`Error::Oversize`](enum.Error.html#variant.Oversize) without sending anything
+    /// if the encoded value is larger than the limit.
+    pub fn send<A: ToSocketAddrs, T: Serialize + ?Sized>(
+        &self,
+        socket: &UdpSocket,
+        addr: A,
+        value: &T,
+    ) -> Result<(), Error> {
+        let bs = to_vec(value)?;
+        if bs.len() > self.max_len {
+            return Err(Error::Oversize {
+                len: bs.len(),
+                max_len: self.max_len,
+            });
+        }
+
+        let n = socket.send_to(&bs, addr)?;
+        if n != bs.len() {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "datagram is sent partially",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Receive a value from a datagram into `buf`.
+    ///
+    /// A datagram filling whole `buf` may be cut by the OS, so it is reported as
+    /// [`Error::Truncated`](enum.Error.html#variant.Truncated).
+    /// Make `buf` longer than the limit to receive datagrams up to the limit.
+    pub fn recv<T: DeserializeOwned>(
+        &self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> Result<(T, SocketAddr), Error> {
+        let (n, addr) = socket.recv_from(buf)?;
+        if n == buf.len() {
+            return Err(Error::Truncated(n));
+        }
+        if n > self.max_len {
+            return Err(Error::Oversize {
+                len: n,
+                max_len: self.max_len,
+            });
+        }
+
+        let mut r = &buf[..n];
+        let value = match T::deserialize(&mut Deserializer::new(&mut r)) {
+            Ok(v) => v,
+            Err(ref e) if e.is_eof() => return Err(Error::Truncated(n)),
+            Err(e) => return Err(Error::Deserialize(e)),
+        };
+        if !r.is_empty() {
+            return Err(Error::TrailingBytes(r.len()));
+        }
+        Ok((value, addr))
+    }
+}
+
+impl Default for Datagram {
+    fn default() -> Datagram {
+        Datagram::new(DEFAULT_MAX_LEN)
+    }
+}
+
+/// An error of sending or receiving a datagram.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The socket returned IO error.
+    #[error("{0}")]
+    IO(#[from] io::Error),
+    /// The datagram is larger than the limit.
+    #[error("datagram of {len} bytes is larger than the limit of {max_len} bytes")]
+    Oversize {
+        /// The size of the datagram.
+        len: usize,
+        /// The size limit.
+        max_len: usize,
+    },
+    /// The received datagram of the given size ends in the middle of the value.
+    #[error("datagram of {0} bytes is truncated")]
+    Truncated(usize),
+    /// The received datagram has the given number of bytes after the value.
+    #[error("datagram has {0} trailing bytes")]
+    TrailingBytes(usize),
+    /// Failed to serialize the value.
+    #[error("{0}")]
+    Serialize(#[from] ser::Error),
+    /// Failed to deserialize the value.
+    #[error("{0}")]
+    Deserialize(de::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pair() -> (UdpSocket, UdpSocket) {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        (a, b)
+    }
+
+    #[test]
+    fn send_and_recv() {
+        let (a, b) = pair();
+        send_datagram(&a, b.local_addr().unwrap(), &(1u32, "abc")).unwrap();
+
+        let mut buf = [0u8; 64];
+        let (v, from): ((u32, String), _) = recv_datagram(&b, &mut buf).unwrap();
+        assert_eq!(v, (1, "abc".to_owned()));
+        assert_eq!(from, a.local_addr().unwrap());
+    }
+
+    #[test]
+    fn oversize_is_not_sent() {
+        let (a, b) = pair();
+        match Datagram::new(16).send(&a, b.local_addr().unwrap(), &vec![0u8; 20]) {
+            Err(Error::Oversize { len, max_len }) => assert_eq!((len, max_len), (21, 16)),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn truncated_by_buffer() {
+        let (a, b) = pair();
+        send_datagram(&a, b.local_addr().unwrap(), &vec![0u8; 20]).unwrap();
+
+        let mut buf = [0u8; 8];
+        match recv_datagram::<Vec<u8>>(&b, &mut buf) {
+            Err(Error::Truncated(8)) => {}
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn incomplete_and_trailing() {
+        let (a, b) = pair();
+        let addr = b.local_addr().unwrap();
+        let mut buf = [0u8; 64];
+
+        a.send_to(&[5u8, b'a', b'b'], addr).unwrap();
+        match recv_datagram::<String>(&b, &mut buf) {
+            Err(Error::Truncated(3)) => {}
+            r => panic!("unexpected: {:?}", r),
+        }
+
+        a.send_to(&[1u8, b'a', b'b'], addr).unwrap();
+        match recv_datagram::<String>(&b, &mut buf) {
+            Err(Error::TrailingBytes(1)) => {}
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+}
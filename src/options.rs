@@ -0,0 +1,954 @@
+//! Settings of serializing and deserializing.
+//!
+//! [`Options`](struct.Options.html) collects settings of [`Serializer`](../ser/struct.Serializer.html)
+//! and [`Deserializer`](../de/struct.Deserializer.html) in one value,
+//! so the same settings can be shared by both sides of a protocol.
+//!
+//! ```
+//! use serde_dokechi::{from_reader_with_config, to_writer_with_config, Options};
+//!
+//! let options = Options::new().reject_non_finite().max_depth(16);
+//!
+//! let mut bs = Vec::new();
+//! to_writer_with_config(&mut bs, &vec![1.5f64], &options).unwrap();
+//! let v: Vec<f64> = from_reader_with_config(bs.as_slice(), &options).unwrap();
+//! assert_eq!(v, vec![1.5]);
+//!
+//! assert!(to_writer_with_config(Vec::new(), &std::f64::NAN, &options).is_err());
+//! ```
+
+use crate::de::{Deserializer, Limits, Narrowing, DEFAULT_MAX_DEPTH};
+use crate::io::{Input, Output};
+use crate::ser::Serializer;
+
+/// How integers wider than 8 bits are encoded.
+///
+/// Both ends must use the same encoding, as it isn't recorded in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntEncoding {
+    /// Variable-length integers, with signed integers zigzagged. This is the default.
+    Varint,
+    /// Fixed-width words of the size of the type, with signed integers in two's complement.
+    ///
+    /// The byte order is set by [`Endian`](enum.Endian.html).
+    ///
+    /// Faster to decode, but larger for small values.
+    /// Lengths and enum variant indexes are still variable-length.
+    Fixed,
+}
+
+impl Default for IntEncoding {
+    fn default() -> IntEncoding {
+        IntEncoding::Varint
+    }
+}
+
+/// How floats are encoded.
+///
+/// Both ends must use the same encoding, as it isn't recorded in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatEncoding {
+    /// IEEE 754 bits of the size of the type. This is the default.
+    Raw,
+    /// A tag byte followed by a zigzagged varuint if the value is an integer within `i64`,
+    /// or by the IEEE 754 bits otherwise.
+    ///
+    /// Whole numbers such as `3.0` take 2 bytes, but other values take 1 byte more than `Raw`.
+    /// `-0.0` is written as bits, so the sign of zero is kept.
+    Compact,
+}
+
+impl Default for FloatEncoding {
+    fn default() -> FloatEncoding {
+        FloatEncoding::Raw
+    }
+}
+
+/// The byte order of floats, chars and fixed-width integers.
+///
+/// Both ends must use the same byte order, as it isn't recorded in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    /// Least significant byte first. This is the default.
+    Little,
+    /// Most significant byte first, also known as network byte order.
+    Big,
+}
+
+impl Default for Endian {
+    fn default() -> Endian {
+        Endian::Little
+    }
+}
+
+impl Endian {
+    /// Put little-endian bytes `bs` in this byte order, or back.
+    pub(crate) fn order(self, bs: &mut [u8]) {
+        if self == Endian::Big {
+            bs.reverse();
+        }
+    }
+}
+
+/// Settings of serializing and deserializing.
+///
+/// The default is the same as [`Serializer::new`](../ser/struct.Serializer.html#method.new)
+/// and [`Deserializer::new`](../de/struct.Deserializer.html#method.new).
+#[derive(Debug, Clone)]
+pub struct Options {
+    reject_non_finite: bool,
+    #[cfg(feature = "unicode-normalization")]
+    normalize_nfc: bool,
+    narrowing: Narrowing,
+    max_depth: usize,
+    limits: Limits,
+    int_encoding: IntEncoding,
+    float_encoding: FloatEncoding,
+    lossy_f64: bool,
+    endian: Endian,
+    option_bitmap: bool,
+    canonical: bool,
+    variant_names: bool,
+    struct_maps: bool,
+    truncated_structs: bool,
+    compact_chars: bool,
+    pack_bools: bool,
+    chunked_seqs: bool,
+}
+
+impl Options {
+    /// Create new `Options` with the default settings.
+    pub fn new() -> Options {
+        Options {
+            reject_non_finite: false,
+            #[cfg(feature = "unicode-normalization")]
+            normalize_nfc: false,
+            narrowing: Narrowing::Error,
+            max_depth: DEFAULT_MAX_DEPTH,
+            limits: Limits::default(),
+            int_encoding: IntEncoding::Varint,
+            float_encoding: FloatEncoding::Raw,
+            lossy_f64: false,
+            endian: Endian::Little,
+            option_bitmap: false,
+            canonical: false,
+            variant_names: false,
+            struct_maps: false,
+            truncated_structs: false,
+            compact_chars: false,
+            pack_bools: false,
+            chunked_seqs: false,
+        }
+    }
+
+    /// Settings for small output.
+    ///
+    /// Floats are [`Compact`](enum.FloatEncoding.html#variant.Compact) and `Option` fields of structs are
+    /// written in [bitmaps](#method.option_bitmap). Integers are variable-length as by default.
+    ///
+    /// Whole-number floats and `Option` fields get smaller, while fractional floats take 1 byte more.
+    /// Structs without `Option` fields take 1 byte more for each 8 fields, for the bitmap.
+    pub fn small() -> Options {
+        Options::new()
+            .float_encoding(FloatEncoding::Compact)
+            .option_bitmap()
+    }
+
+    /// Settings for fast encoding and decoding.
+    ///
+    /// Integers are [`Fixed`](enum.IntEncoding.html#variant.Fixed)-width in little-endian,
+    /// copied without the branches of variable-length integers, at the cost of size.
+    pub fn fast() -> Options {
+        Options::new()
+            .int_encoding(IntEncoding::Fixed)
+            .endian(Endian::Little)
+    }
+
+    /// Settings whose byte layout never changes across versions of this crate.
+    ///
+    /// This is the layout of the defaults at the time of writing: variable-length integers,
+    /// IEEE 754 floats, little-endian byte order, positional struct fields and enum variant indexes.
+    /// Unlike [`new`](#method.new), it stays the same even if the defaults change,
+    /// so use it for data stored for a long time.
+    /// Settings not affecting the layout, such as limits, are the defaults.
+    pub fn compatible() -> Options {
+        Options::new()
+            .int_encoding(IntEncoding::Varint)
+            .float_encoding(FloatEncoding::Raw)
+            .endian(Endian::Little)
+    }
+
+    /// Reject NaN and infinite floats in both serializing and deserializing.
+    ///
+    /// Both `f32` and `f64` values return `NonFiniteFloat` errors of
+    /// [`ser::Error`](../ser/enum.Error.html#variant.NonFiniteFloat) and
+    /// [`de::Error`](../de/enum.Error.html#variant.NonFiniteFloat).
+    pub fn reject_non_finite(mut self) -> Options {
+        self.reject_non_finite = true;
+        self
+    }
+
+    /// Normalize strings into Unicode Normalization Form C before writing.
+    ///
+    /// This method is available with the `unicode-normalization` feature.
+    #[cfg(feature = "unicode-normalization")]
+    pub fn normalize_nfc(mut self) -> Options {
+        self.normalize_nfc = true;
+        self
+    }
+
+    /// Decode integers too large for their types with `policy`.
+    pub fn narrowing(mut self, policy: Narrowing) -> Options {
+        self.narrowing = policy;
+        self
+    }
+
+    /// Set the limit of nesting in deserializing.
+    pub fn max_depth(mut self, max_depth: usize) -> Options {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Set the limits of lengths in deserializing.
+    pub fn limits(mut self, limits: Limits) -> Options {
+        self.limits = limits;
+        self
+    }
+
+    /// Encode integers with `encoding` in both serializing and deserializing.
+    pub fn int_encoding(mut self, encoding: IntEncoding) -> Options {
+        self.int_encoding = encoding;
+        self
+    }
+
+    /// Encode floats with `encoding` in both serializing and deserializing.
+    pub fn float_encoding(mut self, encoding: FloatEncoding) -> Options {
+        self.float_encoding = encoding;
+        self
+    }
+
+    /// Store `f64` as `f32` in both serializing and deserializing.
+    ///
+    /// See [`Serializer::lossy_f64`](../ser/struct.Serializer.html#method.lossy_f64).
+    pub fn lossy_f64(mut self) -> Options {
+        self.lossy_f64 = true;
+        self
+    }
+
+    /// Use `endian` byte order in both serializing and deserializing.
+    pub fn endian(mut self, endian: Endian) -> Options {
+        self.endian = endian;
+        self
+    }
+
+    /// Write `Some`/`None` of `Option` fields of structs as a bitmap before the fields.
+    ///
+    /// See [`Serializer::option_bitmap`](../ser/struct.Serializer.html#method.option_bitmap).
+    pub fn option_bitmap(mut self) -> Options {
+        self.option_bitmap = true;
+        self
+    }
+
+    /// Serialize values in the canonical encoding, and accept only the canonical encoding in deserializing.
+    ///
+    /// See [`Serializer::canonical`](../ser/struct.Serializer.html#method.canonical)
+    /// and [`Deserializer::canonical`](../de/struct.Deserializer.html#method.canonical).
+    pub fn canonical(mut self) -> Options {
+        self.canonical = true;
+        self
+    }
+
+    /// Write enum variants by their names in both serializing and deserializing.
+    ///
+    /// See [`Serializer::variant_names`](../ser/struct.Serializer.html#method.variant_names).
+    pub fn variant_names(mut self) -> Options {
+        self.variant_names = true;
+        self
+    }
+
+    /// Write structs as maps of field names to values in both serializing and deserializing.
+    ///
+    /// Fields can be added with `#[serde(default)]`, but not removed:
+    /// data with a field unknown to the type fails with `Unsupported`.
+    /// See [`Serializer::struct_maps`](../ser/struct.Serializer.html#method.struct_maps).
+    pub fn struct_maps(mut self) -> Options {
+        self.struct_maps = true;
+        self
+    }
+
+    /// End structs at the end of the input in deserializing.
+    ///
+    /// See [`Deserializer::truncated_structs`](../de/struct.Deserializer.html#method.truncated_structs).
+    pub fn truncated_structs(mut self) -> Options {
+        self.truncated_structs = true;
+        self
+    }
+
+    /// Write chars as varuints of their code points in both serializing and deserializing.
+    ///
+    /// See [`Serializer::compact_chars`](../ser/struct.Serializer.html#method.compact_chars).
+    pub fn compact_chars(mut self) -> Options {
+        self.compact_chars = true;
+        self
+    }
+
+    /// Pack consecutive bools into bytes in both serializing and deserializing.
+    ///
+    /// See [`Serializer::pack_bools`](../ser/struct.Serializer.html#method.pack_bools).
+    pub fn pack_bools(mut self) -> Options {
+        self.pack_bools = true;
+        self
+    }
+
+    /// Write and read sequences and maps in chunks, so ones without size hint can be streamed.
+    ///
+    /// See [`Serializer::chunked_seqs`](../ser/struct.Serializer.html#method.chunked_seqs).
+    pub fn chunked_seqs(mut self) -> Options {
+        self.chunked_seqs = true;
+        self
+    }
+
+    /// Create a `Serializer` into `w` with these settings.
+    pub fn serializer<W: Output>(&self, w: W) -> Serializer<W> {
+        let mut serializer = Serializer::new(w)
+            .int_encoding(self.int_encoding)
+            .float_encoding(self.float_encoding)
+            .endian(self.endian);
+        if self.reject_non_finite {
+            serializer = serializer.reject_non_finite();
+        }
+        if self.lossy_f64 {
+            serializer = serializer.lossy_f64();
+        }
+        if self.option_bitmap {
+            serializer = serializer.option_bitmap();
+        }
+        if self.canonical {
+            serializer = serializer.canonical();
+        }
+        if self.variant_names {
+            serializer = serializer.variant_names();
+        }
+        if self.struct_maps {
+            serializer = serializer.struct_maps();
+        }
+        if self.compact_chars {
+            serializer = serializer.compact_chars();
+        }
+        if self.pack_bools {
+            serializer = serializer.pack_bools();
+        }
+        if self.chunked_seqs {
+            serializer = serializer.chunked_seqs();
+        }
+        #[cfg(feature = "unicode-normalization")]
+        {
+            if self.normalize_nfc {
+                serializer = serializer.normalize_nfc();
+            }
+        }
+        serializer
+    }
+
+    /// Create a `Deserializer` from `r` with these settings.
+    pub fn deserializer<R: Input>(&self, r: R) -> Deserializer<R> {
+        let mut deserializer = Deserializer::new(r)
+            .narrowing(self.narrowing)
+            .max_depth(self.max_depth)
+            .limits(self.limits)
+            .int_encoding(self.int_encoding)
+            .float_encoding(self.float_encoding)
+            .endian(self.endian);
+        if self.reject_non_finite {
+            deserializer = deserializer.reject_non_finite();
+        }
+        if self.lossy_f64 {
+            deserializer = deserializer.lossy_f64();
+        }
+        if self.option_bitmap {
+            deserializer = deserializer.option_bitmap();
+        }
+        if self.variant_names {
+            deserializer = deserializer.variant_names();
+        }
+        if self.struct_maps {
+            deserializer = deserializer.struct_maps();
+        }
+        if self.canonical {
+            deserializer = deserializer.canonical();
+        }
+        if self.truncated_structs {
+            deserializer = deserializer.truncated_structs();
+        }
+        if self.compact_chars {
+            deserializer = deserializer.compact_chars();
+        }
+        if self.pack_bools {
+            deserializer = deserializer.pack_bools();
+        }
+        if self.chunked_seqs {
+            deserializer = deserializer.chunked_seqs();
+        }
+        deserializer
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_reader_with_config, from_slice, to_vec, to_writer_with_config};
+
+    #[test]
+    fn default_settings() {
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, (1u32, "a"), &Options::default()).unwrap();
+        assert_eq!(bs, to_vec((1u32, "a")).unwrap());
+
+        let v: (u32, String) = from_reader_with_config(bs.as_slice(), &Options::new()).unwrap();
+        assert_eq!(v, from_slice::<(u32, String)>(&bs).unwrap());
+    }
+
+    #[test]
+    fn configured() {
+        let bs = to_vec((70000u32, std::f32::INFINITY)).unwrap();
+
+        let options = Options::new().narrowing(Narrowing::Saturate);
+        let v: (u16, f32) = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(v.0, u16::max_value());
+
+        let options = options.reject_non_finite();
+        let e = from_reader_with_config::<_, (u16, f32)>(bs.as_slice(), &options).unwrap_err();
+        assert_eq!(e.offset(), Some(7));
+
+        let options = Options::new().max_depth(1);
+        let bs = to_vec(Some(Some(1u8))).unwrap();
+        from_reader_with_config::<_, Option<Option<u8>>>(bs.as_slice(), &options).unwrap_err();
+
+        let options = Options::new().limits(Limits::untrusted());
+        let bs = to_vec(vec![0u8; 2 << 20]).unwrap();
+        from_reader_with_config::<_, Vec<u8>>(bs.as_slice(), &options).unwrap_err();
+    }
+
+    #[test]
+    fn non_finite() {
+        let options = Options::new().reject_non_finite();
+        for &v in &[std::f64::NAN, std::f64::INFINITY, std::f64::NEG_INFINITY] {
+            let e = to_writer_with_config(Vec::new(), v, &options).unwrap_err();
+            assert_eq!(e.kind(), crate::ErrorKind::NonFiniteFloat);
+            let e = to_writer_with_config(Vec::new(), v as f32, &options).unwrap_err();
+            assert_eq!(e.kind(), crate::ErrorKind::NonFiniteFloat);
+
+            let bs = to_vec((v, v as f32)).unwrap();
+            let e = from_reader_with_config::<_, f64>(bs.as_slice(), &options).unwrap_err();
+            assert_eq!(e.kind(), crate::ErrorKind::NonFiniteFloat);
+            let e = from_reader_with_config::<_, f32>(&bs[8..], &options).unwrap_err();
+            assert_eq!(e.kind(), crate::ErrorKind::NonFiniteFloat);
+        }
+
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, (1.5f64, std::f32::MAX), &options).unwrap();
+        let d: (f64, f32) = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, (1.5, std::f32::MAX));
+    }
+
+    #[test]
+    fn fixed_int() {
+        let options = Options::new().int_encoding(IntEncoding::Fixed);
+        let v = (1u16, -2i32, 3u64, -4i128, 5u8, -6i64);
+
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, v, &options).unwrap();
+        assert_eq!(bs.len(), 2 + 4 + 8 + 16 + 1 + 8);
+        assert_eq!(&bs[..6], &[1, 0, 0xFE, 0xFF, 0xFF, 0xFF]);
+
+        let d: (u16, i32, u64, i128, u8, i64) =
+            from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, v);
+
+        let v = (
+            vec![u32::max_value()],
+            Some(i16::min_value()),
+            std::u128::MAX,
+        );
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(bs.len(), 1 + 4 + 1 + 2 + 16);
+        let d: (Vec<u32>, Option<i16>, u128) =
+            from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn big_endian() {
+        let options = Options::new().endian(Endian::Big);
+        let v = (1.0f32, 'あ', 300u16, -1i8);
+
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, v, &options).unwrap();
+        assert_eq!(
+            bs,
+            vec![0x3F, 0x80, 0, 0, 0x00, 0x30, 0x42, 0x81, 0x2C, 0xFF]
+        );
+        let d: (f32, char, u16, i8) = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, v);
+
+        let options = options.int_encoding(IntEncoding::Fixed);
+        let v = (0x0102u16, -2i32, 1.5f64);
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, v, &options).unwrap();
+        assert_eq!(&bs[..6], &[1, 2, 0xFF, 0xFF, 0xFF, 0xFE]);
+        assert_eq!(&bs[6..], &1.5f64.to_be_bytes()[..]);
+        let d: (u16, i32, f64) = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn compact_float() {
+        let options = Options::new().float_encoding(FloatEncoding::Compact);
+        let v = (3.0f64, -2.0f32, 0.5f64, -0.0f64, std::f64::NAN, 1e300f64);
+
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, v, &options).unwrap();
+        assert_eq!(&bs[..4], &[0, 6, 0, 3]);
+        assert_eq!(bs.len(), 2 + 2 + 9 + 9 + 9 + 9);
+
+        let d: (f64, f32, f64, f64, f64, f64) =
+            from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!((d.0, d.1, d.2, d.5), (v.0, v.1, v.2, v.5));
+        assert_eq!(d.3.to_bits(), v.3.to_bits());
+        assert!(d.4.is_nan());
+
+        let options = options.endian(Endian::Big);
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, (0.25f32, 7.0f32), &options).unwrap();
+        assert_eq!(bs, vec![1, 0x3E, 0x80, 0, 0, 0, 14]);
+
+        let e = from_reader_with_config::<_, f64>(&[2u8][..], &options).unwrap_err();
+        assert_eq!(e.offset(), Some(1));
+    }
+
+    #[test]
+    fn lossy_f64() {
+        let options = Options::new().lossy_f64();
+        let v = (0.1f64, 1.5f32, 1e300f64);
+
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, v, &options).unwrap();
+        assert_eq!(bs.len(), 4 + 4 + 4);
+        assert_eq!(&bs[..4], &0.1f32.to_le_bytes()[..]);
+
+        let d: (f64, f32, f64) = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d.0, 0.1f32 as f64);
+        assert_eq!(d.1, 1.5);
+        assert!(d.2.is_infinite());
+
+        let options = options
+            .reject_non_finite()
+            .float_encoding(FloatEncoding::Compact);
+        assert!(to_writer_with_config(Vec::new(), 1e300f64, &options).is_err());
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, (2.0f64, 0.5f64), &options).unwrap();
+        assert_eq!(bs.len(), 2 + 5);
+        let d: (f64, f64) = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, (2.0, 0.5));
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        id: u32,
+        a: Option<u8>,
+        b: Option<String>,
+        c: Option<u16>,
+        tags: Vec<Option<u8>>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Event {
+        Moved { x: Option<i32>, y: Option<i32> },
+    }
+
+    #[test]
+    fn option_bitmap() {
+        let options = Options::new().option_bitmap();
+        let v = Record {
+            id: 7,
+            a: None,
+            b: Some("x".to_owned()),
+            c: None,
+            tags: vec![None, Some(1)],
+        };
+
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(bs, vec![0b0_0100, 7, 1, b'x', 2, 0, 1, 1]);
+        assert_eq!(bs.len() + 2, to_vec(&v).unwrap().len());
+        let d: Record = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, v);
+
+        let v = vec![
+            Event::Moved {
+                x: Some(-1),
+                y: None,
+            },
+            Event::Moved { x: None, y: None },
+        ];
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(bs, vec![2, 0, 0b01, 1, 0, 0b00]);
+        let d: Vec<Event> = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn option_bitmap_overhead() {
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Wide(u8, u8, u8, u8, u8, u8, u8, u8, u8);
+
+        let options = Options::new().option_bitmap();
+        let overhead = |bs: Vec<u8>, plain: Vec<u8>| bs.len() - plain.len();
+
+        let v = Point { x: 1, y: -1 };
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(bs, vec![0, 2, 1]);
+        assert_eq!(overhead(bs, to_vec(&v).unwrap()), 1);
+
+        // Tuple structs have no bitmap.
+        let v = Wide(1, 2, 3, 4, 5, 6, 7, 8, 9);
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(overhead(bs, to_vec(&v).unwrap()), 0);
+
+        let v = vec![Point { x: 0, y: 0 }; 100];
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(overhead(bs, to_vec(&v).unwrap()), 100);
+    }
+
+    #[test]
+    fn variant_names() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Old {
+            Start,
+            Stop(u8),
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum New {
+            Pause { at: u32 },
+            Stop(u8),
+            Start,
+        }
+
+        let options = Options::new().variant_names();
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, vec![Old::Stop(3), Old::Start], &options).unwrap();
+        assert_eq!(&bs[..7], &[2, 4, b'S', b't', b'o', b'p', 3]);
+
+        let d: Vec<New> = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, vec![New::Stop(3), New::Start]);
+
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, New::Pause { at: 1 }, &options).unwrap();
+        let d: New = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, New::Pause { at: 1 });
+        let e = from_reader_with_config::<_, Old>(bs.as_slice(), &options).unwrap_err();
+        assert_eq!(e.kind(), crate::ErrorKind::Serde);
+    }
+
+    #[test]
+    fn struct_maps() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Old {
+            id: u8,
+            name: String,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct New {
+            name: String,
+            id: u8,
+            #[serde(default)]
+            tags: Vec<String>,
+        }
+
+        let options = Options::new().struct_maps().option_bitmap();
+        let v = Old {
+            id: 1,
+            name: "a".to_owned(),
+        };
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(
+            bs,
+            vec![2, 2, b'i', b'd', 1, 4, b'n', b'a', b'm', b'e', 1, b'a']
+        );
+
+        // Readable without the type.
+        let d: (u8, String, u8, String, String) = from_slice(&bs).unwrap();
+        assert_eq!((d.1.as_str(), d.3.as_str()), ("id", "name"));
+
+        let d: New = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(
+            d,
+            New {
+                name: "a".to_owned(),
+                id: 1,
+                tags: Vec::new(),
+            }
+        );
+
+        let v = Event::Moved {
+            x: Some(1),
+            y: None,
+        };
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        let d: Event = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, v);
+
+        // A removed field can't be skipped, as values aren't length-prefixed.
+        let v = New {
+            name: "a".to_owned(),
+            id: 1,
+            tags: vec!["t".to_owned()],
+        };
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        let e = from_reader_with_config::<_, Old>(bs.as_slice(), &options).unwrap_err();
+        assert_eq!(e.kind(), crate::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn truncated_structs() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct V1 {
+            id: u32,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct V2 {
+            id: u32,
+            #[serde(default)]
+            tags: Vec<String>,
+            #[serde(default)]
+            rank: Option<u8>,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct V3 {
+            id: u32,
+            weight: u8,
+        }
+
+        let bs = to_vec(V1 { id: 300 }).unwrap();
+        let options = Options::new().truncated_structs();
+
+        let d: V2 = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(
+            d,
+            V2 {
+                id: 300,
+                tags: Vec::new(),
+                rank: None,
+            }
+        );
+        let e = from_reader_with_config::<_, V3>(bs.as_slice(), &options).unwrap_err();
+        assert_eq!(e.kind(), crate::ErrorKind::Serde);
+        let e = from_slice::<V2>(&bs).unwrap_err();
+        assert!(e.is_eof());
+
+        // Only the end of the input ends structs.
+        let bs = to_vec((V1 { id: 1 }, 2u8)).unwrap();
+        from_reader_with_config::<_, (V2, u8)>(bs.as_slice(), &options).unwrap_err();
+    }
+
+    #[test]
+    fn presets() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Sample {
+            count: u64,
+            ratio: f64,
+            note: Option<String>,
+            flag: Option<bool>,
+        }
+
+        let v = Sample {
+            count: 3,
+            ratio: 10.0,
+            note: None,
+            flag: None,
+        };
+        let size = |options: &Options| {
+            let mut bs = Vec::new();
+            to_writer_with_config(&mut bs, &v, options).unwrap();
+            let d: Sample = from_reader_with_config(bs.as_slice(), options).unwrap();
+            assert_eq!(d, v);
+            bs.len()
+        };
+
+        assert_eq!(size(&Options::compatible()), 1 + 8 + 1 + 1);
+        assert_eq!(size(&Options::small()), 1 + 1 + 2);
+        assert_eq!(size(&Options::fast()), 8 + 8 + 1 + 1);
+
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &Options::compatible()).unwrap();
+        assert_eq!(bs, to_vec(&v).unwrap());
+    }
+
+    #[test]
+    fn compact_chars() {
+        let options = Options::new().compact_chars();
+        for &(c, len) in &[
+            ('a', 1),
+            ('é', 2),
+            ('あ', 2),
+            ('𡈼', 3),
+            (std::char::MAX, 3),
+        ] {
+            let mut bs = Vec::new();
+            to_writer_with_config(&mut bs, c, &options).unwrap();
+            assert_eq!(bs.len(), len, "{}", c);
+            let d: char = from_reader_with_config(bs.as_slice(), &options).unwrap();
+            assert_eq!(d, c);
+        }
+
+        // A surrogate isn't a char.
+        let e = from_reader_with_config::<_, char>(&[0xC0, 0xD8, 0x00][..], &options).unwrap_err();
+        assert_eq!(e.kind(), crate::ErrorKind::Serde);
+    }
+
+    #[test]
+    fn pack_bools() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Flags {
+            a: bool,
+            b: bool,
+            c: bool,
+            id: u8,
+            d: bool,
+            e: Option<bool>,
+            bits: Vec<bool>,
+            f: bool,
+        }
+
+        let round_trip = |options: &Options, v: &Flags| {
+            let mut bs = Vec::new();
+            to_writer_with_config(&mut bs, v, options).unwrap();
+            let d: Flags = from_reader_with_config(bs.as_slice(), options).unwrap();
+            assert_eq!(&d, v);
+            bs
+        };
+
+        let options = Options::new().pack_bools();
+        let v = Flags {
+            a: true,
+            b: false,
+            c: true,
+            id: 7,
+            d: true,
+            e: Some(true),
+            bits: (0..11).map(|i| i % 3 == 0).collect(),
+            f: false,
+        };
+        let bs = round_trip(&options, &v);
+        assert_eq!(bs, vec![0b101, 7, 0b1, 1, 1, 11, 0b0100_1001, 0b010, 0b0]);
+        assert_eq!(to_vec(&v).unwrap().len(), 3 + 1 + 1 + 2 + 1 + 11 + 1);
+
+        round_trip(&options.clone().option_bitmap(), &v);
+        let v = Flags {
+            e: None,
+            bits: vec![true; 16],
+            ..v
+        };
+        let bs = round_trip(&options.clone().option_bitmap(), &v);
+        assert_eq!(&bs[..7], &[0, 0b101, 7, 0b1, 16, 0xFF, 0xFF]);
+        round_trip(&options.clone().struct_maps(), &v);
+
+        let v: Vec<Vec<bool>> = vec![vec![true, false], vec![], vec![false; 9]];
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(bs, vec![3, 2, 0b01, 0, 9, 0, 0]);
+        let d: Vec<Vec<bool>> = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn chunked_seqs() {
+        use std::collections::BTreeMap;
+
+        /// Serialized without size hint.
+        struct Unsized<'a, T>(&'a [T]);
+
+        impl<'a, T: serde::Serialize> serde::Serialize for Unsized<'a, T> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().filter(|_| true))
+            }
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Record {
+            id: u8,
+            tags: Vec<u8>,
+            map: BTreeMap<u8, bool>,
+        }
+
+        let options = Options::new().chunked_seqs();
+        let v = Record {
+            id: 1,
+            tags: vec![2, 3],
+            map: vec![(4, true)].into_iter().collect(),
+        };
+        for options in &[
+            options.clone(),
+            options.clone().canonical(),
+            options.clone().struct_maps(),
+            options.clone().pack_bools(),
+        ] {
+            let mut bs = Vec::new();
+            to_writer_with_config(&mut bs, &v, options).unwrap();
+            let d: Record = from_reader_with_config(bs.as_slice(), options).unwrap();
+            assert_eq!(d, v);
+        }
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, &v, &options).unwrap();
+        assert_eq!(bs, vec![1, 2, 2, 3, 0, 1, 4, 1, 0]);
+
+        // Elements without size hint are written in chunks of about 4 KiB.
+        let elements: Vec<u32> = (0..5000).collect();
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, Unsized(&elements), &options).unwrap();
+        // 2112 elements, 128 of 1 byte and the others of 2 bytes, make the first chunk.
+        assert_eq!(&bs[..2], &[0x88, 0x40]);
+        let d: Vec<u32> = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, elements);
+        assert!(from_reader_with_config::<_, Vec<u32>>(
+            bs.as_slice(),
+            &options.clone().limits(Limits::default().max_seq_len(4999))
+        )
+        .is_err());
+
+        // A run of packed bools ends at the end of a chunk.
+        let bools: Vec<bool> = (0..40000).map(|i| i % 3 == 0).collect();
+        let options = options.pack_bools();
+        let mut bs = Vec::new();
+        to_writer_with_config(&mut bs, Unsized(&bools), &options).unwrap();
+        let d: Vec<bool> = from_reader_with_config(bs.as_slice(), &options).unwrap();
+        assert_eq!(d, bools);
+    }
+}
@@ -0,0 +1,525 @@
+//! Partial updates of structs.
+//!
+//! A patch carries only the fields of a struct which have changed, each prefixed by its field index,
+//! so it can be merged into an existing value leaving the other fields untouched.
+//!
+//! Patch format:
+//!
+//! ```text
+//! count (varuint) , { field index (varuint) , length (varuint) , field value } * count
+//! ```
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+use serde::de::{DeserializeOwned, DeserializeSeed, Error as _, SeqAccess, Unexpected, Visitor};
+use serde::ser::{self, Error as _, Serialize};
+
+use crate::de::{self, Deserializer};
+use crate::error::Offset;
+use crate::io::read_to_vec;
+use crate::ser::{Error, Serializer};
+use crate::varuint::{decode_u64, encode_u64};
+
+/// Serialize the fields of `value` which differ from `base` as a patch into the IO stream.
+///
+/// `T` must serialize as a struct.
+pub fn to_writer_patch<W: Write, T: Serialize>(mut w: W, base: &T, value: &T) -> Result<(), Error> {
+    let base_fields = split_fields(base)?;
+    let fields = split_fields(value)?;
+
+    if base_fields.len() != fields.len() {
+        return Err(Error::custom("field count mismatch"));
+    }
+
+    let changed: Vec<(usize, &Vec<u8>)> = fields
+        .iter()
+        .enumerate()
+        .filter(|(i, f)| base_fields[*i] != **f)
+        .collect();
+
+    encode_u64(&mut w, changed.len() as u64)?;
+    for (i, f) in changed {
+        encode_u64(&mut w, i as u64)?;
+        encode_u64(&mut w, f.len() as u64)?;
+        w.write_all(f)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Deserialize a patch from IO stream and merge it into `place`.
+///
+/// Fields not contained in the patch are left as they are.
+/// Each field is decoded from its own bytes, so a field value shorter or longer than its length is an error.
+pub fn merge_from_reader<R: Read, T: Serialize + DeserializeOwned>(
+    mut r: R,
+    place: &mut T,
+) -> Result<(), de::Error> {
+    let mut fields = split_fields(&*place).map_err(de::Error::custom)?;
+
+    let count = decode_u64(&mut r)?;
+    for _ in 0..count {
+        let idx = decode_u64(&mut r)?;
+        let len = decode_u64(&mut r)?;
+
+        let field = usize::try_from(idx)
+            .ok()
+            .and_then(|idx| fields.get_mut(idx))
+            .ok_or_else(|| de::Error::invalid_value(Unexpected::Unsigned(idx), &"field index"))?;
+        let len = usize::try_from(len)
+            .map_err(|_| de::Error::invalid_value(Unexpected::Unsigned(len), &"field length"))?;
+        // The buffer grows as bytes arrive, so a corrupt length fails at the end of input.
+        read_to_vec(&mut r, field, len)?;
+    }
+
+    *place = T::deserialize(FieldsDeserializer { fields: &fields })?;
+    Ok(())
+}
+
+/// Decode a value from `bs`, which must hold exactly the value.
+fn decode_field<'de, S: DeserializeSeed<'de>>(bs: &[u8], seed: S) -> Result<S::Value, de::Error> {
+    let mut deserializer = Deserializer::new(bs);
+    let value = seed.deserialize(&mut deserializer)?;
+    if deserializer.position() != bs.len() as u64 {
+        return Err(de::Error::custom("trailing bytes in field"));
+    }
+    Ok(value)
+}
+
+fn split_fields<T: Serialize>(value: &T) -> Result<Vec<Vec<u8>>, Error> {
+    let mut fields = Vec::new();
+    value.serialize(FieldSplitter {
+        fields: &mut fields,
+    })?;
+    Ok(fields)
+}
+
+/// Captures encoded bytes of each field of a struct separately.
+struct FieldSplitter<'a> {
+    fields: &'a mut Vec<Vec<u8>>,
+}
+
+impl<'a> FieldSplitter<'a> {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let mut bs = Vec::new();
+        value.serialize(&mut Serializer::new(&mut bs))?;
+        self.fields.push(bs);
+        Ok(())
+    }
+}
+
+fn not_a_struct<T>() -> Result<T, Error> {
+    Err(Error::custom("patch target must be a struct"))
+}
+
+impl<'a> ser::Serializer for FieldSplitter<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = ser::Impossible<(), Error>;
+    type SerializeTuple = ser::Impossible<(), Error>;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = ser::Impossible<(), Error>;
+
+    fn serialize_bool(self, _v: bool) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_char(self, _v: char) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        mut self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Error> {
+        not_a_struct()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        not_a_struct()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        not_a_struct()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        not_a_struct()
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        not_a_struct()
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        not_a_struct()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for FieldSplitter<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeStruct for FieldSplitter<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Deserializes a struct whose fields are encoded separately.
+struct FieldsDeserializer<'a> {
+    fields: &'a [Vec<u8>],
+}
+
+impl<'de, 'a> serde::Deserializer<'de> for FieldsDeserializer<'a> {
+    type Error = de::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, de::Error> {
+        Err(de::Error::Unsupported(
+            "patch target other than struct",
+            Offset::UNKNOWN,
+        ))
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, de::Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, de::Error> {
+        match self.fields {
+            [field] => decode_field(field, NewtypeSeed(visitor)),
+            _ => Err(de::Error::invalid_length(self.fields.len(), &"1 field")),
+        }
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, de::Error> {
+        visitor.visit_seq(FieldsAccess {
+            fields: self.fields.iter(),
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, de::Error> {
+        visitor.visit_seq(FieldsAccess {
+            fields: self.fields.iter(),
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit seq tuple map enum identifier ignored_any
+    }
+}
+
+/// Visits the inner value of a newtype struct with the field deserializer.
+struct NewtypeSeed<V>(V);
+
+impl<'de, V: Visitor<'de>> DeserializeSeed<'de> for NewtypeSeed<V> {
+    type Value = V::Value;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, d: D) -> Result<V::Value, D::Error> {
+        self.0.visit_newtype_struct(d)
+    }
+}
+
+struct FieldsAccess<'a> {
+    fields: std::slice::Iter<'a, Vec<u8>>,
+}
+
+impl<'de, 'a> SeqAccess<'de> for FieldsAccess<'a> {
+    type Error = de::Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, de::Error> {
+        match self.fields.next() {
+            Some(field) => decode_field(field, seed).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::ErrorKind;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        port: u16,
+        verbose: bool,
+        tags: Vec<String>,
+    }
+
+    fn sample() -> Config {
+        Config {
+            name: "server".to_owned(),
+            port: 8080,
+            verbose: false,
+            tags: vec!["a".to_owned(), "b".to_owned()],
+        }
+    }
+
+    #[test]
+    fn patch_changed_fields_only() {
+        let base = sample();
+        let mut value = base.clone();
+        value.port = 9090;
+
+        let mut bs = Vec::new();
+        to_writer_patch(&mut bs, &base, &value).unwrap();
+
+        let mut port = Vec::new();
+        encode_u64(&mut port, 9090).unwrap();
+
+        let mut to_be = vec![1u8, 1, port.len() as u8];
+        to_be.extend(port);
+        assert_eq!(bs, to_be);
+    }
+
+    #[test]
+    fn merge_leaves_untouched_fields() {
+        let base = sample();
+        let mut value = base.clone();
+        value.verbose = true;
+        value.tags.push("c".to_owned());
+
+        let mut bs = Vec::new();
+        to_writer_patch(&mut bs, &base, &value).unwrap();
+
+        let mut place = base.clone();
+        place.name = "local".to_owned();
+        merge_from_reader(bs.as_slice(), &mut place).unwrap();
+
+        assert_eq!(&place.name, "local");
+        assert_eq!(place.port, 8080);
+        assert!(place.verbose);
+        assert_eq!(place.tags, value.tags);
+    }
+
+    #[test]
+    fn merge_empty_patch() {
+        let base = sample();
+
+        let mut bs = Vec::new();
+        to_writer_patch(&mut bs, &base, &base).unwrap();
+        assert_eq!(bs, vec![0u8]);
+
+        let mut place = base.clone();
+        merge_from_reader(bs.as_slice(), &mut place).unwrap();
+        assert_eq!(place, base);
+    }
+
+    #[test]
+    fn merge_fails_with_out_of_range_field() {
+        let bs = [1u8, 4, 1, 0];
+        let mut place = sample();
+        let _ = merge_from_reader(&bs[..], &mut place).unwrap_err();
+    }
+
+    #[test]
+    fn merge_fails_with_wrong_field_length() {
+        let mut port = Vec::new();
+        encode_u64(&mut port, 9090).unwrap();
+        let mut place = sample();
+
+        let mut short = vec![1u8, 1, port.len() as u8 - 1];
+        short.extend(&port[..port.len() - 1]);
+        let e = merge_from_reader(short.as_slice(), &mut place).unwrap_err();
+        assert!(e.is_eof());
+
+        let mut long = vec![1u8, 1, port.len() as u8 + 1];
+        long.extend(&port);
+        long.push(0);
+        let e = merge_from_reader(long.as_slice(), &mut place).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Serde);
+        assert_eq!(place, sample());
+    }
+
+    #[test]
+    fn merge_fails_with_huge_field_length() {
+        let mut bs = vec![1u8, 0];
+        encode_u64(&mut bs, u64::max_value()).unwrap();
+        bs.extend(b"abc");
+
+        let mut place = sample();
+        let _ = merge_from_reader(bs.as_slice(), &mut place).unwrap_err();
+        assert_eq!(place, sample());
+    }
+
+    #[test]
+    fn patch_fails_with_non_struct() {
+        let mut bs = Vec::new();
+        let _ = to_writer_patch(&mut bs, &1u32, &2u32).unwrap_err();
+    }
+}
@@ -0,0 +1,232 @@
+//! Fast path of plain old data buffers for `#[serde(with = "...")]`.
+//!
+//! A `Vec` of [`Pod`](trait.Pod.html) types is copied as one block of bytes,
+//! instead of serializing each field of each element.
+//! This is much faster for large buffers such as vertices or particles,
+//! but fields are written in fixed width, without varuint compaction.
+//!
+//! The buffer is encoded as a byte array of elements in little endian.
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//! use serde_dokechi::pod::Pod;
+//!
+//! #[derive(Debug, Clone, Copy, PartialEq)]
+//! #[repr(C)]
+//! struct Vertex {
+//!     position: [f32; 3],
+//!     color: u32,
+//! }
+//!
+//! // Safety: `Vertex` is `repr(C)` without padding, and any bit pattern is valid.
+//! unsafe impl Pod for Vertex {
+//!     fn reverse_bytes(&mut self) {
+//!         self.position.reverse_bytes();
+//!         self.color.reverse_bytes();
+//!     }
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Mesh {
+//!     #[serde(with = "serde_dokechi::pod")]
+//!     vertices: Vec<Vertex>,
+//! }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::slice;
+
+use serde::de::{Deserializer, Visitor};
+use serde::ser::{Error as _, Serializer};
+
+/// Types copied as raw bytes.
+///
+/// # Safety
+///
+/// Implementing types must be `repr(C)` or `repr(transparent)` of `Pod` fields, without padding bytes,
+/// and any bit pattern must be a valid value. So `bool`, `char` and references can't be fields.
+pub unsafe trait Pod: Copy + 'static {
+    /// Reverse the byte order of each field.
+    ///
+    /// Called only on big endian targets.
+    fn reverse_bytes(&mut self);
+}
+
+macro_rules! impl_pod_int {
+    ($($t:ty),*) => {
+        $(
+            unsafe impl Pod for $t {
+                fn reverse_bytes(&mut self) {
+                    *self = <$t>::swap_bytes(*self);
+                }
+            }
+        )*
+    };
+}
+
+impl_pod_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+unsafe impl Pod for f32 {
+    fn reverse_bytes(&mut self) {
+        *self = f32::from_bits(self.to_bits().swap_bytes());
+    }
+}
+
+unsafe impl Pod for f64 {
+    fn reverse_bytes(&mut self) {
+        *self = f64::from_bits(self.to_bits().swap_bytes());
+    }
+}
+
+macro_rules! impl_pod_array {
+    ($($n:expr),*) => {
+        $(
+            unsafe impl<T: Pod> Pod for [T; $n] {
+                fn reverse_bytes(&mut self) {
+                    for v in self.iter_mut() {
+                        v.reverse_bytes();
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_pod_array!(
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+    27, 28, 29, 30, 31, 32
+);
+
+/// Serialize a buffer of `Pod` values.
+pub fn serialize<T: Pod, S: Serializer>(v: &[T], s: S) -> Result<S::Ok, S::Error> {
+    if mem::size_of::<T>() == 0 {
+        return Err(S::Error::custom("zero-sized Pod types are unsupported"));
+    }
+
+    if cfg!(target_endian = "little") {
+        s.serialize_bytes(as_bytes(v))
+    } else {
+        let mut bs = Vec::with_capacity(mem::size_of_val(v));
+        for &e in v {
+            let mut e = e;
+            e.reverse_bytes();
+            bs.extend_from_slice(as_bytes(slice::from_ref(&e)));
+        }
+        s.serialize_bytes(&bs)
+    }
+}
+
+/// Deserialize a buffer of `Pod` values.
+pub fn deserialize<'de, T: Pod, D: Deserializer<'de>>(d: D) -> Result<Vec<T>, D::Error> {
+    struct BytesVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: Pod> Visitor<'de> for BytesVisitor<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("byte array of plain old data")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Vec<T>, E> {
+            from_bytes(v).map_err(E::custom)
+        }
+    }
+
+    d.deserialize_bytes(BytesVisitor(PhantomData))
+}
+
+fn as_bytes<T: Pod>(v: &[T]) -> &[u8] {
+    // Safety: `Pod` types have no padding, so all bytes are initialized.
+    unsafe { slice::from_raw_parts(v.as_ptr() as *const u8, mem::size_of_val(v)) }
+}
+
+fn from_bytes<T: Pod>(bs: &[u8]) -> Result<Vec<T>, &'static str> {
+    let size = mem::size_of::<T>();
+    if size == 0 {
+        return Err("zero-sized Pod types are unsupported");
+    }
+    if bs.len() % size != 0 {
+        return Err("length is not a multiple of the element size");
+    }
+
+    let len = bs.len() / size;
+    let mut v = Vec::<T>::with_capacity(len);
+    // Safety: the capacity is enough for `bs`, and any bit pattern is a valid `Pod` value.
+    unsafe {
+        ptr::copy_nonoverlapping(bs.as_ptr(), v.as_mut_ptr() as *mut u8, bs.len());
+        v.set_len(len);
+    }
+    if cfg!(target_endian = "big") {
+        for e in v.iter_mut() {
+            e.reverse_bytes();
+        }
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::{from_slice, to_vec};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[repr(C)]
+    struct Particle {
+        position: [f64; 3],
+        mass: f32,
+        id: u32,
+    }
+
+    unsafe impl Pod for Particle {
+        fn reverse_bytes(&mut self) {
+            self.position.reverse_bytes();
+            self.mass.reverse_bytes();
+            self.id.reverse_bytes();
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Particles {
+        #[serde(with = "super")]
+        values: Vec<Particle>,
+    }
+
+    #[test]
+    fn round_trip() {
+        let v = Particles {
+            values: (0..100)
+                .map(|i| Particle {
+                    position: [i as f64, -1.5, 1e10],
+                    mass: 0.25,
+                    id: i,
+                })
+                .collect(),
+        };
+        let bs = to_vec(&v).unwrap();
+        assert_eq!(bs.len(), 2 + 100 * 32);
+
+        let d: Particles = from_slice(&bs).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn little_endian() {
+        #[derive(Serialize)]
+        struct Values(#[serde(with = "super")] Vec<u16>);
+
+        let bs = to_vec(Values(vec![0x0102, 0x0304])).unwrap();
+        assert_eq!(bs, vec![4, 0x02, 0x01, 0x04, 0x03]);
+    }
+
+    #[test]
+    fn partial_element() {
+        let _ = from_bytes::<u32>(&[0, 0, 0, 0, 1]).unwrap_err();
+        assert_eq!(from_bytes::<u32>(&[1, 0, 0, 0]).unwrap(), vec![1]);
+    }
+}
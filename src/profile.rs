@@ -0,0 +1,572 @@
+//! Breakdown of serialized size.
+//!
+//! [`profile`](fn.profile.html) measures how many bytes each part of a value takes in Dokechi format,
+//! to find out which fields are worth quantizing or compressing.
+//!
+//! The result is a tree of [`SizeNode`](struct.SizeNode.html).
+//! Children of a node are named after struct fields, tuple indexes and enum variants.
+//! All elements of a sequence are summed up into a child named `[]`,
+//! and keys and values of a map into `{key}` and `{value}`.
+//!
+//! ```
+//! use serde_derive::Serialize;
+//! use serde_dokechi::profile::profile;
+//!
+//! #[derive(Serialize)]
+//! struct Point {
+//!     name: String,
+//!     coords: Vec<f64>,
+//! }
+//!
+//! let p = Point {
+//!     name: "origin".to_owned(),
+//!     coords: vec![0.0, 0.0, 0.0],
+//! };
+//! let root = profile(&p).unwrap();
+//! assert_eq!(root.bytes, 32);
+//! assert_eq!(root.get(&["coords", "[]"]).unwrap().bytes, 24);
+//! assert_eq!(root.get(&["coords", "[]"]).unwrap().count, 3);
+//! ```
+
+use serde::ser::{self, Serialize};
+
+use crate::ser::{serialized_size, Error};
+use crate::varuint::encoded_len_u64;
+
+/// Serialized size of a part of a value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SizeNode {
+    /// The total number of bytes, including children.
+    pub bytes: u64,
+    /// The number of values summed up, such as elements of a sequence or occurrences of a variant.
+    pub count: u64,
+    /// Named parts of the value, in order of first appearance.
+    pub children: Vec<(String, SizeNode)>,
+}
+
+impl SizeNode {
+    /// The descendant at `path` of child names.
+    pub fn get(&self, path: &[&str]) -> Option<&SizeNode> {
+        let mut node = self;
+        for name in path {
+            node = node
+                .children
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, c)| c)?;
+        }
+        Some(node)
+    }
+
+    /// All descendants as paths joined by `.` and their number of bytes, depth first.
+    pub fn paths(&self) -> Vec<(String, u64)> {
+        let mut paths = Vec::new();
+        self.collect_paths("", &mut paths);
+        paths
+    }
+
+    fn collect_paths(&self, prefix: &str, paths: &mut Vec<(String, u64)>) {
+        for (name, child) in &self.children {
+            let path = if prefix.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", prefix, name)
+            };
+            paths.push((path.clone(), child.bytes));
+            child.collect_paths(&path, paths);
+        }
+    }
+
+    fn child(&mut self, name: &str) -> &mut SizeNode {
+        let i = match self.children.iter().position(|(n, _)| n == name) {
+            Some(i) => i,
+            None => {
+                self.children.push((name.to_owned(), SizeNode::default()));
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[i].1
+    }
+
+    fn add<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<u64, Error> {
+        let n = value.serialize(SizeProfiler { node: self })?;
+        self.bytes += n;
+        self.count += 1;
+        Ok(n)
+    }
+}
+
+/// Measure the serialized size of each part of `value`.
+pub fn profile<T: Serialize + ?Sized>(value: &T) -> Result<SizeNode, Error> {
+    let mut root = SizeNode::default();
+    root.add(value)?;
+    Ok(root)
+}
+
+/// A serializer computing sizes in Dokechi format instead of writing bytes.
+///
+/// Each value returns its size, and records the sizes of its parts into `node`.
+struct SizeProfiler<'a> {
+    node: &'a mut SizeNode,
+}
+
+impl<'a> SizeProfiler<'a> {
+    fn variant(self, variant_index: u32, variant: &'static str) -> (u64, &'a mut SizeNode) {
+        let tag = encoded_len_u64(variant_index as u64) as u64;
+        (tag, self.node.child(variant))
+    }
+}
+
+impl<'a> ser::Serializer for SizeProfiler<'a> {
+    type Ok = u64;
+    type Error = Error;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<u64, Error> {
+        serialized_size(&v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<u64, Error> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<u64, Error> {
+        Ok(encoded_len_u64(v.len() as u64) as u64 + v.len() as u64)
+    }
+
+    fn serialize_none(self) -> Result<u64, Error> {
+        Ok(1)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<u64, Error> {
+        Ok(1 + value.serialize(self)?)
+    }
+
+    fn serialize_unit(self) -> Result<u64, Error> {
+        Ok(0)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<u64, Error> {
+        Ok(0)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<u64, Error> {
+        let (tag, node) = self.variant(variant_index, variant);
+        node.count += 1;
+        Ok(tag)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<u64, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<u64, Error> {
+        let (tag, node) = self.variant(variant_index, variant);
+        Ok(tag + node.add(value)?)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a>, Error> {
+        Ok(Compound::seq(self.node, len))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a>, Error> {
+        Ok(Compound::fields(self.node))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        Ok(Compound::fields(self.node))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        let (tag, node) = self.variant(variant_index, variant);
+        Ok(Compound::variant(node, tag))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a>, Error> {
+        Ok(Compound::seq(self.node, len))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a>, Error> {
+        Ok(Compound::fields(self.node))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        let (tag, node) = self.variant(variant_index, variant);
+        Ok(Compound::variant(node, tag))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The `SizeProfiler`'s state while serializing compound values.
+struct Compound<'a> {
+    node: &'a mut SizeNode,
+    /// Bytes written before the elements, such as a variant index.
+    head: u64,
+    /// Bytes of the elements.
+    body: u64,
+    /// The length of a sequence or a map, or `None` if it has no length prefix.
+    ///
+    /// The length is counted from the elements if the size hint is `None`.
+    len: Option<Option<usize>>,
+    /// The number of elements, or entries of a map.
+    index: usize,
+    /// Whether the node is an enum variant, which counts its own bytes.
+    is_variant: bool,
+}
+
+impl<'a> Compound<'a> {
+    fn seq(node: &'a mut SizeNode, len: Option<usize>) -> Compound<'a> {
+        Compound {
+            node,
+            head: 0,
+            body: 0,
+            len: Some(len),
+            index: 0,
+            is_variant: false,
+        }
+    }
+
+    fn fields(node: &'a mut SizeNode) -> Compound<'a> {
+        Compound {
+            node,
+            head: 0,
+            body: 0,
+            len: None,
+            index: 0,
+            is_variant: false,
+        }
+    }
+
+    fn variant(node: &'a mut SizeNode, head: u64) -> Compound<'a> {
+        Compound {
+            node,
+            head,
+            body: 0,
+            len: None,
+            index: 0,
+            is_variant: true,
+        }
+    }
+
+    fn field<T: ?Sized + Serialize>(&mut self, name: &str, value: &T) -> Result<(), Error> {
+        self.body += self.node.child(name).add(value)?;
+        Ok(())
+    }
+
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let name = self.index.to_string();
+        self.index += 1;
+        self.field(&name, value)
+    }
+
+    fn end(self) -> u64 {
+        let prefix = match self.len {
+            Some(len) => encoded_len_u64(len.unwrap_or(self.index) as u64) as u64,
+            None => 0,
+        };
+        if self.is_variant {
+            self.node.bytes += self.body;
+            self.node.count += 1;
+        }
+        self.head + prefix + self.body
+    }
+}
+
+impl<'a> ser::SerializeSeq for Compound<'a> {
+    type Ok = u64;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.index += 1;
+        self.field("[]", value)
+    }
+
+    fn end(self) -> Result<u64, Error> {
+        Ok(Compound::end(self))
+    }
+}
+
+impl<'a> ser::SerializeTuple for Compound<'a> {
+    type Ok = u64;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<u64, Error> {
+        Ok(Compound::end(self))
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for Compound<'a> {
+    type Ok = u64;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<u64, Error> {
+        Ok(Compound::end(self))
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for Compound<'a> {
+    type Ok = u64;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<u64, Error> {
+        Ok(Compound::end(self))
+    }
+}
+
+impl<'a> ser::SerializeMap for Compound<'a> {
+    type Ok = u64;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        self.index += 1;
+        self.field("{key}", key)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.field("{value}", value)
+    }
+
+    fn end(self) -> Result<u64, Error> {
+        Ok(Compound::end(self))
+    }
+}
+
+impl<'a> ser::SerializeStruct for Compound<'a> {
+    type Ok = u64;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<u64, Error> {
+        Ok(Compound::end(self))
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for Compound<'a> {
+    type Ok = u64;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<u64, Error> {
+        Ok(Compound::end(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use serde_derive::Serialize;
+
+    use crate::to_vec;
+
+    #[derive(Serialize)]
+    enum Shape {
+        Empty,
+        Circle(f32),
+        Rect { w: u32, h: u32 },
+        Line(u8, u8),
+    }
+
+    #[derive(Serialize)]
+    struct Scene {
+        name: String,
+        shapes: Vec<Shape>,
+        tags: BTreeMap<String, Option<u64>>,
+        origin: (i32, i32),
+    }
+
+    fn scene() -> Scene {
+        let mut tags = BTreeMap::new();
+        tags.insert("layer".to_owned(), Some(1000));
+        tags.insert("hidden".to_owned(), None);
+        Scene {
+            name: "demo".to_owned(),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(1.5),
+                Shape::Rect { w: 300, h: 2 },
+                Shape::Circle(2.0),
+                Shape::Line(1, 2),
+            ],
+            tags,
+            origin: (-1, 200),
+        }
+    }
+
+    #[test]
+    fn sizes_add_up() {
+        let v = scene();
+        let root = profile(&v).unwrap();
+        assert_eq!(root.bytes, to_vec(&v).unwrap().len() as u64);
+        assert_eq!(root.count, 1);
+
+        for (name, child) in &root.children {
+            let field_len = match name.as_str() {
+                "name" => to_vec(&v.name),
+                "shapes" => to_vec(&v.shapes),
+                "tags" => to_vec(&v.tags),
+                "origin" => to_vec(v.origin),
+                _ => unreachable!(),
+            };
+            assert_eq!(child.bytes, field_len.unwrap().len() as u64, "{}", name);
+        }
+    }
+
+    #[test]
+    fn tree() {
+        let root = profile(&scene()).unwrap();
+
+        let shapes = root.get(&["shapes", "[]"]).unwrap();
+        assert_eq!(shapes.count, 5);
+        // Variant indexes and payloads of all elements.
+        assert_eq!(shapes.bytes, 5 + 4 + 3 + 4 + 2);
+
+        let circle = shapes.get(&["Circle"]).unwrap();
+        assert_eq!((circle.bytes, circle.count), (8, 2));
+        assert_eq!(shapes.get(&["Empty"]).unwrap().count, 1);
+        assert_eq!(shapes.get(&["Rect", "w"]).unwrap().bytes, 2);
+        assert_eq!(shapes.get(&["Line", "1"]).unwrap().bytes, 1);
+
+        let keys = root.get(&["tags", "{key}"]).unwrap();
+        assert_eq!((keys.bytes, keys.count), (13, 2));
+        let values = root.get(&["tags", "{value}"]).unwrap();
+        assert_eq!((values.bytes, values.count), (4, 2));
+
+        assert_eq!(root.get(&["origin", "1"]).unwrap().bytes, 2);
+        assert!(root.get(&["nothing"]).is_none());
+    }
+
+    #[test]
+    fn paths() {
+        let root = profile(&(1u8, vec![1000u32])).unwrap();
+        assert_eq!(
+            root.paths(),
+            vec![
+                ("0".to_owned(), 1),
+                ("1".to_owned(), 3),
+                ("1.[]".to_owned(), 2),
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Reading a serialized value as a byte stream.
+//!
+//! [`SerializeReader`](struct.SerializeReader.html) exposes a value as `impl Read`
+//! for APIs which pull a body from a reader, such as HTTP clients.
+//! The value is serialized on a background thread as bytes are pulled,
+//! so at most a few chunks of the payload are held in memory.
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+use serde::ser::Serialize;
+
+use crate::ser::to_writer;
+
+const DEFAULT_CHUNK_SIZE: usize = 8 * 1024;
+
+/// A reader producing a value in Dokechi format.
+#[derive(Debug)]
+pub struct SerializeReader {
+    rx: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl SerializeReader {
+    /// Create new `SerializeReader` producing `value`.
+    pub fn new<T: Serialize + Send + 'static>(value: T) -> SerializeReader {
+        SerializeReader::with_chunk_size(value, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Create new `SerializeReader` which serializes `chunk_size` bytes at a time.
+    pub fn with_chunk_size<T: Serialize + Send + 'static>(
+        value: T,
+        chunk_size: usize,
+    ) -> SerializeReader {
+        let (tx, rx) = sync_channel(1);
+        thread::spawn(move || {
+            let mut w = ChannelWriter {
+                tx,
+                buf: Vec::with_capacity(chunk_size),
+                chunk_size: chunk_size.max(1),
+            };
+            // Send errors mean the reader was dropped, so there is nobody to report to.
+            match to_writer(&mut w, value) {
+                Ok(()) => {
+                    let _ = w.send();
+                }
+                Err(e) => {
+                    let _ = w.tx.send(Err(io::Error::new(io::ErrorKind::Other, e)));
+                }
+            }
+        });
+
+        SerializeReader {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl Read for SerializeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                // The serializing thread finished.
+                Err(_) => self.done = true,
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+struct ChannelWriter {
+    tx: SyncSender<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(self.chunk_size));
+        self.tx
+            .send(Ok(chunk))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == self.chunk_size {
+            self.send()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde::ser::{Error as _, Serializer};
+
+    use crate::from_reader;
+
+    #[test]
+    fn read_all() {
+        let v: Vec<String> = (0..10000).map(|i| format!("item {}", i)).collect();
+
+        let r = SerializeReader::with_chunk_size(v.clone(), 100);
+        let d: Vec<String> = from_reader(r).unwrap();
+        assert_eq!(d, v);
+    }
+
+    #[test]
+    fn same_bytes_as_to_writer() {
+        let v = (1u32, "abc".to_owned(), vec![1.5f64; 100]);
+
+        let mut expected = Vec::new();
+        to_writer(&mut expected, &v).unwrap();
+
+        let mut bs = Vec::new();
+        SerializeReader::with_chunk_size(v, 7)
+            .read_to_end(&mut bs)
+            .unwrap();
+        assert_eq!(bs, expected);
+    }
+
+    struct Failing;
+
+    impl Serialize for Failing {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(S::Error::custom("failed"))
+        }
+    }
+
+    #[test]
+    fn error_is_reported() {
+        let mut bs = Vec::new();
+        let e = SerializeReader::new(Failing)
+            .read_to_end(&mut bs)
+            .unwrap_err();
+        #[cfg(not(feature = "compact-errors"))]
+        assert_eq!(e.to_string(), "failed");
+        #[cfg(feature = "compact-errors")]
+        assert_eq!(e.to_string(), "custom error");
+    }
+
+    #[test]
+    fn drop_early() {
+        let v = vec![0u8; 1024 * 1024];
+        let mut r = SerializeReader::with_chunk_size(v, 16);
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf).unwrap();
+        drop(r);
+    }
+}
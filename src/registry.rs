@@ -0,0 +1,191 @@
+//! Schema-ID-prefixed payloads resolved through a schema registry.
+//!
+//! Each payload is prefixed with the ID of its schema, like Kafka with Avro.
+//! A [`SchemaRegistry`](trait.SchemaRegistry.html) maps subjects (names of message types)
+//! to schema IDs. A subject can have several IDs as its schema evolves;
+//! writers use the latest one, and readers accept any ID registered for the expected subject.
+//!
+//! Envelope format:
+//!
+//! ```text
+//! schema ID (varuint) , payload
+//! ```
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Serialize;
+
+use crate::de::{self, from_reader};
+use crate::ser::to_writer;
+use crate::varuint::{decode_u64, encode_u64};
+use crate::Error;
+
+/// A registry of schema IDs.
+///
+/// Implement this to connect to an external registry service.
+pub trait SchemaRegistry {
+    /// The latest schema ID of `subject`.
+    fn latest_id(&self, subject: &str) -> Option<u64>;
+
+    /// The subject which schema `id` belongs to.
+    fn subject(&self, id: u64) -> Option<&str>;
+}
+
+/// A `SchemaRegistry` held in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRegistry {
+    subjects: HashMap<u64, String>,
+    latest: HashMap<String, u64>,
+}
+
+impl MemoryRegistry {
+    /// Create new empty `MemoryRegistry`.
+    pub fn new() -> MemoryRegistry {
+        MemoryRegistry::default()
+    }
+
+    /// Register schema `id` as the latest schema of `subject`.
+    ///
+    /// Returns error if `id` is already registered for another subject.
+    pub fn register(&mut self, subject: &str, id: u64) -> Result<(), Error> {
+        if let Some(s) = self.subjects.get(&id) {
+            if s != subject {
+                return Err(Error::custom(format_args!(
+                    "schema ID {} is already registered for {}",
+                    id, s
+                )));
+            }
+        }
+        self.subjects.insert(id, subject.to_owned());
+        self.latest.insert(subject.to_owned(), id);
+        Ok(())
+    }
+}
+
+impl SchemaRegistry for MemoryRegistry {
+    fn latest_id(&self, subject: &str) -> Option<u64> {
+        self.latest.get(subject).cloned()
+    }
+
+    fn subject(&self, id: u64) -> Option<&str> {
+        self.subjects.get(&id).map(String::as_str)
+    }
+}
+
+/// Serialize `value` prefixed with the latest schema ID of `subject`.
+pub fn to_writer_with_schema<W, S, T>(
+    mut w: W,
+    registry: &S,
+    subject: &str,
+    value: &T,
+) -> Result<(), Error>
+where
+    W: Write,
+    S: SchemaRegistry + ?Sized,
+    T: Serialize,
+{
+    let id = registry
+        .latest_id(subject)
+        .ok_or_else(|| Error::custom(format_args!("subject {} is not registered", subject)))?;
+    encode_u64(&mut w, id)?;
+    to_writer(w, value)?;
+    Ok(())
+}
+
+/// Deserialize a payload after checking its schema ID belongs to `subject`.
+pub fn from_reader_with_schema<R, S, T>(mut r: R, registry: &S, subject: &str) -> Result<T, Error>
+where
+    R: Read,
+    S: SchemaRegistry + ?Sized,
+    T: DeserializeOwned,
+{
+    let id = read_schema_id(&mut r)?;
+    match registry.subject(id) {
+        Some(s) if s == subject => Ok(from_reader(r)?),
+        Some(s) => Err(Error::custom(format_args!(
+            "schema ID {} belongs to {}, expected {}",
+            id, s, subject
+        ))),
+        None => Err(Error::custom(format_args!("unknown schema ID {}", id))),
+    }
+}
+
+/// Read the schema ID prefix, leaving the payload in the IO stream.
+///
+/// Use this to dispatch payloads of several subjects.
+pub fn read_schema_id<R: Read>(mut r: R) -> Result<u64, de::Error> {
+    Ok(decode_u64(&mut r)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn registry() -> MemoryRegistry {
+        let mut registry = MemoryRegistry::new();
+        registry.register("user", 1).unwrap();
+        registry.register("order", 2).unwrap();
+        registry.register("user", 3).unwrap();
+        registry
+    }
+
+    #[test]
+    fn round_trip() {
+        let registry = registry();
+
+        let mut bs = Vec::new();
+        to_writer_with_schema(&mut bs, &registry, "user", &"alice").unwrap();
+        assert_eq!(bs[0], 3);
+
+        let v: String = from_reader_with_schema(bs.as_slice(), &registry, "user").unwrap();
+        assert_eq!(v, "alice");
+    }
+
+    #[test]
+    fn old_schema_id_is_accepted() {
+        let registry = registry();
+
+        let bs = [1u8, 5, b'a', b'l', b'i', b'c', b'e'];
+        let v: String = from_reader_with_schema(&bs[..], &registry, "user").unwrap();
+        assert_eq!(v, "alice");
+    }
+
+    #[test]
+    fn wrong_subject() {
+        let registry = registry();
+
+        let mut bs = Vec::new();
+        to_writer_with_schema(&mut bs, &registry, "order", &12u32).unwrap();
+        let _ = from_reader_with_schema::<_, _, u32>(bs.as_slice(), &registry, "user").unwrap_err();
+    }
+
+    #[test]
+    fn unknown_subject_and_id() {
+        let registry = registry();
+
+        let _ = to_writer_with_schema(Vec::new(), &registry, "item", &0u8).unwrap_err();
+        let _ = from_reader_with_schema::<_, _, u8>(&[9u8, 0][..], &registry, "user").unwrap_err();
+    }
+
+    #[test]
+    fn conflicting_registration() {
+        let mut registry = registry();
+        let _ = registry.register("order", 1).unwrap_err();
+    }
+
+    #[test]
+    fn dispatch_by_id() {
+        let registry = registry();
+
+        let mut bs = Vec::new();
+        to_writer_with_schema(&mut bs, &registry, "order", &12u32).unwrap();
+
+        let mut r = bs.as_slice();
+        let id = read_schema_id(&mut r).unwrap();
+        assert_eq!(registry.subject(id), Some("order"));
+        let v: u32 = from_reader(r).unwrap();
+        assert_eq!(v, 12);
+    }
+}
@@ -0,0 +1,420 @@
+//! Request / response RPC over [frames](../frame/index.html).
+//!
+//! A [`Client`](struct.Client.html) sends requests tagged with request IDs, and matches responses
+//! to them, so several requests can be in flight on one stream.
+//! A [`Server`](struct.Server.html) dispatches requests to typed handlers registered for each method.
+//!
+//! Request frame:
+//!
+//! ```text
+//! channel: method ID
+//! payload: request ID (varuint) , request
+//! ```
+//!
+//! Response frame:
+//!
+//! ```text
+//! channel: request ID
+//! payload: status (u8) , response if status is 0, otherwise error message (string)
+//! ```
+//!
+//! ```
+//! use serde_dokechi::frame::{FrameReader, FrameWriter};
+//! use serde_dokechi::rpc::{Client, Server};
+//!
+//! let mut server = Server::new();
+//! server.register(1, |(a, b): (u32, u32)| a.checked_add(b).ok_or("overflow"));
+//!
+//! // Requests are written into a buffer here, in place of a socket.
+//! let mut client = Client::new(&[][..], Vec::new());
+//! let id = client.send(1, &(1u32, 2u32)).unwrap();
+//!
+//! let mut responses = Vec::new();
+//! let requests = client.writer_mut().clone();
+//! server
+//!     .serve(
+//!         &mut FrameReader::new(requests.as_slice()),
+//!         &mut FrameWriter::new(&mut responses),
+//!     )
+//!     .unwrap();
+//!
+//! let mut client = Client::new(responses.as_slice(), Vec::new());
+//! assert_eq!(client.wait::<u32>(id).unwrap(), 3);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Serialize;
+use thiserror::Error;
+
+use crate::de::{self, from_reader};
+use crate::frame::{Frame, FrameReader, FrameWriter};
+use crate::ser::{self, to_writer};
+use crate::varuint::decode_u64;
+
+/// The status of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The request succeeded.
+    Ok,
+    /// The handler returned an error.
+    Failed,
+    /// No handler is registered for the method.
+    UnknownMethod,
+    /// The request can't be deserialized.
+    BadRequest,
+}
+
+impl Status {
+    /// The status byte in response frames.
+    pub fn code(self) -> u8 {
+        match self {
+            Status::Ok => 0,
+            Status::Failed => 1,
+            Status::UnknownMethod => 2,
+            Status::BadRequest => 3,
+        }
+    }
+
+    /// Get the status of the status byte.
+    pub fn from_code(code: u8) -> Option<Status> {
+        match code {
+            0 => Some(Status::Ok),
+            1 => Some(Status::Failed),
+            2 => Some(Status::UnknownMethod),
+            3 => Some(Status::BadRequest),
+            _ => None,
+        }
+    }
+}
+
+/// An error of RPC.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The underlying stream returned IO error.
+    #[error("{0}")]
+    IO(#[from] io::Error),
+    /// Failed to serialize a request or response.
+    #[error("{0}")]
+    Serialize(#[from] ser::Error),
+    /// Failed to deserialize a request or response.
+    #[error("{0}")]
+    Deserialize(#[from] de::Error),
+    /// The server returned an error.
+    #[error("remote error ({status:?}): {message}")]
+    Remote {
+        /// The status of the response.
+        status: Status,
+        /// The error message from the server.
+        message: String,
+    },
+    /// The stream ended before the response of the request ID arrived.
+    #[error("stream closed before the response of request {0}")]
+    Closed(u64),
+}
+
+/// An RPC client matching responses to pending requests.
+#[derive(Debug)]
+pub struct Client<R: Read, W: Write> {
+    reader: FrameReader<R>,
+    writer: FrameWriter<W>,
+    next_id: u64,
+    /// Responses arrived before they are waited.
+    arrived: HashMap<u64, Vec<u8>>,
+}
+
+impl<R: Read, W: Write> Client<R, W> {
+    /// Create new `Client` reading responses from `r` and writing requests into `w`.
+    pub fn new(r: R, w: W) -> Client<R, W> {
+        Client {
+            reader: FrameReader::new(r),
+            writer: FrameWriter::new(w),
+            next_id: 0,
+            arrived: HashMap::new(),
+        }
+    }
+
+    /// Send a request of `method` and return its request ID.
+    ///
+    /// The writer is flushed, so the server can handle the request at once.
+    pub fn send<T: Serialize>(&mut self, method: u64, request: &T) -> Result<u64, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.writer.send(method, &(id, request))?;
+        self.writer.flush()?;
+        Ok(id)
+    }
+
+    /// Wait for the response of the request ID.
+    ///
+    /// Responses of other requests read meanwhile are kept until they are waited.
+    pub fn wait<T: DeserializeOwned>(&mut self, id: u64) -> Result<T, Error> {
+        if let Some(payload) = self.arrived.remove(&id) {
+            return decode_response(&payload);
+        }
+        loop {
+            match self.reader.read_frame()? {
+                Some(Frame { channel, payload }) if channel == id => {
+                    return decode_response(&payload)
+                }
+                Some(Frame { channel, payload }) => {
+                    self.arrived.insert(channel, payload);
+                }
+                None => return Err(Error::Closed(id)),
+            }
+        }
+    }
+
+    /// Send a request of `method` and wait for its response.
+    pub fn call<T, U>(&mut self, method: u64, request: &T) -> Result<U, Error>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let id = self.send(method, request)?;
+        self.wait(id)
+    }
+
+    /// Get a mutable reference to the writer of requests.
+    pub fn writer_mut(&mut self) -> &mut W {
+        self.writer.get_mut()
+    }
+}
+
+fn decode_response<T: DeserializeOwned>(payload: &[u8]) -> Result<T, Error> {
+    let (&code, body) = payload
+        .split_first()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+    match Status::from_code(code) {
+        Some(Status::Ok) => Ok(from_reader(body)?),
+        Some(status) => Err(Error::Remote {
+            status,
+            message: from_reader(body)?,
+        }),
+        None => Err(de::Error::invalid_value(
+            serde::de::Unexpected::Unsigned(code as u64),
+            &"response status",
+        )
+        .into()),
+    }
+}
+
+type Handler<'a> = Box<dyn FnMut(&[u8], &mut Vec<u8>) -> Status + 'a>;
+
+/// An RPC server dispatching requests to handlers registered for each method.
+#[derive(Default)]
+pub struct Server<'a> {
+    handlers: HashMap<u64, Handler<'a>>,
+    /// Buffer of response payloads.
+    buf: Vec<u8>,
+}
+
+impl<'a> Server<'a> {
+    /// Create new `Server` without handlers.
+    pub fn new() -> Server<'a> {
+        Server {
+            handlers: HashMap::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Register `handler` which responds to requests of type `T` sent to `method`.
+    ///
+    /// Errors returned by the handler are sent to the client as messages.
+    /// A handler already registered for `method` is replaced.
+    pub fn register<T, U, E, F>(&mut self, method: u64, mut handler: F)
+    where
+        T: DeserializeOwned,
+        U: Serialize,
+        E: fmt::Display,
+        F: FnMut(T) -> Result<U, E> + 'a,
+    {
+        self.handlers.insert(
+            method,
+            Box::new(move |request: &[u8], out: &mut Vec<u8>| {
+                let request: T = match from_reader(request) {
+                    Ok(v) => v,
+                    Err(e) => return error_body(out, Status::BadRequest, e),
+                };
+                let response = match handler(request) {
+                    Ok(v) => v,
+                    Err(e) => return error_body(out, Status::Failed, e),
+                };
+                match to_writer(&mut *out, &response) {
+                    Ok(()) => Status::Ok,
+                    Err(e) => {
+                        out.clear();
+                        error_body(out, Status::Failed, e)
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Handle the request of `frame` and write the response into `writer`.
+    ///
+    /// Failures of handlers are sent to the client, and don't make an error.
+    pub fn handle<W: Write>(
+        &mut self,
+        frame: &Frame,
+        writer: &mut FrameWriter<W>,
+    ) -> Result<(), Error> {
+        let mut request = frame.payload.as_slice();
+        let id = decode_u64(&mut request)?;
+
+        self.buf.clear();
+        self.buf.push(0);
+        let status = match self.handlers.get_mut(&frame.channel) {
+            Some(handler) => handler(request, &mut self.buf),
+            None => error_body(
+                &mut self.buf,
+                Status::UnknownMethod,
+                format_args!("no handler for method {}", frame.channel),
+            ),
+        };
+        self.buf[0] = status.code();
+
+        writer.send_payload(id, &self.buf)?;
+        Ok(())
+    }
+
+    /// Handle all requests read from `reader` until the stream ends.
+    ///
+    /// The writer is flushed after each response.
+    pub fn serve<R: Read, W: Write>(
+        &mut self,
+        reader: &mut FrameReader<R>,
+        writer: &mut FrameWriter<W>,
+    ) -> Result<(), Error> {
+        while let Some(frame) = reader.read_frame()? {
+            self.handle(&frame, writer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for Server<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut methods: Vec<&u64> = self.handlers.keys().collect();
+        methods.sort();
+        f.debug_struct("Server").field("methods", &methods).finish()
+    }
+}
+
+/// Write an error message after the status byte of `out`.
+fn error_body<E: fmt::Display>(out: &mut Vec<u8>, status: Status, e: E) -> Status {
+    out.truncate(1);
+    to_writer(&mut *out, e.to_string()).expect("writing to Vec doesn't fail");
+    status
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Divide {
+        a: i32,
+        b: i32,
+    }
+
+    fn server<'a>() -> Server<'a> {
+        let mut server = Server::new();
+        server.register(1, |d: Divide| {
+            d.a.checked_div(d.b).ok_or("division by zero")
+        });
+        server.register(2, |s: String| -> Result<_, String> { Ok(s.to_uppercase()) });
+        server
+    }
+
+    /// Send requests with `f`, and run the server on them.
+    fn exchange<F>(f: F) -> (Vec<u64>, Vec<u8>)
+    where
+        F: FnOnce(&mut Client<&[u8], Vec<u8>>) -> Vec<u64>,
+    {
+        let mut client = Client::new(&[][..], Vec::new());
+        let ids = f(&mut client);
+        let requests = client.writer_mut().clone();
+
+        let mut responses = Vec::new();
+        server()
+            .serve(
+                &mut FrameReader::new(requests.as_slice()),
+                &mut FrameWriter::new(&mut responses),
+            )
+            .unwrap();
+        (ids, responses)
+    }
+
+    #[test]
+    fn call() {
+        let (ids, responses) = exchange(|c| {
+            vec![
+                c.send(1, &Divide { a: 7, b: 2 }).unwrap(),
+                c.send(2, &"abc").unwrap(),
+            ]
+        });
+        assert_eq!(ids, vec![0, 1]);
+
+        let mut client = Client::new(responses.as_slice(), Vec::new());
+        assert_eq!(client.wait::<String>(1).unwrap(), "ABC");
+        assert_eq!(client.wait::<i32>(0).unwrap(), 3);
+        match client.wait::<i32>(2) {
+            Err(Error::Closed(2)) => {}
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let (_, responses) = exchange(|c| {
+            vec![
+                c.send(1, &Divide { a: 1, b: 0 }).unwrap(),
+                c.send(9, &()).unwrap(),
+                c.send(1, &()).unwrap(),
+            ]
+        });
+
+        let mut client = Client::new(responses.as_slice(), Vec::new());
+        let expected = [
+            (Status::Failed, "division by zero"),
+            (Status::UnknownMethod, "no handler for method 9"),
+            (Status::BadRequest, "unexpected end of input at byte 0"),
+        ];
+        for (id, &(status, message)) in expected.iter().enumerate() {
+            match client.wait::<i32>(id as u64) {
+                Err(Error::Remote {
+                    status: s,
+                    message: m,
+                }) => {
+                    assert_eq!(s, status);
+                    assert_eq!(m, message);
+                }
+                r => panic!("unexpected: {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn response_format() {
+        let mut bs = Vec::new();
+        let mut w = FrameWriter::new(&mut bs);
+        server()
+            .handle(
+                &Frame {
+                    channel: 2,
+                    payload: vec![5, 1, b'a'],
+                },
+                &mut w,
+            )
+            .unwrap();
+        assert_eq!(bs, vec![5, 3, 0, 1, b'A']);
+    }
+}
@@ -0,0 +1,584 @@
+//! Structure of types traced through their `Deserialize` implementations.
+
+use std::collections::BTreeMap;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Error as _, IntoDeserializer, Visitor};
+
+use crate::de::Error as DeError;
+use crate::error::Offset;
+
+const MAX_DEPTH: usize = 128;
+const MAX_PASSES: usize = 4096;
+
+/// The structure of a value.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Shape {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    F32,
+    F64,
+    Char,
+    /// Strings, however they are deserialized.
+    Str,
+    /// Byte arrays, however they are deserialized.
+    Bytes,
+    Unit,
+    UnitStruct(&'static str),
+    Option(Box<Shape>),
+    Seq(Box<Shape>),
+    Map(Box<Shape>, Box<Shape>),
+    Tuple(Vec<Shape>),
+    Newtype(&'static str, Box<Shape>),
+    TupleStruct(&'static str, Vec<Shape>),
+    Struct(&'static str, Vec<(&'static str, Shape)>),
+    /// An enum, whose variants are in [`Schema::enums`](struct.Schema.html#structfield.enums).
+    Enum(&'static str),
+    /// A named type inside of itself, which is in [`Schema::named`](struct.Schema.html#structfield.named).
+    Recursive(&'static str),
+}
+
+impl Shape {
+    /// Whether values of this shape are encoded into no bytes.
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            Shape::Unit | Shape::UnitStruct(_) => true,
+            Shape::Newtype(_, s) => s.is_empty(),
+            Shape::Tuple(ss) | Shape::TupleStruct(_, ss) => ss.iter().all(Shape::is_empty),
+            Shape::Struct(_, fs) => fs.iter().all(|(_, s)| s.is_empty()),
+            _ => false,
+        }
+    }
+}
+
+/// The data of an enum variant.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Variant {
+    Unit,
+    Newtype(Shape),
+    Tuple(Vec<Shape>),
+    Struct(Vec<(&'static str, Shape)>),
+}
+
+/// The structure of a type.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Schema {
+    pub(crate) root: Shape,
+    /// Variants of enums, by names.
+    pub(crate) enums: BTreeMap<&'static str, Vec<(&'static str, Variant)>>,
+    /// Named types other than enums, by names.
+    pub(crate) named: BTreeMap<&'static str, Shape>,
+}
+
+/// Trace the structure of `T`.
+///
+/// Types implementing `Deserialize` by `deserialize_any` are not supported.
+pub(crate) fn trace<T: DeserializeOwned>() -> Result<Schema, DeError> {
+    let mut tracer = Tracer {
+        out: Vec::new(),
+        stack: Vec::new(),
+        mute: 0,
+        cycle: 0,
+        variant: None,
+        enums: BTreeMap::new(),
+        named: BTreeMap::new(),
+    };
+
+    // Each pass traces one more variant of enums, until all variants are traced.
+    for _ in 0..MAX_PASSES {
+        tracer.out = vec![Vec::new()];
+        tracer.stack.clear();
+        tracer.cycle = 0;
+        T::deserialize(&mut tracer)?;
+
+        let done = tracer
+            .enums
+            .values()
+            .all(|def| def.contents.iter().all(Option::is_some));
+        if done {
+            let root = one(tracer.out.pop().unwrap_or_default());
+            let enums = tracer
+                .enums
+                .into_iter()
+                .map(|(name, def)| {
+                    let variants = def
+                        .variants
+                        .iter()
+                        .cloned()
+                        .zip(def.contents.into_iter().map(|c| c.unwrap_or(Variant::Unit)))
+                        .collect();
+                    (name, variants)
+                })
+                .collect();
+            return Ok(Schema {
+                root,
+                enums,
+                named: tracer.named,
+            });
+        }
+    }
+    Err(DeError::custom("too many enum variants to trace"))
+}
+
+fn one(shapes: Vec<Shape>) -> Shape {
+    shapes.into_iter().next().unwrap_or(Shape::Unit)
+}
+
+/// A deserializer recording the structure of the type instead of reading values.
+///
+/// Recursive types are recorded as references to the type names.
+/// Inside of them, the tracer is muted and chooses the shortest values,
+/// such as `None` and empty sequences, to finish.
+struct Tracer {
+    /// Shapes of elements of the values being traced.
+    out: Vec<Vec<Shape>>,
+    /// Names of types being traced.
+    stack: Vec<&'static str>,
+    mute: usize,
+    /// A counter to choose variants while muted.
+    cycle: usize,
+    /// The variant traced last.
+    variant: Option<Variant>,
+    enums: BTreeMap<&'static str, EnumDef>,
+    named: BTreeMap<&'static str, Shape>,
+}
+
+struct EnumDef {
+    variants: &'static [&'static str],
+    contents: Vec<Option<Variant>>,
+    visits: usize,
+}
+
+enum Capture {
+    Record,
+    Discard,
+    Mute,
+}
+
+impl Tracer {
+    fn leaf(&mut self, shape: Shape) {
+        if self.mute == 0 {
+            self.out.last_mut().expect("output list").push(shape);
+        }
+    }
+
+    fn open(&mut self) {
+        if self.mute == 0 {
+            self.out.push(Vec::new());
+        }
+    }
+
+    fn close(&mut self) -> Vec<Shape> {
+        if self.mute == 0 {
+            self.out.pop().unwrap_or_default()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn push_name(&mut self, name: &'static str) -> Result<bool, DeError> {
+        if self.stack.len() >= MAX_DEPTH {
+            return Err(DeError::DepthLimitExceeded(MAX_DEPTH, Offset::UNKNOWN));
+        }
+        let recursive = self.stack.contains(&name);
+        self.stack.push(name);
+        Ok(recursive)
+    }
+
+    /// Trace a named type with `f`, muting it if the type is recursive.
+    ///
+    /// `f` records a single shape.
+    fn named<T, F>(&mut self, name: &'static str, f: F) -> Result<T, DeError>
+    where
+        F: FnOnce(&mut Tracer) -> Result<T, DeError>,
+    {
+        let recursive = self.push_name(name)?;
+
+        let r = if recursive {
+            self.leaf(Shape::Recursive(name));
+            self.mute += 1;
+            let r = f(self);
+            self.mute -= 1;
+            r
+        } else {
+            self.open();
+            let r = f(self);
+            if self.mute == 0 {
+                let shape = one(self.close());
+                self.named.entry(name).or_insert_with(|| shape.clone());
+                self.leaf(shape);
+            }
+            r
+        };
+
+        self.stack.pop();
+        r
+    }
+
+    fn elements<'de, V: Visitor<'de>>(
+        &mut self,
+        len: usize,
+        visitor: V,
+    ) -> (Result<V::Value, DeError>, Vec<Shape>) {
+        self.open();
+        let v = visitor.visit_seq(Elements {
+            tracer: &mut *self,
+            len,
+        });
+        (v, self.close())
+    }
+}
+
+macro_rules! trace_primitive {
+    ($method:ident, $visit:ident, $shape:expr, $value:expr) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+            self.leaf($shape);
+            visitor.$visit($value)
+        }
+    };
+}
+
+impl<'de> de::Deserializer<'de> for &mut Tracer {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DeError> {
+        Err(DeError::Unsupported("deserialize_any", Offset::UNKNOWN))
+    }
+
+    trace_primitive!(deserialize_bool, visit_bool, Shape::Bool, false);
+    trace_primitive!(deserialize_i8, visit_i8, Shape::I8, 1);
+    trace_primitive!(deserialize_i16, visit_i16, Shape::I16, 1);
+    trace_primitive!(deserialize_i32, visit_i32, Shape::I32, 1);
+    trace_primitive!(deserialize_i64, visit_i64, Shape::I64, 1);
+    trace_primitive!(deserialize_i128, visit_i128, Shape::I128, 1);
+    trace_primitive!(deserialize_u8, visit_u8, Shape::U8, 1);
+    trace_primitive!(deserialize_u16, visit_u16, Shape::U16, 1);
+    trace_primitive!(deserialize_u32, visit_u32, Shape::U32, 1);
+    trace_primitive!(deserialize_u64, visit_u64, Shape::U64, 1);
+    trace_primitive!(deserialize_u128, visit_u128, Shape::U128, 1);
+    trace_primitive!(deserialize_f32, visit_f32, Shape::F32, 0.0);
+    trace_primitive!(deserialize_f64, visit_f64, Shape::F64, 0.0);
+    trace_primitive!(deserialize_char, visit_char, Shape::Char, 'a');
+    trace_primitive!(deserialize_str, visit_str, Shape::Str, "");
+    trace_primitive!(deserialize_string, visit_string, Shape::Str, String::new());
+    trace_primitive!(deserialize_bytes, visit_bytes, Shape::Bytes, &[]);
+    trace_primitive!(
+        deserialize_byte_buf,
+        visit_byte_buf,
+        Shape::Bytes,
+        Vec::new()
+    );
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.leaf(Shape::Unit);
+        visitor.visit_unit()
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        if self.mute > 0 {
+            return visitor.visit_none();
+        }
+        self.open();
+        let v = visitor.visit_some(&mut *self);
+        let inner = one(self.close());
+        self.leaf(Shape::Option(Box::new(inner)));
+        v
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.leaf(Shape::UnitStruct(name));
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.named(name, |tracer| {
+            tracer.open();
+            let v = visitor.visit_newtype_struct(&mut *tracer);
+            let inner = one(tracer.close());
+            tracer.leaf(Shape::Newtype(name, Box::new(inner)));
+            v
+        })
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.open();
+        let v = visitor.visit_seq(Sample {
+            tracer: &mut *self,
+            done: false,
+        });
+        let element = one(self.close());
+        self.leaf(Shape::Seq(Box::new(element)));
+        v
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        let (v, elements) = self.elements(len, visitor);
+        self.leaf(Shape::Tuple(elements));
+        v
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.named(name, |tracer| {
+            let (v, elements) = tracer.elements(len, visitor);
+            tracer.leaf(Shape::TupleStruct(name, elements));
+            v
+        })
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.open();
+        let v = visitor.visit_map(Sample {
+            tracer: &mut *self,
+            done: false,
+        });
+        let mut entry = self.close().into_iter();
+        let key = entry.next().unwrap_or(Shape::Unit);
+        let value = entry.next().unwrap_or(Shape::Unit);
+        self.leaf(Shape::Map(Box::new(key), Box::new(value)));
+        v
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.named(name, |tracer| {
+            let (v, elements) = tracer.elements(fields.len(), visitor);
+            let fields = fields.iter().cloned().zip(elements).collect();
+            tracer.leaf(Shape::Struct(name, fields));
+            v
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        if variants.is_empty() {
+            return Err(DeError::custom(format_args!(
+                "enum {} has no variants",
+                name
+            )));
+        }
+
+        // The variants are recorded separately, and only the name is recorded here.
+        self.leaf(Shape::Enum(name));
+        let recursive = self.push_name(name)?;
+
+        let (index, capture) = if recursive || self.mute > 0 {
+            self.cycle += 1;
+            ((self.cycle - 1) % variants.len(), Capture::Mute)
+        } else {
+            let def = self.enums.entry(name).or_insert_with(|| EnumDef {
+                variants,
+                contents: vec![None; variants.len()],
+                visits: 0,
+            });
+            def.visits += 1;
+            match def.contents.iter().position(Option::is_none) {
+                Some(i) => (i, Capture::Record),
+                // Visit traced variants in turn to reach enums inside of them.
+                None => ((def.visits - 1) % variants.len(), Capture::Discard),
+            }
+        };
+
+        if let Capture::Mute = capture {
+            self.mute += 1;
+        }
+        let v = visitor.visit_enum(VariantTracer {
+            tracer: &mut *self,
+            index,
+        });
+        let variant = self.variant.take();
+        match capture {
+            Capture::Mute => self.mute -= 1,
+            Capture::Record => {
+                if let Some(def) = self.enums.get_mut(name) {
+                    def.contents[index] = variant;
+                }
+            }
+            Capture::Discard => {}
+        }
+
+        self.stack.pop();
+        v
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DeError> {
+        Err(DeError::Unsupported(
+            "deserialize_identifier",
+            Offset::UNKNOWN,
+        ))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DeError> {
+        Err(DeError::Unsupported(
+            "deserialize_ignored_any",
+            Offset::UNKNOWN,
+        ))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Elements of tuples and structs.
+struct Elements<'a> {
+    tracer: &'a mut Tracer,
+    len: usize,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for Elements<'a> {
+    type Error = DeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, DeError> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.tracer).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+/// A single element of sequences and maps, or nothing while muted.
+struct Sample<'a> {
+    tracer: &'a mut Tracer,
+    done: bool,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for Sample<'a> {
+    type Error = DeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, DeError> {
+        if self.done || self.tracer.mute > 0 {
+            return Ok(None);
+        }
+        self.done = true;
+        seed.deserialize(&mut *self.tracer).map(Some)
+    }
+}
+
+impl<'de, 'a> de::MapAccess<'de> for Sample<'a> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, DeError> {
+        if self.done || self.tracer.mute > 0 {
+            return Ok(None);
+        }
+        self.done = true;
+        seed.deserialize(&mut *self.tracer).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeError> {
+        seed.deserialize(&mut *self.tracer)
+    }
+}
+
+struct VariantTracer<'a> {
+    tracer: &'a mut Tracer,
+    index: usize,
+}
+
+impl<'a> VariantTracer<'a> {
+    fn record(&mut self, variant: Variant) {
+        if self.tracer.mute == 0 {
+            self.tracer.variant = Some(variant);
+        }
+    }
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for VariantTracer<'a> {
+    type Error = DeError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), DeError> {
+        let idx: de::value::U32Deserializer<DeError> = (self.index as u32).into_deserializer();
+        let v = seed.deserialize(idx)?;
+        Ok((v, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for VariantTracer<'a> {
+    type Error = DeError;
+
+    fn unit_variant(mut self) -> Result<(), DeError> {
+        self.record(Variant::Unit);
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        mut self,
+        seed: T,
+    ) -> Result<T::Value, DeError> {
+        self.tracer.open();
+        let v = seed.deserialize(&mut *self.tracer);
+        let inner = one(self.tracer.close());
+        self.record(Variant::Newtype(inner));
+        v
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        mut self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        let (v, elements) = self.tracer.elements(len, visitor);
+        self.record(Variant::Tuple(elements));
+        v
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        mut self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        let (v, elements) = self.tracer.elements(fields.len(), visitor);
+        self.record(Variant::Struct(
+            fields.iter().cloned().zip(elements).collect(),
+        ));
+        v
+    }
+}
@@ -0,0 +1,407 @@
+//! Compressed files with random access, in the seekable format of zstd.
+//!
+//! [`SeekableWriter`](struct.SeekableWriter.html) compresses values into independent zstd frames
+//! of about the same decompressed size, followed by a seek table.
+//! [`SeekableReader`](struct.SeekableReader.html) reads the seek table, maps an offset of
+//! the decompressed stream to a frame and the offset in it, and decompresses only that frame.
+//! So offsets of values recorded while writing stay valid for random access after compression.
+//!
+//! The file is readable by any zstd decoder, and by seekable readers of other languages.
+//!
+//! ```
+//! use std::io::Cursor;
+//!
+//! use serde_dokechi::seekable::{SeekableReader, SeekableWriter};
+//!
+//! let mut w = SeekableWriter::new(Vec::new(), 3).frame_len(1024);
+//! let offsets: Vec<u64> = (0..1000u32)
+//!     .map(|i| w.write_value(&format!("record {}", i)).unwrap())
+//!     .collect();
+//! let file = w.finish().unwrap();
+//!
+//! let mut r = SeekableReader::new(Cursor::new(file)).unwrap();
+//! let v: String = r.read_value_at(offsets[700]).unwrap();
+//! assert_eq!(v, "record 700");
+//! ```
+//!
+//! This module is available with the `zstd` feature.
+
+use std::cmp::Ordering;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::de::{self, from_reader};
+use crate::ser::{self, to_writer};
+
+/// The default decompressed size of frames.
+pub const DEFAULT_FRAME_LEN: usize = 256 * 1024;
+
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+const FOOTER_LEN: u64 = 9;
+const CHECKSUM_FLAG: u8 = 0x80;
+
+/// A writer of values into a seekable compressed file.
+#[derive(Debug)]
+pub struct SeekableWriter<W: Write> {
+    w: W,
+    level: i32,
+    frame_len: usize,
+    buf: Vec<u8>,
+    written: u64,
+    frames: Vec<(u32, u32)>,
+}
+
+impl<W: Write> SeekableWriter<W> {
+    /// Create new `SeekableWriter` compressing with `level`.
+    pub fn new(w: W, level: i32) -> SeekableWriter<W> {
+        SeekableWriter {
+            w,
+            level,
+            frame_len: DEFAULT_FRAME_LEN,
+            buf: Vec::new(),
+            written: 0,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Set the decompressed size in bytes after which a frame is closed.
+    ///
+    /// Smaller frames make random access faster, and compression worse.
+    pub fn frame_len(mut self, frame_len: usize) -> SeekableWriter<W> {
+        self.frame_len = frame_len;
+        self
+    }
+
+    /// Serialize `value`, returning its offset in the decompressed stream.
+    ///
+    /// Frames are closed between values, so a value is in one frame unless it is larger than the frame size.
+    pub fn write_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<u64, ser::Error> {
+        let offset = self.written + self.buf.len() as u64;
+        to_writer(&mut self.buf, value)?;
+        if self.buf.len() >= self.frame_len {
+            self.close_frame()?;
+        }
+        Ok(offset)
+    }
+
+    /// Write the last frame and the seek table, returning the underlying writer.
+    pub fn finish(mut self) -> Result<W, ser::Error> {
+        self.close_frame()?;
+
+        let entries_len = self.frames.len() * 8;
+        let mut table = Vec::with_capacity(8 + entries_len + FOOTER_LEN as usize);
+        table.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        table.extend_from_slice(&((entries_len as u64 + FOOTER_LEN) as u32).to_le_bytes());
+        for &(compressed, decompressed) in &self.frames {
+            table.extend_from_slice(&compressed.to_le_bytes());
+            table.extend_from_slice(&decompressed.to_le_bytes());
+        }
+        table.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        table.push(0);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+
+        self.w.write_all(&table)?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+
+    fn close_frame(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        if self.buf.len() > u32::max_value() as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame is larger than 4 GiB",
+            ));
+        }
+
+        let compressed = zstd::bulk::compress(&self.buf, self.level)?;
+        self.w.write_all(&compressed)?;
+        self.frames
+            .push((compressed.len() as u32, self.buf.len() as u32));
+        self.written += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    compressed_offset: u64,
+    compressed_len: u32,
+    decompressed_offset: u64,
+    decompressed_len: u32,
+}
+
+impl Entry {
+    fn decompressed_end(&self) -> u64 {
+        self.decompressed_offset + self.decompressed_len as u64
+    }
+}
+
+/// A reader of a seekable compressed file, reading and seeking in the decompressed stream.
+#[derive(Debug)]
+pub struct SeekableReader<R> {
+    r: R,
+    entries: Vec<Entry>,
+    pos: u64,
+    frame: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// Create new `SeekableReader`, reading the seek table at the end of `r`.
+    pub fn new(mut r: R) -> Result<SeekableReader<R>, de::Error> {
+        let file_len = r.seek(SeekFrom::End(0))?;
+        if file_len < FOOTER_LEN {
+            return Err(invalid("no seek table").into());
+        }
+
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        r.seek(SeekFrom::Start(file_len - FOOTER_LEN))?;
+        r.read_exact(&mut footer)?;
+        if read_u32(&footer[5..]) != SEEKABLE_MAGIC {
+            return Err(invalid("no seek table").into());
+        }
+        let count = read_u32(&footer[..4]) as u64;
+        let entry_len = if footer[4] & CHECKSUM_FLAG != 0 {
+            12
+        } else {
+            8
+        };
+
+        let table_len = 8 + count * entry_len + FOOTER_LEN;
+        if table_len > file_len {
+            return Err(invalid("seek table is truncated").into());
+        }
+        let mut table = vec![0u8; (table_len - FOOTER_LEN) as usize];
+        r.seek(SeekFrom::Start(file_len - table_len))?;
+        r.read_exact(&mut table)?;
+        if read_u32(&table[..4]) != SKIPPABLE_MAGIC
+            || read_u32(&table[4..8]) as u64 != table_len - 8
+        {
+            return Err(invalid("broken seek table header").into());
+        }
+
+        let mut entries = Vec::with_capacity(count as usize);
+        let mut compressed_offset = 0;
+        let mut decompressed_offset = 0;
+        for e in table[8..].chunks(entry_len as usize) {
+            let entry = Entry {
+                compressed_offset,
+                compressed_len: read_u32(&e[..4]),
+                decompressed_offset,
+                decompressed_len: read_u32(&e[4..8]),
+            };
+            compressed_offset += entry.compressed_len as u64;
+            decompressed_offset += entry.decompressed_len as u64;
+            entries.push(entry);
+        }
+        if compressed_offset > file_len - table_len {
+            return Err(invalid("frames overlap the seek table").into());
+        }
+
+        Ok(SeekableReader {
+            r,
+            entries,
+            pos: 0,
+            frame: None,
+        })
+    }
+
+    /// Deserialize the value at `offset` of the decompressed stream.
+    pub fn read_value_at<T: DeserializeOwned>(&mut self, offset: u64) -> Result<T, de::Error> {
+        self.seek(SeekFrom::Start(offset))?;
+        from_reader(self)
+    }
+
+    /// Decompress frame `index` unless it is the current one.
+    fn load(&mut self, index: usize) -> io::Result<()> {
+        if let Some((current, _)) = &self.frame {
+            if *current == index {
+                return Ok(());
+            }
+        }
+
+        let entry = self.entries[index];
+        let mut compressed = vec![0u8; entry.compressed_len as usize];
+        self.r.seek(SeekFrom::Start(entry.compressed_offset))?;
+        self.r.read_exact(&mut compressed)?;
+
+        let bs = zstd::bulk::decompress(&compressed, entry.decompressed_len as usize)?;
+        if bs.len() != entry.decompressed_len as usize {
+            return Err(invalid("frame size differs from the seek table"));
+        }
+        self.frame = Some((index, bs));
+        Ok(())
+    }
+}
+
+impl<R> SeekableReader<R> {
+    /// The size of the decompressed stream.
+    pub fn decompressed_len(&self) -> u64 {
+        self.entries.last().map_or(0, Entry::decompressed_end)
+    }
+
+    /// The number of frames.
+    pub fn frame_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Map `offset` of the decompressed stream to the index of the frame containing it and the offset in the frame.
+    ///
+    /// Returns `None` if `offset` is at or after the end of the stream.
+    pub fn locate(&self, offset: u64) -> Option<(usize, u64)> {
+        let index = self
+            .entries
+            .binary_search_by(|e| {
+                if e.decompressed_end() <= offset {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|i| i);
+        self.entries
+            .get(index)
+            .map(|e| (index, offset - e.decompressed_offset))
+    }
+
+    /// Unwrap this `SeekableReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (index, offset) = match self.locate(self.pos) {
+            Some(l) => l,
+            None => return Ok(0),
+        };
+        self.load(index)?;
+
+        let frame = match &self.frame {
+            Some((_, bs)) => &bs[offset as usize..],
+            None => unreachable!("frame is loaded"),
+        };
+        let n = frame.len().min(buf.len());
+        buf[..n].copy_from_slice(&frame[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(delta) => (self.decompressed_len(), delta),
+            SeekFrom::Current(delta) => (self.pos, delta),
+        };
+        let pos = if delta < 0 {
+            base.checked_sub(delta.wrapping_neg() as u64)
+        } else {
+            base.checked_add(delta as u64)
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+fn read_u32(bs: &[u8]) -> u32 {
+    u32::from_le_bytes([bs[0], bs[1], bs[2], bs[3]])
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        id: u32,
+        body: String,
+    }
+
+    fn record(id: u32) -> Record {
+        Record {
+            id,
+            body: "x".repeat(id as usize % 100),
+        }
+    }
+
+    fn write(n: u32, frame_len: usize) -> (Vec<u8>, Vec<u64>) {
+        let mut w = SeekableWriter::new(Vec::new(), 1).frame_len(frame_len);
+        let offsets = (0..n).map(|i| w.write_value(&record(i)).unwrap()).collect();
+        (w.finish().unwrap(), offsets)
+    }
+
+    #[test]
+    fn random_access() {
+        let (file, offsets) = write(2000, 4096);
+        let mut r = SeekableReader::new(Cursor::new(file)).unwrap();
+        assert!(r.frame_count() > 10);
+
+        for &i in &[1500u32, 3, 1999, 0, 777] {
+            let v: Record = r.read_value_at(offsets[i as usize]).unwrap();
+            assert_eq!(v, record(i));
+        }
+        assert_eq!(r.locate(0), Some((0, 0)));
+        assert_eq!(r.locate(r.decompressed_len()), None);
+    }
+
+    #[test]
+    fn plain_zstd_stream() {
+        let (file, _) = write(300, 512);
+        let decompressed = zstd::stream::decode_all(file.as_slice()).unwrap();
+
+        let mut r = SeekableReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(r.decompressed_len(), decompressed.len() as u64);
+        let mut all = Vec::new();
+        r.read_to_end(&mut all).unwrap();
+        assert_eq!(all, decompressed);
+
+        let mut de = crate::de::Deserializer::new(decompressed.as_slice());
+        for i in 0..300 {
+            let v: Record = serde::Deserialize::deserialize(&mut de).unwrap();
+            assert_eq!(v, record(i));
+        }
+    }
+
+    #[test]
+    fn broken_table() {
+        let (mut file, _) = write(10, 64);
+        let n = file.len();
+        let _ = SeekableReader::new(Cursor::new(&file[..n - 1])).unwrap_err();
+
+        file[n - 9] = 0xFF;
+        let _ = SeekableReader::new(Cursor::new(file)).unwrap_err();
+
+        let w = SeekableWriter::new(Vec::new(), 1);
+        let r = SeekableReader::new(Cursor::new(w.finish().unwrap())).unwrap();
+        assert_eq!(r.frame_count(), 0);
+        assert_eq!(r.decompressed_len(), 0);
+    }
+}
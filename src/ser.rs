@@ -1,14 +1,23 @@
 //! Serialize Rust data structure to Dokechi format .
 
 use std::fmt::Display;
-use std::io::{self, Write};
+use std::io::{self, BufWriter, Write};
 
 use serde::ser::{self, Serialize};
 use thiserror::Error;
 
-use crate::varuint::{encode_u128, encode_u64};
+#[cfg(feature = "compact-errors")]
+use crate::error::Message;
+use crate::error::{message, ErrorKind};
+use crate::io::Output;
+use crate::layer::{CountingLayer, Layer};
+use crate::options::{Endian, FloatEncoding, IntEncoding, Options};
+use crate::varuint::{encode_u128, encode_u16, encode_u32, encode_u64};
 
 /// Serialize the given data structure as Dokechi format into the IO stream.
+///
+/// Values are written in many small writes. For unbuffered files and sockets,
+/// use [`Serializer::buffered`](struct.Serializer.html#method.buffered) or wrap `w` in a `BufWriter`.
 pub fn to_writer<W: Write, T: Serialize>(w: W, value: T) -> Result<(), Error> {
     let mut serializer = Serializer::new(w);
     value.serialize(&mut serializer)?;
@@ -16,16 +25,296 @@ pub fn to_writer<W: Write, T: Serialize>(w: W, value: T) -> Result<(), Error> {
     Ok(())
 }
 
+/// Serialize the given data structure as Dokechi format into the IO stream with `options`.
+pub fn to_writer_with_config<W: Write, T: Serialize>(
+    w: W,
+    value: T,
+    options: &Options,
+) -> Result<(), Error> {
+    let mut serializer = options.serializer(w);
+    value.serialize(&mut serializer)?;
+    serializer.end()?;
+    Ok(())
+}
+
+/// Serialize the given data structure as Dokechi format into a byte vector.
+pub fn to_vec<T: Serialize>(value: T) -> Result<Vec<u8>, Error> {
+    let mut bs = Vec::new();
+    to_writer(&mut bs, value)?;
+    Ok(bs)
+}
+
+/// The size in bytes of `value` serialized as Dokechi format, without writing it anywhere.
+pub fn serialized_size<T: Serialize + ?Sized>(value: &T) -> Result<u64, Error> {
+    let mut w = CountingLayer.wrap(io::sink());
+    to_writer(&mut w, value)?;
+    Ok(w.count())
+}
+
 /// A structure that serializes Rust values into Dokechi format.
 #[derive(Debug)]
-pub struct Serializer<W: Write> {
+pub struct Serializer<W: Output> {
     w: W,
+    reject_non_finite: bool,
+    int_encoding: IntEncoding,
+    float_encoding: FloatEncoding,
+    lossy_f64: bool,
+    endian: Endian,
+    option_bitmap: bool,
+    canonical: bool,
+    variant_names: bool,
+    struct_maps: bool,
+    compact_chars: bool,
+    pack_bools: bool,
+    chunked_seqs: bool,
+    #[cfg(feature = "unicode-normalization")]
+    nfc: bool,
 }
 
-impl<W: Write> Serializer<W> {
+impl<W: Output> Serializer<W> {
     /// Create new `Serializer`
     pub fn new(w: W) -> Serializer<W> {
-        Serializer { w }
+        Serializer {
+            w,
+            reject_non_finite: false,
+            int_encoding: IntEncoding::Varint,
+            float_encoding: FloatEncoding::Raw,
+            lossy_f64: false,
+            endian: Endian::Little,
+            option_bitmap: false,
+            canonical: false,
+            variant_names: false,
+            struct_maps: false,
+            compact_chars: false,
+            pack_bools: false,
+            chunked_seqs: false,
+            #[cfg(feature = "unicode-normalization")]
+            nfc: false,
+        }
+    }
+
+    /// Return [`Error::NonFiniteFloat`](enum.Error.html#variant.NonFiniteFloat) for NaN and infinite floats
+    /// instead of writing them.
+    pub fn reject_non_finite(mut self) -> Serializer<W> {
+        self.reject_non_finite = true;
+        self
+    }
+
+    fn check_finite(&self, v: f64) -> Result<(), Error> {
+        if self.reject_non_finite && !v.is_finite() {
+            return Err(Error::NonFiniteFloat(v));
+        }
+        Ok(())
+    }
+
+    /// Encode integers wider than 8 bits with `encoding`.
+    pub fn int_encoding(mut self, encoding: IntEncoding) -> Serializer<W> {
+        self.int_encoding = encoding;
+        self
+    }
+
+    /// Write floats, chars and fixed-width integers in `endian` byte order.
+    pub fn endian(mut self, endian: Endian) -> Serializer<W> {
+        self.endian = endian;
+        self
+    }
+
+    /// Write the `Some`/`None` flags of `Option` fields of a struct as a bitmap before its fields,
+    /// packing 8 flags into a byte.
+    ///
+    /// The bitmap has a bit for each field, set if the field is `Some`.
+    /// It takes `ceil(n / 8)` bytes for a struct of `n` fields, with or without `Option` fields,
+    /// as the types of fields are unknown to the deserializer reading the bitmap before the fields.
+    /// So structs without `Option` fields get bigger.
+    pub fn option_bitmap(mut self) -> Serializer<W> {
+        self.option_bitmap = true;
+        self
+    }
+
+    /// Encode floats with `encoding`.
+    pub fn float_encoding(mut self, encoding: FloatEncoding) -> Serializer<W> {
+        self.float_encoding = encoding;
+        self
+    }
+
+    /// Write `f64` as `f32`, halving the size of floats at the cost of precision.
+    ///
+    /// Values keep about 7 significant digits. Values beyond the range of `f32` become infinite,
+    /// and are rejected if [`reject_non_finite`](#method.reject_non_finite) is set.
+    pub fn lossy_f64(mut self) -> Serializer<W> {
+        self.lossy_f64 = true;
+        self
+    }
+
+    /// Write values in the canonical encoding, so equal values are always serialized into the same bytes.
+    ///
+    /// In the canonical encoding:
+    ///
+    /// * Entries of maps are sorted by the bytes of their encoded keys, whatever the iteration order of the map.
+    /// * All NaNs are written as the quiet NaN with no payload (`0x7FC0_0000` and `0x7FF8_0000_0000_0000`).
+    /// * `-0.0` is written as `0.0`.
+    /// * Integers and lengths are in the shortest form, as they always are.
+    ///
+    /// The canonical encoding of a value is stable across versions of this crate with the same settings,
+    /// so it can be hashed or signed.
+    /// Elements of sequences are not sorted, so use `BTreeSet` instead of `HashSet` for sets.
+    ///
+    /// Strings are not normalized, even with the `unicode-normalization` feature,
+    /// as canonically equivalent strings are still different `String` values.
+    /// Features of a crate are unified across the dependency graph, so if `canonical` implied it,
+    /// another crate enabling the feature would change the bytes already hashed or signed.
+    /// Use [`normalize_nfc`](#method.normalize_nfc) with this to write equivalent strings into the same bytes.
+    pub fn canonical(mut self) -> Serializer<W> {
+        self.canonical = true;
+        self
+    }
+
+    /// Write enum variants by their names instead of their indexes.
+    ///
+    /// Variants can then be reordered, added and removed without breaking serialized data,
+    /// but each variant takes the length and bytes of its name.
+    pub fn variant_names(mut self) -> Serializer<W> {
+        self.variant_names = true;
+        self
+    }
+
+    /// Write structs as maps of field names to values, in the same format as maps of `String` keys.
+    ///
+    /// Fields can then be reordered, and the data can be decoded without the Rust type,
+    /// but each field takes the length and bytes of its name.
+    /// Unknown fields can't be skipped in deserializing, so fields can't be removed.
+    /// [`option_bitmap`](#method.option_bitmap) doesn't apply to structs written as maps.
+    pub fn struct_maps(mut self) -> Serializer<W> {
+        self.struct_maps = true;
+        self
+    }
+
+    /// Write chars as varuints of their code points instead of 3 bytes.
+    ///
+    /// ASCII chars take 1 byte, chars up to U+3FFF take 2 bytes, and others take 3 bytes.
+    pub fn compact_chars(mut self) -> Serializer<W> {
+        self.compact_chars = true;
+        self
+    }
+
+    /// Pack consecutive bool elements of sequences, tuples and structs into bytes, 8 bools in a byte.
+    ///
+    /// A run of bools ends at an element of another type, so `Vec<bool>` is a length followed by
+    /// a bitset, and adjacent bool fields of a struct share bytes.
+    /// Bools elsewhere, such as in `Option<bool>` or map entries, take a byte each.
+    pub fn pack_bools(mut self) -> Serializer<W> {
+        self.pack_bools = true;
+        self
+    }
+
+    /// Write sequences and maps in chunks, so ones without size hint can be streamed.
+    ///
+    /// Each chunk is the number of its elements followed by them, and an empty chunk ends the sequence,
+    /// so `vec![1u8, 2]` is `[2, 1, 2, 0]`.
+    /// Elements of a sequence without size hint are buffered only until about 4 KiB make a chunk,
+    /// while a sequence with size hint is written as one chunk.
+    /// Structs written as [maps](#method.struct_maps) are chunked too.
+    ///
+    /// Without this, serializing a sequence or map without size hint returns
+    /// [`Error::NoSequenceSize`](enum.Error.html#variant.NoSequenceSize).
+    pub fn chunked_seqs(mut self) -> Serializer<W> {
+        self.chunked_seqs = true;
+        self
+    }
+
+    /// Write the variant of an enum, by the name or by the index.
+    fn write_variant(&mut self, variant_index: u32, variant: &str) -> Result<(), Error> {
+        if self.variant_names {
+            encode_u64(&mut self.w, variant.len() as u64)?;
+            self.w.write_all(variant.as_bytes())?;
+        } else {
+            encode_u64(&mut self.w, variant_index as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Write the tag of a float and `integral`, the float as an integer if exact, if floats are compact.
+    ///
+    /// Returns `true` if the float is written as an integer.
+    fn write_integral(&mut self, integral: Option<i64>) -> Result<bool, Error> {
+        if self.float_encoding != FloatEncoding::Compact {
+            return Ok(false);
+        }
+        match integral {
+            Some(v) => {
+                self.w.write_all(&[0])?;
+                let u = if v >= 0 {
+                    (v as u64) << 1
+                } else {
+                    ((-(v + 1)) as u64) << 1 | 1
+                };
+                encode_u64(&mut self.w, u)?;
+                Ok(true)
+            }
+            None => {
+                self.w.write_all(&[1])?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Write `bs`, the little-endian bytes of an integer, if integers are fixed-width.
+    ///
+    /// Returns `false` if integers are variable-length.
+    fn write_fixed(&mut self, bs: &[u8]) -> Result<bool, Error> {
+        if self.int_encoding != IntEncoding::Fixed {
+            return Ok(false);
+        }
+        self.write_word(bs)?;
+        Ok(true)
+    }
+
+    /// Write `le`, little-endian bytes of a value, in the configured byte order.
+    fn write_word(&mut self, le: &[u8]) -> Result<(), Error> {
+        let mut buf = [0u8; 16];
+        let bs = &mut buf[..le.len()];
+        bs.copy_from_slice(le);
+        self.endian.order(bs);
+        self.w.write_all(bs)?;
+        Ok(())
+    }
+
+    /// Normalize strings into Unicode Normalization Form C before writing,
+    /// so canonically equivalent strings are serialized into the same bytes.
+    ///
+    /// This method is available with the `unicode-normalization` feature.
+    #[cfg(feature = "unicode-normalization")]
+    pub fn normalize_nfc(mut self) -> Serializer<W> {
+        self.nfc = true;
+        self
+    }
+
+    /// Create a `Serializer` into memory with the same settings.
+    fn buffer(&self) -> Serializer<Vec<u8>> {
+        Serializer {
+            w: Vec::new(),
+            reject_non_finite: self.reject_non_finite,
+            int_encoding: self.int_encoding,
+            float_encoding: self.float_encoding,
+            lossy_f64: self.lossy_f64,
+            endian: self.endian,
+            option_bitmap: self.option_bitmap,
+            canonical: self.canonical,
+            variant_names: self.variant_names,
+            struct_maps: self.struct_maps,
+            compact_chars: self.compact_chars,
+            pack_bools: self.pack_bools,
+            chunked_seqs: self.chunked_seqs,
+            #[cfg(feature = "unicode-normalization")]
+            nfc: self.nfc,
+        }
+    }
+
+    /// Serialize `value` into a new buffer with the same settings.
+    fn encode<T: ?Sized + Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        let mut buffer = self.buffer();
+        value.serialize(&mut buffer)?;
+        Ok(buffer.w)
     }
 
     /// This method should be called after a value has been serialized to ensure all output data written to writer.
@@ -33,16 +322,94 @@ impl<W: Write> Serializer<W> {
         self.w.flush()?;
         Ok(())
     }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.w
+    }
+
+    /// Get a mutable reference to the underlying writer.
+    ///
+    /// Writing to or reading from it directly in the middle of a value breaks the value.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    /// Unwrap this `Serializer`, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+impl<W: Write> Serializer<BufWriter<W>> {
+    /// Create new `Serializer` coalescing small writes into `w` in a buffer.
+    ///
+    /// The buffer is written to `w` by [`end`](#method.end).
+    pub fn buffered(w: W) -> Serializer<BufWriter<W>> {
+        Serializer::new(BufWriter::new(w))
+    }
+}
+
+/// A writer of values back to back into one stream.
+///
+/// The values can be read back with [`Deserializer::into_iter`](../de/struct.Deserializer.html#method.into_iter).
+#[derive(Debug)]
+pub struct StreamSerializer<W: Output> {
+    serializer: Serializer<W>,
+    count: u64,
+}
+
+impl<W: Output> StreamSerializer<W> {
+    /// Create new `StreamSerializer`.
+    pub fn new(w: W) -> StreamSerializer<W> {
+        StreamSerializer::from(Serializer::new(w))
+    }
+
+    /// Serialize `value` after the values written so far.
+    ///
+    /// On error, a part of `value` may have been written.
+    pub fn write_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(&mut self.serializer)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// The number of values written.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.serializer.get_ref()
+    }
+
+    /// Flush the underlying writer, and return it.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.serializer.end()?;
+        Ok(self.serializer.into_inner())
+    }
+}
+
+impl<W: Output> From<Serializer<W>> for StreamSerializer<W> {
+    /// Write values with a configured `Serializer`.
+    fn from(serializer: Serializer<W>) -> StreamSerializer<W> {
+        StreamSerializer {
+            serializer,
+            count: 0,
+        }
+    }
 }
 
-impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
+#[allow(clippy::multiple_bound_locations)]
+impl<'a, W: Output> ser::Serializer for &'a mut Serializer<W> {
     type Ok = ();
     type Error = Error;
-    type SerializeSeq = Compound<'a, W>;
+    type SerializeSeq = SeqCompound<'a, W>;
     type SerializeTuple = Compound<'a, W>;
     type SerializeTupleStruct = Compound<'a, W>;
     type SerializeTupleVariant = Compound<'a, W>;
-    type SerializeMap = Compound<'a, W>;
+    type SerializeMap = SeqCompound<'a, W>;
     type SerializeStruct = Compound<'a, W>;
     type SerializeStructVariant = Compound<'a, W>;
 
@@ -60,6 +427,9 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        if self.write_fixed(&v.to_le_bytes())? {
+            return Ok(());
+        }
         let u = if v >= 0 {
             (v as u16) << 1
         } else {
@@ -69,6 +439,9 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        if self.write_fixed(&v.to_le_bytes())? {
+            return Ok(());
+        }
         let u = if v >= 0 {
             (v as u32) << 1
         } else {
@@ -78,6 +451,9 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        if self.write_fixed(&v.to_le_bytes())? {
+            return Ok(());
+        }
         let u = if v >= 0 {
             (v as u64) << 1
         } else {
@@ -87,6 +463,9 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        if self.write_fixed(&v.to_le_bytes())? {
+            return Ok(());
+        }
         let u = if v >= 0 {
             (v as u128) << 1
         } else {
@@ -102,44 +481,108 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        encode_u64(&mut self.w, v as u64)?;
+        if self.write_fixed(&v.to_le_bytes())? {
+            return Ok(());
+        }
+        encode_u16(&mut self.w, v)?;
         Ok(())
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        encode_u64(&mut self.w, v as u64)?;
+        if self.write_fixed(&v.to_le_bytes())? {
+            return Ok(());
+        }
+        encode_u32(&mut self.w, v)?;
         Ok(())
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        if self.write_fixed(&v.to_le_bytes())? {
+            return Ok(());
+        }
         encode_u64(&mut self.w, v)?;
         Ok(())
     }
 
     fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        if self.write_fixed(&v.to_le_bytes())? {
+            return Ok(());
+        }
         encode_u128(&mut self.w, v)?;
         Ok(())
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        let bs = v.to_le_bytes();
-        self.w.write_all(&bs[..])?;
-        Ok(())
+        self.check_finite(v as f64)?;
+        let v = if !self.canonical {
+            v
+        } else if v.is_nan() {
+            std::f32::NAN
+        } else if v == 0.0 {
+            0.0
+        } else {
+            v
+        };
+        let i = v as i64;
+        let integral = if (i as f32).to_bits() == v.to_bits() {
+            Some(i)
+        } else {
+            None
+        };
+        if self.write_integral(integral)? {
+            return Ok(());
+        }
+        self.write_word(&v.to_le_bytes())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        let bs = v.to_le_bytes();
-        self.w.write_all(&bs[..])?;
-        Ok(())
+        self.check_finite(v)?;
+        if self.lossy_f64 {
+            return self.serialize_f32(v as f32);
+        }
+        let v = if !self.canonical {
+            v
+        } else if v.is_nan() {
+            std::f64::NAN
+        } else if v == 0.0 {
+            0.0
+        } else {
+            v
+        };
+        let i = v as i64;
+        let integral = if (i as f64).to_bits() == v.to_bits() {
+            Some(i)
+        } else {
+            None
+        };
+        if self.write_integral(integral)? {
+            return Ok(());
+        }
+        self.write_word(&v.to_le_bytes())
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        if self.compact_chars {
+            encode_u32(&mut self.w, v as u32)?;
+            return Ok(());
+        }
         let bs = (v as u32).to_le_bytes();
-        self.w.write_all(&bs[..3])?;
-        Ok(())
+        self.write_word(&bs[..3])
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        #[cfg(feature = "unicode-normalization")]
+        {
+            use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+            if self.nfc && is_nfc_quick(v.chars()) != IsNormalized::Yes {
+                let normalized: String = v.nfc().collect();
+                encode_u64(&mut self.w, normalized.len() as u64)?;
+                self.w.write_all(normalized.as_bytes())?;
+                return Ok(());
+            }
+        }
+
         encode_u64(&mut self.w, v.len() as u64)?;
         self.w.write_all(v.as_bytes())?;
         Ok(())
@@ -179,9 +622,9 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
         self,
         _name: &'static str,
         variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        encode_u64(&mut self.w, variant_index as u64)?;
+        self.write_variant(variant_index, variant)?;
         Ok(())
     }
 
@@ -201,25 +644,28 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
         self,
         _name: &'static str,
         variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: Serialize,
     {
-        encode_u64(&mut self.w, variant_index as u64)?;
+        self.write_variant(variant_index, variant)?;
         value.serialize(self)?;
         Ok(())
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        let len = len.ok_or(Error::NoSequenceSize)?;
-        encode_u64(&mut self.w, len as u64)?;
-        Ok(Compound { serializer: self })
+        let pack_bools = self.pack_bools;
+        let mut seq = SeqCompound::new(self, len)?;
+        if pack_bools {
+            seq.run = Some(BoolRun::default());
+        }
+        Ok(seq)
     }
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        Ok(Compound { serializer: self })
+        Ok(Compound::new(self))
     }
 
     fn serialize_tuple_struct(
@@ -227,43 +673,44 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
         _name: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        Ok(Compound { serializer: self })
+        Ok(Compound::new(self))
     }
 
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
         variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        encode_u64(&mut self.w, variant_index as u64)?;
-        Ok(Compound { serializer: self })
+        self.write_variant(variant_index, variant)?;
+        Ok(Compound::new(self))
     }
 
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        let len = len.ok_or(Error::NoSequenceSize)?;
-        encode_u64(&mut self.w, len as u64)?;
-        Ok(Compound { serializer: self })
+        if self.canonical {
+            return Ok(SeqCompound::sorted(self));
+        }
+        SeqCompound::new(self, len)
     }
 
     fn serialize_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        Ok(Compound { serializer: self })
+        Compound::fields(self, len)
     }
 
     fn serialize_struct_variant(
         self,
         _name: &'static str,
         variant_index: u32,
-        _variant: &'static str,
-        _len: usize,
+        variant: &'static str,
+        len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        encode_u64(&mut self.w, variant_index as u64)?;
-        Ok(Compound { serializer: self })
+        self.write_variant(variant_index, variant)?;
+        Compound::fields(self, len)
     }
 
     fn is_human_readable(&self) -> bool {
@@ -274,110 +721,568 @@ impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
 /// An support type of [`Serializer`](struct.Serializer.html).
 ///
 #[derive(Debug)]
-pub struct Compound<'a, W: Write> {
+pub struct Compound<'a, W: Output> {
+    serializer: &'a mut Serializer<W>,
+    bitmap: Option<Bitmap>,
+    named: bool,
+    run: Option<BoolRun>,
+}
+
+/// Fields of a struct buffered until the end, to write the option bitmap before them.
+#[derive(Debug)]
+struct Bitmap {
+    flags: Vec<u8>,
+    buffer: Serializer<Vec<u8>>,
+    index: usize,
+}
+
+/// Consecutive bool elements not written yet, packed into a byte.
+#[derive(Debug, Default)]
+struct BoolRun {
+    bits: u8,
+    len: u8,
+}
+
+impl BoolRun {
+    fn push<W: Output>(&mut self, w: &mut W, v: bool) -> Result<(), Error> {
+        if v {
+            self.bits |= 1 << self.len;
+        }
+        self.len += 1;
+        if self.len == 8 {
+            self.flush(w)?;
+        }
+        Ok(())
+    }
+
+    /// Write the pending bools, ending the run.
+    fn flush<W: Output>(&mut self, w: &mut W) -> Result<(), Error> {
+        if self.len > 0 {
+            w.write_all(&[self.bits])?;
+            *self = BoolRun::default();
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: Output> Compound<'a, W> {
+    fn new(serializer: &'a mut Serializer<W>) -> Compound<'a, W> {
+        let run = if serializer.pack_bools {
+            Some(BoolRun::default())
+        } else {
+            None
+        };
+        Compound {
+            serializer,
+            bitmap: None,
+            named: false,
+            run,
+        }
+    }
+
+    /// Start a struct of `len` fields.
+    fn fields(serializer: &'a mut Serializer<W>, len: usize) -> Result<Compound<'a, W>, Error> {
+        if serializer.struct_maps {
+            if len > 0 || !serializer.chunked_seqs {
+                encode_u64(&mut serializer.w, len as u64)?;
+            }
+            return Ok(Compound {
+                serializer,
+                bitmap: None,
+                named: true,
+                run: None,
+            });
+        }
+
+        let bitmap = if serializer.option_bitmap {
+            Some(Bitmap {
+                flags: vec![0; (len + 7) / 8],
+                buffer: serializer.buffer(),
+                index: 0,
+            })
+        } else {
+            None
+        };
+        let mut compound = Compound::new(serializer);
+        compound.bitmap = bitmap;
+        Ok(compound)
+    }
+
+    fn write_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        match self.run {
+            Some(ref mut run) => value.serialize(ElementSerializer {
+                serializer: &mut *self.serializer,
+                present: None,
+                run: Some(run),
+            }),
+            None => value.serialize(&mut *self.serializer),
+        }
+    }
+
+    fn write_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        if self.named {
+            encode_u64(&mut self.serializer.w, key.len() as u64)?;
+            self.serializer.w.write_all(key.as_bytes())?;
+        }
+
+        let bitmap = match self.bitmap {
+            Some(ref mut bitmap) => bitmap,
+            None => return self.write_element(value),
+        };
+
+        let i = bitmap.index;
+        if i / 8 >= bitmap.flags.len() {
+            return Err(ser::Error::custom(
+                "more fields than the length of the struct",
+            ));
+        }
+        let mut present = false;
+        value.serialize(ElementSerializer {
+            serializer: &mut bitmap.buffer,
+            present: Some(&mut present),
+            run: self.run.as_mut(),
+        })?;
+        if present {
+            bitmap.flags[i / 8] |= 1 << (i % 8);
+        }
+        bitmap.index += 1;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        let mut run = self.run.unwrap_or_default();
+        match self.bitmap {
+            Some(mut bitmap) => {
+                run.flush(&mut bitmap.buffer.w)?;
+                self.serializer.w.write_all(&bitmap.flags)?;
+                self.serializer.w.write_all(&bitmap.buffer.w)?;
+            }
+            None => run.flush(&mut self.serializer.w)?,
+        }
+        if self.named && self.serializer.chunked_seqs {
+            self.serializer.w.write_all(&[0])?;
+        }
+        Ok(())
+    }
+}
+
+/// A serializer of an element of a compound, packing bools into the run of bools
+/// and writing `Some`/`None` of an `Option` field into the bitmap.
+struct ElementSerializer<'a, W: Output> {
     serializer: &'a mut Serializer<W>,
+    present: Option<&'a mut bool>,
+    run: Option<&'a mut BoolRun>,
+}
+
+impl<'a, W: Output> ElementSerializer<'a, W> {
+    /// End the run of bools before a value other than a bool.
+    fn end_run(&mut self) -> Result<(), Error> {
+        if let Some(ref mut run) = self.run {
+            run.flush(&mut self.serializer.w)?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! forward_serialize {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method(mut self, $($arg: $ty),*) -> Result<(), Error> {
+                self.end_run()?;
+                self.serializer.$method($($arg),*)
+            }
+        )*
+    };
 }
 
-impl<'a, W: Write> ser::SerializeSeq for Compound<'a, W> {
+impl<'a, W: Output> ser::Serializer for ElementSerializer<'a, W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = SeqCompound<'a, W>;
+    type SerializeTuple = Compound<'a, W>;
+    type SerializeTupleStruct = Compound<'a, W>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = SeqCompound<'a, W>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+
+    forward_serialize! {
+        serialize_i8(v: i8);
+        serialize_i16(v: i16);
+        serialize_i32(v: i32);
+        serialize_i64(v: i64);
+        serialize_i128(v: i128);
+        serialize_u8(v: u8);
+        serialize_u16(v: u16);
+        serialize_u32(v: u32);
+        serialize_u64(v: u64);
+        serialize_u128(v: u128);
+        serialize_f32(v: f32);
+        serialize_f64(v: f64);
+        serialize_char(v: char);
+        serialize_str(v: &str);
+        serialize_bytes(v: &[u8]);
+        serialize_unit();
+        serialize_unit_struct(name: &'static str);
+        serialize_unit_variant(name: &'static str, variant_index: u32, variant: &'static str);
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        match self.run {
+            Some(run) => run.push(&mut self.serializer.w, v),
+            None => self.serializer.serialize_bool(v),
+        }
+    }
+
+    fn serialize_none(mut self) -> Result<(), Error> {
+        self.end_run()?;
+        match self.present {
+            Some(present) => {
+                *present = false;
+                Ok(())
+            }
+            None => self.serializer.serialize_none(),
+        }
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(mut self, value: &T) -> Result<(), Error> {
+        self.end_run()?;
+        match self.present {
+            Some(present) => {
+                *present = true;
+                value.serialize(self.serializer)
+            }
+            None => self.serializer.serialize_some(value),
+        }
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.end_run()?;
+        self.serializer.serialize_newtype_struct(name, value)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        mut self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.end_run()?;
+        self.serializer
+            .serialize_newtype_variant(name, variant_index, variant, value)
+    }
+
+    fn serialize_seq(mut self, len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        self.end_run()?;
+        self.serializer.serialize_seq(len)
+    }
+
+    fn serialize_tuple(mut self, len: usize) -> Result<Self::SerializeTuple, Error> {
+        self.end_run()?;
+        self.serializer.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_struct(
+        mut self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        self.end_run()?;
+        self.serializer.serialize_tuple_struct(name, len)
+    }
+
+    fn serialize_tuple_variant(
+        mut self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        self.end_run()?;
+        self.serializer
+            .serialize_tuple_variant(name, variant_index, variant, len)
+    }
+
+    fn serialize_map(mut self, len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        self.end_run()?;
+        self.serializer.serialize_map(len)
+    }
+
+    fn serialize_struct(
+        mut self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        self.end_run()?;
+        self.serializer.serialize_struct(name, len)
+    }
+
+    fn serialize_struct_variant(
+        mut self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        self.end_run()?;
+        self.serializer
+            .serialize_struct_variant(name, variant_index, variant, len)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The [Serializer](struct.Serializer.html)'s state while serializing sequences and maps.
+///
+/// Elements of sequences and maps without size hint are buffered in memory
+/// until they make a chunk, in [chunked](struct.Serializer.html#method.chunked_seqs) sequences.
+/// Entries of maps in the canonical encoding are buffered until the end, to sort them.
+#[derive(Debug)]
+pub struct SeqCompound<'a, W: Output> {
+    serializer: &'a mut Serializer<W>,
+    chunk: Option<(Serializer<Vec<u8>>, u64)>,
+    sorted_entries: Option<Vec<(Vec<u8>, Vec<u8>)>>,
+    run: Option<BoolRun>,
+}
+
+/// The size in bytes of elements making a chunk of a sequence without size hint.
+const CHUNK_SIZE: usize = 4096;
+
+impl<'a, W: Output> SeqCompound<'a, W> {
+    fn new(serializer: &'a mut Serializer<W>, len: Option<usize>) -> Result<Self, Error> {
+        let chunk = match len {
+            // The empty chunk at the end is the whole sequence.
+            Some(0) if serializer.chunked_seqs => None,
+            Some(len) => {
+                encode_u64(&mut serializer.w, len as u64)?;
+                None
+            }
+            None if serializer.chunked_seqs => Some((serializer.buffer(), 0)),
+            None => return Err(Error::NoSequenceSize),
+        };
+        Ok(SeqCompound {
+            serializer,
+            chunk,
+            sorted_entries: None,
+            run: None,
+        })
+    }
+
+    /// Start a map whose entries are sorted by encoded keys.
+    fn sorted(serializer: &'a mut Serializer<W>) -> Self {
+        SeqCompound {
+            serializer,
+            chunk: None,
+            sorted_entries: Some(Vec::new()),
+            run: None,
+        }
+    }
+
+    fn write<T: ?Sized + Serialize>(&mut self, value: &T, count: bool) -> Result<(), Error> {
+        match self.chunk {
+            Some((ref mut buffer, ref mut len)) => {
+                if count {
+                    *len += 1;
+                }
+                value.serialize(buffer)
+            }
+            None => value.serialize(&mut *self.serializer),
+        }
+    }
+
+    fn write_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let run = match self.run {
+            Some(ref mut run) => run,
+            None => return self.write(value, true),
+        };
+        match self.chunk {
+            Some((ref mut buffer, ref mut len)) => {
+                *len += 1;
+                value.serialize(ElementSerializer {
+                    serializer: buffer,
+                    present: None,
+                    run: Some(run),
+                })
+            }
+            None => value.serialize(ElementSerializer {
+                serializer: &mut *self.serializer,
+                present: None,
+                run: Some(run),
+            }),
+        }
+    }
+
+    /// Write the buffered elements as a chunk if they are large enough, after a whole element.
+    fn end_element(&mut self) -> Result<(), Error> {
+        match self.chunk {
+            Some((ref buffer, _)) if buffer.w.len() >= CHUNK_SIZE => self.flush_chunk(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the buffered elements as a chunk, ending the run of bools in it.
+    fn flush_chunk(&mut self) -> Result<(), Error> {
+        if let Some((ref mut buffer, ref mut len)) = self.chunk {
+            if let Some(ref mut run) = self.run {
+                run.flush(&mut buffer.w)?;
+            }
+            if *len > 0 {
+                encode_u64(&mut self.serializer.w, *len)?;
+                self.serializer.w.write_all(&buffer.w)?;
+                buffer.w.clear();
+                *len = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        if let Some(mut entries) = self.sorted_entries.take() {
+            entries.sort();
+            if !entries.is_empty() || !self.serializer.chunked_seqs {
+                encode_u64(&mut self.serializer.w, entries.len() as u64)?;
+            }
+            for (key, value) in entries {
+                self.serializer.w.write_all(&key)?;
+                self.serializer.w.write_all(&value)?;
+            }
+        } else if self.chunk.is_some() {
+            self.flush_chunk()?;
+        } else if let Some(ref mut run) = self.run {
+            run.flush(&mut self.serializer.w)?;
+        }
+        if self.serializer.chunked_seqs {
+            self.serializer.w.write_all(&[0])?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: Output> ser::SerializeSeq for SeqCompound<'a, W> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
-        value.serialize(&mut *self.serializer)
+        self.write_element(value)?;
+        self.end_element()
     }
 
     fn end(self) -> Result<(), Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeTuple for Compound<'a, W> {
+impl<'a, W: Output> ser::SerializeTuple for Compound<'a, W> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
-        value.serialize(&mut *self.serializer)
+        self.write_element(value)
     }
 
     fn end(self) -> Result<(), Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeTupleStruct for Compound<'a, W> {
+impl<'a, W: Output> ser::SerializeTupleStruct for Compound<'a, W> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
-        value.serialize(&mut *self.serializer)
+        self.write_element(value)
     }
 
     fn end(self) -> Result<(), Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeTupleVariant for Compound<'a, W> {
+impl<'a, W: Output> ser::SerializeTupleVariant for Compound<'a, W> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
-        value.serialize(&mut *self.serializer)
+        self.write_element(value)
     }
 
     fn end(self) -> Result<(), Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeMap for Compound<'a, W> {
+impl<'a, W: Output> ser::SerializeMap for SeqCompound<'a, W> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
-        key.serialize(&mut *self.serializer)
+        if let Some(ref mut entries) = self.sorted_entries {
+            entries.push((self.serializer.encode(key)?, Vec::new()));
+            return Ok(());
+        }
+        self.write(key, true)
     }
 
     fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
-        value.serialize(&mut *self.serializer)
+        if let Some(ref mut entries) = self.sorted_entries {
+            let value = self.serializer.encode(value)?;
+            match entries.last_mut() {
+                Some(entry) => entry.1 = value,
+                None => return Err(ser::Error::custom("map value without key")),
+            }
+            return Ok(());
+        }
+        self.write(value, false)?;
+        self.end_element()
     }
 
     fn end(self) -> Result<(), Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeStruct for Compound<'a, W> {
+impl<'a, W: Output> ser::SerializeStruct for Compound<'a, W> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_field<T: ?Sized + Serialize>(
         &mut self,
-        _key: &'static str,
+        key: &'static str,
         value: &T,
     ) -> Result<(), Error> {
-        value.serialize(&mut *self.serializer)
+        self.write_field(key, value)
     }
 
     fn end(self) -> Result<(), Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a, W: Write> ser::SerializeStructVariant for Compound<'a, W> {
+impl<'a, W: Output> ser::SerializeStructVariant for Compound<'a, W> {
     type Ok = ();
     type Error = Error;
 
     fn serialize_field<T: ?Sized + Serialize>(
         &mut self,
-        _key: &'static str,
+        key: &'static str,
         value: &T,
     ) -> Result<(), Error> {
-        value.serialize(&mut *self.serializer)
+        self.write_field(key, value)
     }
 
     fn end(self) -> Result<(), Error> {
-        Ok(())
+        self.finish()
     }
 }
 
@@ -389,16 +1294,73 @@ pub enum Error {
     #[error("{0}")]
     IO(#[from] io::Error),
     /// Sequence size is required.
+    ///
+    /// Sequences and maps without size hint can be written in [chunks](struct.Serializer.html#method.chunked_seqs).
     #[error("input sequence has no size hint")]
     NoSequenceSize,
+    /// A non-finite float is written while it is rejected.
+    #[error("non-finite float {0} is rejected")]
+    NonFiniteFloat(f64),
     /// An error from serde framework.
+    #[cfg(not(feature = "compact-errors"))]
     #[error("{0}")]
     Serde(String),
+    /// An error from serde framework.
+    #[cfg(feature = "compact-errors")]
+    #[error("{0}")]
+    Serde(Message),
+}
+
+impl Error {
+    /// A compact code of the error kind, the same as [`crate::Error::code`](../enum.Error.html#method.code).
+    pub fn code(&self) -> u8 {
+        match self {
+            Error::IO(_) => 1,
+            Error::NoSequenceSize => 2,
+            Error::NonFiniteFloat(_) => 4,
+            Error::Serde(_) => 5,
+        }
+    }
+
+    /// The kind of the error, the same as [`crate::Error::kind`](../enum.Error.html#method.kind).
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::IO(_) => ErrorKind::Io,
+            Error::NoSequenceSize => ErrorKind::NoSequenceSize,
+            Error::NonFiniteFloat(_) => ErrorKind::NonFiniteFloat,
+            Error::Serde(_) => ErrorKind::Serde,
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    /// IO errors are unwrapped, and other errors are wrapped in `io::Error` of the matching kind.
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::IO(e) => e,
+            e => io::Error::new(e.kind().io_kind(), e),
+        }
+    }
 }
 
 impl ser::Error for Error {
     fn custom<T: Display>(msg: T) -> Error {
-        Error::Serde(msg.to_string())
+        Error::Serde(message(msg))
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::IO(_) => defmt::write!(f, "E{=u8}: IO error", self.code()),
+            Error::NoSequenceSize => defmt::write!(f, "E{=u8}: no size hint", self.code()),
+            Error::NonFiniteFloat(v) => defmt::write!(f, "E{=u8}: {=f64}", self.code(), v),
+            #[cfg(not(feature = "compact-errors"))]
+            Error::Serde(msg) => defmt::write!(f, "E{=u8}: {=str}", self.code(), msg),
+            #[cfg(feature = "compact-errors")]
+            Error::Serde(msg) => defmt::write!(f, "E{=u8}: {}", self.code(), msg),
+        }
     }
 }
 
@@ -410,7 +1372,7 @@ mod test {
 
     use serde_derive::{Deserialize, Serialize};
 
-    use crate::de::from_reader;
+    use crate::de::{from_reader, Deserializer};
 
     #[test]
     fn serialize_i8() {
@@ -418,7 +1380,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: i8 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -428,7 +1390,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: i16 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -438,7 +1400,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: i32 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -448,7 +1410,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: i64 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -458,7 +1420,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: i128 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -468,7 +1430,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: u8 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -478,7 +1440,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: u16 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -488,7 +1450,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: u32 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -498,7 +1460,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: u64 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -508,17 +1470,44 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: u128 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
+    #[test]
+    fn serialize_128_bit_sizes() {
+        for &(v, len) in &[
+            (0u128, 1),
+            (127, 1),
+            (128, 2),
+            ((1 << 56) - 1, 8),
+            (1 << 56, 17),
+            (u128::max_value(), 17),
+        ] {
+            let bs = to_vec(v).unwrap();
+            assert_eq!(bs.len(), len, "{}", v);
+            assert_eq!(from_reader::<_, u128>(bs.as_slice()).unwrap(), v);
+        }
+
+        for &(v, len) in &[(0i128, 1), (-1, 1), (63, 1), (-64, 1), (64, 2)] {
+            let bs = to_vec(v).unwrap();
+            assert_eq!(bs.len(), len, "{}", v);
+            assert_eq!(from_reader::<_, i128>(bs.as_slice()).unwrap(), v);
+        }
+        let bs = to_vec(i128::min_value()).unwrap();
+        assert_eq!(
+            from_reader::<_, i128>(bs.as_slice()).unwrap(),
+            i128::min_value()
+        );
+    }
+
     #[test]
     fn serialize_f32() {
         let v = 13141.32f32;
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: f32 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -528,7 +1517,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: f64 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -538,7 +1527,7 @@ mod test {
 
         let mut bs = Vec::new();
         to_writer(&mut bs, v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        let d: char = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -562,13 +1551,97 @@ mod test {
         assert_eq!(v, d);
     }
 
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn serialize_str_nfc() {
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+
+        let mut a = Serializer::new(Vec::new()).normalize_nfc();
+        composed.serialize(&mut a).unwrap();
+        let mut b = Serializer::new(Vec::new()).normalize_nfc();
+        decomposed.serialize(&mut b).unwrap();
+        assert_eq!(a.into_inner(), b.into_inner());
+
+        let mut bs = Vec::new();
+        to_writer(&mut bs, decomposed).unwrap();
+        let d: String = from_reader(bs.as_slice()).unwrap();
+        assert_eq!(d, decomposed);
+
+        // `canonical` doesn't imply `normalize_nfc`.
+        let mut c = Serializer::new(Vec::new()).canonical();
+        decomposed.serialize(&mut c).unwrap();
+        assert_eq!(c.into_inner(), bs);
+    }
+
+    #[test]
+    fn serialize_non_finite() {
+        let mut bs = Vec::new();
+        to_writer(&mut bs, std::f64::NAN).unwrap();
+        assert_eq!(bs.len(), 8);
+
+        let mut ser = Serializer::new(Vec::new()).reject_non_finite();
+        match std::f32::INFINITY.serialize(&mut ser) {
+            Err(Error::NonFiniteFloat(v)) => assert!(v.is_infinite()),
+            r => panic!("unexpected: {:?}", r),
+        }
+        1.5f64.serialize(&mut ser).unwrap();
+    }
+
+    /// Serialized without size hint, like entries from a DB cursor.
+    struct Filtered(Vec<(u32, &'static str)>);
+
+    impl Serialize for Filtered {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(self.0.iter().cloned().filter(|_| true))
+        }
+    }
+
+    #[test]
+    fn serialize_unsized_map() {
+        let v = Filtered(vec![(1, "a"), (2, "bc")]);
+
+        match to_writer(&mut Vec::new(), &v) {
+            Err(Error::NoSequenceSize) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut ser = Serializer::new(Vec::new()).chunked_seqs();
+        v.serialize(&mut ser).unwrap();
+        let bs = ser.into_inner();
+        assert_eq!(bs, vec![2, 1, 1, b'a', 2, 2, b'b', b'c', 0]);
+
+        let mut de = Deserializer::new(bs.as_slice()).chunked_seqs();
+        let d: std::collections::BTreeMap<u32, String> =
+            serde::Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(d.len(), 2);
+        assert_eq!(d[&2], "bc");
+    }
+
+    #[test]
+    fn serialize_unsized_seq() {
+        let v = [vec![1u8, 2], vec![], vec![3]];
+
+        let mut ser = Serializer::new(Vec::new()).chunked_seqs();
+        ser::Serializer::collect_seq(
+            &mut ser,
+            v.iter()
+                .map(|inner| Filtered(inner.iter().map(|&x| (x as u32, "")).collect()))
+                .filter(|_| true),
+        )
+        .unwrap();
+        let bs = ser.into_inner();
+        assert_eq!(bs, vec![3, 2, 1, 0, 2, 0, 0, 0, 1, 3, 0, 0, 0]);
+    }
+
     #[test]
     fn serialize_ref() {
         let v = 12345u64;
+        let r = &v;
 
         let mut bs = Vec::new();
-        to_writer(&mut bs, &v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        to_writer(&mut bs, r).unwrap();
+        let d: u64 = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -577,8 +1650,8 @@ mod test {
         let v = Option::<u64>::None;
 
         let mut bs = Vec::new();
-        to_writer(&mut bs, &v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        to_writer(&mut bs, v).unwrap();
+        let d: Option<u64> = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -587,8 +1660,8 @@ mod test {
         let v = Some(123u64);
 
         let mut bs = Vec::new();
-        to_writer(&mut bs, &v).unwrap();
-        let d = from_reader(bs.as_slice()).unwrap();
+        to_writer(&mut bs, v).unwrap();
+        let d: Option<u64> = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
 
@@ -755,4 +1828,147 @@ mod test {
         let d = from_reader(bs.as_slice()).unwrap();
         assert_eq!(v, d);
     }
+
+    #[test]
+    fn accessors() {
+        let mut ser = Serializer::new(Vec::new());
+        ser::Serialize::serialize(&300u16, &mut ser).unwrap();
+        assert_eq!(ser.get_ref(), &vec![0x81, 0x2C]);
+
+        ser.get_mut().clear();
+        ser::Serialize::serialize(&1u16, &mut ser).unwrap();
+        ser.end().unwrap();
+        assert_eq!(ser.into_inner(), vec![1]);
+    }
+
+    #[test]
+    fn buffered() {
+        #[derive(Debug)]
+        struct Writes(Vec<usize>);
+
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.push(buf.len());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let value = (1u32, "abc", vec![1u64, 2, 3], Some(-5i16));
+        let mut ser = Serializer::buffered(Writes(Vec::new()));
+        ser::Serialize::serialize(&value, &mut ser).unwrap();
+        assert!(ser.get_ref().get_ref().0.is_empty());
+
+        ser.end().unwrap();
+        let writes = ser.into_inner().into_inner().unwrap().0;
+        assert_eq!(writes, vec![to_vec(&value).unwrap().len()]);
+    }
+
+    #[test]
+    fn stream_of_values() {
+        let mut stream = StreamSerializer::new(Vec::new());
+        for s in &["a", "bc"] {
+            stream.write_value(*s).unwrap();
+        }
+        stream.write_value(&300u16).unwrap();
+        assert_eq!(stream.count(), 3);
+        assert_eq!(stream.get_ref().len(), 7);
+
+        let bs = stream.finish().unwrap();
+        let mut de = crate::de::Deserializer::new(bs.as_slice());
+        let v: (String, String, u16) = serde::Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(v, ("a".to_owned(), "bc".to_owned(), 300));
+
+        let mut stream = StreamSerializer::from(Serializer::new(Vec::new()).reject_non_finite());
+        let _ = stream.write_value(&std::f64::NAN).unwrap_err();
+        assert_eq!(stream.count(), 0);
+    }
+
+    #[test]
+    fn size_without_writing() {
+        let v = (300u32, "abc", vec![1.5f64; 3], Some('x'));
+        assert_eq!(
+            serialized_size(&v).unwrap(),
+            to_vec(&v).unwrap().len() as u64
+        );
+        assert_eq!(serialized_size(&()).unwrap(), 0);
+        assert_eq!(serialized_size("a").unwrap(), 2);
+    }
+
+    #[test]
+    fn canonical() {
+        fn canonical<T: ?Sized + Serialize>(v: &T) -> Vec<u8> {
+            let mut ser = Serializer::new(Vec::new()).canonical();
+            v.serialize(&mut ser).unwrap();
+            ser.into_inner()
+        }
+
+        let mut a = HashMap::new();
+        let mut b = HashMap::new();
+        for i in 0..100u32 {
+            a.insert(i, vec![i; 2]);
+            b.insert(99 - i, vec![99 - i; 2]);
+        }
+        let sorted: std::collections::BTreeMap<_, _> = a.clone().into_iter().collect();
+        assert_eq!(canonical(&a), canonical(&b));
+        assert_eq!(canonical(&a), to_vec(&sorted).unwrap());
+        let d: HashMap<u32, Vec<u32>> = from_reader(canonical(&a).as_slice()).unwrap();
+        assert_eq!(d, a);
+
+        // Keys are ordered by bytes, not by value: -2 is zigzagged into 3.
+        let m: HashMap<i16, ()> = vec![(-2, ()), (1, ())].into_iter().collect();
+        assert_eq!(canonical(&m), vec![2, 2, 3]);
+
+        let nan = f64::from_bits(0x7FF8_0000_0000_1234);
+        assert_eq!(canonical(&nan), canonical(&-std::f64::NAN));
+        assert_eq!(canonical(&nan), std::f64::NAN.to_le_bytes().to_vec());
+        let nan = f32::from_bits(0xFFC0_0001);
+        assert_eq!(canonical(&nan), std::f32::NAN.to_le_bytes().to_vec());
+        assert_eq!(canonical(&-0.0f64), vec![0; 8]);
+        assert_eq!(canonical(&-0.0f32), vec![0; 4]);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn dokechi_const() {
+        use crate::dokechi_const;
+
+        macro_rules! check {
+            ($v:expr, $t:ty) => {
+                let v: $t = $v;
+                assert_eq!(dokechi_const!($v as $t), &to_vec(&v).unwrap()[..]);
+            };
+        }
+
+        check!(true, bool);
+        check!(200, u8);
+        check!(-100, i8);
+        check!(127, u16);
+        check!(128, u32);
+        check!(0xFFFF_FFFF, u32);
+        check!(0x100_0000_0000_0000, u64);
+        check!(18_446_744_073_709_551_615, u64);
+        check!(-1, i32);
+        check!(-9_223_372_036_854_775_808, i64);
+        check!(300, usize);
+        check!(0x10_0000_0000_0000_0000_0000_0000, u128);
+        check!(-170_141_183_460_469_231_731_687_303_715_884_105_728, i128);
+        check!(-1.5, f32);
+        check!(6.02e23, f64);
+        check!('あ', char);
+        check!("abc", &str);
+        check!(b"\x00\x01", &[u8]);
+        check!(None, Option<u8>);
+        check!(Some(-2), Option<i16>);
+        check!(vec![1, 200, 70000], Vec<u32>);
+        check!(&[Some("a"), None], &[Option<&str>]);
+        check!([1, 2, 3], [u16; 3]);
+        check!((1, ("x", false)), (u8, (&str, bool)));
+        check!((), ());
+        let empty = String::new();
+        assert_eq!(dokechi_const!("" as String), &to_vec(&empty).unwrap()[..]);
+    }
 }
@@ -0,0 +1,176 @@
+//! Storing values in binary database columns.
+//!
+//! [`Dokechi<T>`](struct.Dokechi.html) is encoded into and decoded from `BLOB` / `BYTEA` columns
+//! as Dokechi format, so a struct can be stored in one column without manual conversion at every query.
+//!
+//! Support for `sqlx` and `diesel` is available with the `sqlx` and `diesel` features.
+//! With `diesel`, backends writing binds as raw bytes (PostgreSQL and MySQL) are supported.
+
+use std::ops::{Deref, DerefMut};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+use crate::de::from_slice;
+use crate::ser::to_vec;
+
+/// A value stored in a binary column as Dokechi format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel::expression::AsExpression, diesel::deserialize::FromSqlRow),
+    diesel(sql_type = diesel::sql_types::Binary)
+)]
+pub struct Dokechi<T>(pub T);
+
+impl<T> Dokechi<T> {
+    /// Unwrap the value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Dokechi<T> {
+    fn from(v: T) -> Dokechi<T> {
+        Dokechi(v)
+    }
+}
+
+impl<T> Deref for Dokechi<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Dokechi<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Serialize> Dokechi<T> {
+    fn encode(&self) -> Result<Vec<u8>, crate::ser::Error> {
+        to_vec(&self.0)
+    }
+}
+
+impl<T: DeserializeOwned> Dokechi<T> {
+    fn decode(bs: &[u8]) -> Result<Dokechi<T>, crate::de::Error> {
+        Ok(Dokechi(from_slice(bs)?))
+    }
+}
+
+#[cfg(feature = "sqlx")]
+mod sqlx_impl {
+    use super::*;
+
+    use sqlx::database::Database;
+    use sqlx::decode::Decode;
+    use sqlx::encode::{Encode, IsNull};
+    use sqlx::error::BoxDynError;
+    use sqlx::types::Type;
+
+    impl<T, DB> Type<DB> for Dokechi<T>
+    where
+        DB: Database,
+        Vec<u8>: Type<DB>,
+    {
+        fn type_info() -> DB::TypeInfo {
+            <Vec<u8> as Type<DB>>::type_info()
+        }
+
+        fn compatible(ty: &DB::TypeInfo) -> bool {
+            <Vec<u8> as Type<DB>>::compatible(ty)
+        }
+    }
+
+    impl<'q, T, DB> Encode<'q, DB> for Dokechi<T>
+    where
+        T: Serialize,
+        DB: Database,
+        Vec<u8>: Encode<'q, DB>,
+    {
+        fn encode_by_ref(
+            &self,
+            buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+        ) -> Result<IsNull, BoxDynError> {
+            <Vec<u8> as Encode<'q, DB>>::encode(self.encode()?, buf)
+        }
+    }
+
+    impl<'r, T, DB> Decode<'r, DB> for Dokechi<T>
+    where
+        T: DeserializeOwned,
+        DB: Database,
+        &'r [u8]: Decode<'r, DB>,
+    {
+        fn decode(value: <DB as Database>::ValueRef<'r>) -> Result<Self, BoxDynError> {
+            let bs = <&[u8] as Decode<'r, DB>>::decode(value)?;
+            Ok(Dokechi::decode(bs)?)
+        }
+    }
+}
+
+#[cfg(feature = "diesel")]
+mod diesel_impl {
+    use super::*;
+
+    use std::io::Write;
+
+    use diesel::backend::Backend;
+    use diesel::deserialize::{self, FromSql};
+    use diesel::query_builder::bind_collector::RawBytesBindCollector;
+    use diesel::serialize::{self, IsNull, Output, ToSql};
+    use diesel::sql_types::Binary;
+
+    impl<T, DB> FromSql<Binary, DB> for Dokechi<T>
+    where
+        T: DeserializeOwned,
+        DB: Backend,
+        Vec<u8>: FromSql<Binary, DB>,
+    {
+        fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+            let bs = <Vec<u8> as FromSql<Binary, DB>>::from_sql(bytes)?;
+            Ok(Dokechi::decode(&bs)?)
+        }
+    }
+
+    impl<T, DB> ToSql<Binary, DB> for Dokechi<T>
+    where
+        T: Serialize + std::fmt::Debug,
+        for<'c> DB: Backend<BindCollector<'c> = RawBytesBindCollector<DB>>,
+    {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+            out.write_all(&self.encode()?)?;
+            Ok(IsNull::No)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn round_trip() {
+        let mut m = BTreeMap::new();
+        m.insert("a".to_owned(), 1u32);
+        m.insert("b".to_owned(), 2u32);
+
+        let v = Dokechi(m);
+        let bs = v.encode().unwrap();
+        assert_eq!(Dokechi::decode(&bs).unwrap(), v);
+    }
+
+    #[test]
+    fn deref() {
+        let mut v = Dokechi(vec![1u8]);
+        v.push(2);
+        assert_eq!(v.len(), 2);
+        assert_eq!(v.into_inner(), vec![1, 2]);
+    }
+}
@@ -0,0 +1,163 @@
+//! Statistics of decoded values.
+//!
+//! [`from_reader_with_stats`](fn.from_reader_with_stats.html) counts what a payload is made of
+//! while deserializing it, to find out which part of a pathological payload is large.
+
+use std::cell::Cell;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use serde::de::DeserializeOwned;
+
+use crate::de::{Deserializer, Error};
+
+/// Counts of values decoded from a payload.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The number of strings.
+    pub strings: u64,
+    /// The total length of strings in bytes.
+    pub string_bytes: u64,
+    /// The number of byte arrays.
+    pub byte_arrays: u64,
+    /// The total length of byte arrays in bytes.
+    pub byte_array_bytes: u64,
+    /// The number of sequence elements.
+    pub seq_elements: u64,
+    /// The number of map entries.
+    pub map_entries: u64,
+    /// The total number of bytes read.
+    pub total_bytes: u64,
+    /// The number of bytes of each field of the top-level struct, in order.
+    ///
+    /// Empty if the value is not a struct.
+    pub field_bytes: Vec<(&'static str, u64)>,
+}
+
+/// Deserialize an instance of type `T` from IO stream of Dokechi format,
+/// collecting statistics of decoded values.
+pub fn from_reader_with_stats<R: Read, T: DeserializeOwned>(r: R) -> Result<(T, Stats), Error> {
+    let read = Rc::new(Cell::new(0));
+    let mut deserializer = Deserializer::new(Tally {
+        r,
+        read: read.clone(),
+    });
+    deserializer.stats = Some(Collector {
+        stats: Stats::default(),
+        read,
+        root_seen: false,
+    });
+
+    let value = T::deserialize(&mut deserializer)?;
+
+    let mut collector = deserializer.stats.take().expect("stats are collected");
+    collector.stats.total_bytes = collector.position();
+    Ok((value, collector.stats))
+}
+
+/// Statistics being collected by a [`Deserializer`](../de/struct.Deserializer.html).
+#[derive(Debug)]
+pub(crate) struct Collector {
+    pub(crate) stats: Stats,
+    read: Rc<Cell<u64>>,
+    root_seen: bool,
+}
+
+impl Collector {
+    /// The number of bytes read so far.
+    pub(crate) fn position(&self) -> u64 {
+        self.read.get()
+    }
+
+    /// Whether a struct starting now is the top-level one.
+    pub(crate) fn enter_root(&mut self) -> bool {
+        let root = !self.root_seen && self.position() == 0;
+        self.root_seen = true;
+        root
+    }
+}
+
+/// A reader counting bytes read into a counter shared with `Collector`.
+pub(crate) struct Tally<R> {
+    pub(crate) r: R,
+    pub(crate) read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for Tally<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.r.read(buf)?;
+        self.read.set(self.read.get() + n as u64);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::to_writer;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Inner {
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Payload {
+        id: u32,
+        tags: Vec<String>,
+        attrs: BTreeMap<String, Vec<u8>>,
+        inner: Inner,
+    }
+
+    #[test]
+    fn count_values() {
+        let mut attrs = BTreeMap::new();
+        attrs.insert("k".to_owned(), vec![0u8; 300]);
+        let v = Payload {
+            id: 1,
+            tags: vec!["a".to_owned(), "bc".to_owned()],
+            attrs,
+            inner: Inner {
+                name: "xyz".to_owned(),
+            },
+        };
+
+        let mut bs = Vec::new();
+        to_writer(&mut bs, &v).unwrap();
+
+        let (d, stats): (Payload, _) = from_reader_with_stats(bs.as_slice()).unwrap();
+        assert_eq!(d, v);
+
+        assert_eq!(stats.strings, 4);
+        assert_eq!(stats.string_bytes, 1 + 2 + 1 + 3);
+        assert_eq!(stats.byte_arrays, 0);
+        assert_eq!(stats.seq_elements, 2 + 300);
+        assert_eq!(stats.map_entries, 1);
+        assert_eq!(stats.total_bytes, bs.len() as u64);
+        assert_eq!(
+            stats.field_bytes,
+            vec![
+                ("id", 1),
+                ("tags", 6),
+                ("attrs", 1 + 2 + 2 + 300),
+                ("inner", 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn not_a_struct() {
+        let bs = [2u8, 1, b'a', 0];
+        let (d, stats): (Vec<String>, _) = from_reader_with_stats(&bs[..]).unwrap();
+        assert_eq!(d, vec!["a".to_owned(), String::new()]);
+        assert_eq!(stats.seq_elements, 2);
+        assert_eq!(stats.strings, 2);
+        assert!(stats.field_bytes.is_empty());
+    }
+}
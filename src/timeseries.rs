@@ -0,0 +1,365 @@
+//! Files of timestamped values.
+//!
+//! [`TimeSeriesWriter`](struct.TimeSeriesWriter.html) appends `(timestamp, value)` records
+//! with non-decreasing timestamps in blocks, and [`TimeSeriesReader`](struct.TimeSeriesReader.html)
+//! reads records in a time range, skipping blocks outside of it.
+//! Values are encoded by a [`ValueCodec`](trait.ValueCodec.html), which is reset at each block.
+//!
+//! Block format:
+//!
+//! ```text
+//! record count (varuint) , first timestamp (i64) , last timestamp - first timestamp (varuint) ,
+//! body length (varuint) , body
+//! ```
+//!
+//! Body is a series of records:
+//!
+//! ```text
+//! timestamp - previous timestamp (varuint) , encoded value
+//! ```
+//!
+//! The previous timestamp of the first record is the first timestamp of the block.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use serde::de::{Deserialize, DeserializeOwned, Error as _};
+use serde::ser::{Error as _, Serialize};
+
+use crate::de::{self, Deserializer};
+use crate::io::read_to_vec;
+use crate::ser::{self, Serializer};
+use crate::varuint::{decode_u64, encode_u64};
+
+/// Encoding of values in a time series.
+///
+/// A codec may keep state between values, such as the previous value.
+/// The state is reset at the start of each block.
+pub trait ValueCodec {
+    /// The type of values.
+    type Value;
+
+    /// Encode `value` into `out`.
+    fn encode(&mut self, value: &Self::Value, out: &mut Vec<u8>) -> Result<(), ser::Error>;
+
+    /// Decode a value from the head of `input`, advancing it.
+    fn decode(&mut self, input: &mut &[u8]) -> Result<Self::Value, de::Error>;
+
+    /// Reset the state for a new block.
+    fn reset(&mut self) {}
+}
+
+/// A codec encoding each value in Dokechi format.
+#[derive(Debug)]
+pub struct PlainCodec<T> {
+    _marker: PhantomData<T>,
+}
+
+impl<T> PlainCodec<T> {
+    /// Create new `PlainCodec`.
+    pub fn new() -> PlainCodec<T> {
+        PlainCodec {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for PlainCodec<T> {
+    fn default() -> PlainCodec<T> {
+        PlainCodec::new()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> ValueCodec for PlainCodec<T> {
+    type Value = T;
+
+    fn encode(&mut self, value: &T, out: &mut Vec<u8>) -> Result<(), ser::Error> {
+        value.serialize(&mut Serializer::new(out))
+    }
+
+    fn decode(&mut self, input: &mut &[u8]) -> Result<T, de::Error> {
+        T::deserialize(&mut Deserializer::new(input))
+    }
+}
+
+const DEFAULT_BLOCK_LEN: usize = 1024;
+
+/// Appends timestamped values.
+///
+/// Records are buffered until a block is full, so call [`flush`](#method.flush)
+/// or [`into_inner`](#method.into_inner) to write the last block.
+#[derive(Debug)]
+pub struct TimeSeriesWriter<W: Write, C: ValueCodec> {
+    w: W,
+    codec: C,
+    block_len: usize,
+    count: usize,
+    first: i64,
+    last: i64,
+    body: Vec<u8>,
+}
+
+impl<W: Write, C: ValueCodec> TimeSeriesWriter<W, C> {
+    /// Create new `TimeSeriesWriter` writing blocks of 1024 records.
+    pub fn new(w: W, codec: C) -> TimeSeriesWriter<W, C> {
+        TimeSeriesWriter::with_block_len(w, codec, DEFAULT_BLOCK_LEN)
+    }
+
+    /// Create new `TimeSeriesWriter` writing blocks of `block_len` records.
+    ///
+    /// Smaller blocks make range queries read less, and larger blocks make the file smaller.
+    pub fn with_block_len(w: W, mut codec: C, block_len: usize) -> TimeSeriesWriter<W, C> {
+        codec.reset();
+        TimeSeriesWriter {
+            w,
+            codec,
+            block_len: block_len.max(1),
+            count: 0,
+            first: 0,
+            last: 0,
+            body: Vec::new(),
+        }
+    }
+
+    /// Append a record.
+    ///
+    /// Returns error if `timestamp` is less than the one of the previous record.
+    pub fn append(&mut self, timestamp: i64, value: &C::Value) -> Result<(), ser::Error> {
+        if self.count == 0 {
+            self.first = timestamp;
+        } else if timestamp < self.last {
+            return Err(ser::Error::custom(format_args!(
+                "timestamp {} is less than the previous one {}",
+                timestamp, self.last
+            )));
+        }
+
+        let previous = if self.count == 0 {
+            timestamp
+        } else {
+            self.last
+        };
+        encode_u64(&mut self.body, timestamp.wrapping_sub(previous) as u64)?;
+        self.codec.encode(value, &mut self.body)?;
+        self.last = timestamp;
+        self.count += 1;
+
+        if self.count == self.block_len {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> Result<(), ser::Error> {
+        if self.count == 0 {
+            return Ok(());
+        }
+
+        let mut header = Vec::new();
+        encode_u64(&mut header, self.count as u64)?;
+        (
+            self.first,
+            self.last.wrapping_sub(self.first) as u64,
+            self.body.len() as u64,
+        )
+            .serialize(&mut Serializer::new(&mut header))?;
+        self.w.write_all(&header)?;
+        self.w.write_all(&self.body)?;
+
+        self.body.clear();
+        self.count = 0;
+        self.codec.reset();
+        Ok(())
+    }
+
+    /// Write the pending block and flush the underlying writer.
+    ///
+    /// The following records start a new block.
+    pub fn flush(&mut self) -> Result<(), ser::Error> {
+        self.write_block()?;
+        self.w.flush()?;
+        Ok(())
+    }
+
+    /// Write the pending block and unwrap this `TimeSeriesWriter`, returning the underlying writer.
+    pub fn into_inner(mut self) -> Result<W, ser::Error> {
+        self.flush()?;
+        Ok(self.w)
+    }
+}
+
+/// Reads timestamped values.
+#[derive(Debug)]
+pub struct TimeSeriesReader<R: Read + Seek, C: ValueCodec> {
+    r: R,
+    codec: C,
+}
+
+struct BlockHeader {
+    count: u64,
+    first: i64,
+    last: i64,
+    body_len: u64,
+}
+
+impl<R: Read + Seek, C: ValueCodec> TimeSeriesReader<R, C> {
+    /// Create new `TimeSeriesReader`.
+    pub fn new(r: R, codec: C) -> TimeSeriesReader<R, C> {
+        TimeSeriesReader { r, codec }
+    }
+
+    /// Read records with timestamps in `range`, in order.
+    pub fn range(&mut self, range: Range<i64>) -> Result<Vec<(i64, C::Value)>, de::Error> {
+        self.read_from(range.start, Some(range.end))
+    }
+
+    /// Read all records.
+    pub fn read_all(&mut self) -> Result<Vec<(i64, C::Value)>, de::Error> {
+        self.read_from(i64::min_value(), None)
+    }
+
+    /// Read records with timestamps from `start` and less than `end` if any.
+    fn read_from(
+        &mut self,
+        start: i64,
+        end: Option<i64>,
+    ) -> Result<Vec<(i64, C::Value)>, de::Error> {
+        let before_end = |t: i64| end.map_or(true, |end| t < end);
+        self.r.seek(SeekFrom::Start(0))?;
+
+        let mut records = Vec::new();
+        let mut body = Vec::new();
+        while let Some(header) = self.read_header()? {
+            if !before_end(header.first) {
+                break;
+            }
+            if header.last < start {
+                self.r.seek(SeekFrom::Current(header.body_len as i64))?;
+                continue;
+            }
+
+            let body_len = usize::try_from(header.body_len)
+                .map_err(|_| de::Error::custom("time series block is too long"))?;
+            read_to_vec(&mut self.r, &mut body, body_len)?;
+
+            self.codec.reset();
+            let mut input = body.as_slice();
+            let mut timestamp = header.first;
+            for _ in 0..header.count {
+                timestamp = timestamp.wrapping_add(decode_u64(&mut input)? as i64);
+                let value = self.codec.decode(&mut input)?;
+                if start <= timestamp && before_end(timestamp) {
+                    records.push((timestamp, value));
+                }
+            }
+            if !input.is_empty() {
+                return Err(de::Error::custom("trailing bytes in time series block"));
+            }
+        }
+        Ok(records)
+    }
+
+    fn read_header(&mut self) -> Result<Option<BlockHeader>, de::Error> {
+        let mut head = [0u8];
+        loop {
+            match self.r.read(&mut head) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let count = decode_u64(&mut (&head[..]).chain(&mut self.r))?;
+        let (first, span, body_len): (i64, u64, u64) =
+            Deserialize::deserialize(&mut Deserializer::new(&mut self.r))?;
+        if i64::try_from(body_len).is_err() {
+            return Err(de::Error::custom("time series block is too long"));
+        }
+        Ok(Some(BlockHeader {
+            count,
+            first,
+            last: first.wrapping_add(span as i64),
+            body_len,
+        }))
+    }
+
+    /// Unwrap this `TimeSeriesReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn sample(block_len: usize) -> Vec<u8> {
+        let mut w =
+            TimeSeriesWriter::with_block_len(Vec::new(), PlainCodec::<f64>::new(), block_len);
+        for i in 0..1000i64 {
+            w.append(1_600_000_000_000 + i * 1000, &(i as f64 / 10.0))
+                .unwrap();
+        }
+        w.into_inner().unwrap()
+    }
+
+    #[test]
+    fn read_all_records() {
+        let bs = sample(64);
+        let mut r = TimeSeriesReader::new(Cursor::new(bs), PlainCodec::<f64>::new());
+        let records = r.read_all().unwrap();
+        assert_eq!(records.len(), 1000);
+        for (i, &(t, v)) in records.iter().enumerate() {
+            assert_eq!(t, 1_600_000_000_000 + i as i64 * 1000);
+            assert_eq!(v, i as f64 / 10.0);
+        }
+    }
+
+    #[test]
+    fn timestamps_are_delta_encoded() {
+        // 1000 records of a varuint delta and a f64.
+        let bs = sample(1000);
+        assert!(bs.len() < 1000 * (2 + 8) + 32);
+    }
+
+    #[test]
+    fn range_query() {
+        let bs = sample(64);
+        let mut r = TimeSeriesReader::new(Cursor::new(bs), PlainCodec::<f64>::new());
+
+        let start = 1_600_000_000_000 + 300 * 1000;
+        let records = r.range(start..start + 5000).unwrap();
+        let timestamps: Vec<i64> = records.iter().map(|&(t, _)| t).collect();
+        assert_eq!(
+            timestamps,
+            (0..5).map(|i| start + i * 1000).collect::<Vec<_>>()
+        );
+
+        assert!(r.range(0..1000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn equal_timestamps() {
+        let mut w = TimeSeriesWriter::new(Vec::new(), PlainCodec::<String>::new());
+        w.append(-5, &"a".to_owned()).unwrap();
+        w.append(-5, &"b".to_owned()).unwrap();
+        w.append(3, &"c".to_owned()).unwrap();
+        let bs = w.into_inner().unwrap();
+
+        let mut r = TimeSeriesReader::new(Cursor::new(bs), PlainCodec::<String>::new());
+        let records = r.range(-5..0).unwrap();
+        assert_eq!(records, vec![(-5, "a".to_owned()), (-5, "b".to_owned())]);
+    }
+
+    #[test]
+    fn decreasing_timestamp() {
+        let mut w = TimeSeriesWriter::new(Vec::new(), PlainCodec::<u8>::new());
+        w.append(10, &0).unwrap();
+        let _ = w.append(9, &0).unwrap_err();
+    }
+}
@@ -0,0 +1,210 @@
+//! Logging each step of decoding.
+//!
+//! [`from_reader_traced`](fn.from_reader_traced.html) logs every value it decodes with the byte offset
+//! and the path of the value, at `trace` level to the `log` crate.
+//! When decoding fails, the error is logged at `debug` level with the path it failed in,
+//! so a failure in production can be diagnosed from the last steps before it.
+//!
+//! ```text
+//! struct at byte 0 in .
+//! u32 at byte 0 in .id
+//! seq at byte 1 in .tags
+//! length 2 at byte 2 in .tags
+//! string at byte 2 in .tags[0]
+//! ```
+//!
+//! This module is available with the `log` feature.
+
+use std::cell::Cell;
+use std::fmt;
+use std::io::Read;
+use std::rc::Rc;
+
+use serde::de::DeserializeOwned;
+
+use crate::de::{Deserializer, Error};
+use crate::stats::Tally;
+
+/// Deserialize an instance of type `T` from IO stream of Dokechi format, logging each step.
+pub fn from_reader_traced<R: Read, T: DeserializeOwned>(r: R) -> Result<T, Error> {
+    let read = Rc::new(Cell::new(0));
+    let mut deserializer = Deserializer::new(Tally {
+        r,
+        read: read.clone(),
+    });
+    deserializer.trace = Some(Tracer {
+        read,
+        path: Path(Vec::new()),
+    });
+
+    match T::deserialize(&mut deserializer) {
+        Ok(v) => Ok(v),
+        Err(e) => {
+            if let Some(t) = &deserializer.trace {
+                log::debug!(
+                    "decoding failed at byte {} in {}: {}",
+                    t.read.get(),
+                    t.path,
+                    e
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Tracing state of a [`Deserializer`](../de/struct.Deserializer.html).
+#[derive(Debug)]
+pub(crate) struct Tracer {
+    read: Rc<Cell<u64>>,
+    path: Path,
+}
+
+impl Tracer {
+    /// Log decoding a value of `kind`.
+    pub(crate) fn step(&self, kind: &'static str) {
+        log::trace!("{} at byte {} in {}", kind, self.read.get(), self.path);
+    }
+
+    /// Log a prefix read, such as a length or a variant index.
+    pub(crate) fn prefix(&self, what: &'static str, n: u64) {
+        log::trace!(
+            "{} {} at byte {} in {}",
+            what,
+            n,
+            self.read.get(),
+            self.path
+        );
+    }
+
+    /// Go into a field, or an element if `field` is `None`.
+    pub(crate) fn enter(&mut self, field: Option<&'static str>, index: usize) {
+        let segment = match field {
+            Some(name) => Segment::Field(name),
+            None => Segment::Index(index),
+        };
+        self.path.0.push(segment);
+    }
+
+    /// Go back from the last entered field or element.
+    pub(crate) fn leave(&mut self) {
+        self.path.0.pop();
+    }
+}
+
+#[derive(Debug)]
+struct Path(Vec<Segment>);
+
+#[derive(Debug)]
+enum Segment {
+    Field(&'static str),
+    Index(usize),
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str(".");
+        }
+        for segment in &self.0 {
+            match segment {
+                Segment::Field(name) => write!(f, ".{}", name)?,
+                Segment::Index(i) => write!(f, "[{}]", i)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::to_vec;
+
+    thread_local! {
+        static LINES: RefCell<Vec<(Level, String)>> = RefCell::new(Vec::new());
+    }
+
+    struct Capture;
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            if record.target().starts_with("serde_dokechi") {
+                LINES.with(|l| {
+                    l.borrow_mut()
+                        .push((record.level(), record.args().to_string()))
+                });
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture<F: FnOnce()>(f: F) -> Vec<(Level, String)> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(LevelFilter::Trace);
+        });
+
+        LINES.with(|l| l.borrow_mut().clear());
+        f();
+        LINES.with(|l| l.borrow_mut().drain(..).collect())
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Payload {
+        id: u32,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn steps() {
+        let v = Payload {
+            id: 1,
+            tags: vec!["a".to_owned(), "bc".to_owned()],
+        };
+        let bs = to_vec(&v).unwrap();
+
+        let lines = capture(|| {
+            let d: Payload = from_reader_traced(bs.as_slice()).unwrap();
+            assert_eq!(d, v);
+        });
+        let lines: Vec<&str> = lines.iter().map(|(_, l)| l.as_str()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "struct at byte 0 in .",
+                "u32 at byte 0 in .id",
+                "seq at byte 1 in .tags",
+                "length 2 at byte 2 in .tags",
+                "string at byte 2 in .tags[0]",
+                "length 1 at byte 3 in .tags[0]",
+                "string at byte 4 in .tags[1]",
+                "length 2 at byte 5 in .tags[1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn failure() {
+        let bs = [1u8, 2, 1, b'a', 5, b'x'];
+        let lines = capture(|| {
+            let _ = from_reader_traced::<_, Payload>(&bs[..]).unwrap_err();
+        });
+        let (level, last) = lines.last().unwrap();
+        assert_eq!(*level, Level::Debug);
+        assert!(last.starts_with("decoding failed at byte 6 in .tags[1]: "));
+    }
+}
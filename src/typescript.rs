@@ -0,0 +1,857 @@
+//! TypeScript decoders and encoders generated from Rust types.
+//!
+//! [`typescript`](fn.typescript.html) traces the structure of a type through its `Deserialize` implementation,
+//! and emits a TypeScript module which decodes and encodes the same bytes as [`from_slice`](../fn.from_slice.html)
+//! and [`to_vec`](../fn.to_vec.html) with the default settings.
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Order {
+//!     id: u64,
+//!     items: Vec<String>,
+//!     note: Option<String>,
+//! }
+//!
+//! let ts = serde_dokechi::typescript::typescript::<Order>().unwrap();
+//! assert!(ts.contains("export interface Order {"));
+//! assert!(ts.contains("export function decode(bytes: Uint8Array): Order {"));
+//! ```
+//!
+//! The module exports a type and `decodeName` / `encodeName` functions for each named type,
+//! and `decode` / `encode` functions for the type itself.
+//! Values map to TypeScript as follows:
+//!
+//! | Rust | TypeScript |
+//! |------|------------|
+//! | `bool` | `boolean` |
+//! | `i8` ~ `i32`, `u8` ~ `u32`, `f32`, `f64` | `number` |
+//! | `i64`, `u64`, `i128`, `u128` | `bigint` |
+//! | `char`, `String` | `string` |
+//! | byte arrays | `Uint8Array` |
+//! | `()`, unit structs | `null` |
+//! | `Option<T>` | `T \| null` |
+//! | sequences | `Array<T>` |
+//! | maps | `Map<K, V>` |
+//! | tuples, tuple structs | tuples |
+//! | newtype structs | the inner type |
+//! | structs | interfaces |
+//! | enums | `{ tag: "Variant", value: ... }` unions, without `value` for unit variants |
+//!
+//! The module needs ES2020 for `bigint`, and `TextEncoder` and `TextDecoder`.
+//!
+//! `None` and `Some` of a value mapped to `null`, such as `Option<Option<T>>`, are both `null` in TypeScript,
+//! so they are encoded as `None`.
+//! Types are identified by their names as the schema fingerprints do, so generic types used with
+//! different parameters are not supported.
+//! Other settings of [`Options`](../options/struct.Options.html) are not supported.
+
+use std::fmt::Write as _;
+
+use serde::de::DeserializeOwned;
+
+use crate::de::Error as DeError;
+use crate::schema::{trace, Shape, Variant};
+
+/// Generate a TypeScript module decoding and encoding `T`.
+pub fn typescript<T: DeserializeOwned>() -> Result<String, DeError> {
+    let schema = trace::<T>()?;
+
+    let mut s = String::new();
+    s.push_str("// Generated by serde_dokechi. Do not edit.\n");
+    s.push_str(RUNTIME);
+
+    for (name, shape) in &schema.named {
+        s.push('\n');
+        write_named(&mut s, name, shape);
+    }
+    for (name, variants) in &schema.enums {
+        s.push('\n');
+        write_enum(&mut s, name, variants);
+    }
+
+    let root = type_of(&schema.root);
+    let _ = write!(
+        s,
+        "
+export function decode(bytes: Uint8Array): {ty} {{
+  const r = new DokechiReader(bytes);
+  const v = {dec};
+  r.end();
+  return v;
+}}
+
+export function encode(v: {ty}): Uint8Array {{
+  const w = new DokechiWriter();
+  {enc}
+  return w.finish();
+}}
+",
+        ty = root,
+        dec = decode_of(&schema.root),
+        enc = encode_of(&schema.root, "v", 0),
+    );
+
+    // Values encoded into no bytes leave lines of only indents.
+    let lines: Vec<&str> = s
+        .lines()
+        .filter(|line| line.is_empty() || !line.trim().is_empty())
+        .collect();
+    Ok(lines.join("\n") + "\n")
+}
+
+fn write_named(s: &mut String, name: &str, shape: &Shape) {
+    let id = ident(name);
+    match shape {
+        Shape::Struct(_, fields) => {
+            let _ = writeln!(s, "export interface {} {{", id);
+            for (field, shape) in fields {
+                let _ = writeln!(s, "  {}: {};", key(field), type_of(shape));
+            }
+            s.push_str("}\n");
+        }
+        Shape::TupleStruct(_, shapes) => {
+            let _ = writeln!(s, "export type {} = {};", id, tuple_type(shapes));
+        }
+        Shape::Newtype(_, inner) => {
+            let _ = writeln!(s, "export type {} = {};", id, type_of(inner));
+        }
+        _ => {
+            let _ = writeln!(s, "export type {} = {};", id, type_of(shape));
+        }
+    }
+
+    let body = match shape {
+        Shape::Struct(_, fields) => struct_decode(fields),
+        Shape::TupleStruct(_, shapes) => tuple_decode(shapes),
+        Shape::Newtype(_, inner) => decode_of(inner),
+        _ => decode_of(shape),
+    };
+    let _ = write!(
+        s,
+        "
+export function decode{id}(r: DokechiReader): {id} {{
+  return {body};
+}}
+
+export function encode{id}(w: DokechiWriter, v: {id}): void {{
+  {enc}
+}}
+",
+        id = id,
+        body = body,
+        enc = match shape {
+            Shape::Struct(_, fields) => struct_encode(fields, "v", 0),
+            Shape::TupleStruct(_, shapes) => tuple_encode(shapes, "v", 0),
+            Shape::Newtype(_, inner) => encode_of(inner, "v", 0),
+            _ => encode_of(shape, "v", 0),
+        },
+    );
+}
+
+fn write_enum(s: &mut String, name: &str, variants: &[(&str, Variant)]) {
+    let id = ident(name);
+
+    let _ = write!(s, "export type {} =", id);
+    for (variant, data) in variants {
+        let _ = write!(s, "\n  | {{ tag: {}", quote(variant));
+        match data {
+            Variant::Unit => {}
+            Variant::Newtype(shape) => {
+                let _ = write!(s, "; value: {}", type_of(shape));
+            }
+            Variant::Tuple(shapes) => {
+                let _ = write!(s, "; value: {}", tuple_type(shapes));
+            }
+            Variant::Struct(fields) => {
+                let _ = write!(s, "; value: {}", struct_type(fields));
+            }
+        }
+        s.push_str(" }");
+    }
+    s.push_str(";\n");
+
+    let _ = write!(
+        s,
+        "
+export function decode{id}(r: DokechiReader): {id} {{
+  const i = r.variant();
+  switch (i) {{
+",
+        id = id
+    );
+    for (i, (variant, data)) in variants.iter().enumerate() {
+        let value = match data {
+            Variant::Unit => String::new(),
+            Variant::Newtype(shape) => format!(", value: {}", decode_of(shape)),
+            Variant::Tuple(shapes) => format!(", value: {}", tuple_decode(shapes)),
+            Variant::Struct(fields) => format!(", value: {}", struct_decode(fields)),
+        };
+        let _ = writeln!(
+            s,
+            "    case {}:\n      return {{ tag: {}{} }};",
+            i,
+            quote(variant),
+            value
+        );
+    }
+    let _ = write!(
+        s,
+        "  }}
+  throw new Error(\"invalid variant \" + i + \" of {name}\");
+}}
+
+export function encode{id}(w: DokechiWriter, v: {id}): void {{
+  switch (v.tag) {{
+",
+        id = id,
+        name = name.escape_default(),
+    );
+    for (i, (variant, data)) in variants.iter().enumerate() {
+        let enc = match data {
+            Variant::Unit => String::new(),
+            Variant::Newtype(shape) => encode_of(shape, "v.value", 0),
+            Variant::Tuple(shapes) => tuple_encode(shapes, "v.value", 0),
+            Variant::Struct(fields) => struct_encode(fields, "v.value", 0),
+        };
+        let _ = writeln!(
+            s,
+            "    case {}:\n      w.variant({});\n      {}\n      break;",
+            quote(variant),
+            i,
+            enc
+        );
+    }
+    s.push_str("  }\n}\n");
+}
+
+/// The TypeScript type of values of `shape`.
+fn type_of(shape: &Shape) -> String {
+    match shape {
+        Shape::Bool => "boolean".to_owned(),
+        Shape::I8
+        | Shape::I16
+        | Shape::I32
+        | Shape::U8
+        | Shape::U16
+        | Shape::U32
+        | Shape::F32
+        | Shape::F64 => "number".to_owned(),
+        Shape::I64 | Shape::I128 | Shape::U64 | Shape::U128 => "bigint".to_owned(),
+        Shape::Char | Shape::Str => "string".to_owned(),
+        Shape::Bytes => "Uint8Array".to_owned(),
+        Shape::Unit | Shape::UnitStruct(_) => "null".to_owned(),
+        Shape::Option(inner) => format!("({}) | null", type_of(inner)),
+        Shape::Seq(element) => format!("Array<{}>", type_of(element)),
+        Shape::Map(k, v) => format!("Map<{}, {}>", type_of(k), type_of(v)),
+        Shape::Tuple(shapes) => tuple_type(shapes),
+        Shape::Newtype(name, _)
+        | Shape::TupleStruct(name, _)
+        | Shape::Struct(name, _)
+        | Shape::Enum(name)
+        | Shape::Recursive(name) => ident(name),
+    }
+}
+
+fn tuple_type(shapes: &[Shape]) -> String {
+    let types: Vec<String> = shapes.iter().map(type_of).collect();
+    format!("[{}]", types.join(", "))
+}
+
+fn struct_type(fields: &[(&str, Shape)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(field, shape)| format!("{}: {}", key(field), type_of(shape)))
+        .collect();
+    format!("{{ {} }}", fields.join("; "))
+}
+
+/// An expression decoding a value of `shape` from `r`.
+fn decode_of(shape: &Shape) -> String {
+    match shape {
+        Shape::Bool => "r.bool()".to_owned(),
+        Shape::I8 => "r.i8()".to_owned(),
+        Shape::I16 => "r.i16()".to_owned(),
+        Shape::I32 => "r.i32()".to_owned(),
+        Shape::I64 => "r.i64()".to_owned(),
+        Shape::I128 => "r.i128()".to_owned(),
+        Shape::U8 => "r.u8()".to_owned(),
+        Shape::U16 => "r.u16()".to_owned(),
+        Shape::U32 => "r.u32()".to_owned(),
+        Shape::U64 => "r.u64()".to_owned(),
+        Shape::U128 => "r.u128()".to_owned(),
+        Shape::F32 => "r.f32()".to_owned(),
+        Shape::F64 => "r.f64()".to_owned(),
+        Shape::Char => "r.char()".to_owned(),
+        Shape::Str => "r.str()".to_owned(),
+        Shape::Bytes => "r.bytes()".to_owned(),
+        Shape::Unit | Shape::UnitStruct(_) => "null".to_owned(),
+        Shape::Option(inner) => format!("r.option(() => {})", decode_of(inner)),
+        Shape::Seq(element) => format!("r.seq(() => {})", decode_of(element)),
+        Shape::Map(k, v) => format!("r.map(() => {}, () => {})", decode_of(k), decode_of(v)),
+        Shape::Tuple(shapes) => tuple_decode(shapes),
+        Shape::Newtype(name, _)
+        | Shape::TupleStruct(name, _)
+        | Shape::Struct(name, _)
+        | Shape::Enum(name)
+        | Shape::Recursive(name) => format!("decode{}(r)", ident(name)),
+    }
+}
+
+fn tuple_decode(shapes: &[Shape]) -> String {
+    let elements: Vec<String> = shapes.iter().map(decode_of).collect();
+    format!("[{}]", elements.join(", "))
+}
+
+fn struct_decode(fields: &[(&str, Shape)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(field, shape)| format!("{}: {}", key(field), decode_of(shape)))
+        .collect();
+    format!("{{ {} }}", fields.join(", "))
+}
+
+/// Statements encoding the value `v` of `shape` into `w`.
+///
+/// `depth` numbers the variables of nested callbacks.
+fn encode_of(shape: &Shape, v: &str, depth: usize) -> String {
+    let method = match shape {
+        Shape::Bool => "bool",
+        Shape::I8 => "i8",
+        Shape::I16 => "i16",
+        Shape::I32 => "i32",
+        Shape::I64 => "i64",
+        Shape::I128 => "i128",
+        Shape::U8 => "u8",
+        Shape::U16 => "u16",
+        Shape::U32 => "u32",
+        Shape::U64 => "u64",
+        Shape::U128 => "u128",
+        Shape::F32 => "f32",
+        Shape::F64 => "f64",
+        Shape::Char => "char",
+        Shape::Str => "str",
+        Shape::Bytes => "bytes",
+        Shape::Unit | Shape::UnitStruct(_) => return String::new(),
+        Shape::Option(inner) => {
+            let x = format!("x{}", depth);
+            return format!(
+                "w.option({}, ({}) => {{ {} }});",
+                v,
+                x,
+                encode_of(inner, &x, depth + 1)
+            );
+        }
+        Shape::Seq(element) => {
+            let x = format!("x{}", depth);
+            return format!(
+                "w.seq({}, ({}) => {{ {} }});",
+                v,
+                x,
+                encode_of(element, &x, depth + 1)
+            );
+        }
+        Shape::Map(key, value) => {
+            let k = format!("k{}", depth);
+            let x = format!("x{}", depth);
+            return format!(
+                "w.map({}, ({}) => {{ {} }}, ({}) => {{ {} }});",
+                v,
+                k,
+                encode_of(key, &k, depth + 1),
+                x,
+                encode_of(value, &x, depth + 1)
+            );
+        }
+        Shape::Tuple(shapes) => return tuple_encode(shapes, v, depth),
+        Shape::Newtype(name, _)
+        | Shape::TupleStruct(name, _)
+        | Shape::Struct(name, _)
+        | Shape::Enum(name)
+        | Shape::Recursive(name) => return format!("encode{}(w, {});", ident(name), v),
+    };
+    format!("w.{}({});", method, v)
+}
+
+fn tuple_encode(shapes: &[Shape], v: &str, depth: usize) -> String {
+    let statements: Vec<String> = shapes
+        .iter()
+        .enumerate()
+        .map(|(i, shape)| encode_of(shape, &format!("{}[{}]", v, i), depth))
+        .filter(|s| !s.is_empty())
+        .collect();
+    statements.join(" ")
+}
+
+fn struct_encode(fields: &[(&str, Shape)], v: &str, depth: usize) -> String {
+    let statements: Vec<String> = fields
+        .iter()
+        .map(|(field, shape)| encode_of(shape, &format!("{}{}", v, access(field)), depth))
+        .filter(|s| !s.is_empty())
+        .collect();
+    statements.join(" ")
+}
+
+fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// A TypeScript identifier for the type `name`.
+fn ident(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !is_ident(&id) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+/// A property key for the field `name`.
+fn key(name: &str) -> String {
+    if is_ident(name) {
+        name.to_owned()
+    } else {
+        quote(name)
+    }
+}
+
+/// An access to the field `name`.
+fn access(name: &str) -> String {
+    if is_ident(name) {
+        format!(".{}", name)
+    } else {
+        format!("[{}]", quote(name))
+    }
+}
+
+/// A string literal of `s`.
+fn quote(s: &str) -> String {
+    let mut q = String::with_capacity(s.len() + 2);
+    q.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => q.push_str("\\\""),
+            '\\' => q.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(q, "\\u{:04x}", c as u32);
+            }
+            c => q.push(c),
+        }
+    }
+    q.push('"');
+    q
+}
+
+/// Readers and writers of values, written into every generated module.
+const RUNTIME: &str = r#"
+export class DokechiReader {
+  private readonly buf: Uint8Array;
+  private readonly view: DataView;
+  private pos = 0;
+
+  constructor(bytes: Uint8Array) {
+    this.buf = bytes;
+    this.view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+  }
+
+  end(): void {
+    if (this.pos !== this.buf.length) {
+      throw new Error("trailing bytes after value");
+    }
+  }
+
+  private take(n: number): number {
+    if (n > this.buf.length - this.pos) {
+      throw new Error("unexpected end of input");
+    }
+    const start = this.pos;
+    this.pos += n;
+    return start;
+  }
+
+  private byte(): number {
+    return this.buf[this.take(1)];
+  }
+
+  private varuint(wide: boolean): bigint {
+    const head = this.byte();
+    let n = 0;
+    while (n < 8 && (head & (0x80 >> n)) !== 0) {
+      n++;
+    }
+    let v = BigInt(head & (0x7f >> n));
+    if (n === 8 && wide) {
+      n = 16;
+    }
+    for (let i = 0; i < n; i++) {
+      v = (v << 8n) | BigInt(this.byte());
+    }
+    return v;
+  }
+
+  private small(max: number): number {
+    const v = this.varuint(false);
+    if (v > BigInt(max)) {
+      throw new Error("integer " + v + " out of range");
+    }
+    return Number(v);
+  }
+
+  private count(): number {
+    return this.small(Number.MAX_SAFE_INTEGER);
+  }
+
+  bool(): boolean {
+    const v = this.byte();
+    if (v > 1) {
+      throw new Error("invalid bool " + v);
+    }
+    return v === 1;
+  }
+
+  i8(): number {
+    return this.view.getInt8(this.take(1));
+  }
+
+  i16(): number {
+    return Number(unzigzag(BigInt(this.small(0xffff))));
+  }
+
+  i32(): number {
+    return Number(unzigzag(BigInt(this.small(0xffffffff))));
+  }
+
+  i64(): bigint {
+    return unzigzag(this.varuint(false));
+  }
+
+  i128(): bigint {
+    return unzigzag(this.varuint(true));
+  }
+
+  u8(): number {
+    return this.byte();
+  }
+
+  u16(): number {
+    return this.small(0xffff);
+  }
+
+  u32(): number {
+    return this.small(0xffffffff);
+  }
+
+  u64(): bigint {
+    return this.varuint(false);
+  }
+
+  u128(): bigint {
+    return this.varuint(true);
+  }
+
+  f32(): number {
+    return this.view.getFloat32(this.take(4), true);
+  }
+
+  f64(): number {
+    return this.view.getFloat64(this.take(8), true);
+  }
+
+  char(): string {
+    const start = this.take(3);
+    const c = this.buf[start] | (this.buf[start + 1] << 8) | (this.buf[start + 2] << 16);
+    if (c > 0x10ffff || (c >= 0xd800 && c < 0xe000)) {
+      throw new Error("invalid code point " + c);
+    }
+    return String.fromCodePoint(c);
+  }
+
+  str(): string {
+    const len = this.count();
+    const start = this.take(len);
+    return new TextDecoder("utf-8", { fatal: true }).decode(this.buf.subarray(start, start + len));
+  }
+
+  bytes(): Uint8Array {
+    const len = this.count();
+    const start = this.take(len);
+    return this.buf.slice(start, start + len);
+  }
+
+  option<T>(f: () => T): T | null {
+    const tag = this.byte();
+    if (tag > 1) {
+      throw new Error("invalid option tag " + tag);
+    }
+    return tag === 1 ? f() : null;
+  }
+
+  seq<T>(f: () => T): Array<T> {
+    const len = this.count();
+    const vs: Array<T> = [];
+    for (let i = 0; i < len; i++) {
+      vs.push(f());
+    }
+    return vs;
+  }
+
+  map<K, V>(k: () => K, v: () => V): Map<K, V> {
+    const len = this.count();
+    const m = new Map<K, V>();
+    for (let i = 0; i < len; i++) {
+      const key = k();
+      m.set(key, v());
+    }
+    return m;
+  }
+
+  variant(): number {
+    return this.u32();
+  }
+}
+
+export class DokechiWriter {
+  private buf = new Uint8Array(64);
+  private len = 0;
+
+  finish(): Uint8Array {
+    return this.buf.slice(0, this.len);
+  }
+
+  private reserve(n: number): number {
+    if (this.len + n > this.buf.length) {
+      const grown = new Uint8Array(Math.max(this.buf.length * 2, this.len + n));
+      grown.set(this.buf.subarray(0, this.len));
+      this.buf = grown;
+    }
+    const start = this.len;
+    this.len += n;
+    return start;
+  }
+
+  private byte(v: number): void {
+    const start = this.reserve(1);
+    this.buf[start] = v;
+  }
+
+  private varuint(v: bigint, wide: boolean): void {
+    const bits = v.toString(2).length;
+    let n: number;
+    if (bits <= 56) {
+      n = Math.max(Math.ceil(bits / 7), 1) - 1;
+      this.byte(((0xff00 >> n) & 0xff) | Number(v >> BigInt(8 * n)));
+    } else {
+      n = wide ? 16 : 8;
+      this.byte(0xff);
+    }
+    for (let i = n - 1; i >= 0; i--) {
+      this.byte(Number((v >> BigInt(8 * i)) & 0xffn));
+    }
+  }
+
+  private int(v: number | bigint, min: bigint, max: bigint): bigint {
+    if (typeof v === "number" && !Number.isInteger(v)) {
+      throw new Error("not an integer: " + v);
+    }
+    const i = BigInt(v);
+    if (i < min || i > max) {
+      throw new Error("integer " + i + " out of range");
+    }
+    return i;
+  }
+
+  private count(n: number): void {
+    this.varuint(BigInt(n), false);
+  }
+
+  bool(v: boolean): void {
+    this.byte(v ? 1 : 0);
+  }
+
+  i8(v: number): void {
+    this.byte(Number(this.int(v, -0x80n, 0x7fn)) & 0xff);
+  }
+
+  i16(v: number): void {
+    this.varuint(zigzag(this.int(v, -0x8000n, 0x7fffn)), false);
+  }
+
+  i32(v: number): void {
+    this.varuint(zigzag(this.int(v, -0x80000000n, 0x7fffffffn)), false);
+  }
+
+  i64(v: bigint): void {
+    this.varuint(zigzag(this.int(v, -(1n << 63n), (1n << 63n) - 1n)), false);
+  }
+
+  i128(v: bigint): void {
+    this.varuint(zigzag(this.int(v, -(1n << 127n), (1n << 127n) - 1n)), true);
+  }
+
+  u8(v: number): void {
+    this.byte(Number(this.int(v, 0n, 0xffn)));
+  }
+
+  u16(v: number): void {
+    this.varuint(this.int(v, 0n, 0xffffn), false);
+  }
+
+  u32(v: number): void {
+    this.varuint(this.int(v, 0n, 0xffffffffn), false);
+  }
+
+  u64(v: bigint): void {
+    this.varuint(this.int(v, 0n, (1n << 64n) - 1n), false);
+  }
+
+  u128(v: bigint): void {
+    this.varuint(this.int(v, 0n, (1n << 128n) - 1n), true);
+  }
+
+  f32(v: number): void {
+    const start = this.reserve(4);
+    new DataView(this.buf.buffer).setFloat32(start, v, true);
+  }
+
+  f64(v: number): void {
+    const start = this.reserve(8);
+    new DataView(this.buf.buffer).setFloat64(start, v, true);
+  }
+
+  char(v: string): void {
+    const c = v.codePointAt(0);
+    if (c === undefined || v.length !== (c > 0xffff ? 2 : 1)) {
+      throw new Error("not a single character: " + v);
+    }
+    const start = this.reserve(3);
+    this.buf[start] = c & 0xff;
+    this.buf[start + 1] = (c >> 8) & 0xff;
+    this.buf[start + 2] = c >> 16;
+  }
+
+  str(v: string): void {
+    this.blob(new TextEncoder().encode(v));
+  }
+
+  bytes(v: Uint8Array): void {
+    this.blob(v);
+  }
+
+  private blob(v: Uint8Array): void {
+    this.count(v.length);
+    const start = this.reserve(v.length);
+    this.buf.set(v, start);
+  }
+
+  option<T>(v: T | null, f: (v: T) => void): void {
+    if (v === null) {
+      this.byte(0);
+    } else {
+      this.byte(1);
+      f(v);
+    }
+  }
+
+  seq<T>(vs: Array<T>, f: (v: T) => void): void {
+    this.count(vs.length);
+    for (const v of vs) {
+      f(v);
+    }
+  }
+
+  map<K, V>(m: Map<K, V>, k: (k: K) => void, v: (v: V) => void): void {
+    this.count(m.size);
+    for (const [key, value] of m) {
+      k(key);
+      v(value);
+    }
+  }
+
+  variant(i: number): void {
+    this.count(i);
+  }
+}
+
+function zigzag(v: bigint): bigint {
+  return v < 0n ? ((-v - 1n) << 1n) | 1n : v << 1n;
+}
+
+function unzigzag(v: bigint): bigint {
+  return (v & 1n) === 0n ? v >> 1n : -(v >> 1n) - 1n;
+}
+"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::Deserialize;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Order {
+        id: u64,
+        #[serde(rename = "item-names")]
+        items: Vec<String>,
+        note: Option<String>,
+        status: Status,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Status {
+        Open,
+        Paid(u32),
+        Shipped { carrier: String },
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Tree {
+        children: Vec<Tree>,
+    }
+
+    #[test]
+    fn struct_and_enum() {
+        let ts = typescript::<Order>().unwrap();
+
+        assert!(ts.contains(
+            "export interface Order {\n  id: bigint;\n  \"item-names\": Array<string>;\n  note: (string) | null;\n  status: Status;\n}\n"
+        ));
+        assert!(ts.contains(
+            "  return { id: r.u64(), \"item-names\": r.seq(() => r.str()), note: r.option(() => r.str()), status: decodeStatus(r) };"
+        ));
+        assert!(ts.contains(
+            "w.u64(v.id); w.seq(v[\"item-names\"], (x0) => { w.str(x0); }); w.option(v.note, (x0) => { w.str(x0); }); encodeStatus(w, v.status);"
+        ));
+        assert!(ts.contains(
+            "export type Status =\n  | { tag: \"Open\" }\n  | { tag: \"Paid\"; value: number }\n  | { tag: \"Shipped\"; value: { carrier: string } };\n"
+        ));
+        assert!(ts.contains("      return { tag: \"Shipped\", value: { carrier: r.str() } };"));
+        assert!(ts.contains("export function decode(bytes: Uint8Array): Order {"));
+    }
+
+    #[test]
+    fn recursive() {
+        let ts = typescript::<Tree>().unwrap();
+        assert!(ts.contains("  return { children: r.seq(() => decodeTree(r)) };"));
+        assert!(ts.contains("w.seq(v.children, (x0) => { encodeTree(w, x0); });"));
+    }
+
+    #[test]
+    fn nested_callbacks() {
+        let ts = typescript::<Vec<Option<(u8, ())>>>().unwrap();
+        assert!(ts.contains(
+            "export function decode(bytes: Uint8Array): Array<([number, null]) | null> {"
+        ));
+        assert!(ts.contains("w.seq(v, (x0) => { w.option(x0, (x1) => { w.u8(x1[0]); }); });"));
+    }
+}
@@ -0,0 +1,493 @@
+//! Checking payloads are well-formed without decoding them.
+//!
+//! A [`Validator`](struct.Validator.html) checks lengths, varuints, UTF-8 strings,
+//! option tags, variant indexes and integer ranges the same as the deserializer does,
+//! but it constructs no values and allocates nothing while validating.
+//! Use this to reject bad payloads cheaply, such as in gateways before enqueueing them.
+//!
+//! The structure of the type is traced through its `Deserialize` implementation once
+//! when the `Validator` is created, so reuse it for many payloads.
+//! Checks done inside `Deserialize` implementations, such as ones of `NonZeroU32`,
+//! are not run.
+//!
+//! Payloads are checked in the default format of [`Serializer::new`](../ser/struct.Serializer.html#method.new)
+//! only. Payloads written with other [`Options`](../options/struct.Options.html), such as
+//! fixed-width integers or struct maps, are not supported.
+//! Nesting is limited as in the deserializer, to [`DEFAULT_MAX_DEPTH`](../de/constant.DEFAULT_MAX_DEPTH.html)
+//! levels by default, and lengths can be limited by [`Limits`](../de/struct.Limits.html)
+//! as in the deserializer.
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//! use serde_dokechi::validate::Validator;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Event {
+//!     id: u32,
+//!     name: String,
+//! }
+//!
+//! let validator = Validator::new::<Event>().unwrap();
+//!
+//! let payload = serde_dokechi::to_vec(&Event { id: 1, name: "boot".to_owned() }).unwrap();
+//! validator.validate_slice(&payload).unwrap();
+//! validator.validate_slice(&payload[..3]).unwrap_err();
+//! ```
+
+use std::convert::TryFrom;
+
+use serde::de::{DeserializeOwned, Error as _, Unexpected};
+
+use crate::de::{Error, Limits, DEFAULT_MAX_DEPTH};
+use crate::error::Offset;
+use crate::io::Input;
+use crate::schema::{trace, Schema, Shape, Variant};
+use crate::varuint::{decode_u128, decode_u16, decode_u32, decode_u64};
+
+/// Check a value of type `T` in IO stream is well-formed.
+///
+/// This traces `T` each time. Use [`Validator`](struct.Validator.html) for many payloads.
+pub fn validate<R: Input, T: DeserializeOwned>(r: R) -> Result<(), Error> {
+    Validator::new::<T>()?.validate(r)
+}
+
+/// A validator of payloads of a type.
+#[derive(Debug, Clone)]
+pub struct Validator {
+    schema: Schema,
+    max_depth: usize,
+    limits: Limits,
+}
+
+impl Validator {
+    /// Create new `Validator` of `T`.
+    ///
+    /// Types implementing `Deserialize` by `deserialize_any`, such as `serde(untagged)` enums,
+    /// are not supported, as Dokechi format doesn't support them.
+    pub fn new<T: DeserializeOwned>() -> Result<Validator, Error> {
+        Ok(Validator {
+            schema: trace::<T>()?,
+            max_depth: DEFAULT_MAX_DEPTH,
+            limits: Limits::default(),
+        })
+    }
+
+    /// Set the limit of nesting of values.
+    ///
+    /// See [`Deserializer::max_depth`](../de/struct.Deserializer.html#method.max_depth).
+    pub fn max_depth(mut self, max_depth: usize) -> Validator {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Set the limits of lengths.
+    ///
+    /// Lengths are checked and charged against the allocation budget as the deserializer does,
+    /// though the validator allocates nothing.
+    /// See [`Deserializer::limits`](../de/struct.Deserializer.html#method.limits).
+    pub fn limits(mut self, limits: Limits) -> Validator {
+        self.limits = limits;
+        self
+    }
+
+    /// Check a value in IO stream is well-formed.
+    ///
+    /// Bytes after the value are left unread.
+    pub fn validate<R: Input>(&self, mut r: R) -> Result<(), Error> {
+        Walk {
+            schema: &self.schema,
+            r: &mut r,
+            depth: 0,
+            max_depth: self.max_depth,
+            limits: &self.limits,
+            allocated: 0,
+        }
+        .shape(&self.schema.root)
+    }
+
+    /// Check bytes are a well-formed value, without trailing bytes.
+    pub fn validate_slice(&self, bs: &[u8]) -> Result<(), Error> {
+        let mut r = bs;
+        self.validate(&mut r)?;
+        if !r.is_empty() {
+            return Err(Error::invalid_length(r.len(), &"no trailing bytes"));
+        }
+        Ok(())
+    }
+}
+
+struct Walk<'a, R: Input> {
+    schema: &'a Schema,
+    r: &'a mut R,
+    depth: usize,
+    max_depth: usize,
+    limits: &'a Limits,
+    allocated: usize,
+}
+
+impl<'a, R: Input> Walk<'a, R> {
+    /// Run `f` one level deeper, at the same levels as the deserializer.
+    fn nest<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Walk<'a, R>) -> Result<(), Error>,
+    {
+        if self.depth >= self.max_depth {
+            return Err(Error::DepthLimitExceeded(self.max_depth, Offset::UNKNOWN));
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn shape(&mut self, shape: &Shape) -> Result<(), Error> {
+        match shape {
+            Shape::Bool => {
+                self.tag("0 or 1")?;
+                Ok(())
+            }
+            Shape::I8 | Shape::U8 => self.skip(1),
+            Shape::I16 | Shape::U16 => {
+                let v = decode_u16(self.r)?;
+                self.check_range(v, u16::max_value() as u64, shape)
+            }
+            Shape::I32 | Shape::U32 => {
+                let v = decode_u32(self.r)?;
+                self.check_range(v, u32::max_value() as u64, shape)
+            }
+            Shape::I64 | Shape::U64 => {
+                decode_u64(self.r)?;
+                Ok(())
+            }
+            Shape::I128 | Shape::U128 => {
+                decode_u128(self.r)?;
+                Ok(())
+            }
+            Shape::F32 => self.skip(4),
+            Shape::F64 => self.skip(8),
+            Shape::Char => {
+                let mut bs = [0u8; 4];
+                self.r.read_exact(&mut bs[..3])?;
+                let v = u32::from_le_bytes(bs);
+                if std::char::from_u32(v).is_none() {
+                    return Err(Error::invalid_value(
+                        Unexpected::Unsigned(v as u64),
+                        &"Unicode codepoint",
+                    ));
+                }
+                Ok(())
+            }
+            Shape::Str => self.str(),
+            Shape::Bytes => {
+                let len = self.len(self.limits.max_bytes_len)?;
+                self.skip(len)
+            }
+            Shape::Unit | Shape::UnitStruct(_) => Ok(()),
+            Shape::Option(inner) => {
+                if self.tag("None (0) or Some (1)")? {
+                    self.nest(|w| w.shape(inner))?;
+                }
+                Ok(())
+            }
+            Shape::Seq(element) => {
+                let len = self.len(self.limits.max_seq_len)?;
+                self.nest(|w| {
+                    if !element.is_empty() {
+                        for _ in 0..len {
+                            w.shape(element)?;
+                        }
+                    }
+                    Ok(())
+                })
+            }
+            Shape::Map(key, value) => {
+                let len = self.len(self.limits.max_seq_len)?;
+                self.nest(|w| {
+                    if !key.is_empty() || !value.is_empty() {
+                        for _ in 0..len {
+                            w.shape(key)?;
+                            w.shape(value)?;
+                        }
+                    }
+                    Ok(())
+                })
+            }
+            Shape::Tuple(shapes) | Shape::TupleStruct(_, shapes) => self.nest(|w| w.shapes(shapes)),
+            Shape::Newtype(_, inner) => self.nest(|w| w.shape(inner)),
+            Shape::Struct(_, fields) => self.nest(|w| w.fields(fields)),
+            Shape::Enum(name) => self.nest(|w| {
+                let variants = w.schema.enums.get(name).ok_or_else(|| missing(name))?;
+                let index = decode_u64(w.r)?;
+                let (_, variant) = usize::try_from(index)
+                    .ok()
+                    .and_then(|i| variants.get(i))
+                    .ok_or_else(|| {
+                        Error::invalid_value(Unexpected::Unsigned(index), &"variant index")
+                    })?;
+                match variant {
+                    Variant::Unit => Ok(()),
+                    Variant::Newtype(inner) => w.shape(inner),
+                    Variant::Tuple(shapes) => w.nest(|w| w.shapes(shapes)),
+                    Variant::Struct(fields) => w.nest(|w| w.fields(fields)),
+                }
+            }),
+            Shape::Recursive(name) => {
+                let shape = self.schema.named.get(name).ok_or_else(|| missing(name))?;
+                self.shape(shape)
+            }
+        }
+    }
+
+    fn shapes(&mut self, shapes: &[Shape]) -> Result<(), Error> {
+        for shape in shapes {
+            self.shape(shape)?;
+        }
+        Ok(())
+    }
+
+    fn fields(&mut self, fields: &[(&str, Shape)]) -> Result<(), Error> {
+        for (_, shape) in fields {
+            self.shape(shape)?;
+        }
+        Ok(())
+    }
+
+    /// Read a byte of 0 or 1.
+    fn tag(&mut self, expected: &'static str) -> Result<bool, Error> {
+        let mut bs = [0u8];
+        self.r.read_exact(&mut bs)?;
+        match bs[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(Error::invalid_value(
+                Unexpected::Unsigned(v as u64),
+                &expected,
+            )),
+        }
+    }
+
+    fn check_range(&self, v: u64, max: u64, shape: &Shape) -> Result<(), Error> {
+        if v <= max {
+            return Ok(());
+        }
+        let target = match shape {
+            Shape::I16 => "i16",
+            Shape::U16 => "u16",
+            Shape::I32 => "i32",
+            _ => "u32",
+        };
+        Err(Error::invalid_value(Unexpected::Unsigned(v), &target))
+    }
+
+    /// Read a length, checking it against `max` and the allocation budget as the deserializer does.
+    fn len(&mut self, max: usize) -> Result<u64, Error> {
+        let len = decode_u64(self.r)?;
+        let len = self.limits.charge_len(len, max, &mut self.allocated)?;
+        Ok(len as u64)
+    }
+
+    fn skip(&mut self, mut len: u64) -> Result<(), Error> {
+        let mut buf = [0u8; 256];
+        while len > 0 {
+            let n = len.min(buf.len() as u64) as usize;
+            self.r.read_exact(&mut buf[..n])?;
+            len -= n as u64;
+        }
+        Ok(())
+    }
+
+    /// Check a string is valid UTF-8 in chunks.
+    fn str(&mut self) -> Result<(), Error> {
+        let mut len = self.len(self.limits.max_string_len)?;
+        let mut buf = [0u8; 256];
+        // Bytes of an incomplete character at the end of the previous chunk.
+        let mut carry = 0;
+        while len > 0 {
+            let n = len.min((buf.len() - carry) as u64) as usize;
+            self.r.read_exact(&mut buf[carry..carry + n])?;
+            len -= n as u64;
+
+            let end = carry + n;
+            carry = match std::str::from_utf8(&buf[..end]) {
+                Ok(_) => 0,
+                Err(e) if e.error_len().is_none() => {
+                    let valid = e.valid_up_to();
+                    buf.copy_within(valid..end, 0);
+                    end - valid
+                }
+                Err(_) => return Err(invalid_utf8()),
+            };
+        }
+        if carry > 0 {
+            return Err(invalid_utf8());
+        }
+        Ok(())
+    }
+}
+
+fn invalid_utf8() -> Error {
+    Error::custom("invalid UTF-8 sequence")
+}
+
+fn missing(name: &str) -> Error {
+    Error::custom(format_args!("{} is not in the schema", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    use crate::generator::Generator;
+    use crate::to_vec;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Shape {
+        Circle { r: f64 },
+        Polygon(Vec<(i32, i32)>),
+        Group(Vec<Shape>, Option<Box<Shape>>),
+        Empty,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Drawing {
+        title: String,
+        layers: BTreeMap<u16, Shape>,
+        tags: Vec<char>,
+        flags: (bool, u8, u128),
+    }
+
+    #[test]
+    fn valid_payloads() {
+        let validator = Validator::new::<Drawing>().unwrap();
+        let mut gen = Generator::new(11);
+        for _ in 0..200 {
+            let bs = gen.payload::<Drawing>().unwrap();
+            validator.validate_slice(&bs).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_payloads() {
+        let validator = Validator::new::<(bool, String, Option<u16>)>().unwrap();
+        validator
+            .validate_slice(&to_vec((true, "ab", Some(3u16))).unwrap())
+            .unwrap();
+
+        let cases: &[&[u8]] = &[
+            &[2, 0, 0],
+            &[1, 2, 0xC3, 0x28, 0],
+            &[1, 0, 2],
+            &[1, 0, 1],
+            &[1, 1, b'a', 0, 0],
+            &[1, 1, 0xE3],
+        ];
+        for &bs in cases {
+            validator.validate_slice(bs).unwrap_err();
+        }
+
+        let too_large = to_vec((false, "", Some(70000u32))).unwrap();
+        validator.validate_slice(&too_large).unwrap_err();
+    }
+
+    #[test]
+    fn agrees_with_deserializer() {
+        let validator = Validator::new::<Drawing>().unwrap();
+        let mut gen = Generator::new(12);
+        for _ in 0..50 {
+            let mut bs = gen.payload::<Drawing>().unwrap();
+            // Corrupt a byte and check both reject or both accept.
+            let i = bs.len() / 2;
+            bs[i] = bs[i].wrapping_add(0x41);
+
+            let decoded = crate::from_slice::<Drawing>(&bs);
+            let valid = validator.validate(&bs[..]);
+            assert_eq!(decoded.is_ok(), valid.is_ok(), "{:?}", bs);
+        }
+    }
+
+    #[test]
+    fn utf8_across_chunks() {
+        let s = "あいうえお".repeat(100);
+        let validator = Validator::new::<String>().unwrap();
+        validator.validate_slice(&to_vec(&s).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn depth_limit() {
+        #[derive(Debug, Deserialize)]
+        struct Node(Option<Box<Node>>);
+
+        // A node is two levels deep, of the newtype and of `Some`.
+        let mut bs = vec![1u8; 63];
+        bs.push(0);
+        let validator = Validator::new::<Node>().unwrap();
+        validator.validate_slice(&bs).unwrap();
+        crate::from_slice::<Node>(&bs).unwrap();
+
+        bs.insert(0, 1);
+        match validator.validate_slice(&bs) {
+            Err(Error::DepthLimitExceeded(DEFAULT_MAX_DEPTH, _)) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+        let e = crate::from_slice::<Node>(&bs).unwrap_err();
+        assert_eq!(e.kind(), crate::ErrorKind::DepthLimitExceeded);
+        validator.max_depth(256).validate_slice(&bs).unwrap();
+    }
+
+    #[test]
+    fn limits() {
+        let v = vec!["abc".to_owned(); 10];
+        let bs = to_vec(&v).unwrap();
+        let validator = Validator::new::<Vec<String>>().unwrap();
+
+        let ok = [
+            Limits::default(),
+            Limits::default().max_seq_len(10).max_string_len(3),
+            Limits::default().alloc_budget(40),
+        ];
+        for limits in ok.iter() {
+            validator
+                .clone()
+                .limits(*limits)
+                .validate_slice(&bs)
+                .unwrap();
+        }
+
+        let ng = [
+            Limits::default().max_seq_len(9),
+            Limits::default().max_string_len(2),
+            Limits::default().alloc_budget(39),
+        ];
+        for limits in ng.iter() {
+            let res = validator.clone().limits(*limits).validate_slice(&bs);
+            let mut de = crate::de::Deserializer::new(&bs[..]).limits(*limits);
+            let decoded = <Vec<String> as serde::Deserialize>::deserialize(&mut de);
+            assert_eq!(
+                res.unwrap_err().kind(),
+                decoded.unwrap_err().kind(),
+                "{:?}",
+                limits
+            );
+        }
+
+        // Lengths of zero-sized elements are checked too.
+        let mut bs = Vec::new();
+        crate::varuint::encode_u64(&mut bs, u64::max_value()).unwrap();
+        let validator = Validator::new::<Vec<()>>().unwrap();
+        let _ = validator
+            .limits(Limits::untrusted())
+            .validate_slice(&bs)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn zero_sized_elements() {
+        let mut bs = Vec::new();
+        crate::varuint::encode_u64(&mut bs, u64::max_value()).unwrap();
+        validate::<_, Vec<()>>(&bs[..]).unwrap();
+    }
+}
@@ -1,24 +1,49 @@
-use std::io::{self, Read, Write};
-
-/*
-    Variable length unsigined integer format
-
-    0, 1 : specified bit
-    x    : value bit
-    X    : Value byte (= xxxxxxxx)
-
-    0xxxxxxx          :  7bit (0 ~ 127)
-    10xxxxxx X        : 14bit (128 ~ 16383)
-    110xxxxx XX       : 21bit (16384 ~ 2097151)
-    1110xxxx XXX      : 28bit (2097152 ~ 268435455)
-    11110xxx XXXX     : 35bit (268435456 ~ 34359738367)
-    111110xx XXXXX    : 42bit (34359738368 ~ 4398046511103)
-    1111110x XXXXXX   : 49bit (4398046511104 ~ 562949953421311)
-    11111110 XXXXXXX  : 56bit (562949953421312 ~ 72057594037927935)
-    11111111 XXXXXXXX : 64bit (72057594037927936 ~ 18446744073709551615)
-*/
-
-pub fn encode_u64(mut w: impl Write, v: u64) -> io::Result<()> {
+//! Variable length unsigned integers, the encoding of integers and lengths of Dokechi format.
+//!
+//! The number of leading 1 bits of the first byte is the number of bytes following it.
+//! The value is in the remaining bits, in big-endian.
+//!
+//! ```text
+//! 0, 1 : specified bit
+//! x    : value bit
+//! X    : Value byte (= xxxxxxxx)
+//!
+//! 0xxxxxxx          :  7bit (0 ~ 127)
+//! 10xxxxxx X        : 14bit (128 ~ 16383)
+//! 110xxxxx XX       : 21bit (16384 ~ 2097151)
+//! 1110xxxx XXX      : 28bit (2097152 ~ 268435455)
+//! 11110xxx XXXX     : 35bit (268435456 ~ 34359738367)
+//! 111110xx XXXXX    : 42bit (34359738368 ~ 4398046511103)
+//! 1111110x XXXXXX   : 49bit (4398046511104 ~ 562949953421311)
+//! 11111110 XXXXXXX  : 56bit (562949953421312 ~ 72057594037927935)
+//! 11111111 XXXXXXXX : 64bit (72057594037927936 ~ 18446744073709551615)
+//! ```
+//!
+//! `u128` values are the same up to 56 bits, and larger ones are `11111111` followed by 16 bytes.
+//!
+//! Encoders write the shortest form. Decoders also accept longer forms of the same value.
+//!
+//! This format is stable: it doesn't change in any future version of this crate,
+//! so other crates can read and write the same bytes with these functions.
+//!
+//! ```
+//! use serde_dokechi::varuint::{decode_u64, encode_u64, encoded_len_u64};
+//!
+//! let mut bs = Vec::new();
+//! encode_u64(&mut bs, 300).unwrap();
+//! assert_eq!(bs, vec![0x81, 0x2C]);
+//! assert_eq!(encoded_len_u64(300), 2);
+//!
+//! let mut r = bs.as_slice();
+//! assert_eq!(decode_u64(&mut r).unwrap(), 300);
+//! ```
+
+use std::io;
+
+use crate::io::{Input, Output};
+
+/// Write `v` into `w`.
+pub fn encode_u64<W: Output + ?Sized>(w: &mut W, v: u64) -> io::Result<()> {
     let bs = v.to_be_bytes();
 
     match 64 - v.leading_zeros() {
@@ -61,12 +86,35 @@ pub fn encode_u64(mut w: impl Write, v: u64) -> io::Result<()> {
     Ok(())
 }
 
-pub fn decode_u64(mut r: impl Read) -> io::Result<u64> {
-    let mut head = [0u8];
-    let mut bs = [0u8; 8];
+/// The number of bytes `v` is encoded into.
+pub fn encoded_len_u64(v: u64) -> usize {
+    match 64 - v.leading_zeros() {
+        0 => 1,
+        x if x <= 56 => ((x + 6) / 7) as usize,
+        _ => 9,
+    }
+}
 
+/// The number of bytes `v` is encoded into by `encode_u128`.
+pub(crate) fn encoded_len_u128(v: u128) -> usize {
+    if v >> 56 == 0 {
+        encoded_len_u64(v as u64)
+    } else {
+        17
+    }
+}
+
+/// Read a value from `r`.
+///
+/// Returns error of `UnexpectedEof` kind if `r` ends in the middle of the value.
+pub fn decode_u64<R: Input + ?Sized>(r: &mut R) -> io::Result<u64> {
+    let mut head = [0u8];
     r.read_exact(&mut head)?;
-    let h = head[0];
+    decode_u64_tail(r, head[0])
+}
+
+fn decode_u64_tail<R: Input + ?Sized>(r: &mut R, h: u8) -> io::Result<u64> {
+    let mut bs = [0u8; 8];
 
     match h {
         x if x <= 0b0111_1111 => {
@@ -110,7 +158,74 @@ pub fn decode_u64(mut r: impl Read) -> io::Result<u64> {
     Ok(u64::from_be_bytes(bs))
 }
 
-pub fn encode_u128(mut w: impl Write, v: u128) -> io::Result<()> {
+// Encoding of 16 or 32 bit values in a single write, same as `encode_u64`.
+
+pub(crate) fn encode_u16<W: Output + ?Sized>(w: &mut W, v: u16) -> io::Result<()> {
+    let bs = v.to_be_bytes();
+
+    match 16 - v.leading_zeros() {
+        x if x <= 7 => w.write_all(&[bs[1]]),
+        x if x <= 14 => w.write_all(&[0b1000_0000 | bs[0], bs[1]]),
+        _ => w.write_all(&[0b1100_0000, bs[0], bs[1]]),
+    }
+}
+
+pub(crate) fn encode_u32<W: Output + ?Sized>(w: &mut W, v: u32) -> io::Result<()> {
+    let bs = v.to_be_bytes();
+
+    match 32 - v.leading_zeros() {
+        x if x <= 7 => w.write_all(&[bs[3]]),
+        x if x <= 14 => w.write_all(&[0b1000_0000 | bs[2], bs[3]]),
+        x if x <= 21 => w.write_all(&[0b1100_0000 | bs[1], bs[2], bs[3]]),
+        x if x <= 28 => w.write_all(&[0b1110_0000 | bs[0], bs[1], bs[2], bs[3]]),
+        _ => w.write_all(&[0b1111_0000, bs[0], bs[1], bs[2], bs[3]]),
+    }
+}
+
+// Decoding of values expected to be 16 or 32 bit.
+// Up to 3 or 5 bytes are decoded directly, and longer ones fall back to `decode_u64`,
+// so the caller can still report or narrow too large values.
+
+pub(crate) fn decode_u16<R: Input + ?Sized>(r: &mut R) -> io::Result<u64> {
+    let mut head = [0u8];
+    r.read_exact(&mut head)?;
+    let h = head[0];
+
+    let mut bs = [0u8; 2];
+    match h {
+        x if x <= 0b0111_1111 => Ok(h as u64),
+        x if x <= 0b1011_1111 => {
+            r.read_exact(&mut bs[..1])?;
+            Ok(((0b0011_1111 & h) as u64) << 8 | bs[0] as u64)
+        }
+        x if x <= 0b1101_1111 => {
+            r.read_exact(&mut bs)?;
+            Ok(((0b0001_1111 & h) as u64) << 16 | u16::from_be_bytes(bs) as u64)
+        }
+        _ => decode_u64_tail(r, h),
+    }
+}
+
+pub(crate) fn decode_u32<R: Input + ?Sized>(r: &mut R) -> io::Result<u64> {
+    let mut head = [0u8];
+    r.read_exact(&mut head)?;
+    let h = head[0];
+
+    let mut bs = [0u8; 4];
+    let (mask, len) = match h {
+        x if x <= 0b0111_1111 => return Ok(h as u64),
+        x if x <= 0b1011_1111 => (0b0011_1111, 1),
+        x if x <= 0b1101_1111 => (0b0001_1111, 2),
+        x if x <= 0b1110_1111 => (0b0000_1111, 3),
+        x if x <= 0b1111_0111 => (0b0000_0111, 4),
+        _ => return decode_u64_tail(r, h),
+    };
+    r.read_exact(&mut bs[4 - len..])?;
+    Ok(((mask & h) as u64) << (8 * len) | u32::from_be_bytes(bs) as u64)
+}
+
+/// Write a 128-bit `v` into `w`.
+pub fn encode_u128<W: Output + ?Sized>(w: &mut W, v: u128) -> io::Result<()> {
     let bs = v.to_be_bytes();
 
     match 128 - v.leading_zeros() {
@@ -153,7 +268,10 @@ pub fn encode_u128(mut w: impl Write, v: u128) -> io::Result<()> {
     Ok(())
 }
 
-pub fn decode_u128(mut r: impl Read) -> io::Result<u128> {
+/// Read a 128-bit value from `r`.
+///
+/// Returns error of `UnexpectedEof` kind if `r` ends in the middle of the value.
+pub fn decode_u128<R: Input + ?Sized>(r: &mut R) -> io::Result<u128> {
     let mut head = [0u8];
     let mut bs = [0u8; 8];
 
@@ -249,6 +367,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_encoded_len_u64() {
+        for &v in &[
+            0,
+            1,
+            127,
+            128,
+            16383,
+            16384,
+            2097151,
+            2097152,
+            268435455,
+            268435456,
+            34359738367,
+            34359738368,
+            4398046511103,
+            4398046511104,
+            562949953421311,
+            562949953421312,
+            72057594037927935,
+            72057594037927936,
+            18446744073709551615,
+        ] {
+            assert_eq!(encoded_len_u64(v), run_encode_u64(v).len(), "for {}", v);
+        }
+    }
+
     fn run_encode_u64(v: u64) -> Vec<u8> {
         let mut buf = Vec::new();
         encode_u64(&mut buf, v).unwrap();
@@ -282,7 +427,7 @@ mod test {
         eprintln!("for {}", to_be);
         let mut buf = Vec::new();
         encode_u64(&mut buf, to_be).expect("encode error");
-        let actual = decode_u64(buf.as_slice()).expect("decode error");
+        let actual = decode_u64(&mut buf.as_slice()).expect("decode error");
         assert_eq!(actual, to_be);
     }
 
@@ -311,11 +456,73 @@ mod test {
         decode_test_for_u128(u128::max_value());
     }
 
+    #[test]
+    fn test_encode_decode_u16() {
+        for &v in &[0u16, 1, 127, 128, 16383, 16384, u16::max_value()] {
+            let mut bs = Vec::new();
+            encode_u16(&mut bs, v).unwrap();
+            assert_eq!(bs, run_encode_u64(v as u64), "value = {}", v);
+            assert_eq!(decode_u16(&mut bs.as_slice()).unwrap(), v as u64);
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_u32() {
+        let values = [
+            0u32,
+            127,
+            128,
+            16383,
+            16384,
+            2097151,
+            2097152,
+            268435455,
+            268435456,
+            u32::max_value(),
+        ];
+        for &v in &values {
+            let mut bs = Vec::new();
+            encode_u32(&mut bs, v).unwrap();
+            assert_eq!(bs, run_encode_u64(v as u64), "value = {}", v);
+            assert_eq!(decode_u32(&mut bs.as_slice()).unwrap(), v as u64);
+        }
+    }
+
+    #[test]
+    fn test_decode_u16_u32_too_large() {
+        for &v in &[1u64 << 16, 1 << 21, 1 << 32, 1 << 35, u64::max_value()] {
+            let bs = run_encode_u64(v);
+            assert_eq!(decode_u16(&mut bs.as_slice()).unwrap(), v);
+            assert_eq!(decode_u32(&mut bs.as_slice()).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn test_decode_longer_forms() {
+        let forms: &[&[u8]] = &[
+            &[5],
+            &[0x80, 5],
+            &[0xC0, 0, 5],
+            &[0xFE, 0, 0, 0, 0, 0, 0, 5],
+            &[0xFF, 0, 0, 0, 0, 0, 0, 0, 5],
+        ];
+        for bs in forms {
+            assert_eq!(decode_u64(&mut &bs[..]).unwrap(), 5);
+        }
+        // The 64-bit form differs in `u128`.
+        for bs in &forms[..4] {
+            assert_eq!(decode_u128(&mut &bs[..]).unwrap(), 5);
+        }
+
+        let e = decode_u64(&mut &[0xC0, 0][..]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
     fn decode_test_for_u128(to_be: u128) {
         eprintln!("for {}", to_be);
         let mut buf = Vec::new();
         encode_u128(&mut buf, to_be).expect("encode error");
-        let actual = decode_u128(buf.as_slice()).expect("decode error");
+        let actual = decode_u128(&mut buf.as_slice()).expect("decode error");
         assert_eq!(actual, to_be);
     }
 }
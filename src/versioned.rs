@@ -0,0 +1,267 @@
+//! Versioned payloads upgraded to the current type on decoding.
+//!
+//! Each version of a type is declared with [`versioned!`](../macro.versioned.html),
+//! or with `#[derive(Versioned)]` and `#[dokechi(version = N, from = Previous)]` with the `derive` feature,
+//! naming the previous version, which converts into it by `From`.
+//! [`from_reader_versioned`](fn.from_reader_versioned.html) reads the version header,
+//! decodes the payload as the type of that version, and upgrades it step by step.
+//!
+//! Payload format:
+//!
+//! ```text
+//! version (varuint) , value
+//! ```
+//!
+//! ```
+//! use serde_derive::{Deserialize, Serialize};
+//! use serde_dokechi::versioned;
+//! use serde_dokechi::versioned::{from_reader_versioned, to_writer_versioned};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct UserV1 {
+//!     name: String,
+//! }
+//!
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct User {
+//!     name: String,
+//!     age: Option<u8>,
+//! }
+//!
+//! impl From<UserV1> for User {
+//!     fn from(v: UserV1) -> User {
+//!         User { name: v.name, age: None }
+//!     }
+//! }
+//!
+//! versioned!(UserV1 = 1);
+//! versioned!(User = 2, from UserV1);
+//!
+//! let mut bs = Vec::new();
+//! to_writer_versioned(&mut bs, &UserV1 { name: "alice".to_owned() }).unwrap();
+//!
+//! let user: User = from_reader_versioned(bs.as_slice()).unwrap();
+//! assert_eq!(user, User { name: "alice".to_owned(), age: None });
+//! ```
+
+use std::io::{Read, Write};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Serialize;
+
+use crate::de::Error as DeError;
+use crate::ser::to_writer;
+use crate::varuint::{decode_u32, encode_u64};
+use crate::Error;
+
+/// A type with a version, which can decode payloads of older versions.
+///
+/// Implement this with [`versioned!`](../macro.versioned.html).
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// The version of this type.
+    const VERSION: u32;
+
+    /// Decode a payload of `version`, and upgrade it to this type.
+    fn decode_version<R: Read>(version: u32, r: R) -> Result<Self, DeError>;
+}
+
+/// Implement [`Versioned`](versioned/trait.Versioned.html) for a version of a type.
+///
+/// `versioned!(Type = N)` declares the first version, and
+/// `versioned!(Type = N, from Previous)` declares a version upgraded from `Previous` by `From`.
+/// Versions must increase along the chain.
+#[macro_export]
+macro_rules! versioned {
+    ($t:ty = $version:expr) => {
+        impl $crate::versioned::Versioned for $t {
+            const VERSION: u32 = $version;
+
+            fn decode_version<R: std::io::Read>(
+                version: u32,
+                r: R,
+            ) -> Result<Self, $crate::de::Error> {
+                if version == $version {
+                    $crate::from_reader(r)
+                } else {
+                    Err($crate::versioned::unknown_version(version, $version))
+                }
+            }
+        }
+    };
+    ($t:ty = $version:expr, from $prev:ty) => {
+        impl $crate::versioned::Versioned for $t {
+            const VERSION: u32 = $version;
+
+            fn decode_version<R: std::io::Read>(
+                version: u32,
+                r: R,
+            ) -> Result<Self, $crate::de::Error> {
+                if version == $version {
+                    $crate::from_reader(r)
+                } else if version < $version {
+                    <$prev as $crate::versioned::Versioned>::decode_version(version, r)
+                        .map(<$t>::from)
+                } else {
+                    Err($crate::versioned::unknown_version(version, $version))
+                }
+            }
+        }
+    };
+}
+
+/// Serialize `value` prefixed with its version.
+pub fn to_writer_versioned<W: Write, T: Versioned>(mut w: W, value: &T) -> Result<(), Error> {
+    encode_u64(&mut w, T::VERSION as u64)?;
+    to_writer(w, value)?;
+    Ok(())
+}
+
+/// Deserialize a payload of the version of `T` or an older one, and upgrade it to `T`.
+pub fn from_reader_versioned<R: Read, T: Versioned>(mut r: R) -> Result<T, Error> {
+    let version = decode_u32(&mut r)?;
+    if version > u32::max_value() as u64 {
+        return Err(Error::custom(format_args!(
+            "version {} is too large",
+            version
+        )));
+    }
+    Ok(T::decode_version(version as u32, r)?)
+}
+
+#[doc(hidden)]
+pub fn unknown_version(version: u32, current: u32) -> DeError {
+    if version > current {
+        DeError::custom(format_args!(
+            "version {} is newer than the supported version {}",
+            version, current
+        ))
+    } else {
+        DeError::custom(format_args!("version {} is unknown", version))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct PointV1 {
+        x: i32,
+        y: i32,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct PointV3 {
+        x: i64,
+        y: i64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i64,
+        y: i64,
+        z: i64,
+    }
+
+    impl From<PointV1> for PointV3 {
+        fn from(v: PointV1) -> PointV3 {
+            PointV3 {
+                x: v.x as i64,
+                y: v.y as i64,
+            }
+        }
+    }
+
+    impl From<PointV3> for Point {
+        fn from(v: PointV3) -> Point {
+            Point {
+                x: v.x,
+                y: v.y,
+                z: 0,
+            }
+        }
+    }
+
+    versioned!(PointV1 = 1);
+    versioned!(PointV3 = 3, from PointV1);
+    versioned!(Point = 4, from PointV3);
+
+    #[test]
+    fn upgrade_chain() {
+        let mut bs = Vec::new();
+        to_writer_versioned(&mut bs, &PointV1 { x: 1, y: -2 }).unwrap();
+        assert_eq!(bs[0], 1);
+
+        let p: Point = from_reader_versioned(bs.as_slice()).unwrap();
+        assert_eq!(p, Point { x: 1, y: -2, z: 0 });
+
+        let p: PointV3 = from_reader_versioned(bs.as_slice()).unwrap();
+        assert_eq!(p, PointV3 { x: 1, y: -2 });
+    }
+
+    #[test]
+    fn current_version() {
+        let v = Point { x: 5, y: 6, z: 7 };
+        let mut bs = Vec::new();
+        to_writer_versioned(&mut bs, &v).unwrap();
+        assert_eq!(bs[0], 4);
+
+        let p: Point = from_reader_versioned(bs.as_slice()).unwrap();
+        assert_eq!(p, v);
+    }
+
+    #[test]
+    fn unknown_versions() {
+        let mut bs = Vec::new();
+        to_writer_versioned(&mut bs, &Point { x: 5, y: 6, z: 7 }).unwrap();
+        let _ = from_reader_versioned::<_, PointV3>(bs.as_slice()).unwrap_err();
+
+        // Version 2 is skipped in the chain.
+        let bs = [2u8, 2, 4];
+        let _ = from_reader_versioned::<_, Point>(&bs[..]).unwrap_err();
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derive() {
+        use crate::{DokechiMessage, Versioned};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize, Versioned)]
+        #[dokechi(version = 3)]
+        struct LabelV3(String);
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize, Versioned, DokechiMessage)]
+        #[dokechi(version = 5, from = LabelV3)]
+        struct Label {
+            text: String,
+            color: u8,
+        }
+
+        impl From<LabelV3> for Label {
+            fn from(v: LabelV3) -> Label {
+                Label {
+                    text: v.0,
+                    color: 0,
+                }
+            }
+        }
+
+        let mut bs = Vec::new();
+        to_writer_versioned(&mut bs, &LabelV3("a".to_owned())).unwrap();
+        assert_eq!(bs, vec![3, 1, b'a']);
+
+        let label = Label::from_bytes(&bs).unwrap();
+        assert_eq!(
+            label,
+            Label {
+                text: "a".to_owned(),
+                color: 0
+            }
+        );
+        assert_eq!(label.to_bytes().unwrap(), vec![5, 1, b'a', 0]);
+        let _ = from_reader_versioned::<_, LabelV3>(&[5u8, 1, b'a', 0][..]).unwrap_err();
+        let _ = Label::from_bytes(&[4, 1, b'a']).unwrap_err();
+    }
+}
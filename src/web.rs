@@ -0,0 +1,329 @@
+//! Request and response bodies of web frameworks.
+//!
+//! [`Dokechi<T>`](struct.Dokechi.html) extracts a request body in Dokechi format into `T`,
+//! and writes `T` as a response body in Dokechi format, with [`CONTENT_TYPE`](constant.CONTENT_TYPE.html).
+//!
+//! Requests of other content types are rejected with `415 Unsupported Media Type`,
+//! and bodies failing to decode with `400 Bad Request`.
+//! Bodies are read by the `Bytes` extractor of the framework, so its body size limit applies:
+//! `DefaultBodyLimit` of `axum`, or `PayloadConfig` of `actix-web`.
+//!
+//! Support for `axum` and `actix-web` is available with the `axum` and `actix-web` features.
+//!
+//! ```ignore
+//! async fn create(Dokechi(user): Dokechi<User>) -> Dokechi<UserId> {
+//!     Dokechi(store(user))
+//! }
+//! ```
+
+use std::ops::{Deref, DerefMut};
+
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use thiserror::Error;
+
+use crate::de::{self, from_slice};
+use crate::ser::{self, to_vec};
+
+/// The content type of Dokechi format bodies.
+pub const CONTENT_TYPE: &str = "application/x-dokechi";
+
+/// A request or response body in Dokechi format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Dokechi<T>(pub T);
+
+impl<T> Dokechi<T> {
+    /// Unwrap the value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Dokechi<T> {
+    fn from(v: T) -> Dokechi<T> {
+        Dokechi(v)
+    }
+}
+
+impl<T> Deref for Dokechi<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Dokechi<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Serialize> Dokechi<T> {
+    fn encode(&self) -> Result<Vec<u8>, ser::Error> {
+        to_vec(&self.0)
+    }
+}
+
+impl<T: DeserializeOwned> Dokechi<T> {
+    fn decode(bs: &[u8]) -> Result<Dokechi<T>, Rejection> {
+        Ok(Dokechi(from_slice(bs).map_err(Rejection::Decode)?))
+    }
+}
+
+/// A request rejected by the [`Dokechi`](struct.Dokechi.html) extractor.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Rejection {
+    /// The content type is not [`CONTENT_TYPE`](constant.CONTENT_TYPE.html).
+    ///
+    /// Responded with `415 Unsupported Media Type`.
+    #[error("expected content type {}", CONTENT_TYPE)]
+    UnsupportedContentType,
+    /// The body is not a value of the type in Dokechi format.
+    ///
+    /// Responded with `400 Bad Request`.
+    #[error("invalid body: {0}")]
+    Decode(de::Error),
+}
+
+impl Rejection {
+    /// The status code of the response.
+    fn status(&self) -> u16 {
+        match self {
+            Rejection::UnsupportedContentType => 415,
+            Rejection::Decode(_) => 400,
+        }
+    }
+}
+
+/// Whether the value of a `Content-Type` header is [`CONTENT_TYPE`](constant.CONTENT_TYPE.html),
+/// ignoring parameters.
+fn is_dokechi(content_type: Option<&[u8]>) -> bool {
+    let content_type = match content_type.and_then(|v| std::str::from_utf8(v).ok()) {
+        Some(v) => v,
+        None => return false,
+    };
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    essence.eq_ignore_ascii_case(CONTENT_TYPE)
+}
+
+#[cfg(feature = "axum")]
+mod axum_impl {
+    use super::*;
+
+    use axum::body::Bytes;
+    use axum::extract::{FromRequest, Request};
+    use axum::http::header::{HeaderValue, CONTENT_TYPE as CONTENT_TYPE_HEADER};
+    use axum::http::StatusCode;
+    use axum::response::{IntoResponse, Response};
+
+    impl<T, S> FromRequest<S> for Dokechi<T>
+    where
+        T: DeserializeOwned,
+        S: Send + Sync,
+    {
+        type Rejection = Response;
+
+        async fn from_request(req: Request, state: &S) -> Result<Dokechi<T>, Response> {
+            let content_type = req.headers().get(CONTENT_TYPE_HEADER);
+            if !is_dokechi(content_type.map(HeaderValue::as_bytes)) {
+                return Err(Rejection::UnsupportedContentType.into_response());
+            }
+            let bs = Bytes::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Dokechi::decode(&bs).map_err(IntoResponse::into_response)
+        }
+    }
+
+    impl<T: Serialize> IntoResponse for Dokechi<T> {
+        fn into_response(self) -> Response {
+            match self.encode() {
+                Ok(bs) => (
+                    [(CONTENT_TYPE_HEADER, HeaderValue::from_static(CONTENT_TYPE))],
+                    bs,
+                )
+                    .into_response(),
+                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            }
+        }
+    }
+
+    impl IntoResponse for Rejection {
+        fn into_response(self) -> Response {
+            let status = StatusCode::from_u16(self.status()).expect("valid status code");
+            (status, self.to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(feature = "actix-web")]
+mod actix_impl {
+    use super::*;
+
+    use std::future::Future;
+    use std::pin::Pin;
+
+    use actix_web::body::BoxBody;
+    use actix_web::dev::Payload;
+    use actix_web::http::header::CONTENT_TYPE as CONTENT_TYPE_HEADER;
+    use actix_web::http::StatusCode;
+    use actix_web::web::Bytes;
+    use actix_web::{FromRequest, HttpRequest, HttpResponse, Responder, ResponseError};
+
+    impl<T: DeserializeOwned + 'static> FromRequest for Dokechi<T> {
+        type Error = actix_web::Error;
+        type Future = Pin<Box<dyn Future<Output = Result<Dokechi<T>, actix_web::Error>>>>;
+
+        fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+            let content_type = req.headers().get(CONTENT_TYPE_HEADER);
+            if !is_dokechi(content_type.map(|v| v.as_bytes())) {
+                return Box::pin(async { Err(Rejection::UnsupportedContentType.into()) });
+            }
+            let bs = Bytes::from_request(req, payload);
+            Box::pin(async move {
+                let bs = bs.await?;
+                Ok(Dokechi::decode(&bs)?)
+            })
+        }
+    }
+
+    impl<T: Serialize> Responder for Dokechi<T> {
+        type Body = BoxBody;
+
+        fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+            match self.encode() {
+                Ok(bs) => HttpResponse::Ok().content_type(CONTENT_TYPE).body(bs),
+                Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+            }
+        }
+    }
+
+    impl ResponseError for Rejection {
+        fn status_code(&self) -> StatusCode {
+            StatusCode::from_u16(self.status()).expect("valid status code")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::future::Future;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // Bodies in tests are in memory, so they are always ready.
+    #[allow(dead_code)]
+    fn block_on<F: Future>(f: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+        let mut f = Box::pin(f);
+        loop {
+            if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+                return v;
+            }
+        }
+    }
+
+    #[test]
+    fn content_type() {
+        assert!(is_dokechi(Some(b"application/x-dokechi")));
+        assert!(is_dokechi(Some(b"Application/X-Dokechi; charset=binary")));
+        assert!(!is_dokechi(Some(b"application/json")));
+        assert!(!is_dokechi(Some(b"application/x-dokechi-stream")));
+        assert!(!is_dokechi(None));
+    }
+
+    #[cfg(feature = "axum")]
+    #[test]
+    fn axum() {
+        use axum::body::{to_bytes, Body};
+        use axum::extract::{FromRequest, Request};
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+
+        let request = |content_type: &str, body: Vec<u8>| {
+            Request::builder()
+                .header("content-type", content_type)
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let extract = |req: Request| {
+            block_on(Dokechi::<Vec<String>>::from_request(req, &())).map_err(|e| e.status())
+        };
+
+        let v = vec!["a".to_owned(), "b".to_owned()];
+        let Dokechi(d) = extract(request(CONTENT_TYPE, to_vec(&v).unwrap())).unwrap();
+        assert_eq!(d, v);
+
+        let e = extract(request("application/json", to_vec(&v).unwrap())).unwrap_err();
+        assert_eq!(e, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let e = extract(request(CONTENT_TYPE, vec![2, 1])).unwrap_err();
+        assert_eq!(e, StatusCode::BAD_REQUEST);
+
+        let resp = Dokechi(v.clone()).into_response();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], CONTENT_TYPE);
+        let body = block_on(to_bytes(resp.into_body(), usize::MAX)).unwrap();
+        assert_eq!(body, to_vec(&v).unwrap());
+    }
+
+    #[cfg(feature = "actix-web")]
+    #[test]
+    fn actix_web() {
+        use actix_web::body::MessageBody;
+        use actix_web::http::StatusCode;
+        use actix_web::test::TestRequest;
+        use actix_web::web::PayloadConfig;
+        use actix_web::{FromRequest, Responder};
+
+        let extract = |content_type: &str, body: Vec<u8>| {
+            let (req, mut payload) = TestRequest::default()
+                .insert_header(("content-type", content_type))
+                .set_payload(body)
+                .to_http_parts();
+            block_on(Dokechi::<Vec<String>>::from_request(&req, &mut payload))
+        };
+
+        let v = vec!["a".to_owned(), "b".to_owned()];
+        let Dokechi(d) = extract(CONTENT_TYPE, to_vec(&v).unwrap()).unwrap();
+        assert_eq!(d, v);
+
+        let e = extract("application/json", to_vec(&v).unwrap()).unwrap_err();
+        assert_eq!(
+            e.as_response_error().status_code(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        let e = extract(CONTENT_TYPE, vec![2, 1]).unwrap_err();
+        assert_eq!(e.as_response_error().status_code(), StatusCode::BAD_REQUEST);
+
+        let (req, mut payload) = TestRequest::default()
+            .insert_header(("content-type", CONTENT_TYPE))
+            .app_data(PayloadConfig::new(4))
+            .set_payload(to_vec(&v).unwrap())
+            .to_http_parts();
+        let e = block_on(Dokechi::<Vec<String>>::from_request(&req, &mut payload)).unwrap_err();
+        assert_eq!(
+            e.as_response_error().status_code(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        let req = TestRequest::default().to_http_request();
+        let resp = Dokechi(v.clone()).respond_to(&req);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("content-type").unwrap(), CONTENT_TYPE);
+        let body = resp.into_body().try_into_bytes().unwrap();
+        assert_eq!(body, to_vec(&v).unwrap());
+    }
+}
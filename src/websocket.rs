@@ -0,0 +1,238 @@
+//! WebSocket message helpers for `tungstenite`.
+//!
+//! Values are sent as binary messages holding a single value in Dokechi format.
+//! Receiving functions skip ping and pong messages, return `None` on close,
+//! and reject text messages and messages longer than the given limit.
+//!
+//! This module is available with the `tungstenite` feature.
+//! [`send_async`](fn.send_async.html) and [`recv_async`](fn.recv_async.html)
+//! for `Sink`s and `Stream`s of messages, such as ones of `tokio-tungstenite`,
+//! require the `futures-util` feature too.
+
+use std::io::{self, Read, Write};
+
+use serde::de::{DeserializeOwned, Error as _};
+use serde::ser::Serialize;
+use tungstenite::{Message, WebSocket};
+
+use crate::de::from_slice;
+use crate::error::{Error, Offset};
+use crate::ser::{self, to_vec};
+
+/// Encode `value` into a binary message.
+pub fn to_message<T: Serialize + ?Sized>(value: &T) -> Result<Message, ser::Error> {
+    Ok(Message::binary(to_vec(value)?))
+}
+
+/// Decode a binary message of at most `max_len` bytes.
+///
+/// Returns `None` for ping, pong and close messages.
+pub fn from_message<T: DeserializeOwned>(
+    msg: &Message,
+    max_len: usize,
+) -> Result<Option<T>, Error> {
+    match msg {
+        Message::Binary(bs) => {
+            if bs.len() > max_len {
+                return Err(Error::custom(format_args!(
+                    "message of {} bytes is larger than {} bytes",
+                    bs.len(),
+                    max_len
+                )));
+            }
+            Ok(Some(from_slice(bs)?))
+        }
+        Message::Text(_) => Err(Error::custom("unexpected text message")),
+        _ => Ok(None),
+    }
+}
+
+/// Send `value` as a binary message.
+pub fn send<S: Read + Write, T: Serialize + ?Sized>(
+    ws: &mut WebSocket<S>,
+    value: &T,
+) -> Result<(), Error> {
+    ws.send(to_message(value)?).map_err(transport_error)
+}
+
+/// Receive a value from the next binary message of at most `max_len` bytes.
+///
+/// Returns `None` if the connection is closed.
+pub fn recv<S: Read + Write, T: DeserializeOwned>(
+    ws: &mut WebSocket<S>,
+    max_len: usize,
+) -> Result<Option<T>, Error> {
+    loop {
+        let msg = match ws.read() {
+            Ok(msg) => msg,
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(None),
+            Err(e) => return Err(transport_error(e)),
+        };
+        if let Message::Close(_) = msg {
+            return Ok(None);
+        }
+        if let Some(v) = from_message(&msg, max_len)? {
+            return Ok(Some(v));
+        }
+    }
+}
+
+/// Send `value` as a binary message to `sink`.
+#[cfg(feature = "futures-util")]
+pub async fn send_async<S, T>(sink: &mut S, value: &T) -> Result<(), Error>
+where
+    S: futures_util::Sink<Message> + Unpin,
+    S::Error: std::error::Error + Send + Sync + 'static,
+    T: Serialize + ?Sized,
+{
+    use futures_util::SinkExt;
+
+    let msg = to_message(value)?;
+    sink.send(msg).await.map_err(transport_error)
+}
+
+/// Receive a value from the next binary message of at most `max_len` bytes in `stream`.
+///
+/// Returns `None` if the connection is closed or `stream` ends.
+#[cfg(feature = "futures-util")]
+pub async fn recv_async<S, E, T>(stream: &mut S, max_len: usize) -> Result<Option<T>, Error>
+where
+    S: futures_util::Stream<Item = Result<Message, E>> + Unpin,
+    E: std::error::Error + Send + Sync + 'static,
+    T: DeserializeOwned,
+{
+    use futures_util::StreamExt;
+
+    while let Some(msg) = stream.next().await {
+        let msg = msg.map_err(transport_error)?;
+        if let Message::Close(_) = msg {
+            return Ok(None);
+        }
+        if let Some(v) = from_message(&msg, max_len)? {
+            return Ok(Some(v));
+        }
+    }
+    Ok(None)
+}
+
+fn transport_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::Other, e), Offset::UNKNOWN)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use tungstenite::protocol::Role;
+
+    #[test]
+    fn message_round_trip() {
+        let msg = to_message(&(1u32, "abc")).unwrap();
+        assert!(msg.is_binary());
+
+        let v: Option<(u32, String)> = from_message(&msg, 16).unwrap();
+        assert_eq!(v, Some((1, "abc".to_owned())));
+
+        let v: Option<u32> = from_message(&Message::Ping(Default::default()), 16).unwrap();
+        assert_eq!(v, None);
+        let _ = from_message::<u32>(&Message::text("1"), 16).unwrap_err();
+    }
+
+    #[test]
+    fn message_too_long() {
+        let msg = to_message(&vec![0u8; 100]).unwrap();
+        let _ = from_message::<Vec<u8>>(&msg, 50).unwrap_err();
+    }
+
+    #[test]
+    fn send_and_recv() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = WebSocket::from_raw_socket(stream, Role::Server, None);
+            let v: Vec<String> = recv(&mut ws, 1024).unwrap().unwrap();
+            send(&mut ws, &v.len()).unwrap();
+            ws.close(None).unwrap();
+            while ws.read().is_ok() {}
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut ws = WebSocket::from_raw_socket(stream, Role::Client, None);
+        ws.send(Message::Ping(Default::default())).unwrap();
+        send(&mut ws, &vec!["a".to_owned(), "b".to_owned()]).unwrap();
+        assert_eq!(recv::<_, usize>(&mut ws, 1024).unwrap(), Some(2));
+        assert_eq!(recv::<_, usize>(&mut ws, 1024).unwrap(), None);
+        drop(ws);
+
+        server.join().unwrap();
+    }
+
+    #[cfg(feature = "futures-util")]
+    #[test]
+    fn send_and_recv_async() {
+        use std::convert::Infallible;
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Noop;
+
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        struct VecSink(Vec<Message>);
+
+        impl futures_util::Sink<Message> for VecSink {
+            type Error = Infallible;
+
+            fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Infallible>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Infallible> {
+                self.0.push(item);
+                Ok(())
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Infallible>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Infallible>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        // In-memory sinks and streams are always ready.
+        fn block_on<F: Future>(f: F) -> F::Output {
+            let waker = Waker::from(Arc::new(Noop));
+            let mut cx = Context::from_waker(&waker);
+            let mut f: Pin<Box<F>> = Box::pin(f);
+            loop {
+                if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+                    return v;
+                }
+            }
+        }
+
+        let mut sink = VecSink(Vec::new());
+        block_on(send_async(&mut sink, &42u64)).unwrap();
+        block_on(send_async(&mut sink, "hi")).unwrap();
+        sink.0.insert(1, Message::Pong(Default::default()));
+
+        let mut stream = futures_util::stream::iter(sink.0.into_iter().map(Ok::<_, Infallible>));
+        let v: Option<u64> = block_on(recv_async(&mut stream, 16)).unwrap();
+        assert_eq!(v, Some(42));
+        let v: Option<String> = block_on(recv_async(&mut stream, 16)).unwrap();
+        assert_eq!(v, Some("hi".to_owned()));
+        let v: Option<String> = block_on(recv_async(&mut stream, 16)).unwrap();
+        assert_eq!(v, None);
+    }
+}